@@ -0,0 +1,225 @@
+//! Benchmarks of the hot spots of painting: applying core's updates to the
+//! line cache, converting between utf-8 and utf-16 offsets, and laying out
+//! lines with DirectWrite.
+//!
+//! The front end is a binary, so the modules measured are compiled in from
+//! `src` directly.
+
+#![allow(dead_code)]
+
+#[macro_use]
+extern crate log;
+
+#[path = "../src/linecache.rs"]
+mod linecache;
+#[path = "../src/metrics.rs"]
+mod metrics;
+#[path = "../src/offsets.rs"]
+mod offsets;
+#[path = "../src/protocol.rs"]
+mod protocol;
+#[path = "../src/styles.rs"]
+mod styles;
+#[path = "../src/textline.rs"]
+mod textline;
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use directwrite::TextFormat;
+
+use crate::linecache::{Line, LineCache};
+use crate::metrics::Metrics;
+use crate::offsets::{count_utf16, utf16_to_utf8, utf8_to_utf16};
+use crate::protocol::{Lenient, LineUpdate, Update, UpdateOp};
+use crate::styles::{Style, StyleMap};
+use crate::textline::TextLine;
+
+/// The lines in the cache the updates are applied to.
+const TOTAL: usize = 100_000;
+/// The lines of a huge file.
+const MILLION: usize = 1_000_000;
+
+/// Latin, Cyrillic, CJK, an emoji and a combining accent, each a different
+/// number of utf-8 and utf-16 code units.
+const MULTILINGUAL: &str = "fn main() { println!(\"Привет, 世界 😀 cafe\u{301}\"); } ";
+
+/// A line of code, with its keyword, string and comment styled by
+/// [`CODE_STYLES`] as a syntax highlighter would.
+const CODE: &str = "    let x = \"string\"; // comment\n";
+const CODE_STYLES: [i64; 9] = [4, 3, 2, 5, 8, 3, 2, 10, 3];
+
+fn line(text: &str, cursor: Vec<usize>, styles: &[i64]) -> Lenient<LineUpdate> {
+    Lenient::Ok(LineUpdate {
+        text: text.to_owned(),
+        cursor,
+        styles: styles.to_vec(),
+    })
+}
+
+fn code_line(cursor: Vec<usize>) -> Lenient<LineUpdate> {
+    line(CODE, cursor, &CODE_STYLES)
+}
+
+fn update(ops: Vec<UpdateOp>) -> Update {
+    Update {
+        ops: ops.into_iter().map(Lenient::Ok).collect(),
+        pristine: None,
+        annotations: None,
+    }
+}
+
+fn filled_cache(total: usize) -> LineCache {
+    let mut cache = LineCache::new();
+    let lines = (0..total).map(|_| code_line(vec![])).collect();
+    cache.apply_update(&update(vec![UpdateOp::Ins { lines }]));
+    cache
+}
+
+fn apply_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply_update");
+
+    // A keystroke: one line resent, with the caret.
+    let mut cache = filled_cache(TOTAL);
+    let keystroke = update(vec![
+        UpdateOp::Copy { n: TOTAL / 2 },
+        UpdateOp::Ins {
+            lines: vec![code_line(vec![10])],
+        },
+        UpdateOp::Skip { n: 1 },
+        UpdateOp::Copy { n: TOTAL / 2 - 1 },
+    ]);
+    group.bench_function("keystroke", |b| {
+        b.iter(|| cache.apply_update(black_box(&keystroke)))
+    });
+
+    // The same far down a huge file, which should cost no more.
+    let mut cache = filled_cache(MILLION);
+    let line_num = 765_432;
+    let keystroke = update(vec![
+        UpdateOp::Copy { n: line_num },
+        UpdateOp::Ins {
+            lines: vec![code_line(vec![10])],
+        },
+        UpdateOp::Skip { n: 1 },
+        UpdateOp::Copy {
+            n: MILLION - line_num - 1,
+        },
+    ]);
+    group.bench_function("keystroke_in_a_million_lines", |b| {
+        b.iter(|| cache.apply_update(black_box(&keystroke)))
+    });
+
+    // A paste of a hundred lines, then its undo.
+    let mut cache = filled_cache(TOTAL);
+    let pasted = (0..100).map(|_| code_line(vec![])).collect();
+    let paste = update(vec![
+        UpdateOp::Copy { n: TOTAL / 2 },
+        UpdateOp::Ins { lines: pasted },
+        UpdateOp::Copy { n: TOTAL / 2 },
+    ]);
+    let undo = update(vec![
+        UpdateOp::Copy { n: TOTAL / 2 },
+        UpdateOp::Skip { n: 100 },
+        UpdateOp::Copy { n: TOTAL / 2 },
+    ]);
+    group.bench_function("paste_and_undo", |b| {
+        b.iter(|| {
+            cache.apply_update(black_box(&paste));
+            cache.apply_update(black_box(&undo));
+        })
+    });
+
+    // Scrolling through a file core hasn't sent yet: each screen scrolled
+    // to is sent in place of the invalid lines there.
+    let mut cache = LineCache::new();
+    cache.apply_update(&update(vec![UpdateOp::Invalidate { n: TOTAL }]));
+    let mut first = 0;
+    group.bench_function("scroll_in", |b| {
+        b.iter(|| {
+            let screen = (0..50).map(|_| code_line(vec![])).collect();
+            let next = (first + 50) % (TOTAL - 50);
+            cache.apply_update(&update(vec![
+                UpdateOp::Copy { n: next },
+                UpdateOp::Ins { lines: screen },
+                UpdateOp::Skip { n: 50 },
+                UpdateOp::Copy {
+                    n: TOTAL - next - 50,
+                },
+            ]));
+            first = next;
+        })
+    });
+    group.finish();
+}
+
+fn offsets(c: &mut Criterion) {
+    let text = MULTILINGUAL.repeat(4);
+    let utf16_len = count_utf16(&text);
+    let mut group = c.benchmark_group("offsets");
+    group.bench_function("count_utf16", |b| b.iter(|| count_utf16(black_box(&text))));
+    group.bench_function("utf8_to_utf16", |b| {
+        b.iter(|| {
+            (0..=text.len())
+                .step_by(7)
+                .map(|offset| utf8_to_utf16(black_box(&text), offset))
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("utf16_to_utf8", |b| {
+        b.iter(|| {
+            (0..=utf16_len)
+                .step_by(7)
+                .map(|offset| utf16_to_utf8(black_box(&text), offset))
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+fn layout(c: &mut Criterion) {
+    let factory = directwrite::Factory::new().unwrap();
+    let format = TextFormat::create(&factory)
+        .with_family("Consolas")
+        .with_size(15.0)
+        .build()
+        .unwrap();
+    let metrics = Metrics::measure(&factory, &format);
+    let bold = Style {
+        weight: Some(700),
+        ..Style::default()
+    };
+    let italic = Style {
+        italic: true,
+        ..Style::default()
+    };
+    let styles: StyleMap = [(2, bold), (3, italic)].into_iter().collect();
+    let lines = [
+        ("code", code_line(vec![4])),
+        ("multilingual", line(MULTILINGUAL, vec![], &[])),
+        ("tabs", line("\t\t\tnested();\n", vec![], &[])),
+    ];
+    let mut group = c.benchmark_group("create_from_line");
+    for (name, line) in lines {
+        let Lenient::Ok(line) = line else {
+            unreachable!();
+        };
+        let line = Line::from_update(&line).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                TextLine::create_from_line(
+                    black_box(&line),
+                    &factory,
+                    &format,
+                    &styles,
+                    4,
+                    &metrics,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, apply_update, offsets, layout);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+//! Saving edited files without being asked, every so often and when the
+//! window is deactivated.
+//!
+//! Both are off unless turned on in the xi config, with `autosave_interval`
+//! (in seconds) and `autosave_on_focus_loss`.
+
+use std::time::Duration;
+
+use winapi::shared::minwindef::LOWORD;
+use winapi::shared::windef::HWND;
+use winapi::um::winuser::{WA_INACTIVE, WM_ACTIVATE};
+
+use crate::protocol::ConfigChanges;
+use crate::subclass::{self, HookId};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AutoSaveSettings {
+    /// How often edited files are saved, if at all.
+    pub interval: Option<Duration>,
+    pub on_focus_loss: bool,
+}
+
+impl AutoSaveSettings {
+    /// Applies the changes of a `config_changed` notification.
+    pub fn config_changed(&mut self, changes: &ConfigChanges) {
+        if let Some(secs) = changes.autosave_interval {
+            self.interval = Some(Duration::from_secs(secs)).filter(|i| !i.is_zero());
+        }
+        if let Some(on_focus_loss) = changes.autosave_on_focus_loss {
+            self.on_focus_loss = on_focus_loss;
+        }
+    }
+}
+
+/// Calls `listener` whenever the window is deactivated, such as by
+/// switching to another app. Returns false if the window couldn't be hooked.
+///
+/// # Safety
+///
+/// `hwnd` must be a window created by the current thread.
+pub unsafe fn on_deactivate<F: Fn() + 'static>(hwnd: HWND, listener: F) -> bool {
+    subclass::hook(hwnd, HookId::AutoSave, move |_, msg, wparam, _| {
+        if msg == WM_ACTIVATE && LOWORD(wparam as u32) == WA_INACTIVE {
+            listener();
+        }
+        None
+    })
+}
@@ -0,0 +1,53 @@
+//! Blinking the caret at the rate set in Control Panel, and hiding it while
+//! the window doesn't have the keyboard focus.
+
+use winapi::shared::basetsd::UINT_PTR;
+use winapi::shared::windef::HWND;
+use winapi::um::winbase::INFINITE;
+use winapi::um::winuser::{GetCaretBlinkTime, SetTimer, WM_KILLFOCUS, WM_SETFOCUS, WM_TIMER};
+
+use crate::subclass::{self, HookId};
+
+/// The id of the blink timer. Any nonzero id works, as the window has no
+/// other timers.
+const BLINK_TIMER_ID: UINT_PTR = 1;
+
+/// What the caret should do, passed to the listener given to [`hook`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CaretEvent {
+    /// Half a blink has passed, so the caret should show if it was hidden
+    /// and hide if it was shown.
+    Blink,
+    /// The window gained (true) or lost (false) the keyboard focus.
+    Focus(bool),
+}
+
+/// Starts the blink timer of a window, calling `listener` on every blink
+/// and focus change. Returns false if the window couldn't be hooked.
+///
+/// # Safety
+///
+/// `hwnd` must be a window created by the current thread.
+pub unsafe fn hook<F: Fn(CaretEvent) + 'static>(hwnd: HWND, listener: F) -> bool {
+    let hooked = subclass::hook(hwnd, HookId::CaretBlink, move |_, msg, wparam, _| {
+        match msg {
+            WM_TIMER if wparam == BLINK_TIMER_ID => {
+                listener(CaretEvent::Blink);
+                return Some(0);
+            }
+            WM_SETFOCUS => listener(CaretEvent::Focus(true)),
+            WM_KILLFOCUS => listener(CaretEvent::Focus(false)),
+            _ => (),
+        }
+        None
+    });
+    if !hooked {
+        return false;
+    }
+    // Blinking can be turned off, in which case the caret stays shown.
+    let blink_time = GetCaretBlinkTime();
+    if blink_time != 0 && blink_time != INFINITE {
+        SetTimer(hwnd, BLINK_TIMER_ID, blink_time, None);
+    }
+    true
+}
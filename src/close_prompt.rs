@@ -0,0 +1,24 @@
+//! Letting the app veto closing the main window, such as to ask about unsaved
+//! changes first.
+
+use winapi::shared::windef::HWND;
+use winapi::um::winuser::WM_CLOSE;
+
+use crate::subclass::{self, HookId};
+
+/// Calls `confirm` whenever the window is asked to close, such as with its
+/// close button, and only lets it close if that returns true. Returns false
+/// if the window couldn't be hooked.
+///
+/// # Safety
+///
+/// `hwnd` must be a window created by the current thread.
+pub unsafe fn on_close<F: Fn() -> bool + 'static>(hwnd: HWND, confirm: F) -> bool {
+    subclass::hook(hwnd, HookId::ClosePrompt, move |_, msg, _, _| {
+        if msg == WM_CLOSE && !confirm() {
+            Some(0)
+        } else {
+            None
+        }
+    })
+}
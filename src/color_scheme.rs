@@ -0,0 +1,138 @@
+//! The colors used to draw the editor and its surrounding chrome.
+
+use serde_json::Value;
+
+/// A set of colors, each in `0x00RRGGBB` form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorScheme {
+    pub foreground: u32,
+    pub background: u32,
+    pub selection: u32,
+    pub caret: u32,
+    /// The carets other than the primary one, dimmer so that it stands out.
+    pub secondary_caret: u32,
+    /// The background of find matches.
+    pub find_highlight: u32,
+    /// The line numbers in the gutter.
+    pub gutter_fg: u32,
+    /// The background of the line with the primary caret.
+    pub line_highlight: u32,
+
+    pub tab_active_bg: u32,
+    pub tab_inactive_bg: u32,
+    pub tab_hover_bg: u32,
+    pub tab_fg: u32,
+    /// The dot drawn on tabs with unsaved changes.
+    pub tab_dirty: u32,
+    /// The close glyph drawn on tabs.
+    pub tab_close: u32,
+}
+
+impl ColorScheme {
+    pub fn dark() -> Self {
+        Self::from_editor_colors(0x00f0_f0ea, 0x0027_2822, 0x0049_483e)
+    }
+
+    pub fn light() -> Self {
+        Self::from_editor_colors(0x0033_3333, 0x00ff_ffff, 0x00ad_d6ff)
+    }
+
+    /// Creates a scheme from the editor colors, deriving the tab colors from
+    /// them.
+    pub fn from_editor_colors(foreground: u32, background: u32, selection: u32) -> Self {
+        Self {
+            foreground,
+            background,
+            selection,
+            caret: foreground,
+            secondary_caret: mix(background, foreground, 0.55),
+            find_highlight: mix(background, 0x00ff_c800, 0.35),
+            gutter_fg: mix(background, foreground, 0.45),
+            line_highlight: mix(background, foreground, 0.06),
+            tab_active_bg: background,
+            tab_inactive_bg: mix(background, 0, 0.25),
+            tab_hover_bg: mix(background, foreground, 0.1),
+            tab_fg: foreground,
+            tab_dirty: mix(background, foreground, 0.7),
+            tab_close: mix(background, foreground, 0.5),
+        }
+    }
+}
+
+impl ColorScheme {
+    /// Creates a scheme from the settings of an xi-core theme, as sent with
+    /// `theme_changed`. Colors the theme leaves out are taken from
+    /// `fallback`.
+    pub fn from_xi_theme(theme: &Value, fallback: &Self) -> Self {
+        let color = |key: &str| xi_color(&theme[key]);
+        let mut scheme = Self::from_editor_colors(
+            color("foreground").unwrap_or(fallback.foreground),
+            color("background").unwrap_or(fallback.background),
+            color("selection").unwrap_or(fallback.selection),
+        );
+        if let Some(caret) = color("caret") {
+            scheme.caret = caret;
+            scheme.secondary_caret = mix(scheme.background, caret, 0.55);
+        }
+        if let Some(find_highlight) = color("find_highlight") {
+            scheme.find_highlight = find_highlight;
+        }
+        if let Some(line_highlight) = color("line_highlight") {
+            scheme.line_highlight = line_highlight;
+        }
+        scheme
+    }
+
+    /// Whether the background is dark, so the window frame should be too.
+    pub fn is_dark(&self) -> bool {
+        let channel = |shift: u32| ((self.background >> shift) & 0xff) as f32 / 255.0;
+        // Rec. 709 luma weights.
+        0.2126f32.mul_add(
+            channel(16),
+            0.7152f32.mul_add(channel(8), 0.0722 * channel(0)),
+        ) < 0.5
+    }
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+/// Whether to use the light or dark scheme.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ThemeMode {
+    /// Follow the app theme chosen in the Windows settings.
+    Auto,
+    Light,
+    Dark,
+}
+
+impl ThemeMode {
+    /// Whether this mode is dark, given whether the system theme is.
+    pub const fn is_dark(self, system_dark: bool) -> bool {
+        match self {
+            Self::Auto => system_dark,
+            Self::Light => false,
+            Self::Dark => true,
+        }
+    }
+}
+
+/// Converts a color serialized by xi-core as `{"r", "g", "b", "a"}`.
+fn xi_color(value: &Value) -> Option<u32> {
+    let channel = |key: &str| value[key].as_u64().map(|c| c.min(0xff) as u32);
+    Some(channel("r")? << 16 | channel("g")? << 8 | channel("b")?)
+}
+
+/// Linearly interpolates between two colors, where `t` of 0 is `a` and 1 is
+/// `b`.
+fn mix(a: u32, b: u32, t: f32) -> u32 {
+    let channel = |shift: u32| {
+        let a = ((a >> shift) & 0xff) as f32;
+        let b = ((b >> shift) & 0xff) as f32;
+        ((b - a).mul_add(t, a).round() as u32) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
@@ -0,0 +1,109 @@
+//! Per-monitor DPI awareness.
+//!
+//! The shell lays out and delivers mouse events in window px, but the widgets
+//! work in device independent pixels (dips, 1/96 inch), so that text and
+//! padding are the same physical size on any monitor. They convert at their
+//! edges with [`scale`], and draw through [`begin_paint`]. When the window is
+//! dragged to a monitor with a different DPI, the scale follows it.
+
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use winapi::shared::minwindef::LOWORD;
+use winapi::shared::windef::{DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2, HWND, RECT};
+use winapi::um::winuser::{
+    GetDpiForWindow, InvalidateRect, SetProcessDpiAwarenessContext, SetWindowPos, SWP_NOACTIVATE,
+    SWP_NOZORDER, USER_DEFAULT_SCREEN_DPI, WM_DPICHANGED,
+};
+
+use direct2d::math::Matrix3x2F;
+use direct2d::RenderTarget;
+
+use druid::Geometry;
+
+use crate::subclass::{self, HookId};
+
+/// The window's px per dip, as the bits of an `f32`.
+static SCALE: AtomicU32 = AtomicU32::new(0x3f80_0000); // 1.0
+
+/// The window's px per dip, 1.0 at 96 DPI.
+pub fn scale() -> f32 {
+    f32::from_bits(SCALE.load(Ordering::Relaxed))
+}
+
+fn set_dpi(dpi: u32) {
+    let scale = dpi as f32 / USER_DEFAULT_SCREEN_DPI as f32;
+    SCALE.store(scale.to_bits(), Ordering::Relaxed);
+}
+
+/// Converts window px to dips.
+pub fn to_dips(px: f32) -> f32 {
+    px / scale()
+}
+
+/// Converts dips to window px.
+pub fn to_px(dips: f32) -> f32 {
+    dips * scale()
+}
+
+/// Opts the process in to per-monitor DPI awareness, so that Windows doesn't
+/// stretch the window's bitmap on high DPI monitors. This must come before
+/// the shell sets its own, system wide, awareness, as only the first call
+/// takes effect. Returns false on versions of Windows before 10 1703, which
+/// fall back to the shell's.
+pub fn enable_awareness() -> bool {
+    // SAFETY: This only sets a flag of the process.
+    unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) != 0 }
+}
+
+/// Takes the scale from the DPI of the window's monitor, and follows it as
+/// the window moves between monitors. Returns false if the window couldn't
+/// be hooked.
+///
+/// # Safety
+///
+/// `hwnd` must be a window created by the current thread.
+pub unsafe fn hook(hwnd: HWND) -> bool {
+    let dpi = GetDpiForWindow(hwnd);
+    if dpi != 0 {
+        set_dpi(dpi);
+    }
+    subclass::hook(hwnd, HookId::Dpi, |hwnd, msg, wparam, lparam| {
+        if msg != WM_DPICHANGED {
+            return None;
+        }
+        // The x and y DPI are always the same.
+        set_dpi(u32::from(LOWORD(wparam as u32)));
+        // Windows suggests a rect keeping the window the same physical size.
+        let rect = &*(lparam as *const RECT);
+        SetWindowPos(
+            hwnd,
+            ptr::null_mut(),
+            rect.left,
+            rect.top,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
+            SWP_NOZORDER | SWP_NOACTIVATE,
+        );
+        // Resizing relays out, but the size in px may not have changed.
+        InvalidateRect(hwnd, ptr::null(), 0);
+        Some(0)
+    })
+}
+
+/// Scales drawing to the render target from dips, and returns the widget's
+/// geometry in dips. Every [`begin_paint`] needs an [`end_paint`] before the
+/// next widget draws.
+pub fn begin_paint<R: RenderTarget>(rt: &mut R, geom: &Geometry) -> Geometry {
+    let scale = scale();
+    rt.set_transform(&Matrix3x2F::scale((scale, scale).into(), (0.0, 0.0).into()));
+    Geometry {
+        pos: (geom.pos.0 / scale, geom.pos.1 / scale),
+        size: (geom.size.0 / scale, geom.size.1 / scale),
+    }
+}
+
+/// Goes back to drawing in px.
+pub fn end_paint<R: RenderTarget>(rt: &mut R) {
+    rt.set_transform(&Matrix3x2F::identity());
+}
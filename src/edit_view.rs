@@ -1,23 +1,28 @@
 //! The main edit view.
 
 use std::any::Any;
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::fmt;
 use std::mem;
 use std::ops::Range;
-use std::sync::{Mutex, Weak};
+use std::ptr;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 use serde_json::Value;
 
-use winapi::um::winuser::{
-    VK_BACK, VK_DELETE, VK_DOWN, VK_END, VK_ESCAPE, VK_HOME, VK_LEFT, VK_NEXT, VK_OEM_4, VK_OEM_6,
-    VK_PRIOR, VK_RETURN, VK_RIGHT, VK_TAB, VK_UP,
-};
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::winerror::{D2DERR_RECREATE_TARGET, SUCCEEDED};
+use winapi::um::dwrite::{DWRITE_FONT_STYLE_ITALIC, DWRITE_TEXT_RANGE};
+use winapi::um::winuser::{VK_BACK, VK_ESCAPE, VK_RETURN, VK_TAB};
 
 use direct2d::brush::SolidColorBrush;
+use direct2d::enums::AntialiasMode;
 use direct2d::math::RectF;
 use direct2d::RenderTarget;
-use directwrite::TextFormat;
+use directwrite::{TextFormat, TextLayout};
 
 use druid_win_shell::window::{MouseButton, M_ALT, M_CTRL, M_SHIFT};
 
@@ -28,65 +33,528 @@ use druid::KeyVariant;
 use druid::{BoxConstraints, Geometry, LayoutResult};
 use druid::{HandlerCtx, Id, KeyEvent, LayoutCtx, MouseEvent, PaintCtx};
 
-use crate::linecache::LineCache;
+use crate::caret_blink::CaretEvent;
+use crate::color_scheme::ColorScheme;
+use crate::dpi;
+use crate::expand_selection;
+use crate::find_view::{FindField, FindQuery};
+use crate::ime::{self, ImeEvent};
+use crate::keymap::{KeyCommand, Keymap};
+use crate::linecache::{
+    AnnotationKind, CopiedLines, IndentStyle, Line, LineCache, LineState, FIND_HIGHLIGHT_STYLE_ID,
+    SELECTION_STYLE_ID,
+};
+use crate::metrics::Metrics;
+use crate::modal::{Effect, Modal, Mode, Register, Yank};
+use crate::offsets::{utf16_to_utf8, utf8_to_utf16};
+use crate::prelayout::{self, Prelayout};
+use crate::protocol::{ConfigChanges, FindStatus, Update};
 use crate::rpc::Core;
-use crate::textline::TextLine;
+use crate::status_bar::CaretStatus;
+use crate::styles::{split_alpha, Style, StyleMap};
+use crate::text_rendering::{Antialiasing, TextRendering};
+use crate::textline::{Invisibles, TextLine};
+use crate::view_model::{self, key_action, EditViewModel, KeyAction, Typed};
 
 /// The commands the [`EditView`] widget accepts through `poke`.
 #[derive(Debug)]
 pub enum EditViewCommands {
+    /// Shows the view with this id, opening it if it is new.
     ViewId(String),
-    ApplyUpdate(Value),
-    ScrollTo(usize),
+    /// Forgets a view which core has closed.
+    CloseView(String),
+    /// The file of the view `old` was opened again as `view_id`, to take
+    /// its place. The new view gets the selections and scroll position of
+    /// the old one.
+    Reopened {
+        old: String,
+        view_id: String,
+    },
+    /// Replaces the user's keybindings.
+    SetKeymap(Keymap),
+    ApplyUpdate {
+        view_id: String,
+        update: Update,
+    },
+    ScrollTo {
+        view_id: String,
+        line: usize,
+    },
     Core(Weak<Mutex<Core>>),
+    /// The thread to lay out the lines around the viewport on.
+    Prelayout(Prelayout),
+    /// Lines the layout thread has laid out.
+    LaidOut(prelayout::Done),
     Undo,
     Redo,
     UpperCase,
     LowerCase,
     Transpose,
+    Indent,
+    Outdent,
+    DuplicateLine,
+    DeleteLine,
     AddCursorAbove,
     AddCursorBelow,
     SingleSelection,
     SelectAll,
+    SelectLine,
+    ExpandSelection,
+    ShrinkSelection,
+    InsertSequence,
+    TransformSelections(SelectionTransform),
+    RenameSymbol,
+    /// The `changes` of a `config_changed` notification.
+    ConfigChanged {
+        view_id: String,
+        changes: ConfigChanges,
+    },
+    SetTabSize(usize),
+    SetTranslateTabsToSpaces(bool),
+    ToggleDetectIndentation,
+    /// The user's settings for the language of the view set its indentation,
+    /// which is then not detected.
+    IndentationOverridden(String),
+    ToggleLineNumbers,
+    ToggleIndentGuides,
+    ToggleWhitespace,
+    ToggleMinimap,
+    /// Switches between ClearType and grayscale antialiasing.
+    ToggleGrayscale,
+    ToggleColorFonts,
+    /// Turns vi style modal editing on or off.
+    ToggleModal,
+    SetColorScheme(ColorScheme),
+    /// Hover info from a plugin, in answer to `request_hover`.
+    ShowHover {
+        request_id: usize,
+        text: String,
+    },
+    /// The styles core has defined with `def_style`.
+    SetStyles(StyleMap),
+    /// Reports the caret position of the shown view to listeners on the
+    /// next frame, after a `scroll_to`. Updates report it themselves once
+    /// they are applied.
+    UpdateStatus,
+    /// Opens the find bar, or moves typing back to its find field.
+    Find,
+    FindNext,
+    FindPrevious,
+    /// The `queries` of a `find_status` notification.
+    FindStatus {
+        view_id: String,
+        queries: Vec<FindStatus>,
+    },
+    /// A change to the IME composition.
+    Ime(ImeEvent),
+    /// A blink of the caret, or a change of the window's focus.
+    Caret(CaretEvent),
+    /// Scrolls the shown view to an offset in px, once it has lines to
+    /// scroll through. Used to restore the last session.
+    RestoreScroll(f64),
+    /// The `params` of a `measure_width` request, answered through `reply`.
+    MeasureWidth {
+        params: Value,
+        reply: Sender<Value>,
+    },
+}
+
+impl EditViewCommands {
+    /// Whether the command may look at the lines, so that the updates queued
+    /// before it are applied first. Updates wait to be applied together, and
+    /// the status waits for the next frame.
+    fn reads_lines(&self) -> bool {
+        !matches!(self, Self::ApplyUpdate { .. } | Self::UpdateStatus)
+    }
+}
+
+/// Sent to the edit view's listeners.
+#[derive(Debug)]
+pub enum EditViewEvent {
+    /// The shown view's search changed, or `None` if the find bar is closed.
+    Find(Option<FindQuery>),
+    Caret(CaretStatus),
+    /// The shown view scrolled, to the given offset in px.
+    Scroll {
+        view_id: String,
+        offset: f64,
+    },
+    /// The view was right-clicked, for a context menu at the mouse.
+    ContextMenu,
+    /// A key bound to a menu command was pressed.
+    Command(u32),
+    /// The editing mode changed, or modal editing was turned on or off.
+    Mode(Option<Mode>),
+    /// The shown view's indentation settings changed, or another view was
+    /// shown.
+    Indentation(Indentation),
+    /// Detecting the indentation was turned on or off.
+    DetectIndentation(bool),
+}
+
+/// The indentation settings of a view, as configured in core.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Indentation {
+    pub tab_size: usize,
+    pub translate_tabs_to_spaces: bool,
+}
+
+impl Default for Indentation {
+    fn default() -> Self {
+        Self {
+            tab_size: 4,
+            translate_tabs_to_spaces: true,
+        }
+    }
+}
+
+impl Indentation {
+    /// Applies the changes of a `config_changed` notification.
+    fn config_changed(&mut self, changes: &ConfigChanges) {
+        if let Some(tab_size) = changes.tab_size {
+            self.tab_size = tab_size;
+        }
+        if let Some(translate) = changes.translate_tabs_to_spaces {
+            self.translate_tabs_to_spaces = translate;
+        }
+    }
+}
+
+/// The state of an open view while another one is shown.
+#[derive(Debug, Default)]
+struct Document {
+    line_cache: LineCache,
+    scroll_offset: f64,
+    indentation: Indentation,
+    indentation_detected: bool,
+    find: Option<FindQuery>,
+    restore_scroll: Option<f64>,
+}
+
+/// How an action which had no effect is acknowledged, set by the
+/// `bell_style` key of the user config.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BellStyle {
+    /// Briefly flash the border of the view.
+    Flash,
+    Off,
+}
+
+/// How a drag selection auto-scrolls near the top and bottom of the view.
+///
+/// These are read from the `autoscroll_margin` and `autoscroll_max_speed`
+/// keys of the user config.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoscrollSettings {
+    /// How close (in px) the mouse must be to an edge to start scrolling.
+    pub margin: f32,
+    /// The fastest the view scrolls, in px per second.
+    pub max_speed: f32,
+}
+
+impl Default for AutoscrollSettings {
+    fn default() -> Self {
+        Self {
+            margin: 16.0,
+            max_speed: 2000.0,
+        }
+    }
 }
 
-type Method = String;
-type Params = Value;
+/// A position in the buffer, as a line number and a utf-8 offset in that line.
+pub type Position = (usize, usize);
+
+/// A change made to the text of each selection on its own.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SelectionTransform {
+    UpperCase,
+    LowerCase,
+    /// Removes the whitespace at either end.
+    Trim,
+}
+
+impl SelectionTransform {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Self::UpperCase => text.to_uppercase(),
+            Self::LowerCase => text.to_lowercase(),
+            Self::Trim => text.trim().to_owned(),
+        }
+    }
+}
+
+/// A selection, with its caret at `end`. A bare caret has `start == end`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Selection {
+    pub start: Position,
+    pub end: Position,
+}
 
 /// State and behavior for one editor view.
 pub struct EditView {
     view_id: Option<String>,
+    /// The views which aren't shown, by view id.
+    documents: HashMap<String, Document>,
     line_cache: LineCache,
+    /// The layouts of the lines last painted and of those around them, by
+    /// line number, kept until the line changes or the text format does.
+    text_lines: HashMap<usize, TextLine>,
+    prelayout: Option<Prelayout>,
+    /// Counts the times `text_lines` have been moved or dropped, so that
+    /// layouts of lines which have changed since are thrown away.
+    layout_generation: u64,
+    /// The lines last asked of the layout thread, and the generation then.
+    prelayout_requested: Option<(u64, Range<usize>)>,
+    /// The line of the primary caret, which is highlighted.
+    caret_line: Option<usize>,
     dwrite_factory: directwrite::Factory,
     resources: Option<Resources>,
-    scroll_offset: f32,
-    size: (f32, f32), // in px units
+    text_resources: Option<TextResources>,
+    color_scheme: ColorScheme,
+    styles: StyleMap,
+    /// The font the text is laid out in, set by the `font_face` and
+    /// `font_size` (in dips) keys of the user config.
+    font_face: String,
+    font_size: f32,
+    indentation: Indentation,
+    /// Whether to guess the indentation from the first lines core sends,
+    /// set by the `autodetect_whitespace` key of the user config or from the
+    /// View menu.
+    detect_indentation: bool,
+    indentation_detected: bool,
+    /// Whether typed brackets and quotes are closed, set by the
+    /// `auto_close_brackets` key of the user config. Core has no such
+    /// setting, so it's done here.
+    auto_close_brackets: bool,
+    /// Where Insert Number Sequence counts from, and by how much, unless the
+    /// selections hold numbers. Set by the `sequence_start` and
+    /// `sequence_step` keys of the user config.
+    sequence_start: i64,
+    sequence_step: i64,
+    autoscroll: AutoscrollSettings,
+    bell: BellStyle,
+    /// Whether the gutter shows line numbers, set by the `line_numbers` key
+    /// of the user config or from the View menu.
+    line_numbers: bool,
+    /// Which invisible parts of the text are shown, set by the
+    /// `indent_guides` and `render_whitespace` keys of the user config or
+    /// from the View menu.
+    invisibles: Invisibles,
+    /// How text is antialiased, and whether color fonts are, set by the
+    /// `text_antialiasing` and `color_fonts` keys of the user config or from
+    /// the View menu.
+    text_rendering: TextRendering,
+    /// Whether the minimap shows down the right side, set by the `minimap`
+    /// key of the user config or from the View menu.
+    minimap: bool,
+    /// Whether the minimap is being dragged, which scrolls the view.
+    minimap_drag: bool,
+    /// How long (in ns) the border flash has left to show.
+    flash: Option<u64>,
+    /// The user's keybindings, tried before the built-in keys.
+    keymap: Keymap,
+    /// The state of modal editing, if it's on.
+    modal: Option<Modal>,
+    /// Whether the carets are in the shown half of a blink.
+    caret_shown: bool,
+    /// Set by typing, to keep the carets shown through the next blink.
+    caret_held: bool,
+    /// Whether the window has the keyboard focus. The carets are hidden
+    /// while it doesn't.
+    window_focused: bool,
+    /// The search typed into the find bar, while it is open.
+    find: Option<FindQuery>,
+    /// The other occurrences of a double-clicked word, highlighted with a
+    /// search of core's while the find bar is closed.
+    word_highlight: WordHighlight,
+    /// The scroll offset, the size of the view and the spacing of its text,
+    /// among what doesn't need drawing to test.
+    model: EditViewModel,
+    /// The scroll being animated, if any.
+    scroll_anim: Option<ScrollAnimation>,
+    /// Whether scrolling by keyboard or to the caret is animated, set by the
+    /// `smooth_scroll` key of the user config.
+    smooth_scroll: bool,
+    /// The most memory, in bytes, the lines of a view take up before those
+    /// far from its viewport are dropped, set in MiB by the
+    /// `line_cache_limit` key of the user config.
+    line_cache_limit: usize,
+    /// The lines last sent to core as the viewport.
     viewport: Range<usize>,
+    /// When the viewport was last sent.
+    viewport_sent: Option<Instant>,
+    /// Whether the lines showing have changed since the viewport was last
+    /// sent, which is sent on the next frame.
+    viewport_owed: bool,
     core: Weak<Mutex<Core>>,
-    pending: Vec<(Method, Params)>,
+    /// Last mouse position (in px) while a drag selection is active.
+    drag_pos: Option<(f32, f32)>,
+    /// Where an Alt+drag block selection started, in px across the view and
+    /// down the document.
+    block_anchor: Option<(f32, f64)>,
+    text_drag: Option<TextDrag>,
+    /// Mouse position (in px) and how long (in ns) it has rested there, until
+    /// hover info is requested.
+    hover_dwell: Option<((f32, f32), u64)>,
+    /// The id and mouse position of the latest `request_hover`.
+    hover_request: (usize, (f32, f32)),
+    hover: Option<Hover>,
+    /// The text being composed with an IME, drawn at the caret until it is
+    /// committed.
+    composition: Option<String>,
+    /// The selections before each Expand Selection, so Shrink Selection can
+    /// restore them exactly.
+    expand_history: Vec<Vec<Selection>>,
+    /// The selections the last expand or shrink asked core for. If they no
+    /// longer match, the user has moved on and the history is dropped.
+    expanded: Vec<Selection>,
+    paint_request: PaintRequest,
+    /// The updates to the shown view which have arrived since the last
+    /// paint, applied together before the next one.
+    queued_updates: Vec<Update>,
+    /// Whether the caret or indentation may have changed since they were
+    /// last reported to listeners, which they are on the next frame.
+    status_owed: bool,
+    /// The scroll offset to restore, until the view's lines have arrived.
+    restore_scroll: Option<f64>,
+    /// The scroll offset last reported to listeners.
+    reported_scroll: f64,
+}
+
+/// A scroll in progress, easing from one offset to another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ScrollAnimation {
+    from: f64,
+    to: f64,
+    /// How long (in ns) the animation has been running.
+    elapsed: u64,
+}
+
+/// The highlighting of a double-clicked word's occurrences.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum WordHighlight {
+    Off,
+    /// A word was double-clicked, and core hasn't sent the selection yet.
+    Pending,
+    /// The occurrences of the selected word are highlighted, until the
+    /// selection changes.
+    Shown(Selection),
+}
+
+/// Selected text being dragged to somewhere else in the document.
+#[derive(Clone, Debug)]
+struct TextDrag {
+    selection: Selection,
+    text: String,
+    /// Where the mouse went down, in px.
+    origin: (f32, f32),
+    /// Where the text would be dropped, once the mouse has moved far enough
+    /// to start the drag.
+    drop: Option<Position>,
+}
+
+/// Hover info from a plugin, shown as a tooltip.
+#[derive(Debug)]
+struct Hover {
+    pos: (f32, f32),
+    text: String,
 }
 
 impl fmt::Debug for EditView {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("EditView")
             .field("view_id", &self.view_id)
+            .field("documents", &self.documents)
             .field("line_cache", &self.line_cache)
+            .field("text_lines", &self.text_lines)
+            .field("prelayout", &self.prelayout)
+            .field("layout_generation", &self.layout_generation)
+            .field("prelayout_requested", &self.prelayout_requested)
+            .field("caret_line", &self.caret_line)
             .field("dwrite_factory", &"...")
             .field("resources", &self.resources)
-            .field("scroll_offset", &self.scroll_offset)
-            .field("size", &self.size)
+            .field("text_resources", &self.text_resources)
+            .field("color_scheme", &self.color_scheme)
+            .field("styles", &self.styles)
+            .field("font_face", &self.font_face)
+            .field("font_size", &self.font_size)
+            .field("indentation", &self.indentation)
+            .field("detect_indentation", &self.detect_indentation)
+            .field("indentation_detected", &self.indentation_detected)
+            .field("auto_close_brackets", &self.auto_close_brackets)
+            .field("sequence_start", &self.sequence_start)
+            .field("sequence_step", &self.sequence_step)
+            .field("autoscroll", &self.autoscroll)
+            .field("bell", &self.bell)
+            .field("line_numbers", &self.line_numbers)
+            .field("invisibles", &self.invisibles)
+            .field("text_rendering", &self.text_rendering)
+            .field("minimap", &self.minimap)
+            .field("minimap_drag", &self.minimap_drag)
+            .field("flash", &self.flash)
+            .field("keymap", &self.keymap)
+            .field("modal", &self.modal)
+            .field("caret_shown", &self.caret_shown)
+            .field("caret_held", &self.caret_held)
+            .field("window_focused", &self.window_focused)
+            .field("find", &self.find)
+            .field("word_highlight", &self.word_highlight)
+            .field("model", &self.model)
+            .field("scroll_anim", &self.scroll_anim)
+            .field("smooth_scroll", &self.smooth_scroll)
+            .field("line_cache_limit", &self.line_cache_limit)
             .field("viewport", &self.viewport)
+            .field("viewport_sent", &self.viewport_sent)
+            .field("viewport_owed", &self.viewport_owed)
             .field("core", &self.core)
-            .field("pending", &self.pending)
+            .field("drag_pos", &self.drag_pos)
+            .field("block_anchor", &self.block_anchor)
+            .field("text_drag", &self.text_drag)
+            .field("hover_dwell", &self.hover_dwell)
+            .field("hover_request", &self.hover_request)
+            .field("hover", &self.hover)
+            .field("composition", &self.composition)
+            .field("expand_history", &self.expand_history)
+            .field("expanded", &self.expanded)
+            .field("paint_request", &self.paint_request)
+            .field("queued_updates", &self.queued_updates.len())
+            .field("status_owed", &self.status_owed)
+            .field("restore_scroll", &self.restore_scroll)
+            .field("reported_scroll", &self.reported_scroll)
             .finish()
     }
 }
 
+/// Whether a paint is due, so that a burst of changes within a frame only
+/// invalidates the window once.
+#[derive(Debug, Default)]
+struct PaintRequest {
+    pending: bool,
+}
+
+impl PaintRequest {
+    /// Notes a change, returning whether the window needs invalidating for it.
+    fn request(&mut self) -> bool {
+        !mem::replace(&mut self.pending, true)
+    }
+
+    fn painted(&mut self) {
+        self.pending = false;
+    }
+}
+
 struct Resources {
     fg: SolidColorBrush,
     bg: SolidColorBrush,
     sel: SolidColorBrush,
-    text_format: TextFormat,
+    caret: SolidColorBrush,
+    secondary_caret: SolidColorBrush,
+    find_highlight: SolidColorBrush,
+    gutter_fg: SolidColorBrush,
+    line_highlight: SolidColorBrush,
+    /// The text and background brushes of the styles which set them.
+    style_fgs: HashMap<usize, SolidColorBrush>,
+    style_bgs: HashMap<usize, SolidColorBrush>,
 }
 
 impl fmt::Debug for Resources {
@@ -95,39 +563,205 @@ impl fmt::Debug for Resources {
     }
 }
 
-const TOP_PAD: f32 = 6.0;
-const LEFT_PAD: f32 = 6.0;
-const LINE_SPACE: f32 = 17.0;
+/// The format text is laid out with. Unlike brushes, this doesn't belong to
+/// the render target, so it outlives it.
+struct TextResources {
+    /// Shared with the layout thread.
+    format: Arc<TextFormat>,
+}
+
+impl fmt::Debug for TextResources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextResources")
+            .field("format", &"...")
+            .finish()
+    }
+}
+
+/// The space between the line numbers and the text.
+const GUTTER_PAD: f32 = 12.0;
+/// Auto-scroll speed during a drag selection, in px per second for every px
+/// the mouse is into the auto-scroll margin.
+const AUTOSCROLL_RATE: f32 = 10.0;
+/// How long the mouse must rest before hover info is requested, in ns.
+const HOVER_DELAY: u64 = 500_000_000;
+const HOVER_MAX_WIDTH: f32 = 400.0;
+const HOVER_PAD: f32 = 4.0;
+/// How long the border flashes for the visual bell, in ns.
+const FLASH_DURATION: u64 = 150_000_000;
+const FLASH_WIDTH: f32 = 2.0;
+/// How long a smooth scroll takes, in ns.
+const SCROLL_DURATION: u64 = 100_000_000;
+/// The least time between the viewports sent to core while scrolling, about
+/// a frame.
+const VIEWPORT_INTERVAL: Duration = Duration::from_millis(16);
+const DEFAULT_LINE_CACHE_LIMIT: usize = 64 << 20;
+/// The font until the user config names one, and in place of one which
+/// isn't installed.
+const DEFAULT_FONT_FACE: &str = "Consolas";
+/// In dips.
+const DEFAULT_FONT_SIZE: f32 = 15.0;
+/// How far the mouse moves, in px, before a press on selected text becomes
+/// a drag of it, as for Windows' own drag and drop.
+const TEXT_DRAG_DISTANCE: f32 = 4.0;
+const MINIMAP_WIDTH: f32 = 100.0;
+/// The height of a line in the minimap, and the width of a character.
+const MINIMAP_LINE_SPACE: f32 = 2.0;
+const MINIMAP_CHAR_WIDTH: f32 = 1.0;
 
 impl Widget for EditView {
     fn paint(&mut self, paint_ctx: &mut PaintCtx, geom: &Geometry) {
-        // TODO(Olive): Cache resources, and flush cache when the render target is re-created.
-        self.size = geom.size;
-        let resources = self.create_resources(paint_ctx);
+        let geom = &dpi::begin_paint(paint_ctx.render_target(), geom);
+        self.model.size = geom.size;
+        self.paint_request.painted();
+        self.apply_queued_updates();
+        // Brushes belong to the render target, so they are rebuilt after
+        // drawing finds the device lost, as the target is then re-created.
+        // Text formats and layouts don't depend on the device and survive
+        // that.
+        if self.resources.is_none() {
+            self.resources = Some(self.create_resources(paint_ctx));
+        }
+        if self.text_resources.is_none() {
+            let (format, metrics) = self.create_text_format();
+            self.text_resources = Some(TextResources {
+                format: Arc::new(format),
+            });
+            if metrics != self.model.metrics {
+                // The lines are somewhere else now.
+                self.model.metrics = metrics;
+                self.constrain_scroll();
+                self.update_viewport();
+            }
+        }
+        let Range {
+            start: first_line,
+            end: last_line,
+        } = self.model.lines_showing(self.line_cache.total_lines());
+        let ahead = self.prelayout_range(first_line..last_line);
+        self.layout_lines(first_line..last_line, &ahead);
+        self.request_prelayout(ahead);
+        let resources = self.resources.as_ref().unwrap();
         let rt = paint_ctx.render_target();
-        let rect = RectF::from((0.0, 0.0, self.size.0, self.size.1));
+        self.text_rendering.apply(rt);
+        let (ox, oy) = geom.pos;
+        let rect = RectF::from((ox, oy, ox + self.model.size.0, oy + self.model.size.1));
+        // Lines scrolled partly out of view mustn't spill over the widgets
+        // around the view.
+        rt.push_axis_aligned_clip(rect, AntialiasMode::Aliased);
         rt.fill_rectangle(rect, &resources.bg);
 
-        let first_line = self.y_to_line(0.0);
-        let last_line = min(self.y_to_line(self.size.1) + 1, self.line_cache.height());
-
-        let x0 = LEFT_PAD;
-        let mut y = line_to_content_y(first_line) - self.scroll_offset;
+        let x0 = ox + self.gutter_width();
+        if self.line_numbers {
+            self.draw_line_numbers(rt, (ox, oy), first_line..last_line, resources);
+        }
+        let right = ox + geom.size.0;
+        if let Some(line_num) = self.caret_line {
+            let y = oy
+                + self
+                    .model
+                    .metrics
+                    .line_y(line_num, self.model.scroll_offset);
+            rt.fill_rectangle(
+                (ox, y, right, y + self.model.metrics.line_height),
+                &resources.line_highlight,
+            );
+        }
+        let mut y = oy
+            + self
+                .model
+                .metrics
+                .line_y(first_line, self.model.scroll_offset);
         for line_num in first_line..last_line {
-            if let Some(textline) = self.get_text_line(line_num) {
-                textline.draw_bg(rt, x0, y, &resources.sel);
+            if let Some(textline) = self.text_lines.get(&line_num) {
+                for (&style_id, bg) in &resources.style_bgs {
+                    textline.draw_bg(rt, x0, y, right, style_id, bg);
+                }
+                textline.draw_bg(
+                    rt,
+                    x0,
+                    y,
+                    right,
+                    FIND_HIGHLIGHT_STYLE_ID,
+                    &resources.find_highlight,
+                );
+                textline.draw_bg(rt, x0, y, right, SELECTION_STYLE_ID, &resources.sel);
+                // Newer cores mark the find matches and selections with
+                // annotations instead of styles.
+                let annotations = self.line_cache.annotations_on(line_num);
+                for (kind, brush) in [
+                    (AnnotationKind::Find, &resources.find_highlight),
+                    (AnnotationKind::Selection, &resources.sel),
+                ] {
+                    for (_, range) in annotations.iter().filter(|(k, _)| *k == kind) {
+                        textline.draw_range_bg(rt, x0, y, right, range, brush);
+                    }
+                }
             }
-            y += LINE_SPACE;
+            y += self.model.metrics.line_height;
         }
-        let mut y = line_to_content_y(first_line) - self.scroll_offset;
+        let mut y = oy
+            + self
+                .model
+                .metrics
+                .line_y(first_line, self.model.scroll_offset);
+        let mut caret = None;
         for line_num in first_line..last_line {
-            if let Some(textline) = self.get_text_line(line_num) {
-                textline.draw_text(rt, x0, y, &resources.fg);
-                textline.draw_cursor(rt, x0, y, &resources.fg);
+            if let Some(textline) = self.text_lines.get(&line_num) {
+                textline.draw_text(
+                    rt,
+                    x0,
+                    y,
+                    &resources.fg,
+                    &resources.style_fgs,
+                    self.invisibles,
+                    &resources.gutter_fg,
+                    self.text_rendering.draw_options(),
+                );
+                if self.caret_shown && self.window_focused {
+                    // The primary caret is the first, as for the status bar.
+                    textline.draw_cursor(
+                        rt,
+                        x0,
+                        y,
+                        self.caret_line == Some(line_num),
+                        &resources.caret,
+                        &resources.secondary_caret,
+                    );
+                }
+                if caret.is_none() {
+                    caret = textline.caret_x().map(|x| (x0 + x, y));
+                }
+            }
+            y += self.model.metrics.line_height;
+        }
+        if let Some(drop) = self.text_drag.as_ref().and_then(|drag| drag.drop) {
+            self.draw_drop_caret(rt, (x0, oy), drop, resources);
+        }
+        if let Some((x, y)) = caret {
+            ime::set_caret_pos(x, y, self.model.metrics.line_height);
+            if let Some(text) = &self.composition {
+                self.draw_composition(rt, (x, y), text, resources);
             }
-            y += LINE_SPACE;
         }
-        self.resources = Some(resources);
+        if self.minimap {
+            self.draw_minimap(rt, geom.pos, resources);
+        }
+        if let Some(hover) = &self.hover {
+            self.draw_hover(rt, geom.pos, hover, resources);
+        }
+        if self.flash.is_some() {
+            self.draw_flash(rt, geom.pos, resources);
+        }
+        rt.pop_axis_aligned_clip();
+        // SAFETY: The raw pointer is valid for the lifetime of `rt`, and
+        // flushing has no tags to write.
+        let flushed = unsafe { (*rt.get_raw()).Flush(ptr::null_mut(), ptr::null_mut()) };
+        if flushed == D2DERR_RECREATE_TARGET {
+            warn!("render target lost, rebuilding brushes");
+            self.rebuild_resources();
+        }
+        dpi::end_paint(rt);
     }
 
     fn layout(
@@ -138,57 +772,211 @@ impl Widget for EditView {
         _ctx: &mut LayoutCtx,
     ) -> LayoutResult {
         let size = bc.constrain((0.0, 0.0));
-        self.size = size;
+        self.model.size = (dpi::to_dips(size.0), dpi::to_dips(size.1));
+        // A taller view may leave less to scroll.
+        self.constrain_scroll();
         self.update_viewport();
         LayoutResult::Size(size)
     }
 
-    fn mouse(&mut self, event: &MouseEvent, _ctx: &mut HandlerCtx) -> bool {
+    fn mouse(&mut self, event: &MouseEvent, ctx: &mut HandlerCtx) -> bool {
         let MouseEvent {
             x,
             y,
-            mods: _,
+            mods,
             which,
             count,
         } = *event;
-        if which == MouseButton::Left && count == 1 {
-            let (line, col) = self.xy_to_line_col(x, y);
-            let params = json!({
-                "ty": "point_select",
-                "line": line,
-                "col": col,
-            });
-            self.send_edit_cmd("gesture", &params);
+        self.apply_queued_updates();
+        let (x, y) = (dpi::to_dips(x), dpi::to_dips(y));
+        if which == MouseButton::Left && count > 0 && self.minimap_contains(x) {
+            self.minimap_drag = true;
+            self.scroll_to_minimap_y(y, ctx);
+            ctx.set_active(true);
+        } else if which == MouseButton::Left
+            && count == 1
+            && mods & (M_ALT | M_SHIFT) == 0
+            && self.start_text_drag(x, y)
+        {
+            ctx.set_active(true);
+        } else if which == MouseButton::Left {
+            // Dragging after a double or triple click extends the selection
+            // by whole words or lines.
+            let gesture = match count {
+                1 => Some("point_select"),
+                2 => Some("word_select"),
+                3 => Some("line_select"),
+                _ => None,
+            };
+            if let Some(gesture) = gesture {
+                if count == 2 && self.find.is_none() {
+                    self.word_highlight = WordHighlight::Pending;
+                } else if self.word_highlight == WordHighlight::Pending {
+                    self.word_highlight = WordHighlight::Off;
+                }
+                self.show_caret(ctx);
+                self.send_gesture(x, y, gesture);
+                self.drag_pos = Some((x, y));
+                // Alt+drag selects a block, of the same columns on each line.
+                self.block_anchor = (count == 1 && mods & M_ALT != 0)
+                    .then_some((x, f64::from(y) + self.model.scroll_offset));
+                ctx.set_active(true);
+            } else if count == 0 {
+                // Button released, which ends the drag and any auto-scroll.
+                if let Some(drag) = self.text_drag.take() {
+                    // Holding Ctrl copies the text rather than moving it.
+                    self.drop_text(drag, x, y, mods & M_CTRL != 0);
+                    self.invalidate(ctx);
+                }
+                self.drag_pos = None;
+                self.block_anchor = None;
+                self.minimap_drag = false;
+                ctx.set_active(false);
+            }
+        } else if which == MouseButton::Right && count == 0 {
+            // Like Windows' own context menus, this shows on release.
+            ctx.send_event(EditViewEvent::ContextMenu);
         }
         false
     }
 
+    fn mouse_moved(&mut self, x: f32, y: f32, ctx: &mut HandlerCtx) {
+        self.apply_queued_updates();
+        let (x, y) = (dpi::to_dips(x), dpi::to_dips(y));
+        if self.minimap_drag {
+            self.scroll_to_minimap_y(y, ctx);
+        } else if self.text_drag.is_some() {
+            let pos = self.xy_to_line_col(x, y);
+            if let Some(drag) = &mut self.text_drag {
+                let (ox, oy) = drag.origin;
+                if drag.drop.is_some() || (x - ox).abs().max((y - oy).abs()) > TEXT_DRAG_DISTANCE {
+                    drag.drop = Some(pos);
+                    self.invalidate(ctx);
+                }
+            }
+        } else if self.drag_pos.is_some() {
+            self.drag_pos = Some((x, y));
+            self.drag_to(x, y);
+            if self.autoscroll_velocity(y).is_some() {
+                ctx.request_anim_frame();
+            }
+        } else {
+            if self.hover.take().is_some() {
+                self.invalidate(ctx);
+            }
+            self.hover_dwell = Some(((x, y), 0));
+            ctx.request_anim_frame();
+        }
+    }
+
+    fn anim_frame(&mut self, interval: u64, ctx: &mut HandlerCtx) {
+        // `interval` is in nanoseconds.
+        self.apply_queued_updates();
+        if mem::take(&mut self.status_owed) {
+            ctx.send_event(EditViewEvent::Caret(self.caret_status()));
+            ctx.send_event(EditViewEvent::Indentation(self.indentation));
+        }
+        if self.viewport_owed {
+            self.throttle_viewport(ctx);
+        }
+        if let Some((x, y)) = self.drag_pos {
+            if let Some(velocity) = self.autoscroll_velocity(y) {
+                self.scroll_anim = None;
+                self.model.scroll_offset += f64::from(velocity) * (interval as f64) * 1e-9;
+                self.constrain_scroll();
+                self.throttle_viewport(ctx);
+                // Extend the selection to the line now showing at the edge.
+                self.drag_to(x, y.max(0.0).min(self.model.size.1));
+                self.invalidate(ctx);
+                ctx.request_anim_frame();
+            }
+        }
+        if let Some(mut anim) = self.scroll_anim {
+            anim.elapsed += interval;
+            let t = (anim.elapsed as f32 / SCROLL_DURATION as f32).min(1.0);
+            // Ease out, so the scroll settles gently on its target.
+            let eased = 1.0 - (1.0 - t) * (1.0 - t);
+            self.model.scroll_offset = (anim.to - anim.from).mul_add(f64::from(eased), anim.from);
+            if t < 1.0 {
+                self.scroll_anim = Some(anim);
+                ctx.request_anim_frame();
+            } else {
+                self.scroll_anim = None;
+            }
+            self.throttle_viewport(ctx);
+            self.invalidate(ctx);
+        }
+        if let Some(remaining) = self.flash {
+            self.flash = remaining.checked_sub(interval);
+            if self.flash.is_some() {
+                ctx.request_anim_frame();
+            } else {
+                self.invalidate(ctx);
+            }
+        }
+        if let Some((pos, dwell)) = self.hover_dwell {
+            let dwell = dwell + interval;
+            if dwell < HOVER_DELAY {
+                self.hover_dwell = Some((pos, dwell));
+                ctx.request_anim_frame();
+            } else {
+                self.hover_dwell = None;
+                self.request_hover(pos);
+            }
+        }
+    }
+
     fn poke(&mut self, payload: &mut dyn Any, ctx: &mut HandlerCtx) -> bool {
+        // The layouts are moved out, rather than copied.
+        if let Some(EditViewCommands::LaidOut(done)) = payload.downcast_mut() {
+            if done.generation == self.layout_generation {
+                for (line_num, textline) in mem::take(&mut done.lines) {
+                    self.text_lines.entry(line_num).or_insert(textline);
+                }
+            }
+            return true;
+        }
         if let Some(cmd) = payload.downcast_ref::<EditViewCommands>() {
+            if cmd.reads_lines() {
+                self.apply_queued_updates();
+            }
             match cmd {
                 EditViewCommands::ViewId(view_id) => {
-                    self.view_id = Some(view_id.to_string());
-                    self.viewport = 0..0; // zorch viewport
-                    self.update_viewport();
-
-                    // Fire off the pending notifications
-                    let pending = mem::take(&mut self.pending);
-                    for notification in pending {
-                        let (method, params) = notification;
-                        self.send_edit_cmd(&method, &params);
-                    }
+                    self.show_view(view_id);
+                    ctx.send_event(EditViewEvent::Find(self.find.clone()));
+                    ctx.send_event(EditViewEvent::Caret(self.caret_status()));
+                    ctx.send_event(EditViewEvent::Indentation(self.indentation));
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::CloseView(view_id) => {
+                    self.documents.remove(view_id);
                 }
-                EditViewCommands::ApplyUpdate(update) => {
-                    self.apply_update(update);
-                    ctx.invalidate();
+                EditViewCommands::Reopened { old, view_id } => {
+                    self.carry_over(old, view_id);
+                }
+                EditViewCommands::ApplyUpdate { view_id, update } => {
+                    if self.queue_update(view_id, update) {
+                        self.invalidate(ctx);
+                        ctx.request_anim_frame();
+                    } else if let Some(document) = self.documents.get_mut(view_id) {
+                        document.line_cache.apply_update(update);
+                        let keep = self.model.lines_to_keep(document.scroll_offset);
+                        document.line_cache.evict(&keep, self.line_cache_limit);
+                    }
                 }
-                EditViewCommands::ScrollTo(line) => {
-                    self.scroll_to(*line);
-                    ctx.invalidate();
+                EditViewCommands::ScrollTo { view_id, line } => {
+                    // Views which aren't shown keep their scroll position.
+                    if self.view_id.as_ref() == Some(view_id) {
+                        self.scroll_to(*line, ctx);
+                    }
                 }
                 EditViewCommands::Core(core) => {
                     self.core = core.clone();
                 }
+                EditViewCommands::Prelayout(prelayout) => {
+                    self.prelayout = Some(prelayout.clone());
+                }
+                EditViewCommands::LaidOut(_) => (),
                 EditViewCommands::Undo => {
                     self.send_action("undo");
                 }
@@ -204,6 +992,19 @@ impl Widget for EditView {
                 EditViewCommands::Transpose => {
                     self.send_action("transpose");
                 }
+                EditViewCommands::Indent => {
+                    self.send_action("indent");
+                }
+                EditViewCommands::Outdent => {
+                    self.send_action("outdent");
+                }
+                EditViewCommands::DuplicateLine => {
+                    self.send_action("duplicate_line");
+                }
+                EditViewCommands::DeleteLine => {
+                    self.select_lines();
+                    self.send_action("delete_backward");
+                }
                 EditViewCommands::AddCursorAbove => {
                     // Note: some subtlety around find, the escape key cancels it, but the menu
                     // shouldn't.
@@ -224,12 +1025,204 @@ impl Widget for EditView {
                     // shouldn't.
                     self.send_action("select_all");
                 }
+                EditViewCommands::SelectLine => {
+                    self.select_lines();
+                }
+                EditViewCommands::SetKeymap(keymap) => {
+                    self.keymap = keymap.clone();
+                }
+                EditViewCommands::ExpandSelection => {
+                    self.expand_selection();
+                }
+                EditViewCommands::ShrinkSelection => {
+                    self.shrink_selection();
+                }
+                EditViewCommands::InsertSequence => {
+                    self.insert_sequence();
+                }
+                EditViewCommands::TransformSelections(transform) => {
+                    self.transform_each_selection(*transform);
+                }
+                EditViewCommands::RenameSymbol => {
+                    self.rename_symbol();
+                }
+                EditViewCommands::ConfigChanged { view_id, changes } => {
+                    if self.view_id.as_ref() == Some(view_id) {
+                        self.config_changed(changes);
+                        ctx.send_event(EditViewEvent::Indentation(self.indentation));
+                        if changes.autodetect_whitespace.is_some() {
+                            ctx.send_event(EditViewEvent::DetectIndentation(
+                                self.detect_indentation,
+                            ));
+                        }
+                        self.invalidate(ctx);
+                    } else if let Some(document) = self.documents.get_mut(view_id) {
+                        document.indentation.config_changed(changes);
+                    }
+                }
+                EditViewCommands::SetTabSize(tab_size) => {
+                    self.set_indentation(Indentation {
+                        tab_size: *tab_size,
+                        ..self.indentation
+                    });
+                }
+                EditViewCommands::SetTranslateTabsToSpaces(translate) => {
+                    self.set_indentation(Indentation {
+                        translate_tabs_to_spaces: *translate,
+                        ..self.indentation
+                    });
+                }
+                EditViewCommands::ToggleDetectIndentation => {
+                    self.detect_indentation = !self.detect_indentation;
+                    ctx.send_event(EditViewEvent::DetectIndentation(self.detect_indentation));
+                }
+                EditViewCommands::IndentationOverridden(view_id) => {
+                    if self.view_id.as_ref() == Some(view_id) {
+                        self.indentation_detected = true;
+                    } else if let Some(document) = self.documents.get_mut(view_id) {
+                        document.indentation_detected = true;
+                    }
+                }
+                EditViewCommands::ToggleLineNumbers => {
+                    self.line_numbers = !self.line_numbers;
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::ToggleIndentGuides => {
+                    self.invisibles.indent_guides = !self.invisibles.indent_guides;
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::ToggleWhitespace => {
+                    self.invisibles.whitespace = !self.invisibles.whitespace;
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::ToggleGrayscale => {
+                    self.text_rendering.antialiasing = match self.text_rendering.antialiasing {
+                        Antialiasing::ClearType => Antialiasing::Grayscale,
+                        Antialiasing::Grayscale => Antialiasing::ClearType,
+                    };
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::ToggleColorFonts => {
+                    self.text_rendering.color_fonts = !self.text_rendering.color_fonts;
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::ToggleMinimap => {
+                    self.minimap = !self.minimap;
+                    self.update_viewport();
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::ToggleModal => {
+                    self.modal = match self.modal {
+                        Some(_) => None,
+                        None => Some(Modal::new()),
+                    };
+                    ctx.send_event(EditViewEvent::Mode(self.modal.as_ref().map(Modal::mode)));
+                }
+                EditViewCommands::SetColorScheme(scheme) => {
+                    self.color_scheme = scheme.clone();
+                    self.rebuild_resources();
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::ShowHover { request_id, text } => {
+                    let (latest_id, pos) = self.hover_request;
+                    if *request_id == latest_id && !text.is_empty() {
+                        self.hover = Some(Hover {
+                            pos,
+                            text: text.clone(),
+                        });
+                        self.invalidate(ctx);
+                    }
+                }
+                EditViewCommands::UpdateStatus => {
+                    self.status_owed = true;
+                    ctx.request_anim_frame();
+                }
+                EditViewCommands::SetStyles(styles) => {
+                    self.styles = styles.clone();
+                    // The styles' brushes are made with the others, and
+                    // their weights and slants are set on the layouts.
+                    self.rebuild_resources();
+                    self.rebuild_text_format();
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::Find => {
+                    self.find.get_or_insert_with(FindQuery::default).field = FindField::Find;
+                    ctx.send_event(EditViewEvent::Find(self.find.clone()));
+                }
+                EditViewCommands::FindNext => {
+                    self.find_next(true);
+                }
+                EditViewCommands::FindPrevious => {
+                    self.find_next(false);
+                }
+                EditViewCommands::FindStatus { view_id, queries } => {
+                    let matches = queries.first().and_then(|query| query.matches);
+                    if self.view_id.as_ref() == Some(view_id) {
+                        if let Some(find) = &mut self.find {
+                            // Ring once when a search stops matching, not on every later update.
+                            let failed = matches == Some(0) && find.matches != Some(0);
+                            find.matches = matches;
+                            ctx.send_event(EditViewEvent::Find(self.find.clone()));
+                            if failed {
+                                self.flash(ctx);
+                            }
+                        }
+                    } else if let Some(find) = self
+                        .documents
+                        .get_mut(view_id)
+                        .and_then(|document| document.find.as_mut())
+                    {
+                        find.matches = matches;
+                    }
+                }
+                EditViewCommands::Ime(ImeEvent::Composition(text)) => {
+                    self.composition = Some(text.clone()).filter(|text| !text.is_empty());
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::Ime(ImeEvent::Commit(text)) => {
+                    self.composition = None;
+                    self.send_edit_cmd("insert", &json!({ "chars": text }));
+                }
+                EditViewCommands::Caret(CaretEvent::Blink) => {
+                    if mem::take(&mut self.caret_held) {
+                        return true;
+                    }
+                    self.caret_shown = !self.caret_shown;
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::RestoreScroll(offset) => {
+                    self.scroll_anim = None;
+                    self.restore_scroll = Some(*offset);
+                    self.apply_restored_scroll();
+                    self.update_viewport();
+                    self.invalidate(ctx);
+                }
+                EditViewCommands::MeasureWidth { params, reply } => {
+                    let _ = reply.send(self.measure_width(params));
+                }
+                EditViewCommands::Caret(CaretEvent::Focus(focused)) => {
+                    self.window_focused = *focused;
+                    self.show_caret(ctx);
+                    self.invalidate(ctx);
+                }
             }
         }
         true
     }
 
     fn key(&mut self, event: &KeyEvent, ctx: &mut HandlerCtx) -> bool {
+        self.apply_queued_updates();
+        self.show_caret(ctx);
+        self.hover_dwell = None;
+        if self.hover.take().is_some() {
+            self.invalidate(ctx);
+        }
+        if self.find.is_some() && self.find_key(event, ctx) {
+            return true;
+        }
+        if self.modal.is_some() && self.modal_key(event, ctx) {
+            return true;
+        }
         match event.key {
             KeyVariant::Vkey(vk) => return self.keydown(vk, event.mods, ctx),
             KeyVariant::Char(ch) => {
@@ -244,14 +1237,66 @@ impl EditView {
     pub fn new() -> Self {
         Self {
             view_id: None,
+            documents: HashMap::new(),
             line_cache: LineCache::new(),
+            text_lines: HashMap::new(),
+            prelayout: None,
+            layout_generation: 0,
+            prelayout_requested: None,
+            caret_line: None,
             dwrite_factory: directwrite::Factory::new().unwrap(),
             resources: None,
-            scroll_offset: 0.0,
-            size: (0.0, 0.0),
+            text_resources: None,
+            color_scheme: ColorScheme::default(),
+            styles: StyleMap::new(),
+            font_face: DEFAULT_FONT_FACE.to_owned(),
+            font_size: DEFAULT_FONT_SIZE,
+            indentation: Indentation::default(),
+            detect_indentation: true,
+            indentation_detected: false,
+            auto_close_brackets: true,
+            sequence_start: 1,
+            sequence_step: 1,
+            autoscroll: AutoscrollSettings::default(),
+            bell: BellStyle::Flash,
+            line_numbers: true,
+            invisibles: Invisibles::default(),
+            text_rendering: TextRendering::default(),
+            minimap: false,
+            minimap_drag: false,
+            flash: None,
+            keymap: Keymap::load().unwrap_or_else(|err| {
+                warn!("couldn't load the keymap: {}", err);
+                Keymap::default()
+            }),
+            modal: None,
+            caret_shown: true,
+            caret_held: false,
+            window_focused: true,
+            find: None,
+            word_highlight: WordHighlight::Off,
+            model: EditViewModel::default(),
+            scroll_anim: None,
+            smooth_scroll: true,
+            line_cache_limit: DEFAULT_LINE_CACHE_LIMIT,
             viewport: 0..0,
+            viewport_sent: None,
+            viewport_owed: false,
             core: Weak::new(),
-            pending: vec![],
+            drag_pos: None,
+            block_anchor: None,
+            text_drag: None,
+            hover_dwell: None,
+            hover_request: (0, (0.0, 0.0)),
+            hover: None,
+            composition: None,
+            expand_history: vec![],
+            expanded: vec![],
+            paint_request: PaintRequest::default(),
+            queued_updates: vec![],
+            status_owed: false,
+            restore_scroll: None,
+            reported_scroll: 0.0,
         }
     }
 
@@ -261,230 +1306,1462 @@ impl EditView {
 
     fn create_resources(&mut self, p: &mut PaintCtx) -> Resources {
         let rt = p.render_target();
-        let text_format = TextFormat::create(&self.dwrite_factory)
-            .with_family("Consolas")
-            .with_size(15.0)
-            .build()
-            .unwrap();
+        let style_fgs = style_brushes(rt, &self.styles, |style| style.fg);
+        let style_bgs = style_brushes(rt, &self.styles, |style| style.bg);
         Resources {
+            style_fgs,
+            style_bgs,
             fg: SolidColorBrush::create(rt)
-                .with_color(0x00f0_f0ea)
+                .with_color(self.color_scheme.foreground)
                 .build()
                 .unwrap(),
             bg: SolidColorBrush::create(rt)
-                .with_color(0x0027_2822)
+                .with_color(self.color_scheme.background)
                 .build()
                 .unwrap(),
             sel: SolidColorBrush::create(rt)
-                .with_color(0x0049_483e)
+                .with_color(self.color_scheme.selection)
+                .build()
+                .unwrap(),
+            caret: SolidColorBrush::create(rt)
+                .with_color(self.color_scheme.caret)
+                .build()
+                .unwrap(),
+            secondary_caret: SolidColorBrush::create(rt)
+                .with_color(self.color_scheme.secondary_caret)
+                .build()
+                .unwrap(),
+            find_highlight: SolidColorBrush::create(rt)
+                .with_color(self.color_scheme.find_highlight)
+                .build()
+                .unwrap(),
+            gutter_fg: SolidColorBrush::create(rt)
+                .with_color(self.color_scheme.gutter_fg)
+                .build()
+                .unwrap(),
+            line_highlight: SolidColorBrush::create(rt)
+                .with_color(self.color_scheme.line_highlight)
                 .build()
                 .unwrap(),
-            text_format,
         }
     }
 
-    // pub fn rebuild_resources(&mut self) {
-    //     self.resources = None;
-    // }
+    /// Creates the format text is laid out with, returning it with the
+    /// metrics of its font. Tab stops are every `tab_size` columns.
+    fn create_text_format(&self) -> (TextFormat, Metrics) {
+        let family = if font_installed(&self.dwrite_factory, &self.font_face) {
+            &self.font_face
+        } else {
+            warn!("font \"{}\" isn't installed", self.font_face);
+            DEFAULT_FONT_FACE
+        };
+        let text_format = TextFormat::create(&self.dwrite_factory)
+            .with_family(family)
+            .with_size(self.font_size)
+            .build()
+            .unwrap();
+        let metrics = Metrics::measure(&self.dwrite_factory, &text_format);
+        let tab_width = self.indentation.tab_size as f32 * metrics.char_width;
+        // SAFETY: The raw pointer is valid for the lifetime of `text_format`.
+        unsafe {
+            (*text_format.get_raw()).SetIncrementalTabStop(tab_width);
+        }
+        (text_format, metrics)
+    }
+
+    /// Answers core's `measure_width` request, giving the width in px of
+    /// each string in the style it is sent with.
+    fn measure_width(&self, params: &Value) -> Value {
+        let (text_format, _) = self.create_text_format();
+        let widths: Vec<Vec<f32>> = params
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|request| {
+                let style = request["id"]
+                    .as_u64()
+                    .and_then(|id| self.styles.get(&(id as usize)));
+                request["strings"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|s| self.text_width(s.as_str().unwrap_or(""), &text_format, style))
+                    .collect()
+            })
+            .collect();
+        json!(widths)
+    }
+
+    fn text_width(&self, text: &str, format: &TextFormat, style: Option<&Style>) -> f32 {
+        let Ok(layout) = TextLayout::create(&self.dwrite_factory)
+            .with_text(text)
+            .with_font(format)
+            .with_width(1e6)
+            .with_height(1e6)
+            .build()
+        else {
+            return 0.0;
+        };
+        if let Some(style) = style {
+            let range = DWRITE_TEXT_RANGE {
+                startPosition: 0,
+                length: text.encode_utf16().count() as u32,
+            };
+            // SAFETY: The raw pointer is valid for the lifetime of `layout`.
+            unsafe {
+                let raw = &*layout.get_raw();
+                if let Some(weight) = style.weight {
+                    raw.SetFontWeight(weight, range);
+                }
+                if style.italic {
+                    raw.SetFontStyle(DWRITE_FONT_STYLE_ITALIC, range);
+                }
+            }
+        }
+        layout.get_metrics().width()
+    }
+
+    /// Drops the cached brushes, so the next paint creates them afresh.
+    fn rebuild_resources(&mut self) {
+        self.resources = None;
+    }
+
+    /// Drops the cached text format, along with the line layouts made with
+    /// it, so the next paint creates them afresh.
+    fn rebuild_text_format(&mut self) {
+        self.text_resources = None;
+        self.text_lines.clear();
+        self.layout_generation += 1;
+    }
+
+    /// The format text is laid out with. Only valid once painted.
+    fn text_format(&self) -> &TextFormat {
+        &self.text_resources.as_ref().unwrap().format
+    }
 
     // pub fn clear_line_cache(&mut self) {
     //     self.line_cache = LineCache::new();
     // }
 
-    // signature will change when we start caching
-    fn get_text_line(&self, line_num: usize) -> Option<TextLine> {
-        self.line_cache.get_line(line_num).map(|line| {
-            let format = &self.resources.as_ref().unwrap().text_format;
-            TextLine::create_from_line(line, &self.dwrite_factory, format)
-        })
-    }
-
-    pub fn apply_update(&mut self, update: &Value) {
-        self.line_cache.apply_update(update);
-        self.constrain_scroll();
+    fn layout_line(&self, line: &Line) -> TextLine {
+        TextLine::create_from_line(
+            line,
+            &self.dwrite_factory,
+            self.text_format(),
+            &self.styles,
+            self.indentation.tab_size,
+            &self.model.metrics,
+        )
     }
 
-    pub fn char(&mut self, ch: u32, _mods: u32) {
-        if let Some(c) = ::std::char::from_u32(ch) {
-            if ch >= 0x20 {
-                // Don't insert control characters
-                let params = json!({"chars": c.to_string()});
-                self.send_edit_cmd("insert", &params);
+    /// Lays out the lines in `lines` which aren't already, and drops the
+    /// layouts of any outside `keep`.
+    fn layout_lines(&mut self, lines: Range<usize>, keep: &Range<usize>) {
+        self.text_lines
+            .retain(|line_num, _| keep.contains(line_num));
+        for line_num in lines {
+            if self.text_lines.contains_key(&line_num) {
+                continue;
+            }
+            if let Some(line) = self.line_cache.get_line(line_num) {
+                let textline = self.layout_line(line);
+                self.text_lines.insert(line_num, textline);
             }
         }
     }
 
-    fn send_edit_cmd(&mut self, method: &str, params: &Value) {
-        if let Some((core, view_id)) = self.core.upgrade().zip(self.view_id.as_ref()) {
-            let edit_params = json!({
-                "method": method,
-                "params": params,
-                "view_id": view_id,
-            });
-            core.lock().unwrap().send_notification("edit", &edit_params);
-            // NOTE: For debugging, could be replaced by trace logging
-            // println!("fe->core: {}", json!({
-            //     "method": method,
-            //     "params": params,
-            // }));
-        } else {
-            self.pending.push((method.to_owned(), params.clone()));
+    /// The lines to keep laid out around those showing, a page either side,
+    /// so that scrolling onto them is quick.
+    fn prelayout_range(&self, showing: Range<usize>) -> Range<usize> {
+        let page = showing.end - showing.start;
+        let total = self.line_cache.total_lines();
+        showing.start.saturating_sub(page)..min(showing.end + page, total)
+    }
+
+    /// Asks the layout thread for the lines in `lines` which aren't laid
+    /// out, unless it has been asked for them already.
+    fn request_prelayout(&mut self, lines: Range<usize>) {
+        let requested = Some((self.layout_generation, lines.clone()));
+        if self.prelayout.is_none() || self.prelayout_requested == requested {
+            return;
         }
+        self.prelayout_requested = requested;
+        let lines: Vec<(usize, Line)> = lines
+            .filter(|line_num| !self.text_lines.contains_key(line_num))
+            .filter_map(|line_num| Some((line_num, self.line_cache.get_line(line_num)?.clone())))
+            .collect();
+        if lines.is_empty() {
+            return;
+        }
+        let job = prelayout::Job {
+            generation: self.layout_generation,
+            lines,
+            format: Arc::clone(&self.text_resources.as_ref().unwrap().format),
+            styles: self.styles.clone(),
+            tab_size: self.indentation.tab_size,
+            metrics: self.model.metrics,
+        };
+        self.prelayout.as_ref().unwrap().request(job);
     }
 
-    /// Sends a simple action with no parameters
-    fn send_action(&mut self, method: &str) {
-        self.send_edit_cmd(method, &json!([]));
+    /// Moves the line layouts along with the lines an update kept, dropping
+    /// those of lines which changed.
+    fn move_text_lines(&mut self, copied: &[CopiedLines]) {
+        self.layout_generation += 1;
+        self.text_lines = mem::take(&mut self.text_lines)
+            .into_iter()
+            .filter_map(|(old_ix, textline)| {
+                let new_ix = copied.iter().find_map(|run| run.new_index(old_ix))?;
+                Some((new_ix, textline))
+            })
+            .collect();
     }
 
-    pub fn keydown(&mut self, vk_code: i32, mods: u32, ctx: &mut HandlerCtx) -> bool {
-        // Handle special keys here
-        match vk_code {
-            VK_RETURN => {
-                // TODO(Olive): modifiers are variants of open
-                self.send_action("insert_newline");
-            }
-            VK_TAB => {
-                // TODO(Olive): modified versions
-                self.send_action("insert_tab");
-            }
-            VK_UP => {
-                if mods == M_CTRL {
-                    self.scroll_offset -= LINE_SPACE;
-                    self.constrain_scroll();
-                    self.update_viewport();
-                    ctx.invalidate();
-                } else {
-                    let action = if mods == M_CTRL | M_ALT {
-                        "add_selection_above"
-                    } else {
-                        s(mods, "move_up", "move_up_and_modify_selection")
-                    };
-                    // TODO(Olive): swap line up is ctrl + shift
-                    self.send_action(action);
-                }
-            }
-            VK_DOWN => {
-                if mods == M_CTRL {
-                    self.scroll_offset += LINE_SPACE;
-                    self.constrain_scroll();
-                    self.update_viewport();
-                    ctx.invalidate();
-                } else {
-                    let action = if mods == M_CTRL | M_ALT {
-                        "add_selection_below"
-                    } else {
-                        s(mods, "move_down", "move_down_and_modify_selection")
-                    };
-                    self.send_action(action);
-                }
+    /// Applies the settings from a `config_changed` notification.
+    fn config_changed(&mut self, changes: &ConfigChanges) {
+        let tab_size = self.indentation.tab_size;
+        self.indentation.config_changed(changes);
+        if self.indentation.tab_size != tab_size {
+            // The tab stops are set on the text format.
+            self.rebuild_text_format();
+        }
+        if let Some(font_face) = changes.font_face.as_ref().filter(|f| **f != self.font_face) {
+            self.font_face.clone_from(font_face);
+            self.rebuild_text_format();
+        }
+        if let Some(font_size) = changes.font_size.filter(|&size| size > 0.0) {
+            if font_size != self.font_size {
+                self.font_size = font_size;
+                self.rebuild_text_format();
             }
-            VK_LEFT => {
-                // TODO(Olive): there is a subtle distinction between alt and ctrl
-                let action = if (mods & (M_ALT | M_CTRL)) == 0 {
-                    s(mods, "move_left", "move_left_and_modify_selection")
-                } else {
-                    s(
-                        mods,
-                        "move_word_left",
-                        "move_word_left_and_modify_selection",
-                    )
-                };
-                self.send_action(action);
+        }
+        if let Some(detect) = changes.autodetect_whitespace {
+            self.detect_indentation = detect;
+        }
+        if let Some(auto_close) = changes.auto_close_brackets {
+            self.auto_close_brackets = auto_close;
+        }
+        if let Some(start) = changes.sequence_start {
+            self.sequence_start = start;
+        }
+        if let Some(step) = changes.sequence_step {
+            self.sequence_step = step;
+        }
+        if let Some(bell) = &changes.bell_style {
+            match bell.as_str() {
+                "flash" => self.bell = BellStyle::Flash,
+                "off" => self.bell = BellStyle::Off,
+                _ => warn!("unknown bell_style \"{}\"", bell),
             }
-            VK_RIGHT => {
-                // TODO(Olive): there is a subtle distinction between alt and ctrl
-                let action = if (mods & (M_ALT | M_CTRL)) == 0 {
-                    s(mods, "move_right", "move_right_and_modify_selection")
-                } else {
-                    s(
-                        mods,
-                        "move_word_right",
-                        "move_word_right_and_modify_selection",
-                    )
-                };
-                self.send_action(action);
+        }
+        if let Some(margin) = changes.autoscroll_margin {
+            self.autoscroll.margin = margin.max(0.0);
+        }
+        if let Some(max_speed) = changes.autoscroll_max_speed {
+            self.autoscroll.max_speed = max_speed.max(0.0);
+        }
+        if let Some(line_numbers) = changes.line_numbers {
+            self.line_numbers = line_numbers;
+        }
+        if let Some(smooth_scroll) = changes.smooth_scroll {
+            self.smooth_scroll = smooth_scroll;
+        }
+        if let Some(scroll_off) = changes.scroll_off {
+            self.model.scroll_off = scroll_off;
+        }
+        if let Some(scroll_past_end) = changes.scroll_past_end {
+            self.model.scroll_past_end = scroll_past_end;
+            self.constrain_scroll();
+            self.update_viewport();
+        }
+        if let Some(limit) = changes.line_cache_limit {
+            self.line_cache_limit = limit << 20;
+            self.evict_lines();
+        }
+        if let Some(indent_guides) = changes.indent_guides {
+            self.invisibles.indent_guides = indent_guides;
+        }
+        if let Some(render_whitespace) = changes.render_whitespace {
+            self.invisibles.whitespace = render_whitespace;
+        }
+        if let Some(minimap) = changes.minimap {
+            self.minimap = minimap;
+            self.update_viewport();
+        }
+        if let Some(antialiasing) = &changes.text_antialiasing {
+            match Antialiasing::from_name(antialiasing) {
+                Some(antialiasing) => self.text_rendering.antialiasing = antialiasing,
+                None => warn!("unknown text_antialiasing \"{}\"", antialiasing),
             }
-            VK_PRIOR => {
-                self.send_action(s(mods, "scroll_page_up", "page_up_and_modify_selection"));
-            }
-            VK_NEXT => {
-                self.send_action(s(
-                    mods,
-                    "scroll_page_down",
-                    "page_down_and_modify_selection",
-                ));
-            }
-            VK_HOME => {
-                let action = if (mods & M_CTRL) == 0 {
-                    s(
-                        mods,
-                        "move_to_left_end_of_line",
-                        "move_to_left_end_of_line_and_modify_selection",
-                    )
-                } else {
-                    s(
-                        mods,
-                        "move_to_beginning_of_document",
-                        "move_to_beginning_of_document_and_modify_selection",
-                    )
+        }
+        if let Some(color_fonts) = changes.color_fonts {
+            self.text_rendering.color_fonts = color_fonts;
+        }
+    }
+
+    /// Asks core to change the indentation settings of this view only. Core
+    /// answers with `config_changed`, which updates our copy.
+    pub fn set_indentation(&self, indentation: Indentation) {
+        if let Some((core, view_id)) = self.core.upgrade().zip(self.view_id.as_ref()) {
+            let params = json!({
+                "domain": { "user_override": view_id },
+                "changes": {
+                    "tab_size": indentation.tab_size,
+                    "translate_tabs_to_spaces": indentation.translate_tabs_to_spaces,
+                },
+            });
+            core.lock()
+                .unwrap()
+                .send_notification("modify_user_config", &params);
+        }
+    }
+
+    /// Shows another view, keeping the state of the one shown until now.
+    fn show_view(&mut self, view_id: &str) {
+        if self.view_id.as_deref() == Some(view_id) {
+            return;
+        }
+        // Left highlighted, they would be taken for a search.
+        self.clear_word_highlight();
+        if let Some(previous) = self.view_id.take() {
+            let document = Document {
+                line_cache: mem::take(&mut self.line_cache),
+                // A view switched away from mid-scroll comes back where the
+                // scroll was headed.
+                scroll_offset: self.scroll_target(),
+                indentation: self.indentation,
+                indentation_detected: self.indentation_detected,
+                find: self.find.take(),
+                restore_scroll: self.restore_scroll.take(),
+            };
+            self.documents.insert(previous, document);
+        }
+        let document = self.documents.remove(view_id).unwrap_or_default();
+        self.view_id = Some(view_id.to_owned());
+        self.line_cache = document.line_cache;
+        self.caret_line = self.line_cache.first_caret_line();
+        self.text_lines.clear();
+        self.layout_generation += 1;
+        self.model.scroll_offset = document.scroll_offset;
+        self.scroll_anim = None;
+        if document.indentation.tab_size != self.indentation.tab_size {
+            self.rebuild_text_format();
+        }
+        self.indentation = document.indentation;
+        self.indentation_detected = document.indentation_detected;
+        self.find = document.find;
+        self.restore_scroll = document.restore_scroll;
+        self.apply_restored_scroll();
+        self.drag_pos = None;
+        self.block_anchor = None;
+        self.text_drag = None;
+        self.hover_dwell = None;
+        self.hover = None;
+        self.expand_history.clear();
+        self.detect_indentation_once();
+        self.viewport = 0..0; // zorch viewport
+        self.update_viewport();
+
+        // Fire off the pending notifications
+        let pending = mem::take(&mut self.model.pending);
+        for notification in pending {
+            let (method, params) = notification;
+            self.send_edit_cmd(&method, &params);
+        }
+    }
+
+    /// Queues an update to the shown view, returning whether it was for that
+    /// view.
+    fn queue_update(&mut self, view_id: &str, update: &Update) -> bool {
+        if self.view_id.as_deref() != Some(view_id) {
+            return false;
+        }
+        self.queued_updates.push(update.clone());
+        true
+    }
+
+    /// Applies the updates queued since the last paint. When core streams
+    /// many, as for a large paste, they cost one repaint rather than one
+    /// each, as paints only come as often as the display refreshes.
+    fn apply_queued_updates(&mut self) {
+        if self.queued_updates.is_empty() {
+            return;
+        }
+        let updates = mem::take(&mut self.queued_updates);
+        trace!("applying {} updates in one frame", updates.len());
+        for update in &updates {
+            let copied = self.line_cache.apply_update(update);
+            self.move_text_lines(&copied);
+        }
+        self.caret_line = self.line_cache.first_caret_line();
+        self.apply_restored_scroll();
+        self.constrain_scroll();
+        self.detect_indentation_once();
+        self.evict_lines();
+        self.update_word_highlight();
+        self.status_owed = true;
+    }
+
+    /// Highlights the occurrences of a double-clicked word once core has
+    /// selected it, and stops once the selection changes.
+    fn update_word_highlight(&mut self) {
+        // The find bar's search replaces the word's.
+        if self.find.is_some() {
+            self.word_highlight = WordHighlight::Off;
+        }
+        if self.word_highlight == WordHighlight::Off {
+            return;
+        }
+        let first = self.selections().first().copied();
+        match self.word_highlight {
+            WordHighlight::Off => (),
+            WordHighlight::Pending => {
+                let Some(sel) = first.filter(|sel| sel.start != sel.end) else {
+                    return;
                 };
-                self.send_action(action);
+                let word = self
+                    .selected_text(sel)
+                    .filter(|text| text.chars().all(|c| c.is_alphanumeric() || c == '_'));
+                let Some(word) = word else {
+                    self.word_highlight = WordHighlight::Off;
+                    return;
+                };
+                let params = json!({
+                    "chars": word,
+                    "case_sensitive": true,
+                    "regex": false,
+                    "whole_words": true,
+                });
+                self.send_edit_cmd("find", &params);
+                self.send_edit_cmd("highlight_find", &json!({ "visible": true }));
+                self.word_highlight = WordHighlight::Shown(sel);
+            }
+            WordHighlight::Shown(sel) => {
+                if first != Some(sel) {
+                    self.clear_word_highlight();
+                }
+            }
+        }
+    }
+
+    /// Removes the highlights of a double-clicked word's occurrences.
+    fn clear_word_highlight(&mut self) {
+        if let WordHighlight::Shown(_) = mem::replace(&mut self.word_highlight, WordHighlight::Off)
+        {
+            // An empty query removes the match highlights.
+            self.send_edit_cmd("find", &json!({ "chars": "" }));
+        }
+    }
+
+    /// Keeps the lines of the shown view within the `line_cache_limit`,
+    /// keeping those showing and those around them. Lines dropped are asked
+    /// of core again when scrolled back to.
+    fn evict_lines(&mut self) {
+        let visible = self.visible_lines();
+        let around = self.model.lines_to_keep(self.model.scroll_offset);
+        let keep = min(visible.start, around.start)..max(visible.end, around.end);
+        if self.line_cache.evict(&keep, self.line_cache_limit) {
+            debug!("line cache evicted to {} bytes", self.line_cache.bytes());
+        }
+    }
+
+    /// Gives the view `view_id` the selections and scroll position of `old`,
+    /// whose file it has reopened. The selections are sent to core straight
+    /// away, as it has the text already, and the scroll waits for the lines.
+    fn carry_over(&mut self, old: &str, view_id: &str) {
+        let (selections, scroll_offset) = if self.view_id.as_deref() == Some(old) {
+            (self.selections(), self.scroll_target())
+        } else if let Some(document) = self.documents.get(old) {
+            (selections_in(&document.line_cache), document.scroll_offset)
+        } else {
+            return;
+        };
+        let document = Document {
+            restore_scroll: Some(scroll_offset),
+            ..Document::default()
+        };
+        self.documents.insert(view_id.to_owned(), document);
+        let Some(core) = self.core.upgrade() else {
+            return;
+        };
+        let mut core = core.lock().unwrap();
+        for (i, sel) in selections.iter().enumerate() {
+            let ty = if i == 0 { "point_select" } else { "toggle_sel" };
+            let mut gestures = vec![(sel.start, ty)];
+            if sel.end != sel.start {
+                gestures.push((sel.end, "range_select"));
+            }
+            for ((line, col), ty) in gestures {
+                let params = json!({
+                    "method": "gesture",
+                    "params": { "ty": ty, "line": line, "col": col },
+                    "view_id": view_id,
+                });
+                core.send_notification("edit", &params);
+            }
+        }
+    }
+
+    /// Scrolls to the offset being restored, once the view has lines to
+    /// scroll through.
+    fn apply_restored_scroll(&mut self) {
+        if self.line_cache.height() == 0 {
+            return;
+        }
+        if let Some(offset) = self.restore_scroll.take() {
+            self.model.scroll_offset = offset;
+            self.constrain_scroll();
+        }
+    }
+
+    /// Guesses the indentation from the first lines of the view, unless
+    /// that has been done already, is turned off, or the language of the view
+    /// has its own.
+    fn detect_indentation_once(&mut self) {
+        if self.detect_indentation && !self.indentation_detected && self.line_cache.height() > 0 {
+            self.indentation_detected = true;
+            // Unindented files keep the configured defaults.
+            match self.line_cache.detect_indentation() {
+                Some(IndentStyle::Tabs) => self.set_indentation(Indentation {
+                    translate_tabs_to_spaces: false,
+                    ..self.indentation
+                }),
+                Some(IndentStyle::Spaces(tab_size)) => self.set_indentation(Indentation {
+                    tab_size,
+                    translate_tabs_to_spaces: true,
+                }),
+                None => (),
             }
-            VK_END => {
-                let action = if (mods & M_CTRL) == 0 {
-                    s(
-                        mods,
-                        "move_to_right_end_of_line",
-                        "move_to_right_end_of_line_and_modify_selection",
-                    )
+        }
+    }
+
+    pub fn char(&mut self, ch: u32, _mods: u32) {
+        if let Some(c) = ::std::char::from_u32(ch) {
+            if ch >= 0x20 {
+                // Don't insert control characters
+                match self.typed_at_caret(c) {
+                    Typed::Insert => {
+                        let params = json!({"chars": c.to_string()});
+                        self.send_edit_cmd("insert", &params);
+                    }
+                    Typed::Pair(closer) => {
+                        let params = json!({ "chars": format!("{c}{closer}") });
+                        self.send_edit_cmd("insert", &params);
+                        self.send_action("move_left");
+                    }
+                    Typed::Skip => self.send_action("move_right"),
+                }
+            }
+        }
+    }
+
+    /// What typing `ch` does, going by the text around the first caret. The
+    /// other carets do the same, so they should be somewhere alike.
+    fn typed_at_caret(&self, ch: char) -> Typed {
+        if !self.auto_close_brackets {
+            return Typed::Insert;
+        }
+        let selections = self.selections();
+        let Some(&Selection { start, end }) = selections.first() else {
+            return Typed::Insert;
+        };
+        // What's typed replaces the selection.
+        if start != end {
+            return Typed::Insert;
+        }
+        let (line, col) = end;
+        let Some(text) = self.line_cache.get_line(line).map(Line::text) else {
+            return Typed::Insert;
+        };
+        let before = text.get(..col).and_then(|text| text.chars().next_back());
+        let after = text.get(col..).and_then(|text| text.chars().next());
+        view_model::typed(ch, before, after)
+    }
+
+    /// Shows the carets and keeps them shown through the next blink, so they
+    /// don't blink away while the user is typing.
+    fn show_caret(&mut self, ctx: &mut HandlerCtx) {
+        self.caret_held = true;
+        if !mem::replace(&mut self.caret_shown, true) {
+            self.invalidate(ctx);
+        }
+    }
+
+    /// Schedules a paint, unless one is already due.
+    ///
+    /// Scrolling always repaints, so this is also where a new scroll offset
+    /// is reported to listeners, and the hover tooltip is dismissed.
+    fn invalidate(&mut self, ctx: &mut HandlerCtx) {
+        if self.paint_request.request() {
+            ctx.invalidate();
+        }
+        if self.model.scroll_offset != self.reported_scroll {
+            self.reported_scroll = self.model.scroll_offset;
+            // The tooltip is about the text it was over, which has moved.
+            self.hover = None;
+            self.hover_dwell = None;
+            if let Some(view_id) = &self.view_id {
+                ctx.send_event(EditViewEvent::Scroll {
+                    view_id: view_id.clone(),
+                    offset: self.model.scroll_offset,
+                });
+            }
+        }
+    }
+
+    fn send_edit_cmd(&mut self, method: &str, params: &Value) {
+        if let Some((core, view_id)) = self.core.upgrade().zip(self.view_id.as_ref()) {
+            let edit_params = json!({
+                "method": method,
+                "params": params,
+                "view_id": view_id,
+            });
+            core.lock().unwrap().send_notification("edit", &edit_params);
+            trace!("fe->core: {} {}", method, params);
+        } else {
+            self.model.pending.push((method.to_owned(), params.clone()));
+        }
+    }
+
+    /// Sends a mouse gesture of the given type at x, y in screen-space px.
+    fn send_gesture(&mut self, x: f32, y: f32, ty: &str) {
+        self.send_gesture_at(self.xy_to_line_col(x, y), ty);
+    }
+
+    /// Extends the selection being dragged out to x, y in screen-space px.
+    fn drag_to(&mut self, x: f32, y: f32) {
+        match self.block_anchor {
+            Some(anchor) => {
+                self.select_block(anchor, (x, f64::from(y) + self.model.scroll_offset));
+            }
+            None => self.send_gesture(x, y, "drag"),
+        }
+    }
+
+    /// Selects the block between two points, in px across the view and down
+    /// the document, as a selection on each line between the two xs. Lines
+    /// ending before the block get a caret at their end.
+    fn select_block(&mut self, from: (f32, f64), to: (f32, f64)) {
+        let last_line = self.line_cache.total_lines().saturating_sub(1);
+        let first = min(
+            self.y_to_line((from.1.min(to.1) - self.model.scroll_offset) as f32),
+            last_line,
+        );
+        let last = min(
+            self.y_to_line((from.1.max(to.1) - self.model.scroll_offset) as f32),
+            last_line,
+        );
+        let selections: Vec<Selection> = (first..=last)
+            .map(|line| {
+                // The middle of the line, clear of rounding at its edges.
+                let y = self.model.metrics.line_y(line, self.model.scroll_offset)
+                    + self.model.metrics.line_height / 2.0;
+                Selection {
+                    start: self.xy_to_line_col(from.0, y),
+                    end: self.xy_to_line_col(to.0, y),
+                }
+            })
+            .collect();
+        self.set_selections(&selections);
+    }
+
+    /// Starts dragging the selected text if x, y in screen-space px is
+    /// inside a selection, returning false if it isn't.
+    fn start_text_drag(&mut self, x: f32, y: f32) -> bool {
+        let pos = self.xy_to_line_col(x, y);
+        let Some(selection) = self.selections().into_iter().find(|sel| {
+            let (start, end) = (min(sel.start, sel.end), max(sel.start, sel.end));
+            start < pos && pos < end
+        }) else {
+            return false;
+        };
+        let Some(text) = self.selected_text(selection) else {
+            return false;
+        };
+        self.text_drag = Some(TextDrag {
+            selection,
+            text,
+            origin: (x, y),
+            drop: None,
+        });
+        true
+    }
+
+    /// Ends a drag of text with the mouse at x, y in screen-space px, moving
+    /// or copying the text to where it was dropped.
+    fn drop_text(&mut self, drag: TextDrag, x: f32, y: f32, copy: bool) {
+        let sel = drag.selection;
+        let (start, end) = (min(sel.start, sel.end), max(sel.start, sel.end));
+        let drop = match drag.drop {
+            // Dropped onto itself, which goes nowhere.
+            Some(drop) if start <= drop && drop <= end => drop,
+            Some(drop) => {
+                let drop = if copy {
+                    drop
                 } else {
-                    s(
-                        mods,
-                        "move_to_end_of_document",
-                        "move_to_end_of_document_and_modify_selection",
-                    )
+                    self.set_selections(&[sel]);
+                    self.send_action("delete_backward");
+                    // Text after the selection moves back by its length.
+                    match drop {
+                        (line, col) if line == end.0 => (start.0, start.1 + col - end.1),
+                        (line, col) if line > end.0 => (line - (end.0 - start.0), col),
+                        _ => drop,
+                    }
                 };
-                self.send_action(action);
+                self.send_gesture_at(drop, "point_select");
+                self.send_edit_cmd("insert", &json!({ "chars": drag.text }));
+                return;
+            }
+            // A click without a drag, which places the caret as usual.
+            None => self.xy_to_line_col(x, y),
+        };
+        self.send_gesture_at(drop, "point_select");
+    }
+
+    /// Draws where dragged text would be dropped, as a caret.
+    fn draw_drop_caret<R: RenderTarget>(
+        &self,
+        rt: &mut R,
+        (x0, y0): (f32, f32),
+        (line_num, col): Position,
+        resources: &Resources,
+    ) {
+        let Some((line, textline)) = self
+            .line_cache
+            .get_line(line_num)
+            .zip(self.text_lines.get(&line_num))
+        else {
+            return;
+        };
+        if let Some(x) = textline.offset_x(utf8_to_utf16(line.text(), col)) {
+            let x = x0 + x;
+            let y = y0
+                + self
+                    .model
+                    .metrics
+                    .line_y(line_num, self.model.scroll_offset);
+            rt.draw_line(
+                (x, y),
+                (x, y + self.model.metrics.line_height),
+                &resources.caret,
+                2.0,
+                None,
+            );
+        }
+    }
+
+    /// Sends a gesture of the given type at a buffer position.
+    fn send_gesture_at(&mut self, (line, col): Position, ty: &str) {
+        let params = json!({
+            "ty": ty,
+            "line": line,
+            "col": col,
+        });
+        self.send_edit_cmd("gesture", &params);
+    }
+
+    /// Asks plugins for info about the text at x, y in screen-space px.
+    fn request_hover(&mut self, pos: (f32, f32)) {
+        let (line, column) = self.xy_to_line_col(pos.0, pos.1);
+        let request_id = self.hover_request.0 + 1;
+        self.hover_request = (request_id, pos);
+        let params = json!({
+            "request_id": request_id,
+            "position": { "line": line, "column": column },
+        });
+        self.send_edit_cmd("request_hover", &params);
+    }
+
+    /// The text of a selection, or `None` if part of it isn't in the line
+    /// cache.
+    fn selected_text(&self, sel: Selection) -> Option<String> {
+        let (start, end) = (min(sel.start, sel.end), max(sel.start, sel.end));
+        let mut text = String::new();
+        for line_num in start.0..=end.0 {
+            let line = self.line_cache.get_line(line_num)?.text();
+            let from = if line_num == start.0 { start.1 } else { 0 };
+            let to = if line_num == end.0 { end.1 } else { line.len() };
+            text.push_str(line.get(from..to)?);
+        }
+        Some(text)
+    }
+
+    /// Replaces each selection with its own text, leaving a caret after
+    /// each. `texts` are in buffer order. A bare caret given no text is left
+    /// alone.
+    pub fn replace_each_selection(&mut self, texts: &[String]) {
+        let selections: Vec<Selection> = self
+            .selections()
+            .into_iter()
+            .map(|sel| Selection {
+                start: min(sel.start, sel.end),
+                end: max(sel.start, sel.end),
+            })
+            .collect();
+        // Edits go from the end of the buffer, so the positions of the
+        // selections still to be replaced stay valid.
+        for (sel, text) in selections.iter().zip(texts).rev() {
+            if sel.start == sel.end && text.is_empty() {
+                continue;
             }
-            VK_ESCAPE => {
-                self.send_action("cancel_operation");
+            self.set_selections(&[*sel]);
+            self.send_edit_cmd("insert", &json!({ "chars": text }));
+        }
+        // Where each replacement ends, allowing for the lines removed or
+        // added and the text shifted along by the replacements before it.
+        let mut carets = vec![];
+        let mut line_shift = 0_isize;
+        let mut shift: Option<(usize, isize)> = None;
+        for (sel, text) in selections.iter().zip(texts) {
+            let col_shift = match shift {
+                Some((line, col_shift)) if line == sel.start.0 => col_shift,
+                _ => 0,
+            };
+            let start_col = sel.start.1.wrapping_add_signed(col_shift);
+            let breaks = text.matches('\n').count();
+            let caret_line = sel.start.0.wrapping_add_signed(line_shift) + breaks;
+            let caret_col = match text.rfind('\n') {
+                Some(i) => text.len() - i - 1,
+                None => start_col + text.len(),
+            };
+            carets.push(Selection {
+                start: (caret_line, caret_col),
+                end: (caret_line, caret_col),
+            });
+            line_shift += breaks as isize - (sel.end.0 - sel.start.0) as isize;
+            shift = Some((sel.end.0, caret_col as isize - sel.end.1 as isize));
+        }
+        self.set_selections(&carets);
+    }
+
+    /// Replaces the selections with a sequence of numbers, counting from the
+    /// number in the first selection, with the difference to the number in
+    /// the second selection as the step. Without them, the configured start
+    /// and step are used. Does nothing if the sequence would overflow.
+    pub fn insert_sequence(&mut self) {
+        let selections = self.selections();
+        let number = |i: usize| {
+            selections
+                .get(i)
+                .and_then(|&sel| self.selected_text(sel))
+                .and_then(|text| text.trim().parse::<i64>().ok())
+        };
+        let start = number(0).unwrap_or(self.sequence_start);
+        let step = number(1)
+            .and_then(|second| second.checked_sub(start))
+            .unwrap_or(self.sequence_step);
+        let texts: Option<Vec<String>> = (0..selections.len() as i64)
+            .map(|i| {
+                let number = i.checked_mul(step).and_then(|n| start.checked_add(n))?;
+                Some(number.to_string())
+            })
+            .collect();
+        if let Some(texts) = texts {
+            self.replace_each_selection(&texts);
+        }
+    }
+
+    /// Replaces the text of each selection with its transformed text, leaving
+    /// bare carets alone. Does nothing if any selection reaches lines which
+    /// aren't loaded.
+    pub fn transform_each_selection(&mut self, transform: SelectionTransform) {
+        let texts: Option<Vec<String>> = self
+            .selections()
+            .into_iter()
+            .map(|sel| self.selected_text(sel).map(|text| transform.apply(&text)))
+            .collect();
+        if let Some(texts) = texts {
+            self.replace_each_selection(&texts);
+        }
+    }
+
+    /// Grows each selection to the next enclosing word, bracket pair, lines,
+    /// indentation block or the whole document.
+    pub fn expand_selection(&mut self) {
+        let current = self.selections();
+        if current != self.expanded {
+            self.expand_history.clear();
+        }
+        let expanded: Vec<Selection> = current
+            .iter()
+            .map(|&sel| expand_selection::expand(&self.line_cache, sel).unwrap_or(sel))
+            .collect();
+        if expanded != current {
+            self.expand_history.push(current);
+            self.set_selections(&expanded);
+            self.expanded = expanded;
+        }
+    }
+
+    /// Undoes the last Expand Selection.
+    pub fn shrink_selection(&mut self) {
+        if self.selections() != self.expanded {
+            self.expand_history.clear();
+        }
+        if let Some(previous) = self.expand_history.pop() {
+            self.set_selections(&previous);
+            self.expanded = previous;
+        }
+    }
+
+    /// Acknowledges an action which had no effect, such as moving past the
+    /// end of the document, according to the bell style.
+    pub fn flash(&mut self, ctx: &mut HandlerCtx) {
+        if self.bell == BellStyle::Flash {
+            self.flash = Some(FLASH_DURATION);
+            self.invalidate(ctx);
+            ctx.request_anim_frame();
+        }
+    }
+
+    /// Whether every caret is at the start of the document, or the end if
+    /// `forward`, with nothing selected.
+    fn at_document_edge(&self, forward: bool) -> bool {
+        let edge = if forward {
+            match self.line_cache.end_position() {
+                Some(end) => end,
+                None => return false,
             }
-            VK_BACK => {
-                let action = if (mods & M_CTRL) == 0 {
-                    "delete_backward"
-                } else {
-                    // should be "delete to beginning of paragraph" but not supported
-                    s(mods, "delete_word_backward", "delete_to_beginning_of_line")
+        } else {
+            (0, 0)
+        };
+        let selections = self.selections();
+        !selections.is_empty()
+            && selections
+                .iter()
+                .all(|sel| sel.start == edge && sel.end == edge)
+    }
+
+    fn draw_flash<R: RenderTarget>(&self, rt: &mut R, origin: (f32, f32), resources: &Resources) {
+        let (x0, y0) = origin;
+        let (x1, y1) = (x0 + self.model.size.0, y0 + self.model.size.1);
+        let edges = [
+            (x0, y0, x1, y0 + FLASH_WIDTH),
+            (x0, y1 - FLASH_WIDTH, x1, y1),
+            (x0, y0, x0 + FLASH_WIDTH, y1),
+            (x1 - FLASH_WIDTH, y0, x1, y1),
+        ];
+        for rect in edges {
+            rt.fill_rectangle(rect, &resources.fg);
+        }
+    }
+
+    /// The distance from the left edge of the view to the text, in px. With
+    /// line numbers on, this fits the number of the last line.
+    fn gutter_width(&self) -> f32 {
+        let char_width = self.model.metrics.char_width;
+        if self.line_numbers && char_width > 0.0 {
+            let digits = self.line_cache.total_lines().max(1).to_string().len();
+            (digits as f32).mul_add(char_width, self.model.metrics.left_pad + GUTTER_PAD)
+        } else {
+            self.model.metrics.left_pad
+        }
+    }
+
+    /// Draws the numbers of the given lines, right aligned in the gutter.
+    fn draw_line_numbers<R: RenderTarget>(
+        &self,
+        rt: &mut R,
+        origin: (f32, f32),
+        lines: Range<usize>,
+        resources: &Resources,
+    ) {
+        let right = origin.0 + self.gutter_width() - GUTTER_PAD;
+        let mut y = origin.1
+            + self
+                .model
+                .metrics
+                .line_y(lines.start, self.model.scroll_offset);
+        for line_num in lines {
+            let Ok(layout) = TextLayout::create(&self.dwrite_factory)
+                .with_text(&(line_num + 1).to_string())
+                .with_font(self.text_format())
+                .with_width(1e6)
+                .with_height(1e6)
+                .build()
+            else {
+                continue;
+            };
+            let x = right - layout.get_metrics().width();
+            rt.draw_text_layout(
+                (x, y),
+                &layout,
+                &resources.gutter_fg,
+                self.text_rendering.draw_options(),
+            );
+            y += self.model.metrics.line_height;
+        }
+    }
+
+    fn minimap_contains(&self, x: f32) -> bool {
+        self.minimap && x >= self.model.size.0 - MINIMAP_WIDTH
+    }
+
+    /// How far the minimap is scrolled, in minimap px. When the whole
+    /// document doesn't fit, it scrolls in proportion to the view.
+    fn minimap_offset(&self) -> f32 {
+        let height = self.line_cache.total_lines() as f32 * MINIMAP_LINE_SPACE;
+        let overflow = (height - self.model.size.1).max(0.0);
+        let max_scroll = self.max_scroll();
+        if max_scroll > 0.0 {
+            overflow * (self.model.scroll_offset / max_scroll).min(1.0) as f32
+        } else {
+            0.0
+        }
+    }
+
+    /// The lines the minimap shows.
+    fn minimap_lines(&self) -> Range<usize> {
+        let offset = self.minimap_offset();
+        let first = (offset / MINIMAP_LINE_SPACE).floor() as usize;
+        let last = ((offset + self.model.size.1) / MINIMAP_LINE_SPACE).ceil() as usize;
+        first..last.min(self.line_cache.total_lines())
+    }
+
+    /// Scrolls the view to center the line at screen-space `y` in the
+    /// minimap.
+    fn scroll_to_minimap_y(&mut self, y: f32, ctx: &mut HandlerCtx) {
+        let line = f64::from((y + self.minimap_offset()) / MINIMAP_LINE_SPACE);
+        let Metrics {
+            line_height,
+            top_pad,
+            ..
+        } = self.model.metrics;
+        let target = line.mul_add(f64::from(line_height), f64::from(top_pad))
+            - f64::from(self.model.size.1 / 2.0);
+        self.scroll_anim = None;
+        self.model.scroll_offset = target;
+        self.constrain_scroll();
+        self.throttle_viewport(ctx);
+        self.invalidate(ctx);
+    }
+
+    /// Draws the minimap down the right side, with a block for each style
+    /// span and the part of the document in view highlighted.
+    fn draw_minimap<R: RenderTarget>(&self, rt: &mut R, origin: (f32, f32), resources: &Resources) {
+        let left = origin.0 + self.model.size.0 - MINIMAP_WIDTH;
+        let right = origin.0 + self.model.size.0;
+        rt.fill_rectangle(
+            (left, origin.1, right, origin.1 + self.model.size.1),
+            &resources.bg,
+        );
+        let top = origin.1 - self.minimap_offset();
+        let in_view = ((self.model.scroll_offset - f64::from(self.model.metrics.top_pad))
+            / f64::from(self.model.metrics.line_height)) as f32;
+        let view_top = in_view.mul_add(MINIMAP_LINE_SPACE, top);
+        let view_height = self.model.size.1 / self.model.metrics.line_height * MINIMAP_LINE_SPACE;
+        rt.fill_rectangle(
+            (left, view_top, right, view_top + view_height),
+            &resources.sel,
+        );
+
+        let block = |rt: &mut R, y: f32, cols: Range<usize>, brush: &SolidColorBrush| {
+            let x0 =
+                (cols.start as f32).mul_add(MINIMAP_CHAR_WIDTH, left + self.model.metrics.left_pad);
+            let x1 =
+                (cols.end as f32).mul_add(MINIMAP_CHAR_WIDTH, left + self.model.metrics.left_pad);
+            if x0 < right && x0 < x1 {
+                // A gap is left between lines, so they read as lines.
+                rt.fill_rectangle((x0, y, x1.min(right), y + MINIMAP_LINE_SPACE * 0.75), brush);
+            }
+        };
+        for line_num in self.minimap_lines() {
+            // Lines core hasn't sent are left blank.
+            let Some(line) = self.line_cache.get_line(line_num) else {
+                continue;
+            };
+            let y = (line_num as f32).mul_add(MINIMAP_LINE_SPACE, top);
+            // Columns are in utf-16 code units, like the style spans, and
+            // tabs are a single column.
+            let text = line.text().trim_end_matches(['\r', '\n']);
+            let indent = text.len() - text.trim_start().len();
+            block(rt, y, indent..text.encode_utf16().count(), &resources.fg);
+            for span in line.styles() {
+                if let Some(brush) = resources.style_fgs.get(&span.style_id) {
+                    block(rt, y, span.range.clone(), brush);
+                }
+            }
+        }
+    }
+
+    /// Draws the hover tooltip just below the point it was requested for,
+    /// or above it if there isn't room below, and kept inside the view.
+    fn draw_hover<R: RenderTarget>(
+        &self,
+        rt: &mut R,
+        origin: (f32, f32),
+        hover: &Hover,
+        resources: &Resources,
+    ) {
+        let Ok(layout) = TextLayout::create(&self.dwrite_factory)
+            .with_text(&hover.text)
+            .with_font(self.text_format())
+            .with_width(HOVER_MAX_WIDTH)
+            .with_height(1e6)
+            .build()
+        else {
+            return;
+        };
+        let metrics = layout.get_metrics();
+        let width = 2.0f32.mul_add(HOVER_PAD, metrics.width());
+        let height = 2.0f32.mul_add(HOVER_PAD, metrics.height());
+        let (mouse_x, mouse_y) = (origin.0 + hover.pos.0, origin.1 + hover.pos.1);
+        let x = mouse_x
+            .min(origin.0 + self.model.size.0 - width)
+            .max(origin.0);
+        let below = mouse_y + self.model.metrics.line_height;
+        let above = mouse_y - height;
+        let y = if below + height > origin.1 + self.model.size.1 && above >= origin.1 {
+            above
+        } else {
+            below
+        };
+        let rect = (x, y, x + width, y + height);
+        rt.fill_rectangle(rect, &resources.sel);
+        rt.draw_text_layout(
+            (x + HOVER_PAD, y + HOVER_PAD),
+            &layout,
+            &resources.fg,
+            self.text_rendering.draw_options(),
+        );
+    }
+
+    /// Draws the IME composition over the text at the caret, underlined as
+    /// IMEs expect.
+    fn draw_composition<R: RenderTarget>(
+        &self,
+        rt: &mut R,
+        (x, y): (f32, f32),
+        text: &str,
+        resources: &Resources,
+    ) {
+        let Ok(layout) = TextLayout::create(&self.dwrite_factory)
+            .with_text(text)
+            .with_font(self.text_format())
+            .with_width(1e6)
+            .with_height(1e6)
+            .build()
+        else {
+            return;
+        };
+        let range = DWRITE_TEXT_RANGE {
+            startPosition: 0,
+            length: text.encode_utf16().count() as u32,
+        };
+        // SAFETY: The raw pointer is valid for the lifetime of `layout`.
+        unsafe {
+            (*layout.get_raw()).SetUnderline(1, range);
+        }
+        let width = layout.get_metrics().width();
+        rt.fill_rectangle(
+            (x, y, x + width, y + self.model.metrics.line_height),
+            &resources.bg,
+        );
+        rt.draw_text_layout(
+            (x, y),
+            &layout,
+            &resources.fg,
+            self.text_rendering.draw_options(),
+        );
+    }
+
+    /// Selects every occurrence of the word under the caret, so typing
+    /// renames them all at once. Escape cancels back to a single selection.
+    // TODO(Olive): Ask a language plugin for the symbol's occurrences, so that
+    // only real references (possibly in other buffers) are renamed.
+    fn rename_symbol(&mut self) {
+        self.send_edit_cmd("selection_for_find", &json!({ "case_sensitive": true }));
+        self.send_action("find_all");
+    }
+
+    /// The current selections, in buffer order.
+    pub fn selections(&self) -> Vec<Selection> {
+        selections_in(&self.line_cache)
+    }
+
+    /// Where the first caret is, and how much is selected.
+    ///
+    /// Only the lines the cache knows to be selected are looked at, as this
+    /// is reported after every update.
+    fn caret_status(&self) -> CaretStatus {
+        // The first caret is the first selection's, as they don't overlap.
+        let (line, column) = self
+            .caret_line
+            .and_then(|line_num| {
+                let line = self.line_cache.get_line(line_num)?;
+                let text = line.text();
+                let col = utf16_to_utf8(text, *line.cursor().first()?);
+                Some((line_num, text[..col].chars().count()))
+            })
+            .unwrap_or((0, 0));
+        let mut selected = 0;
+        let mut carets = 0;
+        for &line_num in self.line_cache.selected_lines() {
+            let Some(line) = self.line_cache.get_line(line_num) else {
+                continue;
+            };
+            let text = line.text();
+            selected += line
+                .styles()
+                .iter()
+                .filter(|span| span.style_id == SELECTION_STYLE_ID)
+                .map(|span| {
+                    let start = utf16_to_utf8(text, span.range.start);
+                    let end = utf16_to_utf8(text, span.range.end);
+                    text[start..end].chars().count()
+                })
+                .sum::<usize>();
+            carets += line.cursor().len();
+        }
+        CaretStatus {
+            line: line + 1,
+            column: column + 1,
+            selected,
+            selections: carets.max(1),
+        }
+    }
+
+    /// Replaces the selections with the given ones.
+    pub fn set_selections(&mut self, selections: &[Selection]) {
+        for (i, sel) in selections.iter().enumerate() {
+            let ty = if i == 0 { "point_select" } else { "toggle_sel" };
+            self.send_gesture_at(sel.start, ty);
+            if sel.end != sel.start {
+                self.send_gesture_at(sel.end, "range_select");
+            }
+        }
+    }
+
+    /// Handles a key typed while the find bar is open, returning false for
+    /// keys which should go to the editor instead.
+    fn find_key(&mut self, event: &KeyEvent, ctx: &mut HandlerCtx) -> bool {
+        let Some(find) = &mut self.find else {
+            return false;
+        };
+        match event.key {
+            KeyVariant::Char(ch) if ch as u32 >= 0x20 => {
+                find.field_text().push(ch);
+                self.send_find_query();
+            }
+            KeyVariant::Char(_) => return false,
+            KeyVariant::Vkey(VK_BACK) => {
+                find.field_text().pop();
+                self.send_find_query();
+            }
+            KeyVariant::Vkey(VK_TAB) => {
+                find.field = match find.field {
+                    FindField::Find => FindField::Replace,
+                    FindField::Replace => FindField::Find,
                 };
-                self.send_action(action);
             }
-            VK_DELETE => {
-                let action = if (mods & M_CTRL) == 0 {
-                    // TODO(Olive): shift-delete should be "delete line"
-                    "delete_forward"
-                } else {
-                    s(mods, "delete_word_forward", "delete_to_end_of_paragraph")
+            KeyVariant::Vkey(VK_RETURN) => match find.field {
+                FindField::Find => self.find_next(event.mods & M_SHIFT == 0),
+                FindField::Replace if event.mods == M_CTRL => self.send_action("replace_all"),
+                FindField::Replace => self.send_action("replace_next"),
+            },
+            KeyVariant::Vkey(VK_ESCAPE) => {
+                self.find = None;
+                // An empty query removes the match highlights.
+                self.send_edit_cmd("find", &json!({ "chars": "" }));
+            }
+            KeyVariant::Vkey(vk) if vk == i32::from(b'C') && event.mods == M_ALT => {
+                find.case_sensitive = !find.case_sensitive;
+                self.send_find_query();
+            }
+            KeyVariant::Vkey(_) => return false,
+        }
+        ctx.send_event(EditViewEvent::Find(self.find.clone()));
+        true
+    }
+
+    /// Sends the search and replacement in the find bar to core.
+    fn send_find_query(&mut self) {
+        let Some(find) = &mut self.find else {
+            return;
+        };
+        // The count is stale until core reports on the new query.
+        find.matches = None;
+        let params = json!({
+            "chars": find.chars,
+            "case_sensitive": find.case_sensitive,
+            "regex": false,
+            "whole_words": false,
+        });
+        let replace_params = json!({
+            "chars": find.replacement,
+            "preserve_case": false,
+        });
+        self.send_edit_cmd("find", &params);
+        self.send_edit_cmd("replace", &replace_params);
+    }
+
+    /// Selects the next match of the search, or the previous one.
+    fn find_next(&mut self, forward: bool) {
+        let method = if forward {
+            "find_next"
+        } else {
+            "find_previous"
+        };
+        self.send_edit_cmd(method, &json!({ "wrap_around": true }));
+    }
+
+    /// Sends a simple action with no parameters
+    fn send_action(&mut self, method: &str) {
+        self.send_edit_cmd(method, &json!([]));
+    }
+
+    /// Handles a key the modal editing state machine takes, returning false
+    /// for keys which work as usual, such as the arrows and typing in insert
+    /// mode.
+    fn modal_key(&mut self, event: &KeyEvent, ctx: &mut HandlerCtx) -> bool {
+        let Some(modal) = &mut self.modal else {
+            return false;
+        };
+        let mode = modal.mode();
+        let effects = match event.key {
+            KeyVariant::Vkey(VK_ESCAPE) => modal.escape(),
+            _ if mode == Mode::Insert => return false,
+            KeyVariant::Vkey(VK_RETURN) => modal.char('j'),
+            KeyVariant::Vkey(VK_BACK) => modal.char('h'),
+            KeyVariant::Vkey(VK_TAB) => vec![],
+            // Letters come back as chars, when the key goes unhandled.
+            KeyVariant::Vkey(_) => return false,
+            KeyVariant::Char(ch) => modal.char(ch),
+        };
+        let new_mode = modal.mode();
+        for effect in effects {
+            match effect {
+                Effect::Action(action) => self.send_action(action),
+                Effect::Yank(yank) => self.yank(yank),
+                Effect::Put { after, count } => self.put(after, count),
+            }
+        }
+        if new_mode != mode {
+            ctx.send_event(EditViewEvent::Mode(Some(new_mode)));
+        }
+        true
+    }
+
+    /// Copies text into the modal editing register. Like the caret position
+    /// in the status bar, this goes by the first caret.
+    fn yank(&mut self, yank: Yank) {
+        let selections = self.selections();
+        let Some(&first) = selections.first() else {
+            return;
+        };
+        let (line_num, col) = first.end;
+        let register = match yank {
+            Yank::Chars(n) => {
+                let Some(text) = self
+                    .line_cache
+                    .get_line(line_num)
+                    .and_then(|line| line.text().get(col..))
+                else {
+                    return;
                 };
-                self.send_action(action);
+                let text = text.trim_end_matches(['\r', '\n']);
+                Register {
+                    text: text.chars().take(n).collect(),
+                    linewise: false,
+                }
             }
-            VK_OEM_4 => {
-                // generally '[' key, but might vary on non-US keyboards
-                if mods == M_CTRL {
-                    self.send_action("outdent");
-                } else {
-                    return false;
+            Yank::Lines(n) => {
+                let mut text = String::new();
+                for i in line_num..(line_num + n).min(self.line_cache.height()) {
+                    match self.line_cache.get_line(i) {
+                        Some(line) => text.push_str(line.text()),
+                        None => return,
+                    }
+                }
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                Register {
+                    text,
+                    linewise: true,
                 }
             }
-            VK_OEM_6 => {
-                // generally ']' key, but might vary on non-US keyboards
-                if mods == M_CTRL {
-                    self.send_action("indent");
-                } else {
-                    return false;
+            Yank::Selection => Register {
+                text: selections
+                    .iter()
+                    .filter_map(|&sel| self.selected_text(sel))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                linewise: false,
+            },
+        };
+        if let Some(modal) = &mut self.modal {
+            modal.register = register;
+        }
+    }
+
+    /// Pastes the modal editing register `count` times, with whole lines
+    /// going above or below the caret's.
+    fn put(&mut self, after: bool, count: usize) {
+        let Some(Register { text, linewise }) = self.modal.as_ref().map(|m| m.register.clone())
+        else {
+            return;
+        };
+        if text.is_empty() {
+            return;
+        }
+        let mut text = text.repeat(count);
+        if linewise {
+            let last_line = self.line_cache.height().saturating_sub(1);
+            let on_last_line = self
+                .selections()
+                .first()
+                .is_some_and(|sel| sel.end.0 >= last_line);
+            if after && on_last_line {
+                // There's no line below to put the text before, so it goes
+                // after a new line break instead.
+                text.pop();
+                text.insert(0, '\n');
+                self.send_action("move_to_right_end_of_line");
+            } else {
+                if after {
+                    self.send_action("move_down");
+                }
+                self.send_action("move_to_left_end_of_line");
+            }
+        } else if after {
+            self.send_action("move_right");
+        }
+        self.send_edit_cmd("insert", &json!({ "chars": text }));
+    }
+
+    /// Selects the whole line of every cursor, including its line break.
+    ///
+    /// TODO(Olive): pressing it again should extend the selection by a line,
+    /// rather than starting over from the cursor.
+    fn select_lines(&mut self) {
+        self.send_action("move_to_left_end_of_line");
+        self.send_action("move_down_and_modify_selection");
+    }
+
+    pub fn keydown(&mut self, vk_code: i32, mods: u32, ctx: &mut HandlerCtx) -> bool {
+        if let Some(command) = self.keymap.get(vk_code, mods).cloned() {
+            match command {
+                KeyCommand::Action(action) => self.send_action(&action),
+                KeyCommand::Menu(id) => ctx.send_event(EditViewEvent::Command(id)),
+            }
+            return true;
+        }
+        let Some(action) = key_action(vk_code, mods, |forward| self.at_document_edge(forward))
+        else {
+            return false;
+        };
+        match action {
+            KeyAction::Send { action, bell } => {
+                if bell {
+                    self.flash(ctx);
                 }
+                self.send_action(action);
+            }
+            KeyAction::ScrollLines(lines) => {
+                let delta = f64::from(lines) * f64::from(self.model.metrics.line_height);
+                self.scroll_smoothly(self.scroll_target() + delta, ctx);
             }
-            _ => return false,
         }
         true
     }
@@ -492,77 +2769,338 @@ impl EditView {
     // pub fn mouse_wheel(&mut self, delta: i32, _mods: u32) {
     //     // TODO(Olive): scale properly, taking SPI_GETWHEELSCROLLLINES into account
     //     let scroll_scaling = 0.5;
-    //     self.scroll_offset -= (delta as f32) * scroll_scaling;
+    //     self.model.scroll_offset -= (delta as f32) * scroll_scaling;
     //     self.constrain_scroll();
     //     self.update_viewport();
     // }
 
+    /// The auto-scroll velocity (in px per second) for a drag at screen-space
+    /// `y`, or `None` if `y` is outside the auto-scroll margins.
+    fn autoscroll_velocity(&self, y: f32) -> Option<f32> {
+        let AutoscrollSettings { margin, max_speed } = self.autoscroll;
+        let past_edge = if y < margin {
+            y - margin
+        } else if y > self.model.size.1 - margin {
+            y - (self.model.size.1 - margin)
+        } else {
+            return None;
+        };
+        Some((past_edge * AUTOSCROLL_RATE).max(-max_speed).min(max_speed))
+    }
+
+    fn max_scroll(&self) -> f64 {
+        self.model.max_scroll(self.line_cache.total_lines())
+    }
+
     fn constrain_scroll(&mut self) {
-        let max_scroll =
-            LINE_SPACE.mul_add(self.line_cache.height().saturating_sub(1) as f32, TOP_PAD);
-        if self.scroll_offset < 0.0 {
-            self.scroll_offset = 0.0;
-        } else if self.scroll_offset > max_scroll {
-            self.scroll_offset = max_scroll;
+        self.model.constrain_scroll(self.line_cache.total_lines());
+    }
+
+    /// The offset being scrolled to, or the current one if the view isn't
+    /// scrolling.
+    fn scroll_target(&self) -> f64 {
+        self.scroll_anim
+            .map_or(self.model.scroll_offset, |anim| anim.to)
+    }
+
+    /// Scrolls to `target`, animating the way there unless smooth scrolling
+    /// is turned off. A scroll already underway heads for the new target
+    /// from wherever it has got to.
+    fn scroll_smoothly(&mut self, target: f64, ctx: &mut HandlerCtx) {
+        let target = target.max(0.0).min(self.max_scroll());
+        if self.smooth_scroll && target != self.model.scroll_offset {
+            self.scroll_anim = Some(ScrollAnimation {
+                from: self.model.scroll_offset,
+                to: target,
+                elapsed: 0,
+            });
+            ctx.request_anim_frame();
+        } else {
+            self.scroll_anim = None;
+            self.model.scroll_offset = target;
+            self.throttle_viewport(ctx);
+            self.invalidate(ctx);
         }
     }
 
     // Takes y in screen-space px.
     fn y_to_line(&self, y: f32) -> usize {
-        let mut line = (y + self.scroll_offset - TOP_PAD) / LINE_SPACE;
-        if line < 0.0 {
-            line = 0.0;
-        }
-        let line = line.floor() as usize;
-        min(line, self.line_cache.height())
+        self.model.y_to_line(y, self.line_cache.total_lines())
     }
 
     /// Takes x, y in screen-space px, returns line number and utf8 offset within line.
+    ///
+    /// Below the last line is the end of the document.
     fn xy_to_line_col(&self, x: f32, y: f32) -> (usize, usize) {
         let line_num = self.y_to_line(y);
-        let col = if let (Some(textline), Some(line)) = (
-            self.get_text_line(line_num),
-            self.line_cache.get_line(line_num),
-        ) {
-            textline.hit_test(x - LEFT_PAD, 0.0, line.text())
-        } else {
-            0
+        let line = match self.line_cache.line_state(line_num) {
+            LineState::Loaded(line) => line,
+            LineState::Invalid | LineState::FinalEmpty => return (line_num, 0),
+            LineState::PastEnd => {
+                return self
+                    .line_cache
+                    .end_position()
+                    .unwrap_or((line_num.saturating_sub(1), 0));
+            }
+        };
+        let x = x - self.gutter_width();
+        let col = match self.text_lines.get(&line_num) {
+            Some(textline) => textline.hit_test(x, 0.0, line.text()),
+            // Not painted yet, such as a line scrolled to by a drag.
+            None => self.layout_line(line).hit_test(x, 0.0, line.text()),
         };
         (line_num, col)
     }
 
+    /// Sends core the lines showing, if they have changed.
     fn update_viewport(&mut self) {
-        let first_line = self.y_to_line(0.0);
-        let last_line = first_line + ((self.size.1 / LINE_SPACE).floor() as usize) + 1;
-        let viewport = first_line..last_line;
+        let viewport = self.visible_lines();
         if viewport != self.viewport {
+            self.send_edit_cmd("scroll", &json!([viewport.start, viewport.end]));
+            if let Some(missing) = self.line_cache.evicted_in(viewport.clone()) {
+                self.send_edit_cmd("request_lines", &json!([missing.start, missing.end]));
+            }
             self.viewport = viewport;
-            self.send_edit_cmd("scroll", &json!([first_line, last_line]));
+            self.viewport_sent = Some(Instant::now());
         }
+        self.viewport_owed = false;
     }
 
-    pub fn scroll_to(&mut self, line: usize) {
-        let y = line_to_content_y(line);
-        let bottom_slop = 20.0;
-        if y < self.scroll_offset {
-            self.scroll_offset = y;
-        // TODO(Olive) Get rid of this second check to `y`? It's a float after all.
-        } else if y > self.scroll_offset + self.size.1 - bottom_slop {
-            self.scroll_offset = y - (self.size.1 - bottom_slop);
+    /// Like [`update_viewport`](Self::update_viewport), but sending at most
+    /// once a frame, for scrolls which move the view a little at a time. A
+    /// change held back is sent on a later frame, so core always gets the
+    /// last one.
+    fn throttle_viewport(&mut self, ctx: &mut HandlerCtx) {
+        if self.visible_lines() == self.viewport {
+            self.viewport_owed = false;
+        } else if self
+            .viewport_sent
+            .map_or(true, |sent| sent.elapsed() >= VIEWPORT_INTERVAL)
+        {
+            self.update_viewport();
+        } else {
+            self.viewport_owed = true;
+            ctx.request_anim_frame();
         }
     }
+
+    /// The lines core should send, those showing and any in the minimap.
+    fn visible_lines(&self) -> Range<usize> {
+        let mut first_line = self.y_to_line(0.0);
+        let mut last_line = first_line
+            + ((self.model.size.1 / self.model.metrics.line_height).floor() as usize)
+            + 1;
+        // Core only sends the lines in the viewport, so it takes in those the
+        // minimap shows too.
+        if self.minimap {
+            let minimap_lines = self.minimap_lines();
+            first_line = first_line.min(minimap_lines.start);
+            last_line = last_line.max(minimap_lines.end);
+        }
+        first_line..last_line
+    }
+
+    /// Scrolls a line into view, with `scroll_off` lines of context around
+    /// it.
+    pub fn scroll_to(&mut self, line: usize, ctx: &mut HandlerCtx) {
+        if let Some(target) = self.model.scroll_to_line(line, self.scroll_target()) {
+            self.scroll_smoothly(target, ctx);
+        }
+    }
+}
+
+/// Creates a brush for each style which sets the given color.
+fn style_brushes<R: RenderTarget>(
+    rt: &mut R,
+    styles: &StyleMap,
+    color: fn(&Style) -> Option<u32>,
+) -> HashMap<usize, SolidColorBrush> {
+    styles
+        .iter()
+        .filter_map(|(&id, style)| {
+            let brush = SolidColorBrush::create(rt)
+                .with_color(split_alpha(color(style)?))
+                .build()
+                .unwrap();
+            Some((id, brush))
+        })
+        .collect()
 }
 
-// Helper function for choosing between normal and shifted action
-const fn s<'a>(mods: u32, normal: &'a str, shifted: &'a str) -> &'a str {
-    if (mods & M_SHIFT) == 0 {
-        normal
-    } else {
-        shifted
+/// Whether a font family is installed. DirectWrite lays text out in a
+/// substitute for one which isn't, which needn't be monospaced.
+fn font_installed(factory: &directwrite::Factory, family: &str) -> bool {
+    let name: Vec<u16> = family.encode_utf16().chain(Some(0)).collect();
+    // SAFETY: The raw pointer is valid for the lifetime of `factory`, and
+    // the collection is released once searched.
+    unsafe {
+        let mut collection = ptr::null_mut();
+        if !SUCCEEDED((*factory.get_raw()).GetSystemFontCollection(&mut collection, FALSE)) {
+            return false;
+        }
+        let mut index = 0;
+        let mut exists = FALSE;
+        let found = (*collection).FindFamilyName(name.as_ptr(), &mut index, &mut exists);
+        (*collection).Release();
+        SUCCEEDED(found) && exists != FALSE
+    }
+}
+
+/// The selections of a view, in buffer order. Only lines present in the
+/// line cache are considered, and of those only the ones the cache knows to
+/// be selected, so this costs as much as the selections do.
+fn selections_in(line_cache: &LineCache) -> Vec<Selection> {
+    // Core draws selections as spans of the selection style, split at
+    // line ends, and sends the carets separately.
+    let mut ranges: Vec<(Position, Position)> = vec![];
+    let mut carets = vec![];
+    for &line_num in line_cache.selected_lines() {
+        let Some(line) = line_cache.get_line(line_num) else {
+            continue;
+        };
+        let text = line.text();
+        let spans = line
+            .styles()
+            .iter()
+            .filter(|span| span.style_id == SELECTION_STYLE_ID);
+        for span in spans {
+            let start = (line_num, utf16_to_utf8(text, span.range.start));
+            let mut end = (line_num, utf16_to_utf8(text, span.range.end));
+            if end.1 == text.len() && text.ends_with('\n') {
+                end = (line_num + 1, 0);
+            }
+            match ranges.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => ranges.push((start, end)),
+            }
+        }
+        carets.extend(
+            line.cursor()
+                .iter()
+                .map(|&offset| (line_num, utf16_to_utf8(text, offset))),
+        );
     }
+    let mut selections: Vec<Selection> = ranges
+        .into_iter()
+        .map(|(start, end)| Selection { start, end })
+        .collect();
+    for caret in carets {
+        match selections
+            .iter_mut()
+            .find(|sel| sel.start == caret || sel.end == caret)
+        {
+            // A caret at the start means the selection was made backwards.
+            Some(sel) if sel.start == caret => mem::swap(&mut sel.start, &mut sel.end),
+            Some(_) => (),
+            None => selections.push(Selection {
+                start: caret,
+                end: caret,
+            }),
+        }
+    }
+    selections.sort_by_key(|sel| min(sel.start, sel.end));
+    selections
 }
 
-/// Convert line number to y coordinate in content space.
-fn line_to_content_y(line: usize) -> f32 {
-    (line as f32).mul_add(LINE_SPACE, TOP_PAD)
+#[cfg(test)]
+mod tests {
+    use super::{EditView, EditViewCommands, PaintRequest, SelectionTransform};
+    use crate::protocol::{ConfigChanges, Update};
+    use serde_json::{from_value, json};
+
+    #[test]
+    fn tab_size_sets_the_tab_stops() {
+        let mut view = EditView::new();
+        let changes = ConfigChanges {
+            tab_size: Some(2),
+            ..ConfigChanges::default()
+        };
+        view.config_changed(&changes);
+        let (format, metrics) = view.create_text_format();
+        // SAFETY: The raw pointer is valid for the lifetime of `format`.
+        let tab_stop = unsafe { (*format.get_raw()).GetIncrementalTabStop() };
+        assert_eq!(tab_stop, 2.0 * metrics.char_width);
+    }
+
+    #[test]
+    fn burst_of_changes_invalidates_once_per_frame() {
+        let mut paint_request = PaintRequest::default();
+        let mut invalidations = 0;
+        for _frame in 0..3 {
+            invalidations += (0..10).filter(|_| paint_request.request()).count();
+            paint_request.painted();
+        }
+        assert_eq!(invalidations, 3);
+    }
+
+    #[test]
+    fn burst_of_updates_is_applied_once() {
+        let mut view = EditView::new();
+        view.view_id = Some("view-id-1".to_owned());
+        let mut invalidations = 0;
+        for i in 0..10 {
+            let update: Update = from_value(json!({ "ops": [
+                { "op": "copy", "n": i },
+                { "op": "ins", "lines": [{ "text": format!("{i}\n") }] },
+            ]}))
+            .unwrap();
+            assert!(view.queue_update("view-id-1", &update));
+            invalidations += usize::from(view.paint_request.request());
+            // A `scroll_to` in between asks for the status, which waits too.
+            assert!(!EditViewCommands::UpdateStatus.reads_lines());
+        }
+        assert!(!view.queue_update("view-id-2", &from_value(json!({ "ops": [] })).unwrap()));
+        assert_eq!(invalidations, 1);
+        assert_eq!(view.queued_updates.len(), 10);
+
+        view.apply_queued_updates();
+        assert!(view.queued_updates.is_empty());
+        assert_eq!(view.line_cache.height(), 10);
+        assert!(view.status_owed);
+    }
+
+    #[test]
+    fn transformed_selections_leave_carets_after_them() {
+        let mut view = EditView::new();
+        view.view_id = Some("view-id-1".to_owned());
+        // "b\nc" selected from line 0 to the caret on line 1, and a caret on
+        // line 2.
+        let update: Update = from_value(json!({ "ops": [{ "op": "ins", "lines": [
+            { "text": "ab\n", "styles": [1, 2, 0] },
+            { "text": "cd\n", "cursor": [1], "styles": [0, 1, 0] },
+            { "text": "  e \n", "cursor": [2] },
+        ]}]}))
+        .unwrap();
+        assert!(view.queue_update("view-id-1", &update));
+        view.apply_queued_updates();
+
+        view.transform_each_selection(SelectionTransform::UpperCase);
+        let inserted: Vec<_> = view
+            .model
+            .pending
+            .iter()
+            .filter(|(method, _)| method == "insert")
+            .map(|(_, params)| params["chars"].clone())
+            .collect();
+        assert_eq!(inserted, [json!("B\nC")]);
+        // The carets go after "C", and where the bare caret was.
+        let carets: Vec<_> = view.model.pending[view.model.pending.len() - 2..]
+            .iter()
+            .map(|(_, params)| (params["line"].clone(), params["col"].clone()))
+            .collect();
+        assert_eq!(carets, [(json!(1), json!(1)), (json!(2), json!(2))]);
+        assert_eq!(SelectionTransform::Trim.apply("  e \n"), "e");
+    }
+
+    #[test]
+    fn reopened_view_keeps_the_scroll_position() {
+        let mut view = EditView::new();
+        view.view_id = Some("view-id-1".to_owned());
+        view.model.scroll_offset = 120.0;
+        view.carry_over("view-id-1", "view-id-2");
+        assert_eq!(view.documents["view-id-2"].restore_scroll, Some(120.0));
+        // A view which is gone has nothing to carry over.
+        view.carry_over("view-id-3", "view-id-4");
+        assert!(!view.documents.contains_key("view-id-4"));
+    }
 }
@@ -0,0 +1,253 @@
+//! Text encodings and line endings of files on disk.
+//!
+//! Core only reads and writes utf-8, so files in other encodings are handed
+//! to it as a utf-8 copy, and re-encoded after it saves them.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Byte order mark of utf-8.
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const UTF16LE_BOM: &[u8] = b"\xff\xfe";
+const UTF16BE_BOM: &[u8] = b"\xfe\xff";
+
+/// What Windows-1252 has in place of the C1 controls of Latin-1, from 0x80
+/// on. The five bytes it leaves undefined stay controls, as Windows has them.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+/// Numbers the utf-8 copies made this session, so each has a directory of
+/// its own.
+static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Windows1252,
+}
+
+impl Encoding {
+    pub const ALL: [Self; 5] = [
+        Self::Utf8,
+        Self::Utf8Bom,
+        Self::Utf16Le,
+        Self::Utf16Be,
+        Self::Windows1252,
+    ];
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Utf8 => "UTF-8",
+            Self::Utf8Bom => "UTF-8 with BOM",
+            Self::Utf16Le => "UTF-16 LE",
+            Self::Utf16Be => "UTF-16 BE",
+            Self::Windows1252 => "Western (Windows 1252)",
+        }
+    }
+
+    /// Guesses the encoding of a file's contents from its byte order mark.
+    /// Without one, text which isn't valid utf-8 is taken to be in the
+    /// Windows code page most files in Western languages are.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(UTF8_BOM) {
+            Self::Utf8Bom
+        } else if bytes.starts_with(UTF16LE_BOM) {
+            Self::Utf16Le
+        } else if bytes.starts_with(UTF16BE_BOM) {
+            Self::Utf16Be
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Self::Utf8
+        } else {
+            Self::Windows1252
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub const ALL: [Self; 2] = [Self::Lf, Self::CrLf];
+
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+}
+
+/// The line ending of a file's first line, or `None` if it has only one
+/// line.
+pub fn detect_line_ending(path: &Path) -> io::Result<Option<LineEnding>> {
+    let mut line = vec![];
+    BufReader::new(File::open(path)?).read_until(b'\n', &mut line)?;
+    Ok(match line.strip_suffix(b"\n") {
+        Some(rest) if rest.ends_with(b"\r") => Some(LineEnding::CrLf),
+        Some(_) => Some(LineEnding::Lf),
+        None => None,
+    })
+}
+
+/// Detects the encoding of a file.
+pub fn detect_encoding(path: &Path) -> io::Result<Encoding> {
+    Ok(Encoding::detect(&fs::read(path)?))
+}
+
+/// Decodes a file in the given encoding into a utf-8 copy for core to open,
+/// returning the copy's path. The copy has the name of the file, so core
+/// picks the same syntax for it, and can be removed once core has read it.
+pub fn utf8_copy(path: &Path, encoding: Encoding) -> io::Result<PathBuf> {
+    let text = decode(&fs::read(path)?, encoding)?;
+    let n = COPIES.fetch_add(1, Ordering::Relaxed);
+    let dir = env::temp_dir()
+        .join("xi-win")
+        .join(format!("{}-{}", process::id(), n));
+    fs::create_dir_all(&dir)?;
+    let copy = dir.join(path.file_name().unwrap_or_else(|| "untitled".as_ref()));
+    fs::write(&copy, text)?;
+    Ok(copy)
+}
+
+/// Removes a copy made by [`utf8_copy`], along with its directory.
+pub fn remove_copy(copy: &Path) {
+    if let Some(dir) = copy.parent() {
+        if let Err(err) = fs::remove_dir_all(dir) {
+            warn!("couldn't remove {}: {}", dir.display(), err);
+        }
+    }
+}
+
+/// Decodes text in the given encoding, without its byte order mark.
+fn decode(bytes: &[u8], encoding: Encoding) -> io::Result<String> {
+    let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+    match encoding {
+        Encoding::Utf8 | Encoding::Utf8Bom => {
+            let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+            String::from_utf8(bytes.to_vec()).map_err(|err| invalid(err.to_string()))
+        }
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let bom = if encoding == Encoding::Utf16Le {
+                UTF16LE_BOM
+            } else {
+                UTF16BE_BOM
+            };
+            let bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+            if bytes.len() % 2 != 0 {
+                return Err(invalid("odd number of bytes in utf-16".to_owned()));
+            }
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| {
+                    let pair = [pair[0], pair[1]];
+                    if encoding == Encoding::Utf16Le {
+                        u16::from_le_bytes(pair)
+                    } else {
+                        u16::from_be_bytes(pair)
+                    }
+                })
+                .collect();
+            String::from_utf16(&units).map_err(|err| invalid(err.to_string()))
+        }
+        Encoding::Windows1252 => Ok(bytes
+            .iter()
+            .map(|&b| match b {
+                0x80..=0x9f => WINDOWS_1252_HIGH[usize::from(b - 0x80)],
+                _ => char::from(b),
+            })
+            .collect()),
+    }
+}
+
+/// Rewrites a utf-8 file, as written by core, in the given encoding and line
+/// ending.
+pub fn transcode_file(path: &Path, encoding: Encoding, line_ending: LineEnding) -> io::Result<()> {
+    let bytes = fs::read(path)?;
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(&bytes);
+    let text = std::str::from_utf8(bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    fs::write(path, encode(text, encoding, line_ending))
+}
+
+/// Encodes text with the given encoding and line ending.
+fn encode(text: &str, encoding: Encoding, line_ending: LineEnding) -> Vec<u8> {
+    let text = text.replace("\r\n", "\n");
+    let text = match line_ending {
+        LineEnding::Lf => text,
+        LineEnding::CrLf => text.replace('\n', "\r\n"),
+    };
+    match encoding {
+        Encoding::Utf8 => text.into_bytes(),
+        Encoding::Utf8Bom => [UTF8_BOM, text.as_bytes()].concat(),
+        Encoding::Utf16Le => {
+            let mut bytes = UTF16LE_BOM.to_vec();
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+            bytes
+        }
+        Encoding::Utf16Be => {
+            let mut bytes = UTF16BE_BOM.to_vec();
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+            bytes
+        }
+        // Characters the code page lacks become question marks, as they do
+        // when Windows converts to it.
+        Encoding::Windows1252 => text
+            .chars()
+            .map(|ch| match WINDOWS_1252_HIGH.iter().position(|&c| c == ch) {
+                Some(i) => 0x80 + i as u8,
+                None => u8::try_from(u32::from(ch))
+                    .ok()
+                    .filter(|b| !(0x80..=0x9f).contains(b))
+                    .unwrap_or(b'?'),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Encoding, LineEnding};
+
+    #[test]
+    fn encodings_are_detected() {
+        assert_eq!(Encoding::detect(b"\xef\xbb\xbfa"), Encoding::Utf8Bom);
+        assert_eq!(Encoding::detect(b"\xff\xfea\0"), Encoding::Utf16Le);
+        assert_eq!(Encoding::detect(b"\xfe\xff\0a"), Encoding::Utf16Be);
+        assert_eq!(Encoding::detect("caf\u{e9}".as_bytes()), Encoding::Utf8);
+        assert_eq!(Encoding::detect(b"caf\xe9"), Encoding::Windows1252);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let text = "caf\u{e9} \u{20ac}5\n";
+        for encoding in Encoding::ALL {
+            let bytes = encode(text, encoding, LineEnding::Lf);
+            assert_eq!(decode(&bytes, encoding).unwrap(), text, "{encoding:?}");
+        }
+        assert_eq!(
+            encode(text, Encoding::Windows1252, LineEnding::CrLf),
+            b"caf\xe9 \x805\r\n"
+        );
+        // Characters Windows-1252 lacks, including the C1 controls it
+        // replaced.
+        assert_eq!(
+            encode("\u{3b1}\u{80}", Encoding::Windows1252, LineEnding::Lf),
+            b"??"
+        );
+        assert!(decode(b"\xff\xfea", Encoding::Utf16Le).is_err());
+    }
+}
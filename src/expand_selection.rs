@@ -0,0 +1,210 @@
+//! Growing a selection to the next enclosing word, bracket pair, lines,
+//! indentation block or document, for Expand Selection.
+//!
+//! Core doesn't tell the front end about syntax, so this is a heuristic over
+//! the text in the line cache. Brackets in strings and comments aren't told
+//! apart, and lines missing from the cache end the search.
+
+use crate::edit_view::{Position, Selection};
+use crate::linecache::LineCache;
+
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// The smallest unit of text which strictly contains the selection, or
+/// `None` if there is none, such as when the whole document is selected.
+pub fn expand(cache: &LineCache, sel: Selection) -> Option<Selection> {
+    let (start, end) = if sel.start <= sel.end {
+        (sel.start, sel.end)
+    } else {
+        (sel.end, sel.start)
+    };
+    let mut candidates = vec![word(cache, start, end), lines(cache, start, end)];
+    if let Some((open, close)) = enclosing_brackets(cache, start, end) {
+        candidates.push(Some(((open.0, open.1 + 1), close)));
+        candidates.push(Some((open, (close.0, close.1 + 1))));
+    }
+    candidates.extend(indent_blocks(cache, start, end));
+    candidates.push(document(cache));
+    candidates
+        .into_iter()
+        .flatten()
+        .filter(|&(s, e)| s <= start && end <= e && (s, e) != (start, end))
+        .min_by_key(|&(s, e)| extent(cache, s, e))
+        .map(|(start, end)| Selection { start, end })
+}
+
+/// The text of a line without its line ending.
+fn line_text(cache: &LineCache, line_num: usize) -> Option<&str> {
+    cache
+        .get_line(line_num)
+        .map(|line| line.text().trim_end_matches(&['\r', '\n'][..]))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The word around a selection within one line.
+fn word(cache: &LineCache, start: Position, end: Position) -> Option<(Position, Position)> {
+    if start.0 != end.0 {
+        return None;
+    }
+    let text = line_text(cache, start.0)?;
+    let (before, after) = (text.get(..start.1)?, text.get(end.1..)?);
+    if !text.get(start.1..end.1)?.chars().all(is_word_char) {
+        return None;
+    }
+    let word_start = before
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_word_char(c))
+        .last()
+        .map_or(start.1, |(i, _)| i);
+    let word_end = end.1
+        + after
+            .char_indices()
+            .find(|&(_, c)| !is_word_char(c))
+            .map_or(after.len(), |(i, _)| i);
+    Some(((start.0, word_start), (end.0, word_end)))
+}
+
+/// The whole lines a selection touches, without the final line ending.
+fn lines(cache: &LineCache, start: Position, end: Position) -> Option<(Position, Position)> {
+    // A selection of whole lines ends at the start of the next one.
+    let last = if end.1 == 0 && end.0 > start.0 {
+        end.0 - 1
+    } else {
+        end.0
+    };
+    line_span(cache, start.0, last)
+}
+
+/// Lines `first` to `last`, without the final line ending.
+fn line_span(cache: &LineCache, first: usize, last: usize) -> Option<(Position, Position)> {
+    Some(((first, 0), (last, line_text(cache, last)?.len())))
+}
+
+/// The positions of the innermost bracket pair around a selection.
+fn enclosing_brackets(
+    cache: &LineCache,
+    start: Position,
+    end: Position,
+) -> Option<(Position, Position)> {
+    // Closing brackets seen while searching backwards, of each kind.
+    let mut depth = [0_usize; BRACKETS.len()];
+    let (mut line_num, mut col) = start;
+    loop {
+        let text = cache.get_line(line_num)?.text();
+        for (i, c) in text.get(..col.min(text.len()))?.char_indices().rev() {
+            if let Some(kind) = BRACKETS.iter().position(|&(_, close)| close == c) {
+                depth[kind] += 1;
+            } else if let Some(kind) = BRACKETS.iter().position(|&(open, _)| open == c) {
+                if depth[kind] > 0 {
+                    depth[kind] -= 1;
+                    continue;
+                }
+                let open = (line_num, i);
+                if let Some(close) = matching_close(cache, kind, open).filter(|&close| close >= end)
+                {
+                    return Some((open, close));
+                }
+            }
+        }
+        line_num = line_num.checked_sub(1)?;
+        col = usize::MAX;
+    }
+}
+
+/// The position of the bracket closing the one of the given kind at `open`.
+fn matching_close(cache: &LineCache, kind: usize, open: Position) -> Option<Position> {
+    let (open_char, close_char) = BRACKETS[kind];
+    let mut depth = 0_usize;
+    let (mut line_num, mut col) = (open.0, open.1 + 1);
+    loop {
+        let text = cache.get_line(line_num)?.text();
+        for (i, c) in text.get(col..)?.char_indices() {
+            if c == open_char {
+                depth += 1;
+            } else if c == close_char {
+                if depth == 0 {
+                    return Some((line_num, col + i));
+                }
+                depth -= 1;
+            }
+        }
+        line_num += 1;
+        col = 0;
+    }
+}
+
+/// The indentation of a line, or `None` if it is blank.
+fn indent(cache: &LineCache, line_num: usize) -> Option<usize> {
+    let text = line_text(cache, line_num)?;
+    let trimmed = text.trim_start();
+    (!trimmed.is_empty()).then_some(text.len() - trimmed.len())
+}
+
+/// The block of lines indented at least as deeply as the selected lines,
+/// then that block together with the line introducing it and the line
+/// closing it, if any.
+fn indent_blocks(
+    cache: &LineCache,
+    start: Position,
+    end: Position,
+) -> [Option<(Position, Position)>; 2] {
+    let Some(((first, _), (last, _))) = lines(cache, start, end) else {
+        return [None, None];
+    };
+    let Some(level) = (first..=last).filter_map(|i| indent(cache, i)).min() else {
+        return [None, None];
+    };
+    let in_block =
+        |i: usize| cache.get_line(i).is_some() && indent(cache, i).map_or(true, |n| n >= level);
+    let mut top = first;
+    while top > 0 && in_block(top - 1) {
+        top -= 1;
+    }
+    let mut bottom = last;
+    while in_block(bottom + 1) {
+        bottom += 1;
+    }
+    // Blank lines around the block aren't part of it.
+    while top < first && indent(cache, top).is_none() {
+        top += 1;
+    }
+    while bottom > last && indent(cache, bottom).is_none() {
+        bottom -= 1;
+    }
+    let block = line_span(cache, top, bottom);
+    let header = (0..top)
+        .rev()
+        .find_map(|i| Some((i, indent(cache, i)?)))
+        .filter(|&(_, header_indent)| header_indent < level);
+    let parent = header.and_then(|(header, header_indent)| {
+        let next = (bottom + 1..cache.height()).find(|&i| indent(cache, i).is_some());
+        let closing = next.filter(|&i| {
+            let first_char = line_text(cache, i).and_then(|text| text.trim_start().chars().next());
+            indent(cache, i) == Some(header_indent)
+                && BRACKETS.iter().any(|&(_, close)| Some(close) == first_char)
+        });
+        line_span(cache, header, closing.unwrap_or(bottom))
+    });
+    [block, parent]
+}
+
+fn document(cache: &LineCache) -> Option<(Position, Position)> {
+    let last = cache.height().checked_sub(1)?;
+    Some(((0, 0), (last, line_text(cache, last)?.len())))
+}
+
+/// The number of bytes from `start` to `end`, counting missing lines as
+/// empty.
+fn extent(cache: &LineCache, start: Position, end: Position) -> usize {
+    let line_len = |i: usize| cache.get_line(i).map_or(0, |line| line.text().len());
+    if start.0 == end.0 {
+        end.1 - start.1
+    } else {
+        let middle: usize = (start.0 + 1..end.0).map(line_len).sum();
+        line_len(start.0).saturating_sub(start.1) + middle + end.1
+    }
+}
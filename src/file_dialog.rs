@@ -0,0 +1,276 @@
+//! Open and save dialogs, using `IFileDialog` directly for the options that
+//! druid's file dialog doesn't expose.
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+use std::thread;
+
+use winapi::shared::minwindef::{DWORD, LPVOID, UINT};
+use winapi::shared::shtypes::COMDLG_FILTERSPEC;
+use winapi::shared::windef::HWND;
+use winapi::shared::winerror::{HRESULT, SUCCEEDED};
+use winapi::shared::wtypesbase::CLSCTX_INPROC_SERVER;
+use winapi::um::combaseapi::{CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize};
+use winapi::um::objbase::COINIT_APARTMENTTHREADED;
+use winapi::um::shobjidl::IFileDialogCustomize;
+use winapi::um::shobjidl_core::{
+    CLSID_FileOpenDialog, CLSID_FileSaveDialog, IFileDialog, IShellItem,
+    SHCreateItemFromParsingName, SIGDN_FILESYSPATH,
+};
+use winapi::um::unknwnbase::IUnknown;
+use winapi::um::winuser::GetActiveWindow;
+use winapi::Interface;
+
+use druid_win_shell::window::IdleHandle;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DialogKind {
+    Open,
+    Save,
+}
+
+/// A file type choice in a dialog, such as `("Text files", "*.txt")`.
+#[derive(Clone, Debug)]
+pub struct FileFilter {
+    pub name: &'static str,
+    /// Semicolon separated patterns.
+    pub spec: &'static str,
+}
+
+/// The file types offered by the Open dialog.
+pub const OPEN_FILTERS: &[FileFilter] = &[
+    FileFilter {
+        name: "Source files",
+        spec: "*.rs;*.toml;*.c;*.h;*.cpp;*.hpp;*.cs;*.go;*.java;*.js;*.ts;*.py;*.rb;*.sh;*.json;*.yaml;*.yml;*.xml;*.html;*.css",
+    },
+    FileFilter {
+        name: "Text files",
+        spec: "*.txt;*.md;*.log;*.ini;*.cfg",
+    },
+    FileFilter {
+        name: "All files",
+        spec: "*.*",
+    },
+];
+
+/// The file types offered by the Save dialog.
+pub const SAVE_FILTERS: &[FileFilter] = &[FileFilter {
+    name: "All files",
+    spec: "*.*",
+}];
+
+/// A drop-down list added to a dialog.
+#[derive(Clone, Debug)]
+pub struct Choice {
+    pub label: &'static str,
+    pub items: Vec<&'static str>,
+    /// The index of the selected item, updated when the dialog closes.
+    pub selected: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DialogOptions {
+    /// The directory the dialog starts in.
+    pub folder: Option<PathBuf>,
+    pub filters: &'static [FileFilter],
+    /// The extension (without a dot) appended to names typed without one.
+    pub default_extension: Option<&'static str>,
+    pub choices: Vec<Choice>,
+}
+
+/// The usual file extension for an xi-core language id.
+pub fn extension_for_language(language: &str) -> Option<&'static str> {
+    let extension = match language {
+        "Rust" => "rs",
+        "TOML" => "toml",
+        "C" => "c",
+        "C++" => "cpp",
+        "C#" => "cs",
+        "Go" => "go",
+        "Java" => "java",
+        "JavaScript" => "js",
+        "TypeScript" => "ts",
+        "Python" => "py",
+        "Ruby" => "rb",
+        "Shell-Unix-Generic" | "Bourne Again Shell (bash)" => "sh",
+        "JSON" => "json",
+        "YAML" => "yaml",
+        "XML" => "xml",
+        "HTML" => "html",
+        "CSS" => "css",
+        "Markdown" => "md",
+        "Plain Text" => "txt",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// Shows a modal file dialog, returning the chosen path.
+///
+/// Cancelling the dialog is reported as an error.
+pub fn show(kind: DialogKind, options: &mut DialogOptions) -> io::Result<OsString> {
+    // SAFETY: Just a query of the calling thread's state.
+    let owner = unsafe { GetActiveWindow() };
+    show_owned(kind, options, owner)
+}
+
+/// Shows a file dialog from a thread of its own, so that the window keeps
+/// painting while it is up. `done` is then called on the window's thread
+/// with the result and the options, whose choices are updated.
+///
+/// The dialog is still modal to the window that was active.
+pub fn show_async<F>(kind: DialogKind, mut options: DialogOptions, handle: IdleHandle, done: F)
+where
+    F: FnOnce(io::Result<OsString>, DialogOptions) + Send + 'static,
+{
+    // SAFETY: Just a query of the calling thread's state.
+    let owner = unsafe { GetActiveWindow() } as usize;
+    thread::spawn(move || {
+        // SAFETY: The dialog is a COM object, and COM is set up for this
+        // thread until the dialog is done with.
+        let result = unsafe {
+            let initialized = SUCCEEDED(CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED));
+            let result = show_owned(kind, &mut options, owner as HWND);
+            if initialized {
+                CoUninitialize();
+            }
+            result
+        };
+        handle.add_idle(move |_| done(result, options));
+    });
+}
+
+fn show_owned(kind: DialogKind, options: &mut DialogOptions, owner: HWND) -> io::Result<OsString> {
+    let clsid = match kind {
+        DialogKind::Open => &CLSID_FileOpenDialog,
+        DialogKind::Save => &CLSID_FileSaveDialog,
+    };
+    unsafe {
+        let mut dialog: *mut IFileDialog = ptr::null_mut();
+        check(CoCreateInstance(
+            clsid,
+            ptr::null_mut(),
+            CLSCTX_INPROC_SERVER,
+            &IFileDialog::uuidof(),
+            (&mut dialog as *mut *mut IFileDialog).cast::<LPVOID>(),
+        ))?;
+        let dialog = ComPtr(dialog);
+
+        if !options.filters.is_empty() {
+            let filters: Vec<(Vec<u16>, Vec<u16>)> = options
+                .filters
+                .iter()
+                .map(|filter| (to_wide(filter.name), to_wide(filter.spec)))
+                .collect();
+            let specs: Vec<COMDLG_FILTERSPEC> = filters
+                .iter()
+                .map(|(name, spec)| COMDLG_FILTERSPEC {
+                    pszName: name.as_ptr(),
+                    pszSpec: spec.as_ptr(),
+                })
+                .collect();
+            check((*dialog.0).SetFileTypes(specs.len() as UINT, specs.as_ptr()))?;
+        }
+        if let Some(extension) = options.default_extension {
+            check((*dialog.0).SetDefaultExtension(to_wide(extension).as_ptr()))?;
+        }
+        if let Some(folder) = &options.folder {
+            // A remembered folder which has since been deleted isn't worth
+            // failing over, the dialog just opens in its own default.
+            if let Ok(item) = shell_item(folder) {
+                (*dialog.0).SetFolder(item.0);
+            }
+        }
+
+        let customize = if options.choices.is_empty() {
+            None
+        } else {
+            let mut customize: *mut IFileDialogCustomize = ptr::null_mut();
+            check((*dialog.0).QueryInterface(
+                &IFileDialogCustomize::uuidof(),
+                (&mut customize as *mut *mut IFileDialogCustomize).cast(),
+            ))?;
+            Some(ComPtr(customize))
+        };
+        if let Some(customize) = &customize {
+            for (i, choice) in options.choices.iter().enumerate() {
+                let (group_id, combo_id) = choice_ids(i);
+                check((*customize.0).StartVisualGroup(group_id, to_wide(choice.label).as_ptr()))?;
+                check((*customize.0).AddComboBox(combo_id))?;
+                for (item_id, item) in choice.items.iter().enumerate() {
+                    check((*customize.0).AddControlItem(
+                        combo_id,
+                        item_id as DWORD,
+                        to_wide(item).as_ptr(),
+                    ))?;
+                }
+                check((*customize.0).SetSelectedControlItem(combo_id, choice.selected as DWORD))?;
+                check((*customize.0).EndVisualGroup())?;
+            }
+        }
+
+        check((*dialog.0).Show(owner))?;
+        if let Some(customize) = &customize {
+            for (i, choice) in options.choices.iter_mut().enumerate() {
+                let mut selected = 0;
+                check((*customize.0).GetSelectedControlItem(choice_ids(i).1, &mut selected))?;
+                choice.selected = selected as usize;
+            }
+        }
+        let mut item: *mut IShellItem = ptr::null_mut();
+        check((*dialog.0).GetResult(&mut item))?;
+        let item = ComPtr(item);
+        let mut name = ptr::null_mut();
+        check((*item.0).GetDisplayName(SIGDN_FILESYSPATH, &mut name))?;
+        let len = (0..).take_while(|&i| *name.add(i) != 0).count();
+        let path = OsString::from_wide(slice::from_raw_parts(name, len));
+        CoTaskMemFree(name.cast());
+        Ok(path)
+    }
+}
+
+/// An owned COM interface pointer, released when dropped.
+pub struct ComPtr<T: Interface>(pub *mut T);
+
+impl<T: Interface> Drop for ComPtr<T> {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.0.cast::<IUnknown>()).Release();
+        }
+    }
+}
+
+unsafe fn shell_item(path: &Path) -> io::Result<ComPtr<IShellItem>> {
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut item: *mut IShellItem = ptr::null_mut();
+    check(SHCreateItemFromParsingName(
+        path.as_ptr(),
+        ptr::null_mut(),
+        &IShellItem::uuidof(),
+        (&mut item as *mut *mut IShellItem).cast::<LPVOID>(),
+    ))?;
+    Ok(ComPtr(item))
+}
+
+/// The control ids of the group and combo box for the choice at `index`.
+const fn choice_ids(index: usize) -> (DWORD, DWORD) {
+    let id = index as DWORD * 2;
+    (id, id + 1)
+}
+
+/// Converts a string to a nul-terminated utf-16 string for Windows APIs.
+pub fn to_wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+pub fn check(hr: HRESULT) -> io::Result<()> {
+    if SUCCEEDED(hr) {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(hr))
+    }
+}
@@ -0,0 +1,24 @@
+//! Noticing changes made to files on disk by other programs.
+
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The modification time and size of a file, which change whenever something
+/// writes to it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    /// Reads the current stamp of a file, or `None` if it can't be read.
+    pub fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
@@ -0,0 +1,207 @@
+//! The find bar, shown between the tabs and the editor while searching.
+//!
+//! The query itself belongs to the edit view, which types into it while the
+//! bar is open and sends the searches to core. The bar only draws it.
+
+use std::any::Any;
+use std::fmt;
+
+use direct2d::brush::SolidColorBrush;
+use direct2d::RenderTarget;
+use directwrite::{TextFormat, TextLayout};
+
+use druid_win_shell::util::default_text_options;
+
+use druid::widget::Widget;
+use druid::Ui;
+use druid::{BoxConstraints, Geometry, LayoutResult};
+use druid::{HandlerCtx, Id, LayoutCtx, PaintCtx};
+
+use crate::color_scheme::ColorScheme;
+use crate::dpi;
+
+const BAR_HEIGHT: f32 = 30.0;
+const BAR_PAD: f32 = 4.0;
+const FIELD_WIDTH: f32 = 240.0;
+const FONT_SIZE: f32 = 12.0;
+
+/// The text field of the find bar being typed in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FindField {
+    #[default]
+    Find,
+    Replace,
+}
+
+/// A view's search, as typed into the find bar.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FindQuery {
+    pub chars: String,
+    pub replacement: String,
+    pub field: FindField,
+    pub case_sensitive: bool,
+    /// The number of matches, once core has reported them.
+    pub matches: Option<usize>,
+}
+
+impl FindQuery {
+    /// The text of the field being typed in.
+    pub fn field_text(&mut self) -> &mut String {
+        match self.field {
+            FindField::Find => &mut self.chars,
+            FindField::Replace => &mut self.replacement,
+        }
+    }
+}
+
+/// The commands the [`FindBar`] widget accepts through `poke`.
+#[derive(Debug)]
+pub enum FindBarCommands {
+    /// Shows the query of the focused view, or hides the bar for `None`.
+    SetQuery(Option<FindQuery>),
+    SetColorScheme(ColorScheme),
+}
+
+pub struct FindBar {
+    query: Option<FindQuery>,
+    color_scheme: ColorScheme,
+    dwrite_factory: directwrite::Factory,
+}
+
+impl fmt::Debug for FindBar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FindBar")
+            .field("query", &self.query)
+            .field("color_scheme", &self.color_scheme)
+            .field("dwrite_factory", &"...")
+            .finish()
+    }
+}
+
+impl Widget for FindBar {
+    fn paint(&mut self, paint_ctx: &mut PaintCtx, geom: &Geometry) {
+        let Some(query) = &self.query else {
+            return;
+        };
+        let geom = &dpi::begin_paint(paint_ctx.render_target(), geom);
+        let rt = paint_ctx.render_target();
+        let colors = &self.color_scheme;
+        let [bg, field_bg, fg] =
+            [colors.tab_inactive_bg, colors.tab_active_bg, colors.tab_fg].map(|color| {
+                SolidColorBrush::create(rt)
+                    .with_color(color)
+                    .build()
+                    .unwrap()
+            });
+        let (x0, y0) = geom.pos;
+        let (width, height) = geom.size;
+        rt.fill_rectangle((x0, y0, x0 + width, y0 + height), &bg);
+
+        let format = TextFormat::create(&self.dwrite_factory)
+            .with_family("Segoe UI")
+            .with_size(FONT_SIZE)
+            .build()
+            .unwrap();
+        let matches = match query.matches {
+            Some(1) => "1 match".to_owned(),
+            Some(n) => format!("{n} matches"),
+            None => String::new(),
+        };
+        let case = if query.case_sensitive { "Aa" } else { "" };
+        let items = [
+            ("Find", Some((FindField::Find, &query.chars))),
+            ("Replace", Some((FindField::Replace, &query.replacement))),
+            (case, None),
+            (&matches, None),
+        ];
+        let mut x = x0 + BAR_PAD;
+        for (label, field) in items {
+            if label.is_empty() {
+                continue;
+            }
+            let Some(label) = self.text_layout(label, &format) else {
+                continue;
+            };
+            let metrics = label.get_metrics();
+            let text_y = y0 + (height - metrics.height()) / 2.0;
+            rt.draw_text_layout((x, text_y), &label, &fg, default_text_options());
+            x += metrics.width() + BAR_PAD;
+            let Some((field, text)) = field else {
+                continue;
+            };
+            let (top, bottom) = (y0 + BAR_PAD, y0 + height - BAR_PAD);
+            rt.fill_rectangle((x, top, x + FIELD_WIDTH, bottom), &field_bg);
+            let text_x = x + BAR_PAD;
+            let text_width = self.text_layout(text, &format).map_or(0.0, |layout| {
+                rt.draw_text_layout((text_x, text_y), &layout, &fg, default_text_options());
+                layout.get_metrics().width()
+            });
+            if field == query.field {
+                let caret_x = text_x + text_width.min(FIELD_WIDTH - 2.0 * BAR_PAD);
+                rt.draw_line(
+                    (caret_x, top + 2.0),
+                    (caret_x, bottom - 2.0),
+                    &fg,
+                    1.0,
+                    None,
+                );
+            }
+            x += FIELD_WIDTH + 2.0 * BAR_PAD;
+        }
+        dpi::end_paint(rt);
+    }
+
+    fn layout(
+        &mut self,
+        bc: &BoxConstraints,
+        _children: &[Id],
+        _size: Option<(f32, f32)>,
+        _ctx: &mut LayoutCtx,
+    ) -> LayoutResult {
+        let height = if self.query.is_some() {
+            dpi::to_px(BAR_HEIGHT)
+        } else {
+            0.0
+        };
+        LayoutResult::Size(bc.constrain((f32::INFINITY, height)))
+    }
+
+    fn poke(&mut self, payload: &mut dyn Any, ctx: &mut HandlerCtx) -> bool {
+        if let Some(cmd) = payload.downcast_ref::<FindBarCommands>() {
+            match cmd {
+                FindBarCommands::SetQuery(query) => {
+                    self.query = query.clone();
+                }
+                FindBarCommands::SetColorScheme(scheme) => {
+                    self.color_scheme = scheme.clone();
+                }
+            }
+            ctx.invalidate();
+        }
+        true
+    }
+}
+
+impl FindBar {
+    pub fn new() -> Self {
+        Self {
+            query: None,
+            color_scheme: ColorScheme::default(),
+            dwrite_factory: directwrite::Factory::new().unwrap(),
+        }
+    }
+
+    pub fn ui(self, ctx: &mut Ui) -> Id {
+        ctx.add(self, &[])
+    }
+
+    fn text_layout(&self, text: &str, format: &TextFormat) -> Option<TextLayout> {
+        TextLayout::create(&self.dwrite_factory)
+            .with_text(text)
+            .with_font(format)
+            .with_width(FIELD_WIDTH - 2.0 * BAR_PAD)
+            .with_height(1e6)
+            .build()
+            .ok()
+    }
+}
@@ -0,0 +1,147 @@
+//! Input method (IME) composition, as used to type Chinese, Japanese and
+//! Korean.
+//!
+//! The shell doesn't pass on the `WM_IME_*` messages, so they are taken from
+//! the main window before it sees them. The composition string is drawn
+//! inline by the edit view, and the finished text is inserted like typing.
+
+use std::ptr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use winapi::shared::minwindef::{DWORD, LPARAM};
+use winapi::shared::windef::{HWND, POINT, RECT};
+use winapi::um::commctrl::DefSubclassProc;
+use winapi::um::imm::{
+    ImmGetCompositionStringW, ImmGetContext, ImmReleaseContext, ImmSetCandidateWindow,
+    ImmSetCompositionWindow, CANDIDATEFORM, CFS_CANDIDATEPOS, CFS_POINT, COMPOSITIONFORM,
+    GCS_COMPSTR, GCS_RESULTSTR,
+};
+use winapi::um::winuser::{
+    WM_IME_COMPOSITION, WM_IME_ENDCOMPOSITION, WM_IME_SETCONTEXT, WM_IME_STARTCOMPOSITION,
+};
+
+use crate::dpi;
+use crate::subclass::{self, HookId};
+
+/// Asks the IME not to draw its own composition window, since the edit view
+/// draws the composition inline.
+const ISC_SHOWUICOMPOSITIONWINDOW: u32 = 0x8000_0000;
+
+/// Where the caret was last drawn, in dips, as two `f32`s packed
+/// together. The candidate window is placed just below it.
+static CARET: AtomicU64 = AtomicU64::new(0);
+
+/// A change to the composition, passed to the listener given to [`hook`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ImeEvent {
+    /// The text being composed, which is empty once composition ends.
+    Composition(String),
+    /// Text the user has finished composing, to be inserted.
+    Commit(String),
+}
+
+/// Takes over IME composition for a window, calling `listener` as the
+/// composition changes. Returns false if the window couldn't be hooked.
+///
+/// # Safety
+///
+/// `hwnd` must be a window created by the current thread.
+pub unsafe fn hook<F: Fn(ImeEvent) + 'static>(hwnd: HWND, listener: F) -> bool {
+    subclass::hook(hwnd, HookId::Ime, move |hwnd, msg, wparam, lparam| {
+        match msg {
+            WM_IME_SETCONTEXT => {
+                return Some(DefSubclassProc(
+                    hwnd,
+                    msg,
+                    wparam,
+                    lparam & !(ISC_SHOWUICOMPOSITIONWINDOW as LPARAM),
+                ));
+            }
+            WM_IME_STARTCOMPOSITION => {
+                place_windows(hwnd);
+                return Some(0);
+            }
+            WM_IME_COMPOSITION => {
+                let flags = lparam as DWORD;
+                if flags & GCS_RESULTSTR != 0 {
+                    if let Some(text) = composition_string(hwnd, GCS_RESULTSTR) {
+                        listener(ImeEvent::Commit(text));
+                    }
+                }
+                if flags & GCS_COMPSTR != 0 {
+                    let text = composition_string(hwnd, GCS_COMPSTR).unwrap_or_default();
+                    listener(ImeEvent::Composition(text));
+                }
+                place_windows(hwnd);
+                // Not passed on, so the result doesn't also arrive as `WM_CHAR`.
+                return Some(0);
+            }
+            WM_IME_ENDCOMPOSITION => {
+                listener(ImeEvent::Composition(String::new()));
+            }
+            _ => (),
+        }
+        None
+    })
+}
+
+/// Records where the caret is, in dips, with `line_height` the height
+/// of the line it's on.
+pub fn set_caret_pos(x: f32, y: f32, line_height: f32) {
+    let packed = u64::from(x.to_bits()) << 32 | u64::from((y + line_height).to_bits());
+    CARET.store(packed, Ordering::Relaxed);
+}
+
+/// Reads the composition string of the given kind.
+unsafe fn composition_string(hwnd: HWND, kind: DWORD) -> Option<String> {
+    let himc = ImmGetContext(hwnd);
+    if himc.is_null() {
+        return None;
+    }
+    // The length is in bytes.
+    let len = ImmGetCompositionStringW(himc, kind, ptr::null_mut(), 0);
+    let text = if len > 0 {
+        let mut buf = vec![0u16; len as usize / 2];
+        ImmGetCompositionStringW(himc, kind, buf.as_mut_ptr().cast(), len as DWORD);
+        Some(String::from_utf16_lossy(&buf))
+    } else {
+        None
+    };
+    ImmReleaseContext(hwnd, himc);
+    text
+}
+
+/// Moves the IME's windows to follow the caret.
+unsafe fn place_windows(hwnd: HWND) {
+    let himc = ImmGetContext(hwnd);
+    if himc.is_null() {
+        return;
+    }
+    let packed = CARET.load(Ordering::Relaxed);
+    let x = dpi::to_px(f32::from_bits((packed >> 32) as u32)) as i32;
+    let y = dpi::to_px(f32::from_bits(packed as u32)) as i32;
+    let mut composition = COMPOSITIONFORM {
+        dwStyle: CFS_POINT,
+        ptCurrentPos: POINT { x, y },
+        rcArea: RECT {
+            left: 0,
+            top: 0,
+            right: 0,
+            bottom: 0,
+        },
+    };
+    ImmSetCompositionWindow(himc, &mut composition);
+    let mut candidate = CANDIDATEFORM {
+        dwIndex: 0,
+        dwStyle: CFS_CANDIDATEPOS,
+        ptCurrentPos: POINT { x, y },
+        rcArea: RECT {
+            left: 0,
+            top: 0,
+            right: 0,
+            bottom: 0,
+        },
+    };
+    ImmSetCandidateWindow(himc, &mut candidate);
+    ImmReleaseContext(hwnd, himc);
+}
@@ -0,0 +1,197 @@
+//! The recently opened files, shown in the jump list of the app's taskbar
+//! button so that they can be opened, or pinned, from there.
+//!
+//! The items start the app with `--reuse-window` and the file, so they open
+//! in the running window if there is one, see
+//! [`single_instance`](crate::single_instance).
+
+use std::env;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::thread;
+
+use winapi::shared::guiddef::GUID;
+use winapi::shared::minwindef::{LPVOID, UINT};
+use winapi::shared::winerror::SUCCEEDED;
+use winapi::shared::wtypes::{PROPERTYKEY, VT_LPWSTR};
+use winapi::shared::wtypesbase::CLSCTX_INPROC_SERVER;
+use winapi::um::combaseapi::{CoCreateInstance, CoInitializeEx, CoTaskMemAlloc, CoUninitialize};
+use winapi::um::objbase::COINIT_APARTMENTTHREADED;
+use winapi::um::objectarray::{IObjectArray, IObjectCollection};
+use winapi::um::propidl::{PropVariantClear, PROPVARIANT};
+use winapi::um::propsys::IPropertyStore;
+use winapi::um::shobjidl_core::{ICustomDestinationList, IShellLinkW};
+use winapi::um::unknwnbase::IUnknown;
+use winapi::Interface;
+
+use crate::file_dialog::{check, to_wide, ComPtr};
+
+/// How many recent files are kept.
+pub const MAX_RECENT: usize = 10;
+
+/// The flag the jump list's items start the app with.
+pub const REUSE_WINDOW: &str = "--reuse-window";
+
+const CLSID_DESTINATION_LIST: GUID = GUID {
+    Data1: 0x77f1_0cf0,
+    Data2: 0x3db5,
+    Data3: 0x4966,
+    Data4: [0xb5, 0x20, 0xb7, 0xc5, 0x4f, 0xd3, 0x5e, 0xd6],
+};
+const CLSID_ENUMERABLE_OBJECT_COLLECTION: GUID = GUID {
+    Data1: 0x2d34_68c1,
+    Data2: 0x36a7,
+    Data3: 0x43b6,
+    Data4: [0xac, 0x24, 0xd3, 0xf0, 0x2f, 0xd9, 0x60, 0x7a],
+};
+const CLSID_SHELL_LINK: GUID = GUID {
+    Data1: 0x0002_1401,
+    Data2: 0x0000,
+    Data3: 0x0000,
+    Data4: [0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+};
+/// The title of a jump list item, without which it isn't shown.
+const PKEY_TITLE: PROPERTYKEY = PROPERTYKEY {
+    fmtid: GUID {
+        Data1: 0xf29f_85e0,
+        Data2: 0x4ff9,
+        Data3: 0x1068,
+        Data4: [0xab, 0x91, 0x08, 0x00, 0x2b, 0x27, 0xb3, 0xd9],
+    },
+    pid: 2,
+};
+
+/// Moves `path` to the front of the recent files, which are most recent
+/// first.
+pub fn add_recent(recent: &mut Vec<String>, path: &str) {
+    recent.retain(|recent_path| recent_path != path);
+    recent.insert(0, path.to_owned());
+    recent.truncate(MAX_RECENT);
+}
+
+/// Replaces the files in the jump list. The shell can be slow to answer, so
+/// this is done on a thread of its own.
+pub fn update(recent: Vec<String>) {
+    thread::spawn(move || {
+        // SAFETY: COM is initialized for this thread only, and everything
+        // made with it is released before it is uninitialized.
+        unsafe {
+            if !SUCCEEDED(CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED)) {
+                return;
+            }
+            if let Err(err) = build(&recent) {
+                warn!("couldn't update the jump list: {}", err);
+            }
+            CoUninitialize();
+        }
+    });
+}
+
+unsafe fn build(recent: &[String]) -> io::Result<()> {
+    let exe = env::current_exe()?;
+    let exe = to_wide(&exe.to_string_lossy());
+    let list: ComPtr<ICustomDestinationList> = create(&CLSID_DESTINATION_LIST)?;
+    let mut min_slots: UINT = 0;
+    let mut removed: *mut IObjectArray = ptr::null_mut();
+    check((*list.0).BeginList(
+        &mut min_slots,
+        &IObjectArray::uuidof(),
+        (&mut removed as *mut *mut IObjectArray).cast::<LPVOID>(),
+    ))?;
+    let removed = ComPtr(removed);
+    // Adding an item the user removed from the list fails the whole list.
+    let removed = removed_paths(&removed);
+
+    let items: ComPtr<IObjectCollection> = create(&CLSID_ENUMERABLE_OBJECT_COLLECTION)?;
+    for path in recent
+        .iter()
+        .filter(|path| !removed.contains(*path))
+        .take(min_slots as usize)
+    {
+        let link = shell_link(&exe, path)?;
+        check((*items.0).AddObject(link.0.cast::<IUnknown>()))?;
+    }
+    check((*list.0).AppendCategory(to_wide("Recent").as_ptr(), items.0.cast::<IObjectArray>()))?;
+    check((*list.0).CommitList())
+}
+
+unsafe fn create<T: Interface>(clsid: &GUID) -> io::Result<ComPtr<T>> {
+    let mut object: *mut T = ptr::null_mut();
+    check(CoCreateInstance(
+        clsid,
+        ptr::null_mut(),
+        CLSCTX_INPROC_SERVER,
+        &T::uuidof(),
+        (&mut object as *mut *mut T).cast::<LPVOID>(),
+    ))?;
+    Ok(ComPtr(object))
+}
+
+/// An item opening `path` in the app, titled with the file's name.
+unsafe fn shell_link(exe: &[u16], path: &str) -> io::Result<ComPtr<IShellLinkW>> {
+    let link: ComPtr<IShellLinkW> = create(&CLSID_SHELL_LINK)?;
+    check((*link.0).SetPath(exe.as_ptr()))?;
+    let args = format!("{REUSE_WINDOW} \"{path}\"");
+    check((*link.0).SetArguments(to_wide(&args).as_ptr()))?;
+    check((*link.0).SetDescription(to_wide(path).as_ptr()))?;
+
+    let mut store: *mut IPropertyStore = ptr::null_mut();
+    check((*link.0).QueryInterface(
+        &IPropertyStore::uuidof(),
+        (&mut store as *mut *mut IPropertyStore).cast(),
+    ))?;
+    let store = ComPtr(store);
+    let name = path.rsplit(['\\', '/']).next().unwrap_or(path);
+    let mut title = prop_string(name);
+    let result =
+        check((*store.0).SetValue(&PKEY_TITLE, &title)).and_then(|()| check((*store.0).Commit()));
+    PropVariantClear(&mut title);
+    result?;
+    Ok(link)
+}
+
+/// A `PROPVARIANT` of a string, to be freed with `PropVariantClear`.
+unsafe fn prop_string(s: &str) -> PROPVARIANT {
+    let wide = to_wide(s);
+    let buf = CoTaskMemAlloc(wide.len() * 2).cast::<u16>();
+    let mut variant: PROPVARIANT = mem::zeroed();
+    if buf.is_null() {
+        return variant;
+    }
+    ptr::copy_nonoverlapping(wide.as_ptr(), buf, wide.len());
+    let inner = variant.u.s_mut();
+    inner.vt = VT_LPWSTR as u16;
+    *inner.data.pwszVal_mut() = buf;
+    variant
+}
+
+/// The files of the items the user removed from the list.
+unsafe fn removed_paths(removed: &ComPtr<IObjectArray>) -> Vec<String> {
+    let mut count: UINT = 0;
+    if !SUCCEEDED((*removed.0).GetCount(&mut count)) {
+        return vec![];
+    }
+    let mut paths = vec![];
+    for i in 0..count {
+        let mut link: *mut IShellLinkW = ptr::null_mut();
+        let got = (*removed.0).GetAt(
+            i,
+            &IShellLinkW::uuidof(),
+            (&mut link as *mut *mut IShellLinkW).cast::<LPVOID>(),
+        );
+        if !SUCCEEDED(got) {
+            continue;
+        }
+        let link = ComPtr(link);
+        let mut args = [0_u16; 1024];
+        if SUCCEEDED((*link.0).GetArguments(args.as_mut_ptr(), args.len() as i32)) {
+            let len = args.iter().position(|&c| c == 0).unwrap_or(args.len());
+            let args = String::from_utf16_lossy(&args[..len]);
+            if let Some(path) = args.strip_prefix(REUSE_WINDOW) {
+                paths.push(path.trim().trim_matches('"').to_owned());
+            }
+        }
+    }
+    paths
+}
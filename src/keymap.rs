@@ -0,0 +1,261 @@
+//! Keybindings from `%APPDATA%\xi-win\keymap.json`, which take precedence
+//! over the edit view's built-in keys.
+//!
+//! The file is a list of bindings, each either to an edit action sent to
+//! core or to a command of the menus:
+//!
+//! ```json
+//! [
+//!     { "key": "ctrl+shift+up", "action": "move_up_and_modify_selection" },
+//!     { "key": "alt+s", "command": "save" }
+//! ]
+//! ```
+//!
+//! Keys with an accelerator in the menus, such as ctrl+s, reach the menus
+//! first, so can't be rebound here.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use winapi::um::winuser::{
+    VK_BACK, VK_DELETE, VK_DOWN, VK_END, VK_ESCAPE, VK_F1, VK_HOME, VK_INSERT, VK_LEFT, VK_NEXT,
+    VK_PRIOR, VK_RETURN, VK_RIGHT, VK_SPACE, VK_TAB, VK_UP,
+};
+
+use druid_win_shell::window::{M_ALT, M_CTRL, M_SHIFT};
+
+use crate::menus::MenuEntries;
+use crate::xi_config;
+
+/// What a key does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyCommand {
+    /// An edit action, sent to core.
+    Action(String),
+    /// A menu command, by id.
+    Menu(u32),
+}
+
+/// The user's keybindings, by virtual key code and modifiers.
+#[derive(Clone, Debug, Default)]
+pub struct Keymap {
+    bindings: HashMap<(i32, u32), KeyCommand>,
+}
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    BadKey(String),
+    /// A binding with no action or command, or an unknown command.
+    BadCommand(String),
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "couldn't read {}: {}", keymap_path().display(), err),
+            Self::Json(err) => write!(f, "invalid keymap: {}", err),
+            Self::BadKey(key) => write!(f, "unknown key \"{}\"", key),
+            Self::BadCommand(key) => write!(f, "bad command for \"{}\"", key),
+        }
+    }
+}
+
+impl Error for KeymapError {}
+
+/// A binding as written in the file.
+#[derive(Deserialize)]
+struct BindingDef {
+    key: String,
+    #[serde(default)]
+    action: Option<String>,
+    #[serde(default)]
+    command: Option<String>,
+}
+
+impl Keymap {
+    /// Loads the user's keymap, which is empty if there is no file.
+    pub fn load() -> Result<Self, KeymapError> {
+        match fs::read_to_string(keymap_path()) {
+            Ok(json) => Self::parse(&json),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(KeymapError::Io(err)),
+        }
+    }
+
+    fn parse(json: &str) -> Result<Self, KeymapError> {
+        let defs: Vec<BindingDef> = serde_json::from_str(json).map_err(KeymapError::Json)?;
+        let mut bindings = HashMap::new();
+        for def in defs {
+            let key = parse_key(&def.key).ok_or_else(|| KeymapError::BadKey(def.key.clone()))?;
+            let command = match (def.action, def.command) {
+                (Some(action), None) => KeyCommand::Action(action),
+                (None, Some(command)) => menu_command(&command)
+                    .map(|entry| KeyCommand::Menu(entry as u32))
+                    .ok_or_else(|| KeymapError::BadCommand(def.key.clone()))?,
+                _ => return Err(KeymapError::BadCommand(def.key)),
+            };
+            // Later bindings of a key replace earlier ones.
+            bindings.insert(key, command);
+        }
+        Ok(Self { bindings })
+    }
+
+    /// What the key does, if the user has bound it.
+    pub fn get(&self, vk_code: i32, mods: u32) -> Option<&KeyCommand> {
+        self.bindings.get(&(vk_code, mods))
+    }
+}
+
+fn keymap_path() -> PathBuf {
+    xi_config::config_dir().join("keymap.json")
+}
+
+/// Parses a key such as `"ctrl+shift+k"` into a virtual key code and
+/// modifiers.
+fn parse_key(key: &str) -> Option<(i32, u32)> {
+    let key = key.to_ascii_lowercase();
+    let mut parts: Vec<&str> = key.split('+').map(str::trim).collect();
+    let name = parts.pop()?;
+    let mut mods = 0;
+    for part in parts {
+        mods |= match part {
+            "ctrl" => M_CTRL,
+            "shift" => M_SHIFT,
+            "alt" => M_ALT,
+            _ => return None,
+        };
+    }
+    let vk = match name {
+        "up" => VK_UP,
+        "down" => VK_DOWN,
+        "left" => VK_LEFT,
+        "right" => VK_RIGHT,
+        "home" => VK_HOME,
+        "end" => VK_END,
+        "pageup" => VK_PRIOR,
+        "pagedown" => VK_NEXT,
+        "enter" => VK_RETURN,
+        "tab" => VK_TAB,
+        "space" => VK_SPACE,
+        "escape" => VK_ESCAPE,
+        "backspace" => VK_BACK,
+        "delete" => VK_DELETE,
+        "insert" => VK_INSERT,
+        _ => match name.strip_prefix('f').and_then(|n| n.parse::<i32>().ok()) {
+            Some(n @ 1..=24) => VK_F1 + n - 1,
+            _ => {
+                // Letters and digits are their own virtual key codes.
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) if ch.is_ascii_alphanumeric() => {
+                        ch.to_ascii_uppercase() as i32
+                    }
+                    _ => return None,
+                }
+            }
+        },
+    };
+    Some((vk, mods))
+}
+
+/// The menu command with this name.
+fn menu_command(name: &str) -> Option<MenuEntries> {
+    Some(match name {
+        "new" => MenuEntries::New,
+        "open" => MenuEntries::Open,
+        "close" => MenuEntries::Close,
+        "save" => MenuEntries::Save,
+        "save_as" => MenuEntries::SaveAs,
+        "undo" => MenuEntries::Undo,
+        "redo" => MenuEntries::Redo,
+        "cut" => MenuEntries::Cut,
+        "copy" => MenuEntries::Copy,
+        "paste" => MenuEntries::Paste,
+        "upper_case" => MenuEntries::UpperCase,
+        "lower_case" => MenuEntries::LowerCase,
+        "transpose" => MenuEntries::Transpose,
+        "indent" => MenuEntries::Indent,
+        "outdent" => MenuEntries::Outdent,
+        "duplicate_line" => MenuEntries::DuplicateLine,
+        "delete_line" => MenuEntries::DeleteLine,
+        "format_document" => MenuEntries::FormatDocument,
+        "rename_symbol" => MenuEntries::RenameSymbol,
+        "find" => MenuEntries::Find,
+        "find_next" => MenuEntries::FindNext,
+        "find_previous" => MenuEntries::FindPrevious,
+        "single_selection" => MenuEntries::SingleSelection,
+        "add_cursor_above" => MenuEntries::AddCursorAbove,
+        "add_cursor_below" => MenuEntries::AddCursorBelow,
+        "select_all" => MenuEntries::SelectAll,
+        "select_line" => MenuEntries::SelectLine,
+        "expand_selection" => MenuEntries::ExpandSelection,
+        "shrink_selection" => MenuEntries::ShrinkSelection,
+        "insert_sequence" => MenuEntries::InsertSequence,
+        "upper_case_each" => MenuEntries::UpperCaseEach,
+        "lower_case_each" => MenuEntries::LowerCaseEach,
+        "trim_each" => MenuEntries::TrimEach,
+        "next_tab" => MenuEntries::NextTab,
+        "previous_tab" => MenuEntries::PreviousTab,
+        "reload_keymap" => MenuEntries::ReloadKeymap,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_key, KeyCommand, Keymap, KeymapError};
+    use crate::menus::MenuEntries;
+    use druid_win_shell::window::{M_ALT, M_CTRL, M_SHIFT};
+    use winapi::um::winuser::{VK_F1, VK_UP};
+
+    #[test]
+    fn keys_parse() {
+        assert_eq!(
+            parse_key("Ctrl+Shift+K"),
+            Some(('K' as i32, M_CTRL | M_SHIFT))
+        );
+        assert_eq!(parse_key("up"), Some((VK_UP, 0)));
+        assert_eq!(parse_key("f12"), Some((VK_F1 + 11, 0)));
+        assert_eq!(parse_key("7"), Some(('7' as i32, 0)));
+        for bad in ["", "ctrl+", "hyper+k", "kk", "f25"] {
+            assert_eq!(parse_key(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn bindings_parse() {
+        let keymap = Keymap::parse(
+            r#"[
+                { "key": "alt+up", "action": "move_up" },
+                { "key": "alt+s", "command": "save" }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            keymap.get(VK_UP, M_ALT),
+            Some(&KeyCommand::Action("move_up".into()))
+        );
+        assert_eq!(
+            keymap.get('S' as i32, M_ALT),
+            Some(&KeyCommand::Menu(MenuEntries::Save as u32))
+        );
+        for bad in [
+            r#"[{ "key": "alt+s" }]"#,
+            r#"[{ "key": "alt+s", "command": "bogus" }]"#,
+            r#"[{ "key": "alt+s", "action": "a", "command": "save" }]"#,
+        ] {
+            assert!(matches!(
+                Keymap::parse(bad),
+                Err(KeymapError::BadCommand(_))
+            ));
+        }
+    }
+}
@@ -1,10 +1,14 @@
 //! The line cache (text, styles and cursors for a view).
 
-use serde_json::Value;
+use std::error::Error;
+use std::fmt;
 use std::mem;
 use std::ops::Range;
 
-#[derive(Debug)]
+use crate::offsets::{count_utf16, utf8_to_utf16};
+use crate::protocol::{AnnotationUpdate, Lenient, LineUpdate, Update, UpdateOp};
+
+#[derive(Clone, Debug)]
 pub struct Line {
     text: String,
     /// List of carets, in units of utf-16 code units.
@@ -12,6 +16,11 @@ pub struct Line {
     styles: Vec<StyleSpan>,
 }
 
+/// The style id core reserves for selections.
+pub const SELECTION_STYLE_ID: usize = 0;
+/// The style core gives to the matches of a find.
+pub const FIND_HIGHLIGHT_STYLE_ID: usize = 1;
+
 #[derive(Clone, Debug)]
 pub struct StyleSpan {
     pub style_id: usize,
@@ -20,40 +29,55 @@ pub struct StyleSpan {
 }
 
 impl Line {
-    // TODO(Olive) - This function should return Result<Self, SerdeErrorType>.
-    pub fn from_json(v: &Value) -> Self {
-        let text = v["text"].as_str().unwrap().to_owned();
-        let mut cursor = vec![];
-        if let Some(arr) = v["cursor"].as_array() {
-            cursor = Vec::with_capacity(arr.len());
-            for c in arr {
-                let offset_utf8 = c.as_u64().unwrap() as usize;
-                cursor.push(count_utf16(&text[..offset_utf8]));
-            }
+    /// Converts a line from core to utf-16 offsets, checking each offset
+    /// lands on a character in the line.
+    pub fn from_update(line: &LineUpdate) -> Result<Self, UpdateError> {
+        let text = line.text.clone();
+        let mut cursor = Vec::with_capacity(line.cursor.len());
+        for &offset_utf8 in &line.cursor {
+            let before = text
+                .get(..offset_utf8)
+                .ok_or(UpdateError::BadOffset(offset_utf8 as i64))?;
+            cursor.push(count_utf16(before));
         }
         let mut styles = vec![];
-        if let Some(arr) = v["styles"].as_array() {
-            let mut ix: i64 = 0;
-            for triple in arr.chunks(3) {
-                let start = ix + triple[0].as_i64().unwrap();
-                let end = start + triple[1].as_i64().unwrap();
-                let style_id = triple[2].as_u64().unwrap() as usize;
-                // TODO(Olive): count utf from last end, if <=
-                let start_utf16 = count_utf16(&text[..start as usize]);
-                let end_utf16 = start_utf16 + count_utf16(&text[start as usize..end as usize]);
-                let style_span = StyleSpan {
-                    style_id,
-                    range: start_utf16..end_utf16,
-                };
-                styles.push(style_span);
-                ix = end;
-            }
+        let mut ix: i64 = 0;
+        for triple in line.styles.chunks(3) {
+            let &[start, len, style_id] = triple else {
+                return Err(UpdateError::BadField("styles"));
+            };
+            let style_id =
+                usize::try_from(style_id).map_err(|_| UpdateError::BadField("styles"))?;
+            // Each span starts relative to the end of the one before.
+            let start = ix.checked_add(start).ok_or(UpdateError::BadOffset(start))?;
+            let end = start
+                .checked_add(len)
+                .ok_or(UpdateError::BadOffset(start))?;
+            let (Ok(start_utf8), Ok(end_utf8)) = (usize::try_from(start), usize::try_from(end))
+            else {
+                return Err(UpdateError::BadOffset(start.min(end)));
+            };
+            // TODO(Olive): count utf from last end, if <=
+            let before = text
+                .get(..start_utf8)
+                .ok_or(UpdateError::BadOffset(start))?;
+            let span = text
+                .get(start_utf8..end_utf8)
+                .ok_or(UpdateError::BadOffset(end))?;
+            let start_utf16 = count_utf16(before);
+            let end_utf16 = start_utf16 + count_utf16(span);
+            let style_span = StyleSpan {
+                style_id,
+                range: start_utf16..end_utf16,
+            };
+            styles.push(style_span);
+            ix = end;
         }
-        Self {
+        Ok(Self {
             text,
             cursor,
             styles,
-        }
+        })
     }
 
     pub fn text(&self) -> &str {
@@ -67,47 +91,298 @@ impl Line {
     pub fn styles(&self) -> &[StyleSpan] {
         &self.styles
     }
+
+    /// Whether the line has a caret or selected text.
+    fn is_selected(&self) -> bool {
+        !self.cursor.is_empty()
+            || self
+                .styles
+                .iter()
+                .any(|span| span.style_id == SELECTION_STYLE_ID)
+    }
+
+    /// Roughly the memory the line takes up, in bytes.
+    pub fn size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.text.capacity()
+            + self.cursor.capacity() * mem::size_of::<usize>()
+            + self.styles.capacity() * mem::size_of::<StyleSpan>()
+    }
+}
+
+/// The indentation style of a document, as guessed from its contents.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IndentStyle {
+    Tabs,
+    /// Spaces, with the given indent width.
+    Spaces(usize),
+}
+
+/// What was wrong with a line or op sent by core.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UpdateError {
+    /// The field is missing or has the wrong type.
+    BadField(&'static str),
+    /// The utf-8 offset is outside its line, or inside a character.
+    BadOffset(i64),
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadField(field) => write!(f, "missing or malformed field `{field}`"),
+            Self::BadOffset(offset) => write!(f, "offset {offset} is not in the line"),
+        }
+    }
+}
+
+impl Error for UpdateError {}
+
+/// A run of lines an update kept from the old cache, which may have moved.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CopiedLines {
+    /// Where the lines were before the update.
+    pub old: Range<usize>,
+    /// Where the first of them is now.
+    pub new_start: usize,
+}
+
+impl CopiedLines {
+    /// Where the line at `old_ix` before the update is now, if it was kept
+    /// by this run.
+    pub fn new_index(&self, old_ix: usize) -> Option<usize> {
+        self.old
+            .contains(&old_ix)
+            .then(|| old_ix - self.old.start + self.new_start)
+    }
+}
+
+/// What an annotation marks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AnnotationKind {
+    Selection,
+    Find,
+}
+
+/// A range of the buffer marked by core, from a line and utf-8 column to
+/// another.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Annotation {
+    kind: AnnotationKind,
+    start: (usize, usize),
+    end: (usize, usize),
+}
+
+/// What the cache knows of a line of the document.
+#[derive(Clone, Copy, Debug)]
+pub enum LineState<'a> {
+    Loaded(&'a Line),
+    /// A line core hasn't sent yet.
+    Invalid,
+    /// The empty line after a final line break, which core doesn't always
+    /// send.
+    FinalEmpty,
+    /// Beyond the end of the document.
+    PastEnd,
 }
 
 #[derive(Debug, Default)]
 pub struct LineCache {
     lines: Vec<Option<Line>>,
+    /// The annotations of the last update which had any.
+    annotations: Vec<Annotation>,
+    /// The sum of the [`Line::size`] of the loaded lines.
+    bytes: usize,
+    /// Whether lines have ever been evicted. Core still counts those as
+    /// sent, so they have to be asked for again.
+    evicted: bool,
+    /// The loaded lines with carets, in order.
+    carets: Vec<usize>,
+    /// The loaded lines with carets or selected text, in order, so the
+    /// selections are found without a pass over the whole cache.
+    selected: Vec<usize>,
 }
 
 impl LineCache {
     pub const fn new() -> Self {
-        Self { lines: vec![] }
+        Self {
+            lines: vec![],
+            annotations: vec![],
+            bytes: 0,
+            evicted: false,
+            carets: vec![],
+            selected: vec![],
+        }
     }
 
-    fn push_opt_line(&mut self, line: Option<Line>) {
-        self.lines.push(line);
+    /// Applies an `update` from core, returning the lines it kept unchanged.
+    ///
+    /// The lines are updated in place, so an update only costs as much as
+    /// the lines it changes, plus a move of those after them if it changes
+    /// how many there are. Typing in a huge file stays cheap.
+    ///
+    /// Malformed ops are logged and skipped, and malformed lines are left
+    /// out of the cache as if invalidated, so a bad update can't take the UI
+    /// down.
+    pub fn apply_update(&mut self, update: &Update) -> Vec<CopiedLines> {
+        if let Some(new) = &update.annotations {
+            self.annotations = parse_annotations(new);
+        }
+        let old_carets = mem::take(&mut self.carets);
+        let old_selected = mem::take(&mut self.selected);
+        let mut copied = vec![];
+        // `self.lines[..done]` are the new lines so far, and the rest the old
+        // lines not yet copied, the first `skipped` of which have been
+        // skipped. The lines `inserted` go in place of the skipped ones.
+        let mut done = 0;
+        let mut skipped = 0;
+        let mut inserted = vec![];
+        // The index in the old cache of the next line to copy or skip.
+        let mut old_ix = 0;
+        for op in &update.ops {
+            match op {
+                Lenient::Ok(UpdateOp::Ins { lines }) => {
+                    inserted.extend(lines.iter().map(parse_line));
+                }
+                &Lenient::Ok(UpdateOp::Copy { n }) => {
+                    done = self.splice(done, skipped, &mut inserted);
+                    skipped = 0;
+                    let kept = n.min(self.lines.len() - done);
+                    copied.push(CopiedLines {
+                        old: old_ix..old_ix + kept,
+                        new_start: done,
+                    });
+                    done += kept;
+                    old_ix += kept;
+                    // Lines past the end of the old cache are missing, just
+                    // as if invalidated.
+                    if kept < n {
+                        warn!("update copies {} lines, but only {} are left", n, kept);
+                        inserted.extend((kept..n).map(|_| None));
+                    }
+                }
+                &Lenient::Ok(UpdateOp::Skip { n }) => {
+                    let n = n.min(self.lines.len() - done - skipped);
+                    skipped += n;
+                    old_ix += n;
+                }
+                &Lenient::Ok(UpdateOp::Invalidate { n }) => {
+                    inserted.extend((0..n).map(|_| None));
+                }
+                Lenient::Malformed(op) => warn!("malformed update op: {}", op),
+            }
+        }
+        done = self.splice(done, skipped, &mut inserted);
+        // Old lines the update neither copied nor skipped are gone too.
+        for line in self.lines.drain(done..).flatten() {
+            self.bytes -= line.size();
+        }
+        move_indexed(&mut self.carets, old_carets, &copied);
+        move_indexed(&mut self.selected, old_selected, &copied);
+        copied
     }
 
-    pub fn apply_update(&mut self, update: &Value) {
-        let old_cache = mem::take(self);
-        let mut old_iter = old_cache.lines.into_iter();
-        for op in update["ops"].as_array().unwrap() {
-            let op_type = &op["op"];
-            if op_type == "ins" {
-                for line in op["lines"].as_array().unwrap() {
-                    let line = Line::from_json(line);
-                    self.push_opt_line(Some(line));
+    /// Puts the lines `inserted` in place of `skipped` lines at `at`,
+    /// returning the index after them.
+    fn splice(&mut self, at: usize, skipped: usize, inserted: &mut Vec<Option<Line>>) -> usize {
+        for (i, line) in inserted.iter().enumerate() {
+            if let Some(line) = line {
+                self.bytes += line.size();
+                if !line.cursor.is_empty() {
+                    self.carets.push(at + i);
                 }
-            } else if op_type == "copy" {
-                let n = op["n"].as_u64().unwrap();
-                for _ in 0..n {
-                    self.push_opt_line(old_iter.next().unwrap_or_default());
+                if line.is_selected() {
+                    self.selected.push(at + i);
                 }
-            } else if op_type == "skip" {
-                let n = op["n"].as_u64().unwrap().try_into().unwrap();
-                let _skip = old_iter.nth(n);
-            } else if op_type == "invalidate" {
-                let n = op["n"].as_u64().unwrap();
-                self.lines.extend((0..n).map(|_| None));
             }
         }
+        let end = at + inserted.len();
+        for line in self
+            .lines
+            .splice(at..at + skipped, inserted.drain(..))
+            .flatten()
+        {
+            self.bytes -= line.size();
+        }
+        end
     }
 
+    /// The annotated ranges of a line, in utf-16 code units. A range which
+    /// runs on to the next line includes the newline.
+    pub fn annotations_on(&self, line_num: usize) -> Vec<(AnnotationKind, Range<usize>)> {
+        let Some(line) = self.get_line(line_num) else {
+            return vec![];
+        };
+        let text = line.text();
+        // Columns inside a character are moved to its start.
+        let utf16_col = |col: usize| utf8_to_utf16(text, col);
+        self.annotations
+            .iter()
+            .filter(|a| a.start.0 <= line_num && line_num <= a.end.0)
+            .map(|a| {
+                let start = if a.start.0 == line_num {
+                    utf16_col(a.start.1)
+                } else {
+                    0
+                };
+                let end = if a.end.0 == line_num {
+                    utf16_col(a.end.1)
+                } else {
+                    count_utf16(text)
+                };
+                (a.kind, start..end)
+            })
+            .filter(|(_, range)| range.start < range.end)
+            .collect()
+    }
+
+    /// Roughly the memory the loaded lines take up, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Drops loaded lines, those farthest from `keep` first, until the cache
+    /// takes up no more than `limit` bytes. The lines in `keep` are never
+    /// dropped. Returns whether any were.
+    pub fn evict(&mut self, keep: &Range<usize>, limit: usize) -> bool {
+        let start = keep.start.min(self.lines.len());
+        let end = keep.end.clamp(start, self.lines.len());
+        let (mut below, mut above) = (0, self.lines.len());
+        let mut evicted = false;
+        while self.bytes > limit && (below < start || above > end) {
+            let ix = if start - below >= above - end {
+                below += 1;
+                below - 1
+            } else {
+                above -= 1;
+                above
+            };
+            if let Some(line) = self.lines[ix].take() {
+                self.bytes -= line.size();
+                self.carets.retain(|&caret| caret != ix);
+                self.selected.retain(|&selected| selected != ix);
+                evicted = true;
+            }
+        }
+        self.evicted |= evicted;
+        evicted
+    }
+
+    /// The span of `lines` from the first to the last which isn't loaded,
+    /// if lines have ever been evicted. Core sends the lines of the
+    /// viewport it hasn't sent before, but evicted ones need asking for.
+    pub fn evicted_in(&self, lines: Range<usize>) -> Option<Range<usize>> {
+        if !self.evicted {
+            return None;
+        }
+        let end = lines.end.min(self.lines.len());
+        let missing = |&ix: &usize| self.lines[ix].is_none();
+        let first = (lines.start..end).find(missing)?;
+        let last = (first..end).rfind(missing)?;
+        Some(first..last + 1)
+    }
+
+    /// The number of lines core has told us about, loaded or not.
     pub fn height(&self) -> usize {
         self.lines.len()
     }
@@ -115,18 +390,492 @@ impl LineCache {
     pub fn get_line(&self, i: usize) -> Option<&Line> {
         self.lines.get(i).and_then(Option::as_ref)
     }
+
+    pub fn line_state(&self, i: usize) -> LineState<'_> {
+        match self.lines.get(i) {
+            Some(Some(line)) => LineState::Loaded(line),
+            Some(None) => LineState::Invalid,
+            None if i == self.height() && self.ends_with_newline() == Some(true) => {
+                LineState::FinalEmpty
+            }
+            None => LineState::PastEnd,
+        }
+    }
+
+    /// Whether the document ends with a line break, or `None` if its last
+    /// line isn't loaded.
+    pub fn ends_with_newline(&self) -> Option<bool> {
+        let last = self.lines.last()?.as_ref()?;
+        Some(last.text.ends_with('\n'))
+    }
+
+    /// The number of lines the document shows, counting the empty line after
+    /// a final line break if core left it out.
+    pub fn total_lines(&self) -> usize {
+        if self.ends_with_newline() == Some(true) {
+            self.height() + 1
+        } else {
+            self.height()
+        }
+    }
+
+    /// The position of the end of the document, as a line and utf-8 offset,
+    /// or `None` if its last line isn't loaded.
+    pub fn end_position(&self) -> Option<(usize, usize)> {
+        let last = self.lines.last()?.as_ref()?;
+        if last.text.ends_with('\n') {
+            Some((self.height(), 0))
+        } else {
+            Some((self.height() - 1, last.text.len()))
+        }
+    }
+
+    /// The line of the first caret, if any of the loaded lines has one.
+    pub fn first_caret_line(&self) -> Option<usize> {
+        self.carets.first().copied()
+    }
+
+    /// The loaded lines with carets or selected text, in order.
+    pub fn selected_lines(&self) -> &[usize] {
+        &self.selected
+    }
+
+    /// Guesses the indentation style from the leading whitespace of the
+    /// loaded lines, or returns `None` if none of them are indented.
+    pub fn detect_indentation(&self) -> Option<IndentStyle> {
+        let mut tab_lines = 0;
+        let mut space_lines = 0;
+        // How often each change in indentation between consecutive
+        // space-indented lines occurs, which is a good guess at the width.
+        let mut deltas = [0_usize; 9];
+        let mut prev_indent = 0;
+        for line in self.lines.iter().flatten() {
+            let text = line.text();
+            if text.trim().is_empty() {
+                continue;
+            }
+            if text.starts_with('\t') {
+                tab_lines += 1;
+                continue;
+            }
+            let indent = text.len() - text.trim_start_matches(' ').len();
+            if indent > 0 {
+                space_lines += 1;
+            }
+            if let Some(count) = deltas.get_mut(indent.abs_diff(prev_indent)) {
+                *count += 1;
+            }
+            prev_indent = indent;
+        }
+        if tab_lines == 0 && space_lines == 0 {
+            None
+        } else if tab_lines > space_lines {
+            Some(IndentStyle::Tabs)
+        } else {
+            // On a tie, prefer the narrower width.
+            let width = (2..deltas.len())
+                .rev()
+                .max_by_key(|&width| deltas[width])
+                .filter(|&width| deltas[width] > 0)
+                .unwrap_or(4);
+            Some(IndentStyle::Spaces(width))
+        }
+    }
+}
+
+/// Adds the lines of an index from before an update which it kept to the
+/// index of the lines it inserted, at their new places.
+fn move_indexed(index: &mut Vec<usize>, old: Vec<usize>, copied: &[CopiedLines]) {
+    index.extend(
+        old.into_iter()
+            .filter_map(|ix| copied.iter().find_map(|run| run.new_index(ix))),
+    );
+    index.sort_unstable();
+}
+
+/// Converts a line from an update, or logs why it can't be.
+fn parse_line(line: &Lenient<LineUpdate>) -> Option<Line> {
+    match line {
+        Lenient::Ok(line) => Line::from_update(line)
+            .map_err(|err| warn!("malformed line in update: {}", err))
+            .ok(),
+        Lenient::Malformed(line) => {
+            warn!("malformed line in update: {}", line);
+            None
+        }
+    }
 }
 
-/// Counts the number of utf-16 code units in the given string.
-fn count_utf16(s: &str) -> usize {
-    let mut utf16_count = 0;
-    for &b in s.as_bytes() {
-        if b as i8 >= -0x40 {
-            utf16_count += 1;
+/// Reads the annotations of an update, skipping types not drawn.
+fn parse_annotations(annotations: &[Lenient<AnnotationUpdate>]) -> Vec<Annotation> {
+    let mut parsed = vec![];
+    for annotation in annotations {
+        let annotation = match annotation {
+            Lenient::Ok(annotation) => annotation,
+            Lenient::Malformed(annotation) => {
+                warn!("malformed annotation in update: {}", annotation);
+                continue;
+            }
+        };
+        let kind = match annotation.kind.as_str() {
+            "selection" => AnnotationKind::Selection,
+            "find" => AnnotationKind::Find,
+            _ => continue,
+        };
+        parsed.extend(annotation.ranges.iter().map(
+            |&[start_line, start_col, end_line, end_col]| Annotation {
+                kind,
+                start: (start_line, start_col),
+                end: (end_line, end_col),
+            },
+        ));
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnnotationKind, IndentStyle, Line, LineCache, LineState, UpdateError};
+    use crate::protocol::{Lenient, LineUpdate, Update, UpdateOp};
+    use serde_json::{from_value, json, Value};
+
+    fn texts(cache: &LineCache) -> Vec<Option<&str>> {
+        (0..cache.height())
+            .map(|i| cache.get_line(i).map(Line::text))
+            .collect()
+    }
+
+    fn update(json: Value) -> Update {
+        from_value(json).unwrap()
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        for line in [
+            json!({}),
+            json!({ "text": 5 }),
+            json!({ "text": "ab", "cursor": ["1"] }),
+            json!({ "text": "ab", "cursor": [-1] }),
+        ] {
+            assert!(from_value::<LineUpdate>(line.clone()).is_err(), "{line}");
         }
-        if b >= 0xf0 {
-            utf16_count += 1;
+        let cases = [
+            (
+                json!({ "text": "ab", "cursor": [3] }),
+                UpdateError::BadOffset(3),
+            ),
+            // Inside the two bytes of "é".
+            (
+                json!({ "text": "é", "cursor": [1] }),
+                UpdateError::BadOffset(1),
+            ),
+            (
+                json!({ "text": "ab", "styles": [0, 1] }),
+                UpdateError::BadField("styles"),
+            ),
+            (
+                json!({ "text": "ab", "styles": [0, 1, -2] }),
+                UpdateError::BadField("styles"),
+            ),
+            (
+                json!({ "text": "ab", "styles": [-1, 1, 2] }),
+                UpdateError::BadOffset(-1),
+            ),
+            (
+                json!({ "text": "ab", "styles": [1, 5, 2] }),
+                UpdateError::BadOffset(6),
+            ),
+        ];
+        for (line, err) in cases {
+            let line_update: LineUpdate = from_value(line.clone()).unwrap();
+            assert_eq!(Line::from_update(&line_update).unwrap_err(), err, "{line}");
         }
     }
-    utf16_count
+
+    #[test]
+    fn malformed_ops_are_skipped() {
+        let mut cache = LineCache::new();
+        cache.apply_update(&update(json!({ "ops": [
+            { "op": "ins", "lines": [{ "text": "a\n" }, { "text": "b\n" }] },
+        ]})));
+        cache.apply_update(&update(json!({ "ops": [
+            { "op": "copy" },
+            { "op": "bogus", "n": 1 },
+            { "op": "ins", "lines": [{ "text": 1 }, { "text": "c\n" }] },
+            { "op": "copy", "n": 3 },
+        ]})));
+        assert_eq!(
+            texts(&cache),
+            [None, Some("c\n"), Some("a\n"), Some("b\n"), None]
+        );
+    }
+
+    #[test]
+    fn eviction_drops_the_farthest_lines_first() {
+        let mut cache = LineCache::new();
+        let lines: Vec<Value> = (0..10)
+            .map(|i| json!({ "text": format!("{i}\n") }))
+            .collect();
+        cache.apply_update(&update(json!({ "ops": [{ "op": "ins", "lines": lines }] })));
+        let line_size = cache.get_line(0).unwrap().size();
+        assert_eq!(cache.bytes(), 10 * line_size);
+        assert_eq!(cache.evicted_in(0..10), None);
+
+        assert!(cache.evict(&(4..6), 5 * line_size));
+        assert_eq!(cache.bytes(), 5 * line_size);
+        let loaded: Vec<bool> = (0..10).map(|i| cache.get_line(i).is_some()).collect();
+        assert_eq!(
+            loaded,
+            [false, false, false, true, true, true, true, true, false, false]
+        );
+        assert_eq!(cache.evicted_in(0..10), Some(0..10));
+        assert_eq!(cache.evicted_in(3..8), None);
+
+        // The lines kept are never dropped, whatever the limit.
+        cache.evict(&(4..6), 0);
+        assert_eq!(cache.bytes(), 2 * line_size);
+        assert!(cache.get_line(4).is_some() && cache.get_line(5).is_some());
+
+        // Copied lines keep counting, and the cache still knows to ask.
+        cache.apply_update(&update(json!({ "ops": [{ "op": "copy", "n": 10 }] })));
+        assert_eq!(cache.bytes(), 2 * line_size);
+        assert_eq!(cache.evicted_in(5..7), Some(6..7));
+    }
+
+    fn line_update(text: String, cursor: Vec<usize>) -> Lenient<LineUpdate> {
+        Lenient::Ok(LineUpdate {
+            text,
+            cursor,
+            styles: vec![],
+        })
+    }
+
+    /// An update changing one line, as typing on it does.
+    fn edit_line(line_num: usize, total: usize, text: String, cursor: Vec<usize>) -> Update {
+        Update {
+            ops: vec![
+                Lenient::Ok(UpdateOp::Copy { n: line_num }),
+                Lenient::Ok(UpdateOp::Ins {
+                    lines: vec![line_update(text, cursor)],
+                }),
+                Lenient::Ok(UpdateOp::Skip { n: 1 }),
+                Lenient::Ok(UpdateOp::Copy {
+                    n: total - line_num - 1,
+                }),
+            ],
+            pristine: None,
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn a_million_lines_stay_cheap_to_edit() {
+        const TOTAL: usize = 1_000_000;
+        let mut cache = LineCache::new();
+        let lines = (0..TOTAL)
+            .map(|i| line_update(format!("line {i}\n"), vec![]))
+            .collect();
+        cache.apply_update(&Update {
+            ops: vec![Lenient::Ok(UpdateOp::Ins { lines })],
+            pristine: None,
+            annotations: None,
+        });
+        assert_eq!(cache.total_lines(), TOTAL + 1);
+        assert_eq!(cache.first_caret_line(), None);
+
+        // Typing along a line far down, each keystroke one update. These
+        // neither move nor look at the other lines; how long they take is
+        // measured by the `keystroke_in_a_million_lines` benchmark.
+        let line_num = 765_432;
+        let mut text = String::new();
+        for ch in "typing in a huge file".chars().cycle().take(1000) {
+            text.push(ch);
+            let update = edit_line(line_num, TOTAL, format!("{text}\n"), vec![text.len()]);
+            let copied = cache.apply_update(&update);
+            assert_eq!(copied.len(), 2);
+            assert_eq!(copied[1].new_index(line_num + 1), Some(line_num + 1));
+        }
+        assert_eq!(cache.height(), TOTAL);
+        assert_eq!(cache.first_caret_line(), Some(line_num));
+        // The selections are read from the one line that has any.
+        assert_eq!(cache.selected_lines(), [line_num]);
+        assert_eq!(
+            cache.get_line(line_num).unwrap().text(),
+            format!("{text}\n")
+        );
+        assert_eq!(
+            cache.get_line(line_num + 1).unwrap().text(),
+            "line 765433\n"
+        );
+        let bytes: usize = (0..TOTAL)
+            .filter_map(|i| cache.get_line(i))
+            .map(Line::size)
+            .sum();
+        assert_eq!(cache.bytes(), bytes);
+
+        // A new line shifts those after it down by one.
+        let update = Update {
+            ops: vec![
+                Lenient::Ok(UpdateOp::Copy { n: 10 }),
+                Lenient::Ok(UpdateOp::Ins {
+                    lines: vec![line_update("new\n".to_owned(), vec![0])],
+                }),
+                Lenient::Ok(UpdateOp::Copy { n: TOTAL - 10 }),
+            ],
+            pristine: None,
+            annotations: None,
+        };
+        cache.apply_update(&update);
+        assert_eq!(cache.height(), TOTAL + 1);
+        assert_eq!(cache.first_caret_line(), Some(10));
+        assert_eq!(cache.selected_lines(), [10, line_num + 1]);
+        assert_eq!(
+            cache.get_line(line_num + 1).unwrap().text(),
+            format!("{text}\n")
+        );
+
+        // Eviction brings the cache back down without touching the lines
+        // kept.
+        let keep = line_num..line_num + 100;
+        assert!(cache.evict(&keep, 1 << 20));
+        assert!(cache.bytes() <= 1 << 20);
+        assert!(keep.clone().all(|i| cache.get_line(i).is_some()));
+        assert_eq!(cache.first_caret_line(), Some(line_num + 1));
+        assert_eq!(cache.selected_lines(), [line_num + 1]);
+    }
+
+    #[test]
+    fn selected_lines_are_indexed() {
+        let mut cache = LineCache::new();
+        // A selection from the middle of line 1 to the caret on line 3.
+        cache.apply_update(&update(json!({ "ops": [{ "op": "ins", "lines": [
+            { "text": "a\n" },
+            { "text": "bcd\n", "styles": [1, 3, 0] },
+            { "text": "e\n", "styles": [0, 2, 0] },
+            { "text": "fg\n", "cursor": [1], "styles": [0, 1, 0] },
+            { "text": "h\n" },
+        ]}]})));
+        assert_eq!(cache.selected_lines(), [1, 2, 3]);
+        assert_eq!(cache.first_caret_line(), Some(3));
+
+        // The selection collapses to the caret, after a new first line.
+        cache.apply_update(&update(json!({ "ops": [
+            { "op": "ins", "lines": [{ "text": "z\n" }] },
+            { "op": "copy", "n": 1 },
+            { "op": "ins", "lines": [{ "text": "bcd\n" }, { "text": "e\n" }] },
+            { "op": "skip", "n": 2 },
+            { "op": "copy", "n": 2 },
+        ]})));
+        assert_eq!(cache.selected_lines(), [4]);
+        assert_eq!(cache.first_caret_line(), Some(4));
+    }
+
+    fn detect(text: &[&str]) -> Option<IndentStyle> {
+        let lines: Vec<Value> = text.iter().map(|t| json!({ "text": t })).collect();
+        let mut cache = LineCache::new();
+        cache.apply_update(&update(json!({ "ops": [{ "op": "ins", "lines": lines }] })));
+        cache.detect_indentation()
+    }
+
+    #[test]
+    fn indentation_is_detected() {
+        let tabs = ["fn f() {\n", "\tif x {\n", "\t\ty();\n", "\t}\n", "}\n"];
+        assert_eq!(detect(&tabs), Some(IndentStyle::Tabs));
+        let two = ["a:\n", "  b:\n", "    c: 1\n", "\n", "  d: 2\n"];
+        assert_eq!(detect(&two), Some(IndentStyle::Spaces(2)));
+        let four = [
+            "fn f() {\n",
+            "    if x {\n",
+            "        y();\n",
+            "    }\n",
+            "}\n",
+        ];
+        assert_eq!(detect(&four), Some(IndentStyle::Spaces(4)));
+        assert_eq!(detect(&["a\n", "\n", "b\n"]), None);
+    }
+
+    #[test]
+    fn mixed_indentation_goes_with_the_majority() {
+        let mostly_tabs = ["a {\n", "\tb\n", "\tc\n", "    d\n", "}\n"];
+        assert_eq!(detect(&mostly_tabs), Some(IndentStyle::Tabs));
+        let mostly_spaces = ["a {\n", "    b\n", "\tc\n", "    d\n", "}\n"];
+        assert_eq!(detect(&mostly_spaces), Some(IndentStyle::Spaces(4)));
+        // A tie between the widths picks the narrower one.
+        let both = ["a\n", "  b\n", "c\n", "    d\n", "e\n"];
+        assert_eq!(detect(&both), Some(IndentStyle::Spaces(2)));
+    }
+
+    #[test]
+    fn junk_never_panics() {
+        let junk = [
+            Value::Null,
+            json!([]),
+            json!({ "ops": 1 }),
+            json!({ "ops": [null, 1, "copy", []] }),
+            json!({ "ops": [{ "op": "skip", "n": u64::MAX }, { "op": "copy", "n": -1 }] }),
+            json!({ "ops": [{ "op": "ins", "lines": [{ "text": "x", "styles": [0, i64::MAX, 0] }] }] }),
+            json!({ "ops": [{ "op": "ins", "lines": [{ "text": "x", "styles": [i64::MAX, 1, 0] }] }] }),
+        ];
+        for json in junk {
+            // Updates which aren't even shaped like one never reach the cache.
+            if let Ok(update) = from_value::<Update>(json) {
+                LineCache::new().apply_update(&update);
+            }
+        }
+    }
+
+    #[test]
+    fn final_line_break_ends_with_an_empty_line() {
+        let mut cache = LineCache::new();
+        cache.apply_update(&update(json!({ "ops": [
+            { "op": "invalidate", "n": 1 },
+            { "op": "ins", "lines": [{ "text": "ab\n" }] },
+        ]})));
+        assert_eq!(cache.total_lines(), 3);
+        assert_eq!(cache.end_position(), Some((2, 0)));
+        assert!(matches!(cache.line_state(0), LineState::Invalid));
+        assert!(matches!(cache.line_state(2), LineState::FinalEmpty));
+        assert!(matches!(cache.line_state(3), LineState::PastEnd));
+
+        // A cache which has the final empty line doesn't count it twice.
+        cache.apply_update(&update(json!({ "ops": [
+            { "op": "copy", "n": 2 },
+            { "op": "ins", "lines": [{ "text": "" }] },
+        ]})));
+        assert_eq!(cache.total_lines(), 3);
+        assert_eq!(cache.end_position(), Some((2, 0)));
+        assert!(matches!(cache.line_state(3), LineState::PastEnd));
+
+        cache.apply_update(&update(json!({ "ops": [
+            { "op": "copy", "n": 1 },
+            { "op": "ins", "lines": [{ "text": "cd" }] },
+        ]})));
+        assert_eq!(cache.total_lines(), 2);
+        assert_eq!(cache.end_position(), Some((1, 2)));
+    }
+
+    #[test]
+    fn annotations_are_split_by_line() {
+        let mut cache = LineCache::new();
+        cache.apply_update(&update(json!({
+            "ops": [{ "op": "ins", "lines": [{ "text": "héllo\n" }, { "text": "world" }] }],
+            "annotations": [
+                { "type": "selection", "ranges": [[0, 3, 1, 2]] },
+                { "type": "find", "ranges": [[1, 0, 1, 5], [1, 4, 1, 4]] },
+                { "type": "plugin", "ranges": [[0, 0, 0, 1]] },
+            ],
+        })));
+        // "é" is two bytes, but one utf-16 code unit.
+        assert_eq!(cache.annotations_on(0), [(AnnotationKind::Selection, 2..6)]);
+        assert_eq!(
+            cache.annotations_on(1),
+            [
+                (AnnotationKind::Selection, 0..2),
+                (AnnotationKind::Find, 0..5)
+            ]
+        );
+        // Updates without annotations keep the last ones.
+        cache.apply_update(&update(json!({ "ops": [{ "op": "copy", "n": 2 }] })));
+        assert_eq!(cache.annotations_on(1).len(), 2);
+    }
 }
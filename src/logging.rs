@@ -0,0 +1,219 @@
+//! Logging to a file, as the app has no console for `println!`.
+//!
+//! Logs go to `%LOCALAPPDATA%\xi-win\logs\xi-win.log`, which is rotated at
+//! startup and whenever it grows too big, keeping a few old ones around. The
+//! level can be set like `env_logger`'s, with `XI_WIN_LOG=debug`. A console
+//! showing the log as it is written can be opened from the View menu.
+//!
+//! Launched from a terminal, the log and stdout and stderr are shown in it.
+//! Otherwise stdout and stderr go to `stdout.log` beside the logs.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::windows::io::IntoRawHandle;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use winapi::shared::minwindef::{FALSE, UINT};
+use winapi::um::consoleapi::AllocConsole;
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::processenv::{GetStdHandle, SetStdHandle};
+use winapi::um::winbase::{STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+use winapi::um::wincon::{AttachConsole, FreeConsole, GetConsoleWindow, ATTACH_PARENT_PROCESS};
+use winapi::um::winuser::{DeleteMenu, GetSystemMenu, MF_BYCOMMAND, SC_CLOSE};
+
+/// The size a log file is rotated at, in bytes.
+const MAX_LOG_SIZE: u64 = 1 << 20;
+/// How many rotated logs are kept, besides the current one.
+const KEEP_LOGS: usize = 4;
+const LOG_NAME: &str = "xi-win";
+/// Where stdout and stderr go, in the log directory.
+const STD_NAME: &str = "stdout.log";
+
+/// Whether the debug console is open.
+static CONSOLE: AtomicBool = AtomicBool::new(false);
+
+struct FileLogger {
+    dir: PathBuf,
+    file: Mutex<Option<LogFile>>,
+}
+
+struct LogFile {
+    file: File,
+    /// The bytes written so far, to know when to rotate.
+    len: u64,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let line = format!(
+            "{secs:.3} {:<5} {}: {}\n",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if CONSOLE.load(Ordering::Relaxed) {
+            eprint!("{line}");
+        }
+        let mut file = self.file.lock().unwrap();
+        if file.as_ref().map_or(false, |f| f.len >= MAX_LOG_SIZE) {
+            *file = open_log(&self.dir);
+        }
+        if let Some(log_file) = file.as_mut() {
+            if log_file.file.write_all(line.as_bytes()).is_ok() {
+                log_file.len += line.len() as u64;
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(log_file) = self.file.lock().unwrap().as_mut() {
+            let _ = log_file.file.flush();
+        }
+    }
+}
+
+/// Starts logging, and logs panics before they take the app down. Without
+/// a usable log directory, only the debug console shows the log.
+pub fn init() {
+    let level = env::var("XI_WIN_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::Info);
+    let dir = env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join("xi-win")
+        .join("logs");
+    attach_std(&dir);
+    let file = open_log(&dir);
+    let logger = FileLogger {
+        dir,
+        file: Mutex::new(file),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_err() {
+        return;
+    }
+    log::set_max_level(level);
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        error!("{}", info);
+        log::logger().flush();
+        default_hook(info);
+    }));
+}
+
+/// Gives stdout and stderr somewhere to go, as a windowed app starts without
+/// them and what's printed to them, such as by panics, is otherwise lost.
+/// Launched from a terminal, they go to it, as does the log. Otherwise they
+/// go to a file beside the logs. Handles redirected at launch are left be.
+fn attach_std(dir: &Path) {
+    // SAFETY: These only affect the process's standard handles and console,
+    // which are set up before anything else uses them.
+    unsafe {
+        let stderr = GetStdHandle(STD_ERROR_HANDLE);
+        if !stderr.is_null() && stderr != INVALID_HANDLE_VALUE {
+            return;
+        }
+        // NOTE: The terminal's prompt doesn't wait for the app, so what's
+        // printed is interleaved with whatever is typed next.
+        if AttachConsole(ATTACH_PARENT_PROCESS) != 0 {
+            CONSOLE.store(true, Ordering::Relaxed);
+            return;
+        }
+        let file = fs::create_dir_all(dir).and_then(|()| File::create(dir.join(STD_NAME)));
+        if let Ok(file) = file {
+            let handle = file.into_raw_handle();
+            SetStdHandle(STD_OUTPUT_HANDLE, handle.cast());
+            SetStdHandle(STD_ERROR_HANDLE, handle.cast());
+        }
+    }
+}
+
+/// Opens or closes the console showing the log.
+pub fn set_console(show: bool) {
+    if CONSOLE.load(Ordering::Relaxed) == show {
+        return;
+    }
+    // SAFETY: These only affect the process's console, which nothing else
+    // uses.
+    let ok = unsafe {
+        if show {
+            let ok = AllocConsole() != 0;
+            // Closing a console ends its process, so it's closed from the
+            // menu instead.
+            if ok {
+                let menu = GetSystemMenu(GetConsoleWindow(), FALSE);
+                DeleteMenu(menu, SC_CLOSE as UINT, MF_BYCOMMAND);
+            }
+            ok
+        } else {
+            FreeConsole() != 0
+        }
+    };
+    if ok {
+        CONSOLE.store(show, Ordering::Relaxed);
+    }
+}
+
+pub fn console_shown() -> bool {
+    CONSOLE.load(Ordering::Relaxed)
+}
+
+/// Rotates the logs in `dir` and opens a fresh one.
+fn open_log(dir: &Path) -> Option<LogFile> {
+    let result = fs::create_dir_all(dir).and_then(|()| {
+        rotate(dir)?;
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(log_path(dir, 0))
+    });
+    match result {
+        Ok(file) => Some(LogFile { file, len: 0 }),
+        Err(err) => {
+            // Nowhere else to say so.
+            if CONSOLE.load(Ordering::Relaxed) {
+                eprintln!("Couldn't open a log in {}: {}", dir.display(), err);
+            }
+            None
+        }
+    }
+}
+
+/// Shifts `xi-win.log` to `xi-win.1.log` and so on, dropping the oldest.
+fn rotate(dir: &Path) -> io::Result<()> {
+    for n in (0..KEEP_LOGS).rev() {
+        let from = log_path(dir, n);
+        if from.exists() {
+            fs::rename(&from, log_path(dir, n + 1))?;
+        }
+    }
+    Ok(())
+}
+
+fn log_path(dir: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        dir.join(format!("{LOG_NAME}.log"))
+    } else {
+        dir.join(format!("{LOG_NAME}.{n}.log"))
+    }
+}
@@ -1,9 +1,8 @@
 //! The main module for the xi editor front end.
 
-// NOTE: This disables stdout, so no println :(
-// TODO(Olive): If we checked what GetStdHandle returns for stdout and see
-// that it is an invalid handle (either -1 or 0), then we can open up
-// up a file to log stdout and SetStdHandle.
+// NOTE: This starts the app without stdout, so diagnostics go through `log`
+// to a file, and stdout and stderr are given a terminal or file at startup.
+// See the `logging` module.
 #![windows_subsystem = "windows"]
 #![allow(
     clippy::cast_possible_truncation,
@@ -22,6 +21,8 @@ extern crate winapi;
 extern crate serde;
 #[macro_use]
 extern crate serde_json;
+#[macro_use]
+extern crate log;
 
 extern crate xi_core_lib;
 extern crate xi_rpc;
@@ -29,28 +30,96 @@ extern crate xi_rpc;
 extern crate druid_win_shell;
 extern crate druid;
 
+mod auto_save;
+mod caret_blink;
+mod close_prompt;
+mod color_scheme;
+mod dpi;
 mod edit_view;
+mod encoding;
+mod expand_selection;
+mod file_dialog;
+mod file_stamp;
+mod find_view;
+mod ime;
+mod jump_list;
+mod keymap;
 mod linecache;
+mod logging;
 mod menus;
+mod metrics;
+#[cfg(test)]
+mod mock_core;
+mod modal;
+mod offsets;
+mod plugins;
+mod preferences;
+mod prelayout;
+mod protocol;
 mod rpc;
+mod rpc_trace;
+mod session;
+mod single_instance;
+mod status_bar;
+mod styles;
+mod subclass;
+mod system_theme;
+mod tab_bar;
+mod text_rendering;
 mod textline;
+mod view_model;
+mod xi_config;
 mod xi_thread;
 
+use std::cmp::{max, min};
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use serde_json::Value;
 
-use crate::edit_view::EditView;
+use winapi::shared::minwindef::{UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::winuser::{
+    GetActiveWindow, MessageBoxW, PostMessageW, ShowWindow, IDNO, IDYES, MB_ICONERROR,
+    MB_ICONINFORMATION, MB_ICONWARNING, MB_OK, MB_YESNO, MB_YESNOCANCEL, SW_MAXIMIZE, WM_CLOSE,
+    WM_COMMAND,
+};
+
+use crate::auto_save::AutoSaveSettings;
+use crate::color_scheme::{ColorScheme, ThemeMode};
+use crate::edit_view::{EditView, EditViewEvent, SelectionTransform};
+use crate::encoding::{Encoding, LineEnding};
+use crate::file_dialog::{
+    extension_for_language, to_wide, Choice, DialogKind, DialogOptions, OPEN_FILTERS, SAVE_FILTERS,
+};
+use crate::file_stamp::FileStamp;
+use crate::find_view::{FindBar, FindBarCommands};
+use crate::keymap::Keymap;
 use crate::menus::MenuEntries;
+use crate::plugins::{find_formatter, PluginCommand};
+use crate::preferences::Preferences;
+use crate::prelayout::Prelayout;
+use crate::protocol::{Lenient, Notification};
 use crate::rpc::{Core, Handler};
+use crate::session::{Session, SessionFile};
+use crate::status_bar::{StatusBar, StatusBarCommands, StatusBarEvent};
+use crate::styles::{Style, StyleMap};
+use crate::tab_bar::{Tab, TabBar, TabBarCommands, TabEvent};
+use crate::xi_config;
 use crate::xi_thread::start_xi_thread;
 
 use druid_win_shell::win_main::{self};
 use druid_win_shell::window::{Cursor, IdleHandle, WindowBuilder};
 
+use druid::widget::Column;
 use druid::Id;
-use druid::{FileDialogOptions, FileDialogType};
 use druid::{UiMain, UiState};
 
 use std::fmt;
@@ -59,11 +128,56 @@ use crate::edit_view::EditViewCommands;
 
 type ViewId = String;
 
+/// How often open files are checked for changes made by other programs.
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How often the auto-save timer checks whether a save is due.
+const AUTO_SAVE_TICK: Duration = Duration::from_secs(1);
+
+/// The ids of the widgets in the main window.
+#[derive(Clone, Copy, Debug, Default)]
+struct Widgets {
+    /// The widget showing the focused view.
+    edit_view: Id,
+    tab_bar: Id,
+    find_bar: Id,
+    status_bar: Id,
+}
+
 #[derive(Clone)]
 struct ViewState {
     id: Id,
     filename: Option<String>,
     handle: IdleHandle,
+    /// Pinned views are kept first in the display order and are skipped by
+    /// middle-click closes.
+    pinned: bool,
+    /// The format to rewrite the file in after core saves it, if it should
+    /// differ from core's utf-8.
+    save_format: Option<(Encoding, LineEnding)>,
+    /// The line ending of the file when it was opened.
+    line_ending: Option<LineEnding>,
+    /// The stamp of the file when it was loaded or last saved.
+    file_stamp: Option<FileStamp>,
+    /// The stamp the file had before a save sent to core, until core is
+    /// seen to have written it. `Some(None)` if there was no file.
+    saving: Option<Option<FileStamp>>,
+    /// Whether the buffer has no unsaved edits.
+    pristine: bool,
+    /// The scroll offset in px, as last reported by the edit view.
+    scroll_offset: f64,
+}
+
+impl ViewState {
+    /// The name shown on the view's tab.
+    fn title(&self) -> String {
+        self.filename
+            .as_deref()
+            .and_then(|filename| Path::new(filename).file_name())
+            .map_or_else(
+                || "Untitled".to_owned(),
+                |name| name.to_string_lossy().into_owned(),
+            )
+    }
 }
 
 impl fmt::Debug for ViewState {
@@ -72,6 +186,13 @@ impl fmt::Debug for ViewState {
             .field("id", &self.id)
             .field("filename", &self.filename)
             .field("handle", &"...")
+            .field("pinned", &self.pinned)
+            .field("save_format", &self.save_format)
+            .field("line_ending", &self.line_ending)
+            .field("file_stamp", &self.file_stamp)
+            .field("saving", &self.saving)
+            .field("pristine", &self.pristine)
+            .field("scroll_offset", &self.scroll_offset)
             .finish()
     }
 }
@@ -80,6 +201,47 @@ impl fmt::Debug for ViewState {
 struct AppState {
     focused: Option<ViewId>,
     views: HashMap<ViewId, ViewState>,
+    /// The views in display order, since `views` has no stable ordering.
+    order: Vec<ViewId>,
+    /// The names of the plugins running for each view.
+    plugins: HashMap<ViewId, Vec<String>>,
+    /// The commands of those plugins, from their manifests.
+    plugin_commands: HashMap<ViewId, Vec<PluginCommand>>,
+    /// The language id of each view, as reported by `language_changed`.
+    languages: HashMap<ViewId, String>,
+    /// The directory of the last file opened or saved through a dialog.
+    last_dir: Option<PathBuf>,
+    /// Whether views without unsaved edits are reloaded without asking when
+    /// their file changes on disk.
+    auto_reload: bool,
+    /// Whether the edit view guesses the indentation of the files it opens,
+    /// for the check of the menus.
+    detect_indentation: bool,
+    auto_save: AutoSaveSettings,
+    theme_mode: ThemeMode,
+    /// Whether the Windows app theme is dark.
+    system_dark: bool,
+    /// The themes core offers, from `available_themes`.
+    themes: Vec<String>,
+    /// The languages core offers, from `available_languages`.
+    available_languages: Vec<String>,
+    /// The core theme chosen in place of `theme_mode`, if any.
+    xi_theme: Option<String>,
+    /// The colors of `xi_theme`, once core has sent them.
+    xi_scheme: Option<ColorScheme>,
+    /// The styles core has defined, which every view draws with.
+    styles: StyleMap,
+    /// The main window, as an address since `HWND` isn't `Send`.
+    hwnd: usize,
+    widgets: Widgets,
+    /// The files of the last session which are still opening, by path.
+    restoring: HashMap<String, SessionFile>,
+    /// The path of the view to focus once the last session has reopened.
+    restore_focus: Option<String>,
+    /// The files last opened, most recent first, as shown in the jump list.
+    recent: Vec<String>,
+    /// The settings of the Preferences dialog, as core last sent them.
+    preferences: Preferences,
 }
 
 impl AppState {
@@ -87,6 +249,153 @@ impl AppState {
         Self {
             focused: None,
             views: HashMap::new(),
+            order: vec![],
+            plugins: HashMap::new(),
+            plugin_commands: HashMap::new(),
+            languages: HashMap::new(),
+            last_dir: None,
+            auto_reload: true,
+            detect_indentation: true,
+            auto_save: AutoSaveSettings::default(),
+            theme_mode: ThemeMode::Auto,
+            system_dark: system_theme::is_dark(),
+            themes: vec![],
+            available_languages: vec![],
+            xi_theme: None,
+            xi_scheme: None,
+            styles: StyleMap::new(),
+            hwnd: 0,
+            widgets: Widgets::default(),
+            restoring: HashMap::new(),
+            restore_focus: None,
+            recent: vec![],
+            preferences: Preferences::default(),
+        }
+    }
+
+    fn is_dark(&self) -> bool {
+        match &self.xi_scheme {
+            Some(scheme) => scheme.is_dark(),
+            None => self.theme_mode.is_dark(self.system_dark),
+        }
+    }
+
+    fn color_scheme(&self) -> ColorScheme {
+        if let Some(scheme) = &self.xi_scheme {
+            scheme.clone()
+        } else if self.theme_mode.is_dark(self.system_dark) {
+            ColorScheme::dark()
+        } else {
+            ColorScheme::light()
+        }
+    }
+
+    fn insert_view(&mut self, view_id: ViewId, view_state: ViewState) {
+        if self.views.insert(view_id.clone(), view_state).is_none() {
+            self.order.push(view_id);
+        }
+    }
+
+    /// Forgets a closed view, returning the view to focus in its place, if
+    /// any are left.
+    fn remove_view(&mut self, view_id: &str) -> Option<ViewId> {
+        let i = self.order.iter().position(|id| id == view_id)?;
+        self.order.remove(i);
+        self.views.remove(view_id);
+        self.plugins.remove(view_id);
+        self.plugin_commands.remove(view_id);
+        self.languages.remove(view_id);
+        // The tab after the closed one, or the last tab.
+        self.order.get(i).or_else(|| self.order.last()).cloned()
+    }
+
+    /// Puts a new view in the place of an old one, which is forgotten.
+    fn replace_view(&mut self, old: &str, view_id: ViewId, mut view_state: ViewState) {
+        match self.order.iter().position(|id| id == old) {
+            Some(i) => self.order[i] = view_id.clone(),
+            None => self.order.push(view_id.clone()),
+        }
+        if let Some(old_state) = self.views.remove(old) {
+            view_state.pinned = old_state.pinned;
+        }
+        self.plugins.remove(old);
+        self.plugin_commands.remove(old);
+        self.languages.remove(old);
+        self.views.insert(view_id, view_state);
+    }
+
+    /// The number of pinned views, which make up the front of `order`.
+    fn pinned_count(&self) -> usize {
+        self.order
+            .iter()
+            .take_while(|view_id| self.views[*view_id].pinned)
+            .count()
+    }
+
+    /// Moves the view at display position `from` to position `to`, keeping
+    /// pinned views ahead of unpinned ones.
+    fn move_view(&mut self, from: usize, to: usize) {
+        if from < self.order.len() && to < self.order.len() {
+            let pinned_count = self.pinned_count();
+            let to = if from < pinned_count {
+                min(to, pinned_count - 1)
+            } else {
+                max(to, pinned_count)
+            };
+            let view_id = self.order.remove(from);
+            self.order.insert(to, view_id);
+        }
+    }
+
+    /// Pins or unpins a view, moving it to the edge of the pinned views.
+    fn set_pinned(&mut self, view_id: &str, pinned: bool) {
+        let Some(view_state) = self.views.get(view_id) else {
+            return;
+        };
+        if view_state.pinned == pinned {
+            return;
+        }
+        let from = self.order.iter().position(|id| id == view_id).unwrap();
+        // Pinning moves the view to the end of the pinned views, and
+        // unpinning moves it to the start of the unpinned ones.
+        let to = self.pinned_count() - usize::from(!pinned);
+        self.views.get_mut(view_id).unwrap().pinned = pinned;
+        let view_id = self.order.remove(from);
+        self.order.insert(to, view_id);
+    }
+
+    /// Options for a file dialog, starting in the last used directory or
+    /// else the focused file's directory. Saving suggests an extension for
+    /// the focused view's language.
+    fn dialog_options(&self, kind: DialogKind) -> DialogOptions {
+        let focused_dir = self
+            .focused
+            .as_ref()
+            .and_then(|view_id| self.views.get(view_id))
+            .and_then(|view_state| view_state.filename.as_ref())
+            .and_then(|filename| Path::new(filename).parent())
+            .map(Path::to_path_buf);
+        let (filters, default_extension) = match kind {
+            DialogKind::Open => (OPEN_FILTERS, None),
+            DialogKind::Save => (
+                SAVE_FILTERS,
+                self.focused
+                    .as_ref()
+                    .and_then(|view_id| self.languages.get(view_id))
+                    .and_then(|language| extension_for_language(language)),
+            ),
+        };
+        DialogOptions {
+            folder: self.last_dir.clone().or(focused_dir),
+            filters,
+            default_extension,
+            choices: vec![],
+        }
+    }
+
+    fn remember_dir(&mut self, filename: &str) {
+        if let Some(dir) = Path::new(filename).parent() {
+            self.last_dir = Some(dir.to_path_buf());
         }
     }
 
@@ -102,10 +411,25 @@ impl AppState {
     }
 }
 
-#[derive(Clone, Debug)]
+/// A callback invoked with the new view id when the focused view changes,
+/// or with `None` once no view is left.
+type FocusListener = Box<dyn Fn(Option<&str>) + Send>;
+
+#[derive(Clone)]
 struct App {
     core: Arc<Mutex<Core>>,
     state: Arc<Mutex<AppState>>,
+    focus_listeners: Arc<Mutex<Vec<FocusListener>>>,
+}
+
+impl fmt::Debug for App {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("App")
+            .field("core", &self.core)
+            .field("state", &self.state)
+            .field("focus_listeners", &"...")
+            .finish()
+    }
 }
 
 impl App {
@@ -113,6 +437,7 @@ impl App {
         Self {
             core: Arc::new(Mutex::new(core)),
             state: Arc::new(Mutex::new(AppState::new())),
+            focus_listeners: Arc::default(),
         }
     }
 
@@ -126,6 +451,679 @@ impl App {
 
         UiMain::send_ext(&focused.handle.clone(), focused.id, cmd);
     }
+
+    /// Sends a command to the edit view holding the given view.
+    fn send_cmd_for_view(&self, view_id: &str, cmd: EditViewCommands) {
+        let state = self.get_state();
+        if let Some(view_state) = state.views.get(view_id) {
+            UiMain::send_ext(&view_state.handle, view_state.id, cmd);
+        }
+    }
+
+    /// Shows the focused view's language and encoding in the status bar.
+    fn update_file_info(&self) {
+        let mut state = self.get_state();
+        let Some(view_id) = state.focused.clone() else {
+            return;
+        };
+        let language = state.languages.get(&view_id).cloned();
+        let index = language.as_ref().and_then(|language| {
+            state
+                .available_languages
+                .iter()
+                .position(|available| available == language)
+        });
+        // SAFETY: `hwnd` is the main window, which lives as long as the app.
+        unsafe {
+            menus::check_language(state.hwnd as HWND, index);
+        }
+        let status_bar = state.widgets.status_bar;
+        let view_state = state.get_focused_viewstate();
+        // Core reads and writes utf-8, and keeps whatever line endings the
+        // file has.
+        let encoding = match (view_state.save_format, view_state.line_ending) {
+            (Some((encoding, line_ending)), _) => {
+                format!("{}  {}", encoding.label(), line_ending.label())
+            }
+            (None, Some(line_ending)) => format!("UTF-8  {}", line_ending.label()),
+            (None, None) => "UTF-8".to_owned(),
+        };
+        let handle = view_state.handle.clone();
+        UiMain::send_ext(
+            &handle,
+            status_bar,
+            StatusBarCommands::SetFileInfo { language, encoding },
+        );
+    }
+
+    /// Converts the focused view's file to the given line ending. An unedited
+    /// file is rewritten and reopened straight away, while one with unsaved
+    /// edits or in another encoding is converted when next saved, as by Save
+    /// with Encoding.
+    fn set_line_ending(&self, line_ending: LineEnding) {
+        let (view_id, view_state) = {
+            let mut state = self.get_state();
+            let Some(view_id) = state.focused.clone() else {
+                return;
+            };
+            (view_id, state.get_focused_viewstate().clone())
+        };
+        if let (Some(filename), None, true) = (
+            &view_state.filename,
+            view_state.save_format,
+            view_state.pristine,
+        ) {
+            let path = Path::new(filename);
+            match encoding::transcode_file(path, Encoding::Utf8, line_ending) {
+                Ok(()) => {
+                    // So the file watcher doesn't take the rewrite for
+                    // another program's.
+                    if let Some(view_state) = self.get_state().views.get_mut(&view_id) {
+                        view_state.file_stamp = FileStamp::read(path);
+                    }
+                    self.req_new_view(Some(filename), view_state.handle, Some(view_id));
+                }
+                Err(err) => {
+                    error!("Failed to convert {}: {}", path.display(), err);
+                    let msg = format!("Couldn't convert {filename}: {err}");
+                    view_state
+                        .handle
+                        .add_idle(move |_| show_message("xi-editor", &msg));
+                }
+            }
+            return;
+        }
+        {
+            let mut state = self.get_state();
+            let view_state = state.get_focused_viewstate();
+            let encoding = view_state
+                .save_format
+                .map_or(Encoding::Utf8, |(encoding, _)| encoding);
+            view_state.save_format = Some((encoding, line_ending));
+        }
+        self.update_file_info();
+    }
+
+    /// Reopens the focused view's file read in the given encoding, once the
+    /// user agrees to lose any unsaved edits.
+    fn reopen_with_encoding(&self, encoding: Encoding) {
+        let (view_id, view_state) = {
+            let mut state = self.get_state();
+            let Some(view_id) = state.focused.clone() else {
+                return;
+            };
+            (view_id, state.get_focused_viewstate().clone())
+        };
+        let Some(filename) = &view_state.filename else {
+            return;
+        };
+        if !view_state.pristine {
+            let text = format!(
+                "Reopen {filename} as {}? Your unsaved changes will be lost.",
+                encoding.label()
+            );
+            if message_box("Reopen", &text, MB_YESNO | MB_ICONWARNING) != IDYES {
+                return;
+            }
+        }
+        self.req_new_view_in(
+            Some(filename),
+            Some(encoding),
+            view_state.handle,
+            Some(view_id),
+        );
+    }
+
+    /// Has the status bar show the caret of a view, if it's the focused one.
+    fn update_status(&self, view_id: &str) {
+        if self.get_state().focused.as_deref() == Some(view_id) {
+            self.send_view_cmd(EditViewCommands::UpdateStatus);
+        }
+    }
+
+    /// Shows a view in the editor and selects its tab, through the focus
+    /// listeners registered in `main`.
+    fn focus_view(&self, view_id: &str) {
+        if self.get_state().views.contains_key(view_id) {
+            self.set_focused(Some(view_id));
+        }
+    }
+
+    /// Focuses the next view in display order, or the previous one, wrapping
+    /// around at the ends.
+    fn cycle_focus(&self, forward: bool) {
+        let next = {
+            let state = self.get_state();
+            let Some(i) = state
+                .focused
+                .as_ref()
+                .and_then(|focused| state.order.iter().position(|id| id == focused))
+            else {
+                return;
+            };
+            let n = state.order.len();
+            state.order[if forward {
+                (i + 1) % n
+            } else {
+                (i + n - 1) % n
+            }]
+            .clone()
+        };
+        self.focus_view(&next);
+    }
+
+    /// Sends the open views, in display order, to the tab bar.
+    fn update_tab_bar(&self) {
+        let state = self.get_state();
+        let Some(handle) = state
+            .focused
+            .as_ref()
+            .and_then(|focused| state.views.get(focused))
+            .map(|view_state| view_state.handle.clone())
+        else {
+            return;
+        };
+        let tabs = state
+            .order
+            .iter()
+            .filter_map(|view_id| {
+                let view_state = state.views.get(view_id)?;
+                Some(Tab {
+                    view_id: view_id.clone(),
+                    title: view_state.title(),
+                    dirty: !view_state.pristine,
+                    pinned: view_state.pinned,
+                })
+            })
+            .collect();
+        let active = state
+            .focused
+            .as_ref()
+            .and_then(|focused| state.order.iter().position(|id| id == focused));
+        UiMain::send_ext(
+            &handle,
+            state.widgets.tab_bar,
+            TabBarCommands::SetTabs { tabs, active },
+        );
+    }
+
+    /// Shows a file dialog without blocking the window, then calls `done` on
+    /// the window's thread with the chosen file name and the dialog's
+    /// options. Nothing is called if the user cancels.
+    fn choose_file<F>(&self, kind: DialogKind, options: DialogOptions, done: F)
+    where
+        F: FnOnce(&App, String, DialogOptions) + Send + 'static,
+    {
+        let app = self.clone();
+        let handle = self.get_state().get_focused_viewstate().handle.clone();
+        file_dialog::show_async(kind, options, handle, move |filename, options| {
+            let Some(filename) = extract_string_from_file_dialog(filename) else {
+                return;
+            };
+            app.get_state().remember_dir(&filename);
+            done(&app, filename, options);
+        });
+    }
+
+    /// Saves the focused view to its file, asking for a file name if it has
+    /// none. Returns false if the user cancelled.
+    ///
+    /// This blocks until the save is done, as closing needs to know.
+    fn save(&self) -> bool {
+        let filename = self.get_state().get_focused_viewstate().filename.clone();
+        let filename = match filename {
+            Some(filename) => filename,
+            None => {
+                let mut options = self.get_state().dialog_options(DialogKind::Save);
+                let filename = file_dialog::show(DialogKind::Save, &mut options);
+                let Some(filename) = extract_string_from_file_dialog(filename) else {
+                    return false;
+                };
+                let mut state = self.get_state();
+                state.remember_dir(&filename);
+                state.get_focused_viewstate().filename = Some(filename.clone());
+                filename
+            }
+        };
+        self.save_focused(&filename)
+    }
+
+    /// Saves the focused view to `filename`, rewriting it in the view's save
+    /// format if it has one. Returns false if the user cancelled.
+    ///
+    /// If another program changed the file since it was loaded or last saved,
+    /// the user chooses between overwriting it, reloading it and cancelling.
+    fn save_focused(&self, filename: &str) -> bool {
+        let (view_id, changed_on_disk, handle) = {
+            let mut state = self.get_state();
+            let view_state = state.get_focused_viewstate();
+            let changed_on_disk = view_state.filename.as_deref() == Some(filename)
+                && view_state.file_stamp.is_some()
+                && view_state.file_stamp != FileStamp::read(Path::new(filename));
+            let handle = view_state.handle.clone();
+            (state.get_focused(), changed_on_disk, handle)
+        };
+        if changed_on_disk {
+            let text = format!(
+                "{filename} has been changed by another program.\n\n\
+                 Overwrite it with your version? Choose No to reload it instead."
+            );
+            match message_box("Save", &text, MB_YESNOCANCEL | MB_ICONWARNING) {
+                IDYES => (),
+                IDNO => {
+                    self.req_new_view(Some(filename), handle, Some(view_id));
+                    return false;
+                }
+                _ => return false,
+            }
+        }
+        self.write_view(&view_id, filename);
+        true
+    }
+
+    /// Has core save a view to `filename`, rewriting it in the view's save
+    /// format if it has one. Doesn't ask the user anything.
+    fn write_view(&self, view_id: &str, filename: &str) {
+        {
+            let mut state = self.get_state();
+            let Some(view_state) = state.views.get_mut(view_id) else {
+                return;
+            };
+            view_state.filename = Some(filename.to_owned());
+            // Our own write isn't an outside change, so stop watching until
+            // the new stamp is known.
+            view_state.file_stamp = None;
+            view_state.saving = Some(FileStamp::read(Path::new(filename)));
+        }
+        self.update_tab_bar();
+        self.send_notification(
+            "save",
+            &json!({
+                "view_id": view_id,
+                "file_path": filename,
+            }),
+        );
+    }
+
+    /// Finishes a save once core has written the file, rewriting it in the
+    /// view's save format and watching it again from its new stamp.
+    ///
+    /// Core marks the view pristine in an update once it has written the
+    /// file. Updates sent before it got to the save may say the same, so the
+    /// file has to have changed as well.
+    fn finish_save(&self, view_id: &str) {
+        let (path, save_format) = {
+            let mut state = self.get_state();
+            let Some(view_state) = state.views.get_mut(view_id) else {
+                return;
+            };
+            let (Some(before), Some(filename)) = (view_state.saving, &view_state.filename) else {
+                return;
+            };
+            let path = PathBuf::from(filename);
+            if FileStamp::read(&path) == before {
+                return;
+            }
+            view_state.saving = None;
+            (path, view_state.save_format)
+        };
+        if let Some((encoding, line_ending)) = save_format {
+            if let Err(err) = encoding::transcode_file(&path, encoding, line_ending) {
+                error!("Failed to re-encode {}: {}", path.display(), err);
+            }
+        }
+        if let Some(view_state) = self.get_state().views.get_mut(view_id) {
+            view_state.file_stamp = FileStamp::read(&path);
+        }
+    }
+
+    /// Saves every view with unsaved edits which has a file. Views whose file
+    /// another program changed are left to the file watcher, which asks the
+    /// user about them.
+    fn auto_save(&self) {
+        let due: Vec<(ViewId, String)> = {
+            let state = self.get_state();
+            state
+                .views
+                .iter()
+                .filter(|(_, view_state)| !view_state.pristine)
+                .filter_map(|(view_id, view_state)| {
+                    let filename = view_state.filename.clone()?;
+                    let changed_on_disk = view_state.file_stamp.is_some()
+                        && view_state.file_stamp != FileStamp::read(Path::new(&filename));
+                    (!changed_on_disk).then(|| (view_id.clone(), filename))
+                })
+                .collect()
+        };
+        for (view_id, filename) in due {
+            self.write_view(&view_id, &filename);
+        }
+    }
+
+    /// Starts a thread which saves edited files at the interval set in the
+    /// config, if any.
+    fn start_auto_saver(&self) {
+        let app = self.clone();
+        thread::spawn(move || {
+            let mut since_save = Duration::ZERO;
+            loop {
+                thread::sleep(AUTO_SAVE_TICK);
+                since_save += AUTO_SAVE_TICK;
+                let interval = app.get_state().auto_save.interval;
+                if interval.map_or(false, |interval| since_save >= interval) {
+                    since_save = Duration::ZERO;
+                    app.auto_save();
+                }
+            }
+        });
+    }
+
+    /// Offers to save each view with unsaved changes, returning whether the
+    /// window may close. Saves have finished by the time this returns.
+    fn confirm_close(&self) -> bool {
+        let dirty: Vec<ViewId> = {
+            let state = self.get_state();
+            state
+                .order
+                .iter()
+                .filter(|view_id| state.views.get(*view_id).map_or(false, |v| !v.pristine))
+                .cloned()
+                .collect()
+        };
+        let mut saved = false;
+        for view_id in &dirty {
+            match self.offer_to_save(view_id) {
+                Some(true) => saved = true,
+                Some(false) => (),
+                None => return false,
+            }
+        }
+        if saved {
+            self.wait_for_core(&dirty[0]);
+        }
+        true
+    }
+
+    /// Focuses a view with unsaved changes and asks whether to save them.
+    /// Returns whether the view was saved, or `None` if the user cancelled.
+    fn offer_to_save(&self, view_id: &str) -> Option<bool> {
+        self.focus_view(view_id);
+        let title = self.get_state().views[view_id].title();
+        let text = format!("Do you want to save the changes you made to {title}?");
+        match message_box("xi-editor", &text, MB_YESNOCANCEL | MB_ICONWARNING) {
+            IDYES => self.save().then_some(true),
+            IDNO => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Closes the focused view, offering to save it first if it has unsaved
+    /// changes. The window closes along with the last view.
+    fn close_focused(&self) {
+        let Some(view_id) = self.get_state().focused.clone() else {
+            return;
+        };
+        let pristine = self.get_state().get_focused_viewstate().pristine;
+        if !pristine {
+            match self.offer_to_save(&view_id) {
+                // The save must be written before the view goes.
+                Some(true) => self.wait_for_core(&view_id),
+                Some(false) => (),
+                None => return,
+            }
+        }
+        let (next, handle, edit_view, hwnd) = {
+            let mut state = self.get_state();
+            let view_state = state.get_focused_viewstate();
+            let (handle, edit_view) = (view_state.handle.clone(), view_state.id);
+            let next = state.remove_view(&view_id);
+            (next, handle, edit_view, state.hwnd)
+        };
+        if next.is_none() {
+            self.set_focused(None);
+        }
+        self.send_notification("close_view", &json!({ "view_id": view_id }));
+        match next {
+            Some(next) => {
+                // Showing another view first stashes the closed one, which
+                // the edit view can then drop.
+                self.focus_view(&next);
+                UiMain::send_ext(&handle, edit_view, EditViewCommands::CloseView(view_id));
+            }
+            // SAFETY: `hwnd` is the main window.
+            None => unsafe {
+                PostMessageW(hwnd as HWND, WM_CLOSE, 0, 0);
+            },
+        }
+    }
+
+    /// Blocks until core has handled everything sent to it so far, or a few
+    /// seconds have passed.
+    fn wait_for_core(&self, view_id: &str) {
+        let (tx, rx) = mpsc::channel();
+        // Core answers requests in order, and callbacks are run in order.
+        self.get_core()
+            .send_request("get_config", &json!({ "view_id": view_id }), move |_| {
+                let _ = tx.send(());
+            });
+        let _ = rx.recv_timeout(Duration::from_secs(5));
+    }
+
+    /// Starts a thread which regularly checks open files for changes made by
+    /// other programs.
+    fn start_file_watcher(&self) {
+        let app = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(FILE_POLL_INTERVAL);
+            app.check_files_on_disk();
+        });
+    }
+
+    /// Reloads views whose file changed on disk, in place and keeping their
+    /// selections and scroll position. Views with unsaved edits are only
+    /// reloaded if the user agrees, as are unedited views when `auto_reload`
+    /// is off. Called from the watcher thread, so the prompt and the reload
+    /// are left to the UI thread.
+    fn check_files_on_disk(&self) {
+        let (changed, auto_reload) = {
+            let mut state = self.get_state();
+            let mut changed = vec![];
+            for (view_id, view_state) in &mut state.views {
+                let Some(filename) = view_state.filename.clone() else {
+                    continue;
+                };
+                let file_stamp = FileStamp::read(Path::new(&filename));
+                if view_state.file_stamp.is_some() && view_state.file_stamp != file_stamp {
+                    view_state.file_stamp = file_stamp;
+                    let handle = view_state.handle.clone();
+                    changed.push((view_id.clone(), filename, view_state.pristine, handle));
+                }
+            }
+            (changed, state.auto_reload)
+        };
+        for (view_id, filename, pristine, handle) in changed {
+            let app = self.clone();
+            let reload_handle = handle.clone();
+            handle.add_idle(move |_| {
+                let reload = (pristine && auto_reload) || {
+                    let text = format!(
+                        "{filename} has been changed by another program.\n\n\
+                         Reload it? Your unsaved changes will be lost."
+                    );
+                    message_box("Reload", &text, MB_YESNO | MB_ICONWARNING) == IDYES
+                };
+                // The view may have been closed while the prompt was up.
+                if reload && app.get_state().views.contains_key(&view_id) {
+                    app.req_new_view(Some(&filename), reload_handle, Some(view_id));
+                }
+            });
+        }
+    }
+
+    /// Applies the Windows app theme whenever it changes, from the UI thread.
+    fn start_theme_watcher(&self) {
+        let app = self.clone();
+        let hwnd = self.get_state().hwnd as HWND;
+        // SAFETY: `hwnd` is the main window, and this is called from the
+        // thread which created it.
+        unsafe {
+            system_theme::watch(hwnd, move |dark| {
+                app.get_state().system_dark = dark;
+                app.apply_theme();
+            });
+        }
+    }
+
+    /// Shows the Preferences dialog, and hands what it changed to core, which
+    /// sends the new settings to every view with `config_changed`.
+    fn show_preferences(&self) {
+        let (hwnd, old) = {
+            let state = self.get_state();
+            let old = Preferences {
+                theme: state.theme_mode,
+                ..state.preferences.clone()
+            };
+            (state.hwnd as HWND, old)
+        };
+        // SAFETY: `hwnd` is the main window, and menu commands arrive on the
+        // thread which created it.
+        let Some(new) = (unsafe { preferences::show(hwnd, &old) }) else {
+            return;
+        };
+        let changes = new.changes_from(&old);
+        if !changes.is_empty() {
+            let params = json!({ "domain": "general", "changes": changes });
+            self.send_notification("modify_user_config", &params);
+        }
+        if new.theme != old.theme {
+            self.set_theme_mode(new.theme);
+        }
+    }
+
+    /// Switches to one of the built in color schemes, leaving any core theme.
+    fn set_theme_mode(&self, theme_mode: ThemeMode) {
+        {
+            let mut state = self.get_state();
+            state.theme_mode = theme_mode;
+            state.xi_theme = None;
+            state.xi_scheme = None;
+        }
+        self.apply_theme();
+    }
+
+    /// Asks core for one of its themes. The colors change once core answers
+    /// with `theme_changed`.
+    fn choose_xi_theme(&self, index: usize) {
+        let Some(name) = self.get_state().themes.get(index).cloned() else {
+            return;
+        };
+        self.get_state().xi_theme = Some(name.clone());
+        self.send_notification("set_theme", &json!({ "theme_name": name }));
+    }
+
+    /// Switches the focused view to the language at `index` in the Syntax
+    /// menu. Core answers with `language_changed`.
+    fn choose_language(&self, index: usize) {
+        let (view_id, language) = {
+            let state = self.get_state();
+            let Some(language) = state.available_languages.get(index).cloned() else {
+                return;
+            };
+            let Some(view_id) = state.focused.clone() else {
+                return;
+            };
+            (view_id, language)
+        };
+        self.send_notification(
+            "set_language",
+            &json!({ "view_id": view_id, "language_id": language }),
+        );
+    }
+
+    /// Colors the title bar and the views for the current theme mode.
+    fn apply_theme(&self) {
+        let (dark, scheme, hwnd, has_view) = {
+            let state = self.get_state();
+            let has_view = state.focused.is_some();
+            (state.is_dark(), state.color_scheme(), state.hwnd, has_view)
+        };
+        // SAFETY: `hwnd` is the main window, which lives as long as the app.
+        unsafe {
+            system_theme::set_dark_title_bar(hwnd as HWND, dark);
+        }
+        if has_view {
+            let handle = self.get_state().get_focused_viewstate().handle.clone();
+            let widgets = self.get_state().widgets;
+            UiMain::send_ext(
+                &handle,
+                widgets.tab_bar,
+                TabBarCommands::SetColorScheme(scheme.clone()),
+            );
+            UiMain::send_ext(
+                &handle,
+                widgets.find_bar,
+                FindBarCommands::SetColorScheme(scheme.clone()),
+            );
+            UiMain::send_ext(
+                &handle,
+                widgets.status_bar,
+                StatusBarCommands::SetColorScheme(scheme.clone()),
+            );
+            self.send_view_cmd(EditViewCommands::SetColorScheme(scheme));
+        }
+    }
+
+    /// Runs a plugin command on the focused view.
+    fn send_plugin_command(&self, cmd: &PluginCommand) {
+        let view_id = self.get_state().get_focused();
+        self.send_notification("plugin_rpc", &cmd.to_rpc_params(&view_id));
+    }
+
+    /// Runs the focused view's formatter plugin command on the document, or
+    /// only the selections. The edits come back through the usual updates,
+    /// and core keeps the carets in place across them.
+    fn format(&self, selection_only: bool) {
+        let formatter = {
+            let state = self.get_state();
+            let view_id = state.get_focused();
+            let running = state.plugins.get(&view_id).map_or(&[][..], Vec::as_slice);
+            state
+                .plugin_commands
+                .get(&view_id)
+                .and_then(|commands| find_formatter(commands, running, selection_only))
+                .cloned()
+        };
+        match formatter {
+            Some(formatter) => self.send_plugin_command(&formatter),
+            None => show_message("Format", "No formatter is available for this file."),
+        }
+    }
+
+    /// Registers a listener to be called whenever the focused view changes.
+    fn add_focus_listener<F>(&self, listener: F)
+    where
+        F: Fn(Option<&str>) + Send + 'static,
+    {
+        self.focus_listeners
+            .lock()
+            .unwrap()
+            .push(Box::new(listener));
+    }
+
+    /// Focuses the given view, or none, notifying the focus listeners if it
+    /// changed. Every change of focus goes through here.
+    fn set_focused(&self, view_id: Option<&str>) {
+        {
+            let mut state = self.get_state();
+            if state.focused.as_deref() == view_id {
+                return;
+            }
+            state.focused = view_id.map(str::to_owned);
+        }
+        for listener in self.focus_listeners.lock().unwrap().iter() {
+            listener(view_id);
+        }
+    }
 }
 
 impl App {
@@ -139,52 +1137,473 @@ impl App {
 }
 
 impl App {
-    fn req_new_view(&self, filename: Option<&str>, handle: IdleHandle) {
-        let mut params = json!({});
+    /// Opens a new view, in a new tab or in place of the view `replace`. The
+    /// file's encoding is detected.
+    fn req_new_view(&self, filename: Option<&str>, handle: IdleHandle, replace: Option<ViewId>) {
+        let encoding = filename.and_then(|f| encoding::detect_encoding(Path::new(f)).ok());
+        self.req_new_view_in(filename, encoding, handle, replace);
+    }
 
-        let filename = filename.map(|f| {
-            params["file_path"] = json!(f);
-            f.to_string()
-        });
+    /// Opens a new view like [`req_new_view`](Self::req_new_view), reading
+    /// the file in the given encoding. Saving writes it back in the same one.
+    fn req_new_view_in(
+        &self,
+        filename: Option<&str>,
+        encoding: Option<Encoding>,
+        handle: IdleHandle,
+        replace: Option<ViewId>,
+    ) {
+        let mut params = json!({});
+        // Core only reads utf-8, so it's handed a copy of anything else.
+        let mut copy = None;
+        if let Some(f) = filename {
+            let path = match encoding {
+                None | Some(Encoding::Utf8) => PathBuf::from(f),
+                Some(encoding) => match encoding::utf8_copy(Path::new(f), encoding) {
+                    Ok(path) => {
+                        copy = Some(path.clone());
+                        path
+                    }
+                    Err(err) => {
+                        error!("Failed to read {} as {}: {}", f, encoding.label(), err);
+                        let msg = format!("Couldn't read {f} as {}: {err}", encoding.label());
+                        handle.add_idle(move |_| show_message("xi-editor", &msg));
+                        return;
+                    }
+                },
+            };
+            params["file_path"] = json!(path);
+        }
+        let filename = filename.map(str::to_owned);
+        let line_ending = copy
+            .as_deref()
+            .or_else(|| filename.as_deref().map(Path::new))
+            .and_then(|path| encoding::detect_line_ending(path).ok().flatten());
+        let save_format = encoding
+            .filter(|&encoding| encoding != Encoding::Utf8)
+            .map(|encoding| (encoding, line_ending.unwrap_or(LineEnding::CrLf)));
 
-        let edit_view = 0;
+        let edit_view = self.get_state().widgets.edit_view;
         let core = Arc::downgrade(&self.core);
-        let state = self.state.clone();
+        let app = self.clone();
         self.core
             .lock()
             .unwrap()
-            .send_request("new_view", &params, move |value| {
-                let view_id = value.clone().as_str().unwrap().to_string();
-                let mut state = state.lock().unwrap();
+            .send_request("new_view", &params, move |result| {
+                // Core has read the copy by the time it answers.
+                if let Some(copy) = &copy {
+                    encoding::remove_copy(copy);
+                }
+                let view_id = match result.map(Value::as_str) {
+                    Ok(Some(view_id)) => view_id.to_owned(),
+                    Ok(None) => {
+                        error!("new_view answered without a view id");
+                        return;
+                    }
+                    Err(err) => {
+                        let name = filename.as_deref().unwrap_or("a new file");
+                        error!("Failed to open {}: {}", name, err);
+                        let msg = format!("Couldn't open {name}: {err}");
+                        handle.add_idle(move |_| show_message("xi-editor", &msg));
+                        return;
+                    }
+                };
                 let handle = handle.clone();
-                state.focused = Some(view_id.clone());
-                state.views.insert(
-                    view_id.clone(),
-                    ViewState {
-                        id: 0,
-                        filename: filename.clone(),
-                        handle: handle.clone(),
-                    },
-                );
-                UiMain::send_ext(&handle, edit_view, EditViewCommands::Core(core));
-                UiMain::send_ext(&handle, edit_view, EditViewCommands::ViewId(view_id));
+                let view_state = ViewState {
+                    id: edit_view,
+                    filename: filename.clone(),
+                    handle: handle.clone(),
+                    pinned: false,
+                    save_format,
+                    line_ending,
+                    file_stamp: filename
+                        .as_deref()
+                        .and_then(|f| FileStamp::read(Path::new(f))),
+                    saving: None,
+                    pristine: true,
+                    scroll_offset: 0.0,
+                };
+                let focus = {
+                    let mut state = app.get_state();
+                    let focus = replace.is_none() || state.focused == replace;
+                    match &replace {
+                        Some(old) => state.replace_view(old, view_id.clone(), view_state),
+                        None => state.insert_view(view_id.clone(), view_state),
+                    }
+                    focus
+                };
+                if let Some(old) = &replace {
+                    let reopened = EditViewCommands::Reopened {
+                        old: old.clone(),
+                        view_id: view_id.clone(),
+                    };
+                    UiMain::send_ext(&handle, edit_view, reopened);
+                    app.send_notification("close_view", &json!({ "view_id": old }));
+                    UiMain::send_ext(&handle, edit_view, EditViewCommands::CloseView(old.clone()));
+                }
+                UiMain::send_ext(&handle, edit_view, EditViewCommands::Core(core.clone()));
+                let styles = app.get_state().styles.clone();
+                UiMain::send_ext(&handle, edit_view, EditViewCommands::SetStyles(styles));
+                if focus {
+                    app.focus_view(&view_id);
+                    app.apply_theme();
+                } else {
+                    app.update_tab_bar();
+                }
+                let restored = filename
+                    .as_ref()
+                    .and_then(|filename| app.get_state().restoring.remove(filename));
+                match (restored, &filename) {
+                    (Some(file), _) => app.restore_view(&view_id, &file),
+                    (None, Some(filename)) => app.add_recent(filename),
+                    (None, None) => (),
+                }
+            });
+    }
+
+    /// Reopens the files of the last session, returning false if none of
+    /// them are left to open.
+    fn restore_session(&self, session: &Session, handle: &IdleHandle) -> bool {
+        let files: Vec<&SessionFile> = session
+            .files
+            .iter()
+            .filter(|file| Path::new(&file.path).exists())
+            .collect();
+        if files.is_empty() {
+            return false;
+        }
+        {
+            let mut state = self.get_state();
+            state.restore_focus = session
+                .focused
+                .and_then(|i| session.files.get(i))
+                .map(|file| file.path.clone());
+            state.restoring = files
+                .iter()
+                .map(|&file| (file.path.clone(), file.clone()))
+                .collect();
+        }
+        for file in files {
+            self.req_new_view(Some(&file.path), handle.clone(), None);
+        }
+        true
+    }
+
+    /// Restores the scroll position and pinning of a reopened view. Once the
+    /// last one has opened, the view which had the focus gets it back.
+    fn restore_view(&self, view_id: &str, file: &SessionFile) {
+        self.send_cmd_for_view(view_id, EditViewCommands::RestoreScroll(file.scroll_offset));
+        let focus = {
+            let mut state = self.get_state();
+            if file.pinned {
+                state.set_pinned(view_id, true);
+            }
+            if state.restoring.is_empty() {
+                let path = state.restore_focus.take();
+                path.and_then(|path| {
+                    state
+                        .order
+                        .iter()
+                        .find(|id| state.views[*id].filename.as_ref() == Some(&path))
+                        .cloned()
+                })
+            } else {
+                None
+            }
+        };
+        match focus {
+            Some(view_id) => self.focus_view(&view_id),
+            None => self.update_tab_bar(),
+        }
+    }
+
+    /// Remembers the open files and where the window is, for the next
+    /// launch.
+    fn save_session(&self) {
+        if let Err(err) = self.session().save() {
+            error!("Failed to save the session: {}", err);
+        }
+    }
+
+    /// The open files and where the window is. Untitled views aren't kept.
+    fn session(&self) -> Session {
+        let state = self.get_state();
+        let mut files = vec![];
+        let mut focused = None;
+        for view_id in &state.order {
+            let view_state = &state.views[view_id];
+            let Some(path) = view_state.filename.clone() else {
+                continue;
+            };
+            if state.focused.as_ref() == Some(view_id) {
+                focused = Some(files.len());
+            }
+            files.push(SessionFile {
+                path,
+                scroll_offset: view_state.scroll_offset,
+                pinned: view_state.pinned,
             });
+        }
+        // SAFETY: `hwnd` is the main window, which lives as long as the app.
+        let placement = unsafe { session::get_placement(state.hwnd as HWND) };
+        Session {
+            files,
+            focused,
+            placement,
+            recent: state.recent.clone(),
+        }
+    }
+
+    /// Puts a file at the top of the recent files in the jump list.
+    fn add_recent(&self, path: &str) {
+        let recent = {
+            let mut state = self.get_state();
+            jump_list::add_recent(&mut state.recent, path);
+            state.recent.clone()
+        };
+        jump_list::update(recent);
+    }
+
+    /// A view's idle handle, to run code on the UI thread with.
+    fn idle_handle(&self) -> Option<IdleHandle> {
+        let state = self.get_state();
+        state
+            .views
+            .values()
+            .next()
+            .map(|view_state| view_state.handle.clone())
+    }
+
+    /// Starts a new core after the last one stopped, and reopens the files
+    /// which were open. Edits which weren't saved went with the old core.
+    fn restart_core(&self, handler: AppDispatcher, handle: &IdleHandle) {
+        let session = self.session();
+        let (xi_peer, rx) = start_xi_thread();
+        *self.get_core() = Core::new(xi_peer, rx, handler);
+        batch_sends(&self.get_core(), handle);
+        self.set_focused(None);
+        let (edit_view, old_views) = {
+            let mut state = self.get_state();
+            state.order.clear();
+            state.plugins.clear();
+            state.plugin_commands.clear();
+            state.languages.clear();
+            let old_views: Vec<ViewId> = state.views.drain().map(|(view_id, _)| view_id).collect();
+            (state.widgets.edit_view, old_views)
+        };
+        for view_id in old_views {
+            UiMain::send_ext(handle, edit_view, EditViewCommands::CloseView(view_id));
+        }
+        let params = json!({ "config_dir": xi_config::config_dir() });
+        self.send_notification("client_started", &params);
+        self.apply_theme();
+        if !self.restore_session(&session, handle) {
+            self.req_new_view(None, handle.clone(), None);
+        }
     }
 
     fn handle_cmd(&self, method: &str, params: &Value) {
+        let notification = match Notification::parse(method, params) {
+            Ok(notification) => notification,
+            Err(err) => {
+                warn!("unhandled core->fe method {}: {}", method, err);
+                return;
+            }
+        };
+        match notification {
+            Notification::Update { view_id, update } => {
+                if let Some(pristine) = update.pristine {
+                    let changed = self
+                        .get_state()
+                        .views
+                        .get_mut(&view_id)
+                        .map_or(false, |view_state| {
+                            mem::replace(&mut view_state.pristine, pristine) != pristine
+                        });
+                    if changed {
+                        self.update_tab_bar();
+                    }
+                    if pristine {
+                        self.finish_save(&view_id);
+                    }
+                }
+                self.send_cmd_for_view(
+                    &view_id,
+                    EditViewCommands::ApplyUpdate {
+                        view_id: view_id.clone(),
+                        update,
+                    },
+                );
+            }
+            Notification::ScrollTo { view_id, line } => {
+                self.send_cmd_for_view(
+                    &view_id,
+                    EditViewCommands::ScrollTo {
+                        view_id: view_id.clone(),
+                        line,
+                    },
+                );
+                self.update_status(&view_id);
+            }
+            Notification::ShowHover { request_id, result } => {
+                self.send_view_cmd(EditViewCommands::ShowHover {
+                    request_id,
+                    text: result,
+                })
+            }
+            Notification::FindStatus { view_id, queries } => {
+                self.send_cmd_for_view(
+                    &view_id,
+                    EditViewCommands::FindStatus {
+                        view_id: view_id.clone(),
+                        queries,
+                    },
+                );
+            }
+            Notification::ReplaceStatus(_) => (),
+            Notification::ConfigChanged { view_id, changes } => {
+                {
+                    let mut state = self.get_state();
+                    state.auto_save.config_changed(&changes);
+                    state.preferences.config_changed(&changes);
+                }
+                self.send_cmd_for_view(
+                    &view_id,
+                    EditViewCommands::ConfigChanged {
+                        view_id: view_id.clone(),
+                        changes,
+                    },
+                );
+            }
+            Notification::AvailablePlugins { view_id, plugins } => {
+                let running = plugins
+                    .into_iter()
+                    .filter(|plugin| plugin.running)
+                    .map(|plugin| plugin.name)
+                    .collect();
+                self.get_state().plugins.insert(view_id, running);
+            }
+            Notification::PluginStarted { view_id, plugin } => {
+                let mut state = self.get_state();
+                let plugins = state.plugins.entry(view_id).or_default();
+                if !plugins.contains(&plugin) {
+                    plugins.push(plugin);
+                }
+            }
+            Notification::PluginStopped { view_id, plugin } => {
+                let mut state = self.get_state();
+                if let Some(plugins) = state.plugins.get_mut(&view_id) {
+                    plugins.retain(|name| *name != plugin);
+                }
+                if let Some(commands) = state.plugin_commands.get_mut(&view_id) {
+                    commands.retain(|cmd| cmd.receiver != plugin);
+                }
+            }
+            Notification::UpdateCmds {
+                view_id,
+                plugin,
+                cmds,
+            } => {
+                let mut state = self.get_state();
+                let commands = state.plugin_commands.entry(view_id).or_default();
+                commands.retain(|cmd| cmd.receiver != plugin);
+                for cmd in cmds {
+                    match cmd {
+                        Lenient::Ok(cmd) => {
+                            commands.push(PluginCommand::from_manifest(&plugin, cmd));
+                        }
+                        Lenient::Malformed(cmd) => warn!("bad command of {}: {}", plugin, cmd),
+                    }
+                }
+            }
+            Notification::LanguageChanged {
+                view_id,
+                language_id,
+            } => {
+                if xi_config::overrides_indentation(&language_id) {
+                    let cmd = EditViewCommands::IndentationOverridden(view_id.clone());
+                    self.send_cmd_for_view(&view_id, cmd);
+                }
+                self.get_state().languages.insert(view_id, language_id);
+                self.update_file_info();
+            }
+            Notification::AvailableThemes { themes } => {
+                let hwnd = self.get_state().hwnd;
+                // SAFETY: `hwnd` is the main window, which lives as long as the app.
+                unsafe {
+                    menus::set_theme_entries(hwnd as HWND, &themes);
+                }
+                self.get_state().themes = themes;
+            }
+            Notification::ThemeChanged { name, theme } => {
+                // Core sends its default theme on startup, which is ignored
+                // unless it was chosen from the menu.
+                let applies = {
+                    let mut state = self.get_state();
+                    let applies = state.xi_theme.as_deref() == Some(name.as_str());
+                    if applies {
+                        let scheme = ColorScheme::from_xi_theme(&theme, &ColorScheme::default());
+                        state.xi_scheme = Some(scheme);
+                    }
+                    applies
+                };
+                if applies {
+                    self.apply_theme();
+                }
+            }
+            Notification::DefStyle(def) => {
+                let (styles, has_view) = {
+                    let mut state = self.get_state();
+                    state.styles.insert(def.id, Style::from_def_style(&def));
+                    (state.styles.clone(), state.focused.is_some())
+                };
+                if has_view {
+                    self.send_view_cmd(EditViewCommands::SetStyles(styles));
+                }
+            }
+            Notification::Alert { msg } => show_message("xi-editor", &msg),
+            Notification::AvailableLanguages { languages } => {
+                let hwnd = self.get_state().hwnd;
+                // SAFETY: `hwnd` is the main window, which lives as long as the app.
+                unsafe {
+                    menus::set_language_entries(hwnd as HWND, &languages);
+                }
+                self.get_state().available_languages = languages;
+                self.update_file_info();
+            }
+        }
+    }
+
+    fn handle_request(&self, method: &str, params: &Value) -> Value {
         match method {
-            "update" => self.send_view_cmd(EditViewCommands::ApplyUpdate(params["update"].clone())),
-            "scroll_to" => self.send_view_cmd(EditViewCommands::ScrollTo(
-                params["line"].as_u64().unwrap() as usize,
-            )),
-            "available_themes"
-            | "available_plugins"
-            | "available_languages"
-            | "config_changed"
-            | "language_changed" => (), // TODO(Olive)
-            _ => println!("unhandled core->fe method {}", method),
+            "measure_width" => self.measure_width(params),
+            _ => {
+                warn!("unhandled core->fe request {}", method);
+                Value::Null
+            }
         }
     }
+
+    /// Measures strings in the edit view's font, as core needs for word
+    /// wrap. Blocks until the edit view has answered, or a few seconds have
+    /// passed.
+    fn measure_width(&self, params: &Value) -> Value {
+        let (handle, edit_view) = {
+            let state = self.get_state();
+            let Some(view_state) = state.focused.as_ref().and_then(|id| state.views.get(id)) else {
+                return Value::Null;
+            };
+            (view_state.handle.clone(), view_state.id)
+        };
+        let (tx, rx) = mpsc::channel();
+        let cmd = EditViewCommands::MeasureWidth {
+            params: params.clone(),
+            reply: tx,
+        };
+        UiMain::send_ext(&handle, edit_view, cmd);
+        rx.recv_timeout(Duration::from_secs(5))
+            .unwrap_or(Value::Null)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -203,76 +1622,293 @@ impl AppDispatcher {
         *self.app.lock().unwrap() = Some(app.clone());
     }
 
-    fn set_menu_listeners(&self, state: &mut UiState) {
+    fn set_tab_listener(&self, state: &mut UiState, tab_bar: Id) {
         let app = self.app.clone();
-        state.set_command_listener(move |cmd, mut ctx| {
-            match cmd {
-                cmd if cmd == MenuEntries::Exit as u32 => {
-                    ctx.close();
+        state.add_listener(tab_bar, move |event: &mut TabEvent, _ctx| {
+            if let Some(app) = app.lock().unwrap().as_ref() {
+                match event {
+                    TabEvent::Select(view_id) => app.focus_view(view_id),
+                    TabEvent::Overflow => {
+                        let mut state = app.get_state();
+                        let hwnd = state.hwnd;
+                        let titles: Vec<String> = state
+                            .order
+                            .iter()
+                            .map(|view_id| state.views[view_id].title())
+                            .collect();
+                        let focused = state
+                            .focused
+                            .as_ref()
+                            .and_then(|focused| state.order.iter().position(|id| id == focused));
+                        let handle = state.get_focused_viewstate().handle.clone();
+                        // Its choices come back as `TAB_ENTRIES` commands.
+                        handle.add_idle(move |_| {
+                            // SAFETY: `hwnd` is the main window.
+                            unsafe { menus::show_tab_list(hwnd as HWND, &titles, focused) }
+                        });
+                    }
+                    TabEvent::Move { from, to } => {
+                        app.get_state().move_view(*from, *to);
+                        app.update_tab_bar();
+                    }
+                    TabEvent::ContextMenu(view_id) => {
+                        // The menu's commands act on the focused view.
+                        app.focus_view(view_id);
+                        let mut state = app.get_state();
+                        let hwnd = state.hwnd;
+                        let view_state = state.get_focused_viewstate();
+                        let (handle, pinned) = (view_state.handle.clone(), view_state.pinned);
+                        // The menu runs a modal loop, so it waits until the
+                        // event has been handled.
+                        handle.add_idle(move |_| {
+                            // SAFETY: `hwnd` is the main window.
+                            unsafe { menus::show_tab_menu(hwnd as HWND, pinned) }
+                        });
+                    }
+                    TabEvent::Close(view_id) => {
+                        app.focus_view(view_id);
+                        let handle = app.get_state().get_focused_viewstate().handle.clone();
+                        let app = app.clone();
+                        // Closing may ask about unsaved changes, in a modal
+                        // loop of its own.
+                        handle.add_idle(move |_| app.close_focused());
+                    }
                 }
-                cmd if cmd == MenuEntries::Open as u32 => {
-                    if let Some(app) = app.lock().unwrap().as_ref() {
-                        let filename =
-                            ctx.file_dialog(FileDialogType::Open, FileDialogOptions::default());
-                        if filename.is_err() {
-                            return;
+            }
+        });
+    }
+
+    fn set_edit_view_listener(&self, state: &mut UiState, edit_view: Id) {
+        let app = self.app.clone();
+        state.add_listener(edit_view, move |event: &mut EditViewEvent, _ctx| {
+            if let Some(app) = app.lock().unwrap().as_ref() {
+                match event {
+                    EditViewEvent::Find(query) => {
+                        let mut state = app.get_state();
+                        let find_bar = state.widgets.find_bar;
+                        let handle = state.get_focused_viewstate().handle.clone();
+                        UiMain::send_ext(
+                            &handle,
+                            find_bar,
+                            FindBarCommands::SetQuery(query.take()),
+                        );
+                    }
+                    EditViewEvent::Caret(caret) => {
+                        let mut state = app.get_state();
+                        let status_bar = state.widgets.status_bar;
+                        let handle = state.get_focused_viewstate().handle.clone();
+                        UiMain::send_ext(&handle, status_bar, StatusBarCommands::SetCaret(*caret));
+                    }
+                    EditViewEvent::Scroll { view_id, offset } => {
+                        if let Some(view_state) = app.get_state().views.get_mut(view_id) {
+                            view_state.scroll_offset = *offset;
                         }
-                        let filename = filename.unwrap().into_string();
-                        if filename.is_err() {
-                            // invalid unicode data
-                            return;
+                    }
+                    EditViewEvent::Mode(mode) => {
+                        let mut state = app.get_state();
+                        let status_bar = state.widgets.status_bar;
+                        let handle = state.get_focused_viewstate().handle.clone();
+                        UiMain::send_ext(&handle, status_bar, StatusBarCommands::SetMode(*mode));
+                    }
+                    EditViewEvent::Indentation(indentation) => {
+                        let mut state = app.get_state();
+                        let status_bar = state.widgets.status_bar;
+                        let handle = state.get_focused_viewstate().handle.clone();
+                        UiMain::send_ext(
+                            &handle,
+                            status_bar,
+                            StatusBarCommands::SetIndentation(*indentation),
+                        );
+                    }
+                    EditViewEvent::DetectIndentation(detect) => {
+                        let mut state = app.get_state();
+                        state.detect_indentation = *detect;
+                        // SAFETY: `hwnd` is the main window.
+                        unsafe {
+                            menus::check_detect_indentation(state.hwnd as HWND, *detect);
+                        }
+                    }
+                    EditViewEvent::Command(id) => {
+                        let hwnd = app.get_state().hwnd as HWND;
+                        // Posted, to go through the menus' dispatch once this
+                        // event has been handled.
+                        // SAFETY: `hwnd` is the main window.
+                        unsafe {
+                            PostMessageW(hwnd, WM_COMMAND, *id as WPARAM, 0);
                         }
-                        let filename = filename.unwrap();
+                    }
+                    EditViewEvent::ContextMenu => {
                         let mut state = app.get_state();
-                        let mut view_state = state.get_focused_viewstate();
-                        app.req_new_view(Some(&filename), view_state.handle.clone());
-                        view_state.filename = Some(filename);
+                        let hwnd = state.hwnd;
+                        let handle = state.get_focused_viewstate().handle.clone();
+                        // The menu runs a modal loop, so it waits until the
+                        // event has been handled.
+                        handle.add_idle(move |_| {
+                            // SAFETY: `hwnd` is the main window.
+                            unsafe { menus::show_context_menu(hwnd as HWND) }
+                        });
+                    }
+                }
+            }
+        });
+    }
+
+    fn set_status_bar_listener(&self, state: &mut UiState, status_bar: Id) {
+        let app = self.app.clone();
+        state.add_listener(status_bar, move |event: &mut StatusBarEvent, _ctx| {
+            if let Some(app) = app.lock().unwrap().as_ref() {
+                match *event {
+                    StatusBarEvent::Indentation(indentation) => {
+                        let mut state = app.get_state();
+                        let hwnd = state.hwnd;
+                        let detect = state.detect_indentation;
+                        let handle = state.get_focused_viewstate().handle.clone();
+                        // Its choices are the View > Indentation commands,
+                        // which change the focused view only.
+                        handle.add_idle(move |_| {
+                            // SAFETY: `hwnd` is the main window.
+                            unsafe {
+                                menus::show_indentation_menu(hwnd as HWND, indentation, detect)
+                            }
+                        });
+                    }
+                }
+            }
+        });
+    }
+
+    fn set_menu_listeners(&self, state: &mut UiState) {
+        let app = self.app.clone();
+        state.set_command_listener(move |cmd, _ctx| {
+            match cmd {
+                cmd if cmd == MenuEntries::Exit as u32 => {
+                    // Closing goes through the same unsaved changes prompt as
+                    // the window's close button.
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let hwnd = app.get_state().hwnd as HWND;
+                        // SAFETY: `hwnd` is the main window.
+                        unsafe {
+                            PostMessageW(hwnd, WM_CLOSE, 0, 0);
+                        }
+                    }
+                }
+                cmd if cmd == MenuEntries::New as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        // The new view opens in a tab of its own and takes
+                        // the focus once core has created it.
+                        let handle = app.get_state().get_focused_viewstate().handle.clone();
+                        app.req_new_view(None, handle, None);
+                    }
+                }
+                cmd if cmd == MenuEntries::Close as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let handle = app.get_state().get_focused_viewstate().handle.clone();
+                        let app = app.clone();
+                        // Run once the dispatcher's lock is released, as
+                        // saving waits for core, whose notifications need it.
+                        handle.add_idle(move |_| app.close_focused());
+                    }
+                }
+                cmd if cmd == MenuEntries::Open as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let options = app.get_state().dialog_options(DialogKind::Open);
+                        app.choose_file(DialogKind::Open, options, |app, filename, _| {
+                            let handle = app.get_state().get_focused_viewstate().handle.clone();
+                            app.req_new_view(Some(&filename), handle, None);
+                        });
                     }
                 }
                 cmd if cmd == MenuEntries::Save as u32 => {
                     if let Some(app) = app.lock().unwrap().as_ref() {
-                        {
-                            let mut state = app.get_state();
-                            let mut view_state = state.get_focused_viewstate();
-                            if view_state.filename.is_none() {
-                                let filename = ctx.file_dialog(
-                                    FileDialogType::Save,
-                                    FileDialogOptions::default(),
-                                );
-                                let filename = extract_string_from_file_dialog(filename);
-                                if filename.is_none() {
-                                    return;
-                                }
-                                view_state.filename = filename;
-                            }
+                        if app.get_state().get_focused_viewstate().filename.is_some() {
+                            app.save();
+                            return;
                         }
-                        let state = app.get_state();
-                        let view_state = &state.views[&state.get_focused()];
-                        app.send_notification(
-                            "save",
-                            &json!({
-                                "view_id": &state.focused,
-                                "file_path": view_state.filename.clone().unwrap(),
-                            }),
-                        );
+                        let options = app.get_state().dialog_options(DialogKind::Save);
+                        app.choose_file(DialogKind::Save, options, |app, filename, _| {
+                            app.get_state().get_focused_viewstate().filename =
+                                Some(filename.clone());
+                            app.save_focused(&filename);
+                        });
                     }
                 }
                 cmd if cmd == MenuEntries::SaveAs as u32 => {
                     if let Some(app) = app.lock().unwrap().as_ref() {
-                        let filename =
-                            ctx.file_dialog(FileDialogType::Save, FileDialogOptions::default());
-                        let filename = extract_string_from_file_dialog(filename);
-                        if filename.is_none() {
-                            return;
+                        let options = app.get_state().dialog_options(DialogKind::Save);
+                        app.choose_file(DialogKind::Save, options, |app, filename, _| {
+                            app.save_focused(&filename);
+                        });
+                    }
+                }
+                cmd if cmd == MenuEntries::SaveWithEncoding as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let (mut options, (encoding, line_ending)) = {
+                            let mut state = app.get_state();
+                            let save_format = state
+                                .get_focused_viewstate()
+                                .save_format
+                                .unwrap_or((Encoding::Utf8, LineEnding::Lf));
+                            (state.dialog_options(DialogKind::Save), save_format)
+                        };
+                        options.choices = vec![
+                            Choice {
+                                label: "Encoding:",
+                                items: Encoding::ALL.iter().map(|e| e.label()).collect(),
+                                selected: Encoding::ALL
+                                    .iter()
+                                    .position(|&e| e == encoding)
+                                    .unwrap(),
+                            },
+                            Choice {
+                                label: "Line endings:",
+                                items: LineEnding::ALL.iter().map(|l| l.label()).collect(),
+                                selected: LineEnding::ALL
+                                    .iter()
+                                    .position(|&l| l == line_ending)
+                                    .unwrap(),
+                            },
+                        ];
+                        app.choose_file(DialogKind::Save, options, |app, filename, options| {
+                            let save_format = (
+                                Encoding::ALL[options.choices[0].selected],
+                                LineEnding::ALL[options.choices[1].selected],
+                            );
+                            app.get_state().get_focused_viewstate().save_format = Some(save_format);
+                            app.update_file_info();
+                            app.save_focused(&filename);
+                        });
+                    }
+                }
+                cmd if cmd == MenuEntries::LineEndingLf as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.set_line_ending(LineEnding::Lf);
+                    }
+                }
+                cmd if cmd == MenuEntries::LineEndingCrLf as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.set_line_ending(LineEnding::CrLf);
+                    }
+                }
+                cmd if cmd == MenuEntries::AutoReload as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let mut state = app.get_state();
+                        state.auto_reload = !state.auto_reload;
+                    }
+                }
+                cmd if cmd == MenuEntries::ReloadKeymap as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        match Keymap::load() {
+                            Ok(keymap) => app.send_view_cmd(EditViewCommands::SetKeymap(keymap)),
+                            Err(err) => {
+                                // Until it's fixed, the keys stay as they were.
+                                warn!("couldn't reload the keymap: {}", err);
+                                let msg = format!("Couldn't reload the keymap: {}", err);
+                                let handle = app.get_state().get_focused_viewstate().handle.clone();
+                                handle.add_idle(move |_| {
+                                    message_box("xi-editor", &msg, MB_OK | MB_ICONWARNING);
+                                });
+                            }
                         }
-                        app.send_notification(
-                            "save",
-                            &json!({
-                                "view_id": app.get_state().focused,
-                                "file_path": filename.clone().unwrap(),
-                            }),
-                        );
-                        app.get_state().get_focused_viewstate().filename = filename;
                     }
                 }
                 cmd if cmd == MenuEntries::Undo as u32 => {
@@ -301,6 +1937,56 @@ impl AppDispatcher {
                         app.send_view_cmd(EditViewCommands::Transpose);
                     }
                 }
+                cmd if cmd == MenuEntries::Indent as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::Indent);
+                    }
+                }
+                cmd if cmd == MenuEntries::Outdent as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::Outdent);
+                    }
+                }
+                cmd if cmd == MenuEntries::DuplicateLine as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::DuplicateLine);
+                    }
+                }
+                cmd if cmd == MenuEntries::DeleteLine as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::DeleteLine);
+                    }
+                }
+                cmd if cmd == MenuEntries::FormatDocument as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.format(false);
+                    }
+                }
+                cmd if cmd == MenuEntries::FormatSelection as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.format(true);
+                    }
+                }
+                cmd if cmd == MenuEntries::Find as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::Find);
+                    }
+                }
+                cmd if cmd == MenuEntries::FindNext as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::FindNext);
+                    }
+                }
+                cmd if cmd == MenuEntries::FindPrevious as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::FindPrevious);
+                    }
+                }
+                cmd if cmd == MenuEntries::RenameSymbol as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::RenameSymbol);
+                    }
+                }
                 cmd if cmd == MenuEntries::AddCursorAbove as u32 => {
                     if let Some(app) = app.lock().unwrap().as_ref() {
                         app.send_view_cmd(EditViewCommands::AddCursorAbove);
@@ -321,7 +2007,183 @@ impl AppDispatcher {
                         app.send_view_cmd(EditViewCommands::SelectAll);
                     }
                 }
-                _ => println!("unexpected cmd {}", cmd),
+                cmd if cmd == MenuEntries::SelectLine as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::SelectLine);
+                    }
+                }
+                cmd if cmd == MenuEntries::ExpandSelection as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ExpandSelection);
+                    }
+                }
+                cmd if cmd == MenuEntries::ShrinkSelection as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ShrinkSelection);
+                    }
+                }
+                cmd if cmd == MenuEntries::InsertSequence as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::InsertSequence);
+                    }
+                }
+                cmd if cmd == MenuEntries::UpperCaseEach as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let transform = SelectionTransform::UpperCase;
+                        app.send_view_cmd(EditViewCommands::TransformSelections(transform));
+                    }
+                }
+                cmd if cmd == MenuEntries::LowerCaseEach as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let transform = SelectionTransform::LowerCase;
+                        app.send_view_cmd(EditViewCommands::TransformSelections(transform));
+                    }
+                }
+                cmd if cmd == MenuEntries::TrimEach as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let transform = SelectionTransform::Trim;
+                        app.send_view_cmd(EditViewCommands::TransformSelections(transform));
+                    }
+                }
+                cmd if cmd == MenuEntries::IndentUsingSpaces as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::SetTranslateTabsToSpaces(true));
+                    }
+                }
+                cmd if cmd == MenuEntries::IndentUsingTabs as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::SetTranslateTabsToSpaces(false));
+                    }
+                }
+                cmd if cmd == MenuEntries::TabWidth2 as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::SetTabSize(2));
+                    }
+                }
+                cmd if cmd == MenuEntries::TabWidth4 as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::SetTabSize(4));
+                    }
+                }
+                cmd if cmd == MenuEntries::TabWidth8 as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::SetTabSize(8));
+                    }
+                }
+                cmd if cmd == MenuEntries::DetectIndentation as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ToggleDetectIndentation);
+                    }
+                }
+                cmd if cmd == MenuEntries::Preferences as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.show_preferences();
+                    }
+                }
+                cmd if cmd == MenuEntries::DebugConsole as u32 => {
+                    logging::set_console(!logging::console_shown());
+                }
+                cmd if cmd == MenuEntries::LineNumbers as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ToggleLineNumbers);
+                    }
+                }
+                cmd if cmd == MenuEntries::IndentGuides as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ToggleIndentGuides);
+                    }
+                }
+                cmd if cmd == MenuEntries::RenderWhitespace as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ToggleWhitespace);
+                    }
+                }
+                cmd if cmd == MenuEntries::Minimap as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ToggleMinimap);
+                    }
+                }
+                cmd if cmd == MenuEntries::GrayscaleText as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ToggleGrayscale);
+                    }
+                }
+                cmd if cmd == MenuEntries::ColorFonts as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ToggleColorFonts);
+                    }
+                }
+                cmd if cmd == MenuEntries::ViMode as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.send_view_cmd(EditViewCommands::ToggleModal);
+                    }
+                }
+                cmd if cmd == MenuEntries::ThemeAuto as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.set_theme_mode(ThemeMode::Auto);
+                    }
+                }
+                cmd if cmd == MenuEntries::ThemeLight as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.set_theme_mode(ThemeMode::Light);
+                    }
+                }
+                cmd if cmd == MenuEntries::ThemeDark as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.set_theme_mode(ThemeMode::Dark);
+                    }
+                }
+                cmd if cmd == MenuEntries::NextTab as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.cycle_focus(true);
+                    }
+                }
+                cmd if cmd == MenuEntries::PreviousTab as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.cycle_focus(false);
+                    }
+                }
+                cmd if cmd == MenuEntries::PinTab as u32 => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        {
+                            let mut state = app.get_state();
+                            if let Some(focused) = state.focused.clone() {
+                                let pinned = state.views[&focused].pinned;
+                                state.set_pinned(&focused, !pinned);
+                            }
+                        }
+                        app.update_tab_bar();
+                    }
+                }
+                cmd if menus::ENCODING_ENTRIES.contains(&cmd) => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let index = (cmd - menus::ENCODING_ENTRIES.start) as usize;
+                        if let Some(&encoding) = Encoding::ALL.get(index) {
+                            app.reopen_with_encoding(encoding);
+                        }
+                    }
+                }
+                cmd if menus::THEME_ENTRIES.contains(&cmd) => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.choose_xi_theme((cmd - menus::THEME_ENTRIES.start) as usize);
+                    }
+                }
+                cmd if cmd == MenuEntries::NoLanguages as u32 => (),
+                cmd if menus::TAB_ENTRIES.contains(&cmd) => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        let index = (cmd - menus::TAB_ENTRIES.start) as usize;
+                        let view_id = app.get_state().order.get(index).cloned();
+                        if let Some(view_id) = view_id {
+                            app.focus_view(&view_id);
+                        }
+                    }
+                }
+                cmd if menus::LANGUAGE_ENTRIES.contains(&cmd) => {
+                    if let Some(app) = app.lock().unwrap().as_ref() {
+                        app.choose_language((cmd - menus::LANGUAGE_ENTRIES.start) as usize);
+                    }
+                }
+                _ => warn!("unexpected cmd {}", cmd),
             }
         });
     }
@@ -329,40 +2191,146 @@ impl AppDispatcher {
 
 impl Handler for AppDispatcher {
     fn notification(&self, method: &str, params: &Value) {
-        // NOTE: For debugging, could be replaced by trace logging
-        // println!("core->fe: {} {}", method, params);
+        trace!("core->fe: {} {}", method, params);
         if let Some(ref app) = *self.app.lock().unwrap() {
             app.handle_cmd(method, params);
         }
     }
+
+    fn request(&self, method: &str, params: &Value) -> Value {
+        self.app
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(Value::Null, |app| app.handle_request(method, params))
+    }
+
+    fn disconnected(&self) {
+        error!("xi-core stopped");
+        let Some(app) = self.app.lock().unwrap().clone() else {
+            return;
+        };
+        let Some(handle) = app.idle_handle() else {
+            return;
+        };
+        let dispatcher = self.clone();
+        let idle = handle.clone();
+        handle.add_idle(move |_| {
+            let restart = message_box(
+                "xi-editor",
+                "xi-core stopped unexpectedly, and unsaved edits were lost.\n\n\
+                 Restart it and reopen your files?",
+                MB_YESNO | MB_ICONERROR,
+            );
+            if restart == IDYES {
+                app.restart_core(dispatcher, &idle);
+            }
+        });
+    }
 }
 
-fn extract_string_from_file_dialog(
-    result: Result<std::ffi::OsString, druid::Error>,
-) -> Option<String> {
+fn extract_string_from_file_dialog(result: io::Result<OsString>) -> Option<String> {
     if result.is_err() {
-        println!("File dialog encountered an error: {:?}", result);
+        // Cancelling is reported as an error too.
+        debug!("File dialog encountered an error: {:?}", result);
         return None;
     }
     let result = result.unwrap().into_string();
     if result.is_err() {
-        println!("Invalid utf returned");
+        warn!("Invalid utf returned");
         return None;
     }
     Some(result.unwrap())
 }
 
-fn build_app(state: &mut UiState) {
-    // TODO(Olive): widgets which support tabs and split panes
+/// Shows a modal message box with an OK button.
+fn show_message(title: &str, text: &str) {
+    message_box(title, text, MB_OK | MB_ICONINFORMATION);
+}
+
+/// Shows a modal message box with the given `MB_*` flags, returning the
+/// `ID*` of the button pressed.
+fn message_box(title: &str, text: &str, flags: UINT) -> i32 {
+    let title = to_wide(title);
+    let text = to_wide(text);
+    unsafe { MessageBoxW(GetActiveWindow(), text.as_ptr(), title.as_ptr(), flags) }
+}
+
+/// Holds the notifications sent to core during a turn of the run loop, such
+/// as the keystrokes and scrolls of a burst of input, and sends them together
+/// once it is idle.
+fn batch_sends(core: &Core, handle: &IdleHandle) {
+    let handle = handle.clone();
+    core.set_waker(move |core| {
+        let core = core.clone();
+        handle.add_idle(move |_| core.flush());
+    });
+}
+
+/// Removes a flag from the command line arguments, returning whether it was
+/// there.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != len
+}
+
+/// Removes an option and its value from the command line arguments,
+/// returning the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == name)?;
+    let value = args.get(i + 1).cloned();
+    args.drain(i..args.len().min(i + 2));
+    value
+}
+
+fn build_app(state: &mut UiState) -> Widgets {
+    // TODO(Olive): split panes
     let edit_view = EditView::new().ui(state);
-    state.set_root(edit_view);
+    let tab_bar = TabBar::new().ui(state);
+    let find_bar = FindBar::new().ui(state);
+    let mut column = Column::new();
+    column.set_flex(edit_view, 1.0);
+    let status_bar = StatusBar::new().ui(state);
+    let root = state.add(column, &[tab_bar, find_bar, edit_view, status_bar]);
+    state.set_root(root);
     state.set_focus(Some(edit_view));
+    Widgets {
+        edit_view,
+        tab_bar,
+        find_bar,
+        status_bar,
+    }
 }
 
 fn main() {
+    logging::init();
+    if !dpi::enable_awareness() {
+        warn!("per-monitor DPI awareness is unavailable");
+    }
     druid_win_shell::init();
 
-    let (xi_peer, rx) = start_xi_thread();
+    let mut files: Vec<String> = env::args().skip(1).collect();
+    // From the jump list, the files go to the window already open.
+    if take_flag(&mut files, jump_list::REUSE_WINDOW) && single_instance::forward(&files) {
+        return;
+    }
+    let trace =
+        take_option(&mut files, "--trace-rpc").or_else(|| env::var(rpc_trace::TRACE_VAR).ok());
+    if let Some(path) = trace {
+        if let Err(err) = rpc_trace::start(Path::new(&path)) {
+            warn!("couldn't record rpc to {}: {}", path, err);
+        }
+    }
+    let replay = take_option(&mut files, "--replay-rpc");
+    let (xi_peer, rx) = match replay.map(|path| rpc_trace::replay(Path::new(&path))) {
+        Some(Ok(replayed)) => replayed,
+        Some(Err(err)) => {
+            warn!("couldn't replay rpc: {}", err);
+            start_xi_thread()
+        }
+        None => start_xi_thread(),
+    };
 
     let mut runloop = win_main::RunLoop::new();
     let mut builder = WindowBuilder::new();
@@ -370,7 +2338,10 @@ fn main() {
 
     let handler = AppDispatcher::new();
     handler.set_menu_listeners(&mut state);
-    build_app(&mut state);
+    let widgets = build_app(&mut state);
+    handler.set_tab_listener(&mut state, widgets.tab_bar);
+    handler.set_edit_view_listener(&mut state, widgets.edit_view);
+    handler.set_status_bar_listener(&mut state, widgets.status_bar);
     menus::set_accel(&mut runloop);
 
     builder.set_handler(Box::new(UiMain::new(state)));
@@ -380,14 +2351,121 @@ fn main() {
     let window = builder.build().unwrap();
 
     let core = Core::new(xi_peer, rx, handler.clone());
+    batch_sends(&core, &window.get_idle_handle().unwrap());
     let app = App::new(core);
     handler.set_app(&app);
+    {
+        let mut state = app.get_state();
+        state.hwnd = window.get_hwnd().unwrap() as usize;
+        state.widgets = widgets;
+    }
+    app.apply_theme();
+    {
+        // The edit view, the tab bar and the status bar follow the focus.
+        let listener_app = app.clone();
+        app.add_focus_listener(move |view_id| {
+            if let Some(view_id) = view_id {
+                let cmd = EditViewCommands::ViewId(view_id.to_owned());
+                listener_app.send_cmd_for_view(view_id, cmd);
+            }
+        });
+        let listener_app = app.clone();
+        app.add_focus_listener(move |_| listener_app.update_tab_bar());
+        let listener_app = app.clone();
+        app.add_focus_listener(move |_| listener_app.update_file_info());
+    }
+    {
+        let app = app.clone();
+        // SAFETY: The window was created by this thread.
+        unsafe {
+            close_prompt::on_close(window.get_hwnd().unwrap(), move || {
+                let close = app.confirm_close();
+                if close {
+                    app.save_session();
+                }
+                close
+            });
+        }
+    }
+    {
+        let app = app.clone();
+        // SAFETY: The window was created by this thread.
+        unsafe {
+            auto_save::on_deactivate(window.get_hwnd().unwrap(), move || {
+                if app.get_state().auto_save.on_focus_loss {
+                    app.auto_save();
+                }
+            });
+        }
+    }
+    {
+        let app = app.clone();
+        let handle = window.get_idle_handle().unwrap();
+        // SAFETY: The window was created by this thread.
+        unsafe {
+            single_instance::hook(window.get_hwnd().unwrap(), move |files| {
+                for file in &files {
+                    app.req_new_view(Some(file), handle.clone(), None);
+                }
+            });
+        }
+    }
+    {
+        let handle = window.get_idle_handle().unwrap();
+        let edit_view = widgets.edit_view;
+        // SAFETY: The window was created by this thread.
+        unsafe {
+            dpi::hook(window.get_hwnd().unwrap());
+            let ime_handle = handle.clone();
+            ime::hook(window.get_hwnd().unwrap(), move |event| {
+                UiMain::send_ext(&ime_handle, edit_view, EditViewCommands::Ime(event));
+            });
+            let caret_handle = handle.clone();
+            caret_blink::hook(window.get_hwnd().unwrap(), move |event| {
+                UiMain::send_ext(&caret_handle, edit_view, EditViewCommands::Caret(event));
+            });
+        }
+        let laid_out_handle = handle.clone();
+        let prelayout = Prelayout::start(move |done| {
+            UiMain::send_ext(&laid_out_handle, edit_view, EditViewCommands::LaidOut(done));
+        });
+        UiMain::send_ext(&handle, edit_view, EditViewCommands::Prelayout(prelayout));
+    }
 
-    app.send_notification("client_started", &json!({}));
+    let params = json!({ "config_dir": xi_config::config_dir() });
+    app.send_notification("client_started", &params);
 
+    // Files named on the command line, as by "Open with", each get a tab.
+    // Without any, the files open when the app last closed are reopened.
     let handle = window.get_idle_handle().unwrap();
-    app.req_new_view(None, handle);
+    let session = Session::load().unwrap_or_default();
+    app.get_state().recent = session.recent.clone();
+    jump_list::update(session.recent.clone());
+    if let Some(placement) = &session.placement {
+        // SAFETY: The window was created by this thread.
+        unsafe {
+            session::set_placement(window.get_hwnd().unwrap(), placement);
+        }
+    }
+    if files.is_empty() && !app.restore_session(&session, &handle) {
+        app.req_new_view(None, handle.clone(), None);
+    }
+    for file in &files {
+        app.req_new_view(Some(file), handle.clone(), None);
+    }
+    app.start_file_watcher();
+    app.start_theme_watcher();
+    app.start_auto_saver();
 
     window.show();
+    if session
+        .placement
+        .map_or(false, |placement| placement.maximized)
+    {
+        // SAFETY: The window was created by this thread.
+        unsafe {
+            ShowWindow(window.get_hwnd().unwrap(), SW_MAXIMIZE);
+        }
+    }
     runloop.run();
 }
@@ -1,19 +1,39 @@
 //! Configuration and runtime for the main window's menus.
 
+use std::ffi::OsStr;
+use std::ops::Range;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+
 use winapi::shared::minwindef::WORD;
-use winapi::um::winuser::{ACCEL, FCONTROL, FSHIFT, FVIRTKEY};
+use winapi::shared::windef::{HMENU, HWND, POINT};
+use winapi::um::winuser::{
+    AppendMenuW, CheckMenuItem, CheckMenuRadioItem, CreatePopupMenu, DeleteMenu, DestroyMenu,
+    GetCursorPos, GetMenu, GetMenuItemCount, GetMenuItemID, GetSubMenu, TrackPopupMenu, ACCEL,
+    FALT, FCONTROL, FSHIFT, FVIRTKEY, MF_BYCOMMAND, MF_BYPOSITION, MF_CHECKED, MF_GRAYED,
+    MF_SEPARATOR, MF_STRING, MF_UNCHECKED, TPM_RIGHTBUTTON, VK_DOWN, VK_F2, VK_F3, VK_OEM_4,
+    VK_OEM_6, VK_TAB, VK_UP,
+};
 
 use druid_win_shell::menu::Menu;
 use druid_win_shell::win_main::RunLoop;
 
+use crate::edit_view::Indentation;
+use crate::encoding::Encoding;
+
 #[derive(Debug)]
 #[repr(u32)]
 pub enum MenuEntries {
     // File menu entries
     Exit = 0x100,
+    New,
     Open,
+    Close,
     Save,
     SaveAs,
+    SaveWithEncoding,
+    AutoReload,
+    ReloadKeymap,
 
     // Edit menu entries
     Undo,
@@ -24,19 +44,94 @@ pub enum MenuEntries {
     UpperCase,
     LowerCase,
     Transpose,
+    Indent,
+    Outdent,
+    DuplicateLine,
+    DeleteLine,
+    FormatDocument,
+    FormatSelection,
+    RenameSymbol,
+    Find,
+    FindNext,
+    FindPrevious,
+    LineEndingLf,
+    LineEndingCrLf,
+    Preferences,
 
     // Selection menu entries
     SingleSelection,
     AddCursorAbove,
     AddCursorBelow,
     SelectAll,
+    SelectLine,
+    ExpandSelection,
+    ShrinkSelection,
+    InsertSequence,
+    UpperCaseEach,
+    LowerCaseEach,
+    TrimEach,
+
+    // View menu entries
+    IndentUsingSpaces,
+    IndentUsingTabs,
+    TabWidth2,
+    TabWidth4,
+    TabWidth8,
+    DetectIndentation,
+    ThemeAuto,
+    ThemeLight,
+    ThemeDark,
+    NextTab,
+    PreviousTab,
+    PinTab,
+    LineNumbers,
+    IndentGuides,
+    RenderWhitespace,
+    Minimap,
+    GrayscaleText,
+    ColorFonts,
+    ViMode,
+    DebugConsole,
+    /// Stands in for the languages until core has listed them.
+    NoLanguages,
 }
 
+/// The command ids of the xi-core themes listed in the Theme menu, in the
+/// order of `available_themes`.
+pub const THEME_ENTRIES: Range<u32> = 0x1000..0x1100;
+
+/// The command ids of the languages listed in the Syntax menu, in the order
+/// of `available_languages`.
+pub const LANGUAGE_ENTRIES: Range<u32> = 0x1100..0x1200;
+
+/// The command ids of the encodings listed in the Reopen with Encoding menu,
+/// in the order of `Encoding::ALL`.
+pub const ENCODING_ENTRIES: Range<u32> = 0x1200..0x1210;
+
+/// The command ids of the open views listed by the tab bar's overflow
+/// button, in display order.
+pub const TAB_ENTRIES: Range<u32> = 0x1300..0x1400;
+
 pub fn create_menus() -> Menu {
     let mut file_menu = Menu::new();
+    file_menu.add_item(MenuEntries::New as u32, "&New\tCtrl+N");
     file_menu.add_item(MenuEntries::Open as u32, "&Open…\tCtrl+O");
+    file_menu.add_item(MenuEntries::Close as u32, "&Close\tCtrl+W");
     file_menu.add_item(MenuEntries::Save as u32, "&Save\tCtrl+S");
     file_menu.add_item(MenuEntries::SaveAs as u32, "Save &as…\tCtrl+Shift+S");
+    file_menu.add_item(MenuEntries::SaveWithEncoding as u32, "Save with &Encoding…");
+    let mut encoding_menu = Menu::new();
+    for (id, encoding) in ENCODING_ENTRIES.zip(Encoding::ALL) {
+        encoding_menu.add_item(id, encoding.label());
+    }
+    file_menu.add_dropdown(encoding_menu, "Reopen with E&ncoding");
+    file_menu.add_separator();
+    file_menu.add_item(
+        MenuEntries::AutoReload as u32,
+        "Auto-&Reload Unedited Files",
+    );
+    file_menu.add_item(MenuEntries::ReloadKeymap as u32, "Reload &Keymap");
+    file_menu.add_separator();
     file_menu.add_item(MenuEntries::Exit as u32, "E&xit");
     let mut menubar = Menu::new();
     menubar.add_dropdown(file_menu, "&File");
@@ -50,6 +145,28 @@ pub fn create_menus() -> Menu {
     edit_menu.add_item(MenuEntries::UpperCase as u32, "Upper Case");
     edit_menu.add_item(MenuEntries::LowerCase as u32, "Lower Case");
     edit_menu.add_item(MenuEntries::Transpose as u32, "Transpose");
+    edit_menu.add_item(MenuEntries::Indent as u32, "&Indent\tCtrl+]");
+    edit_menu.add_item(MenuEntries::Outdent as u32, "&Outdent\tCtrl+[");
+    edit_menu.add_item(MenuEntries::DuplicateLine as u32, "&Duplicate Line\tCtrl+D");
+    edit_menu.add_item(MenuEntries::DeleteLine as u32, "De&lete Line\tCtrl+Shift+K");
+    edit_menu.add_separator();
+    edit_menu.add_item(
+        MenuEntries::FormatDocument as u32,
+        "&Format Document\tShift+Alt+F",
+    );
+    edit_menu.add_item(MenuEntries::FormatSelection as u32, "Format Selectio&n");
+    edit_menu.add_item(MenuEntries::RenameSymbol as u32, "Rena&me Symbol\tF2");
+    edit_menu.add_separator();
+    edit_menu.add_item(MenuEntries::Find as u32, "&Find…\tCtrl+F");
+    edit_menu.add_item(MenuEntries::FindNext as u32, "Find &Next\tF3");
+    edit_menu.add_item(MenuEntries::FindPrevious as u32, "Find Pre&vious\tShift+F3");
+    edit_menu.add_separator();
+    let mut line_ending_menu = Menu::new();
+    line_ending_menu.add_item(MenuEntries::LineEndingCrLf as u32, "&Windows (CRLF)");
+    line_ending_menu.add_item(MenuEntries::LineEndingLf as u32, "&Unix (LF)");
+    edit_menu.add_dropdown(line_ending_menu, "Line &Endings");
+    edit_menu.add_separator();
+    edit_menu.add_item(MenuEntries::Preferences as u32, "Pre&ferences…");
     menubar.add_dropdown(edit_menu, "&Edit");
     let mut selection_menu = Menu::new();
     selection_menu.add_item(
@@ -65,13 +182,77 @@ pub fn create_menus() -> Menu {
         "Single Selection\tEscape",
     );
     selection_menu.add_item(MenuEntries::SelectAll as u32, "Select All\tCtrl+A");
+    selection_menu.add_item(MenuEntries::SelectLine as u32, "Select &Line\tCtrl+L");
+    selection_menu.add_separator();
+    selection_menu.add_item(
+        MenuEntries::ExpandSelection as u32,
+        "&Expand Selection\tAlt+Up",
+    );
+    selection_menu.add_item(
+        MenuEntries::ShrinkSelection as u32,
+        "&Shrink Selection\tAlt+Down",
+    );
+    selection_menu.add_separator();
+    selection_menu.add_item(
+        MenuEntries::InsertSequence as u32,
+        "Insert Number Se&quence",
+    );
+    let mut transform_menu = Menu::new();
+    transform_menu.add_item(MenuEntries::UpperCaseEach as u32, "&Upper Case");
+    transform_menu.add_item(MenuEntries::LowerCaseEach as u32, "&Lower Case");
+    transform_menu.add_item(MenuEntries::TrimEach as u32, "&Trim Whitespace");
+    selection_menu.add_dropdown(transform_menu, "&Transform Each Selection");
     menubar.add_dropdown(selection_menu, "&Selection");
+    let mut indentation_menu = Menu::new();
+    indentation_menu.add_item(
+        MenuEntries::IndentUsingSpaces as u32,
+        "Indent Using &Spaces",
+    );
+    indentation_menu.add_item(MenuEntries::IndentUsingTabs as u32, "Indent Using &Tabs");
+    indentation_menu.add_separator();
+    indentation_menu.add_item(MenuEntries::TabWidth2 as u32, "Tab Width: 2");
+    indentation_menu.add_item(MenuEntries::TabWidth4 as u32, "Tab Width: 4");
+    indentation_menu.add_item(MenuEntries::TabWidth8 as u32, "Tab Width: 8");
+    indentation_menu.add_separator();
+    indentation_menu.add_item(
+        MenuEntries::DetectIndentation as u32,
+        "&Detect Indentation from Content",
+    );
+    let mut theme_menu = Menu::new();
+    theme_menu.add_item(MenuEntries::ThemeAuto as u32, "&Auto");
+    theme_menu.add_item(MenuEntries::ThemeLight as u32, "&Light");
+    theme_menu.add_item(MenuEntries::ThemeDark as u32, "&Dark");
+    let mut language_menu = Menu::new();
+    language_menu.add_item(MenuEntries::NoLanguages as u32, "(None)");
+    let mut view_menu = Menu::new();
+    view_menu.add_dropdown(indentation_menu, "&Indentation");
+    view_menu.add_dropdown(theme_menu, "&Theme");
+    view_menu.add_dropdown(language_menu, "&Syntax");
+    view_menu.add_separator();
+    view_menu.add_item(MenuEntries::LineNumbers as u32, "&Line Numbers");
+    view_menu.add_item(MenuEntries::IndentGuides as u32, "Indentation &Guides");
+    view_menu.add_item(MenuEntries::RenderWhitespace as u32, "Render &Whitespace");
+    view_menu.add_item(MenuEntries::Minimap as u32, "&Minimap");
+    view_menu.add_item(MenuEntries::GrayscaleText as u32, "Gr&ayscale Text");
+    view_menu.add_item(MenuEntries::ColorFonts as u32, "&Color Emoji");
+    view_menu.add_item(MenuEntries::ViMode as u32, "&Vi Mode");
+    view_menu.add_separator();
+    view_menu.add_item(MenuEntries::NextTab as u32, "&Next Tab\tCtrl+Tab");
+    view_menu.add_item(
+        MenuEntries::PreviousTab as u32,
+        "&Previous Tab\tCtrl+Shift+Tab",
+    );
+    view_menu.add_separator();
+    view_menu.add_item(MenuEntries::DebugConsole as u32, "&Debug Console");
+    menubar.add_dropdown(view_menu, "&View");
     menubar
 }
 
 pub fn set_accel(runloop: &mut RunLoop) {
     let accel = accel! {
+        FCONTROL, 'N', MenuEntries::New,
         FCONTROL, 'O', MenuEntries::Open,
+        FCONTROL, 'W', MenuEntries::Close,
         FCONTROL, 'S', MenuEntries::Save,
         FCONTROL | FSHIFT, 'S', MenuEntries::SaveAs,
 
@@ -82,9 +263,265 @@ pub fn set_accel(runloop: &mut RunLoop) {
         FCONTROL, 'C', MenuEntries::Copy,
         FCONTROL, 'V', MenuEntries::Paste,
         FCONTROL, 'T', MenuEntries::Transpose,
+        // The bracket keys vary on non-US keyboards.
+        FCONTROL, VK_OEM_6, MenuEntries::Indent,
+        FCONTROL, VK_OEM_4, MenuEntries::Outdent,
+        FCONTROL, 'D', MenuEntries::DuplicateLine,
+        FCONTROL | FSHIFT, 'K', MenuEntries::DeleteLine,
+        FALT | FSHIFT, 'F', MenuEntries::FormatDocument,
+        FCONTROL, 'F', MenuEntries::Find,
+        0, VK_F3, MenuEntries::FindNext,
+        FSHIFT, VK_F3, MenuEntries::FindPrevious,
+        0, VK_F2, MenuEntries::RenameSymbol,
 
-        // Note: arrow keys and escape are actually handled in edit_view
+        FCONTROL, VK_TAB, MenuEntries::NextTab,
+        FCONTROL | FSHIFT, VK_TAB, MenuEntries::PreviousTab,
+
+        FCONTROL | FALT, VK_UP, MenuEntries::AddCursorAbove,
+        FCONTROL | FALT, VK_DOWN, MenuEntries::AddCursorBelow,
         FCONTROL, 'A', MenuEntries::SelectAll,
+        FCONTROL, 'L', MenuEntries::SelectLine,
+        FALT, VK_UP, MenuEntries::ExpandSelection,
+        FALT, VK_DOWN, MenuEntries::ShrinkSelection,
+        // Note: escape is handled in edit_view, as it also closes the find bar
     };
     runloop.set_accel(&accel);
 }
+
+/// Shows the edit view's context menu at the mouse. The chosen entry is
+/// sent to the window as a `WM_COMMAND`, the same as from the menu bar.
+///
+/// # Safety
+///
+/// `hwnd` must be the main window.
+pub unsafe fn show_context_menu(hwnd: HWND) {
+    let entries = [
+        Some((MenuEntries::Undo, "&Undo\tCtrl+Z")),
+        Some((MenuEntries::Redo, "&Redo\tCtrl+Y")),
+        None,
+        Some((MenuEntries::Cut, "Cu&t\tCtrl+X")),
+        Some((MenuEntries::Copy, "&Copy\tCtrl+C")),
+        Some((MenuEntries::Paste, "&Paste\tCtrl+V")),
+        None,
+        Some((MenuEntries::SelectAll, "Select &All\tCtrl+A")),
+    ];
+    show_popup(hwnd, entries, |_| false);
+}
+
+/// Shows a tab's context menu at the mouse, for the focused view, which is
+/// `pinned` or not.
+///
+/// # Safety
+///
+/// `hwnd` must be the main window.
+pub unsafe fn show_tab_menu(hwnd: HWND, pinned: bool) {
+    let entries = [
+        Some((MenuEntries::PinTab, "&Pin Tab")),
+        None,
+        Some((MenuEntries::Close, "&Close\tCtrl+W")),
+    ];
+    show_popup(hwnd, entries, |id| {
+        matches!(id, MenuEntries::PinTab) && pinned
+    });
+}
+
+/// Shows the status bar's indentation menu at the mouse, with the same
+/// choices as View > Indentation, and `indentation` and `detect` checked.
+///
+/// # Safety
+///
+/// `hwnd` must be the main window.
+pub unsafe fn show_indentation_menu(hwnd: HWND, indentation: Indentation, detect: bool) {
+    let entries = [
+        Some((MenuEntries::IndentUsingSpaces, "Indent Using &Spaces")),
+        Some((MenuEntries::IndentUsingTabs, "Indent Using &Tabs")),
+        None,
+        Some((MenuEntries::TabWidth2, "Tab Width: 2")),
+        Some((MenuEntries::TabWidth4, "Tab Width: 4")),
+        Some((MenuEntries::TabWidth8, "Tab Width: 8")),
+        None,
+        Some((
+            MenuEntries::DetectIndentation,
+            "&Detect Indentation from Content",
+        )),
+    ];
+    show_popup(hwnd, entries, |id| match id {
+        MenuEntries::IndentUsingSpaces => indentation.translate_tabs_to_spaces,
+        MenuEntries::IndentUsingTabs => !indentation.translate_tabs_to_spaces,
+        MenuEntries::TabWidth2 => indentation.tab_size == 2,
+        MenuEntries::TabWidth4 => indentation.tab_size == 4,
+        MenuEntries::TabWidth8 => indentation.tab_size == 8,
+        MenuEntries::DetectIndentation => detect,
+        _ => false,
+    });
+}
+
+/// Shows a menu at the mouse, with `None` for a separator. The chosen entry
+/// is sent to the window as a `WM_COMMAND`, the same as from the menu bar.
+unsafe fn show_popup<const N: usize>(
+    hwnd: HWND,
+    entries: [Option<(MenuEntries, &str)>; N],
+    checked: impl Fn(&MenuEntries) -> bool,
+) {
+    let menu = CreatePopupMenu();
+    if menu.is_null() {
+        return;
+    }
+    for entry in entries {
+        if let Some((id, name)) = entry {
+            let name: Vec<u16> = OsStr::new(name).encode_wide().chain(Some(0)).collect();
+            let check = if checked(&id) {
+                MF_CHECKED
+            } else {
+                MF_UNCHECKED
+            };
+            AppendMenuW(menu, MF_STRING | check, id as usize, name.as_ptr());
+        } else {
+            AppendMenuW(menu, MF_SEPARATOR, 0, ptr::null());
+        }
+    }
+    let mut pos = POINT { x: 0, y: 0 };
+    if GetCursorPos(&mut pos) != 0 {
+        TrackPopupMenu(menu, TPM_RIGHTBUTTON, pos.x, pos.y, 0, hwnd, ptr::null());
+    }
+    DestroyMenu(menu);
+}
+
+/// Shows the list of every open view at the mouse, for when their tabs don't
+/// all fit, with the focused one checked.
+///
+/// # Safety
+///
+/// `hwnd` must be the main window.
+pub unsafe fn show_tab_list(hwnd: HWND, titles: &[String], focused: Option<usize>) {
+    let menu = CreatePopupMenu();
+    if menu.is_null() {
+        return;
+    }
+    for (i, (id, title)) in TAB_ENTRIES.zip(titles).enumerate() {
+        let name: Vec<u16> = OsStr::new(title).encode_wide().chain(Some(0)).collect();
+        let check = if Some(i) == focused {
+            MF_CHECKED
+        } else {
+            MF_UNCHECKED
+        };
+        AppendMenuW(menu, MF_STRING | check, id as usize, name.as_ptr());
+    }
+    let mut pos = POINT { x: 0, y: 0 };
+    if GetCursorPos(&mut pos) != 0 {
+        TrackPopupMenu(menu, TPM_RIGHTBUTTON, pos.x, pos.y, 0, hwnd, ptr::null());
+    }
+    DestroyMenu(menu);
+}
+
+/// Lists xi-core's themes at the end of the Theme menu, replacing any listed
+/// before.
+///
+/// # Safety
+///
+/// `hwnd` must be the main window, with the menus from [`create_menus`].
+pub unsafe fn set_theme_entries(hwnd: HWND, themes: &[String]) {
+    let Some(theme_menu) = find_submenu(GetMenu(hwnd), MenuEntries::ThemeDark as u32) else {
+        return;
+    };
+    // Everything after Dark is a separator and the previous themes.
+    let count = GetMenuItemCount(theme_menu);
+    if let Some(dark) =
+        (0..count).find(|&i| GetMenuItemID(theme_menu, i) == MenuEntries::ThemeDark as u32)
+    {
+        for i in (dark + 1..count).rev() {
+            DeleteMenu(theme_menu, i as u32, MF_BYPOSITION);
+        }
+    }
+    if themes.is_empty() {
+        return;
+    }
+    AppendMenuW(theme_menu, MF_SEPARATOR, 0, ptr::null());
+    for (id, theme) in THEME_ENTRIES.zip(themes) {
+        let name: Vec<u16> = OsStr::new(theme).encode_wide().chain(Some(0)).collect();
+        AppendMenuW(theme_menu, MF_STRING, id as usize, name.as_ptr());
+    }
+}
+
+/// Lists the languages core knows in the Syntax menu, replacing any listed
+/// before.
+///
+/// # Safety
+///
+/// `hwnd` must be the main window, with the menus from [`create_menus`].
+pub unsafe fn set_language_entries(hwnd: HWND, languages: &[String]) {
+    let Some(language_menu) = find_language_menu(hwnd) else {
+        return;
+    };
+    for i in (0..GetMenuItemCount(language_menu)).rev() {
+        DeleteMenu(language_menu, i as u32, MF_BYPOSITION);
+    }
+    if languages.is_empty() {
+        let name: Vec<u16> = OsStr::new("(None)").encode_wide().chain(Some(0)).collect();
+        let id = MenuEntries::NoLanguages as usize;
+        AppendMenuW(language_menu, MF_STRING | MF_GRAYED, id, name.as_ptr());
+        return;
+    }
+    for (id, language) in LANGUAGE_ENTRIES.zip(languages) {
+        let name: Vec<u16> = OsStr::new(language).encode_wide().chain(Some(0)).collect();
+        AppendMenuW(language_menu, MF_STRING, id as usize, name.as_ptr());
+    }
+}
+
+/// Checks or unchecks View > Indentation > Detect Indentation from Content.
+///
+/// # Safety
+///
+/// `hwnd` must be the main window, with the menus from [`create_menus`].
+pub unsafe fn check_detect_indentation(hwnd: HWND, detect: bool) {
+    let check = if detect { MF_CHECKED } else { MF_UNCHECKED };
+    CheckMenuItem(
+        GetMenu(hwnd),
+        MenuEntries::DetectIndentation as u32,
+        MF_BYCOMMAND | check,
+    );
+}
+
+/// Puts the radio check on the language at `index` in the Syntax menu, or
+/// on none of them.
+///
+/// # Safety
+///
+/// `hwnd` must be the main window, with the menus from [`create_menus`].
+pub unsafe fn check_language(hwnd: HWND, index: Option<usize>) {
+    let Some(language_menu) = find_language_menu(hwnd) else {
+        return;
+    };
+    let count = GetMenuItemCount(language_menu).max(0) as u32;
+    let (first, last) = (LANGUAGE_ENTRIES.start, LANGUAGE_ENTRIES.start + count);
+    for id in first..last {
+        CheckMenuItem(language_menu, id, MF_BYCOMMAND | MF_UNCHECKED);
+    }
+    if let Some(index) = index {
+        let id = first + index as u32;
+        CheckMenuRadioItem(language_menu, first, last - 1, id, MF_BYCOMMAND);
+    }
+}
+
+unsafe fn find_language_menu(hwnd: HWND) -> Option<HMENU> {
+    let menu = GetMenu(hwnd);
+    find_submenu(menu, MenuEntries::NoLanguages as u32)
+        .or_else(|| find_submenu(menu, LANGUAGE_ENTRIES.start))
+}
+
+/// The submenu, at any depth, which directly contains the item `id`.
+unsafe fn find_submenu(menu: HMENU, id: u32) -> Option<HMENU> {
+    for i in 0..GetMenuItemCount(menu) {
+        let submenu = GetSubMenu(menu, i);
+        if submenu.is_null() {
+            continue;
+        }
+        if (0..GetMenuItemCount(submenu)).any(|j| GetMenuItemID(submenu, j) == id) {
+            return Some(submenu);
+        }
+        if let Some(found) = find_submenu(submenu, id) {
+            return Some(found);
+        }
+    }
+    None
+}
@@ -0,0 +1,102 @@
+//! The spacing of the edit view's text, measured from its font so that
+//! lines, carets and selections stay in step whatever font and size the text
+//! is laid out in.
+//!
+//! Everything is in dips; the window's DPI is applied when drawing, see
+//! [`dpi`](crate::dpi).
+
+use directwrite::{TextFormat, TextLayout};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics {
+    /// The distance from the top of one line to the next, which is also the
+    /// height of carets and selections.
+    pub line_height: f32,
+    /// The advance width of a space. The font is monospaced, so this is the
+    /// width of every column.
+    pub char_width: f32,
+    /// The space above the first line.
+    pub top_pad: f32,
+    /// The space left of the text, or of the line numbers.
+    pub left_pad: f32,
+}
+
+impl Default for Metrics {
+    /// The metrics until the font has been measured, with columns of no
+    /// width.
+    fn default() -> Self {
+        Self {
+            line_height: 17.0,
+            char_width: 0.0,
+            top_pad: 6.0,
+            left_pad: 6.0,
+        }
+    }
+}
+
+impl Metrics {
+    /// Measures the font of a text format.
+    pub fn measure(factory: &directwrite::Factory, format: &TextFormat) -> Self {
+        let layout = TextLayout::create(factory)
+            .with_text(" ")
+            .with_font(format)
+            .with_width(1e6)
+            .with_height(1e6)
+            .build();
+        match layout {
+            Ok(layout) => {
+                let metrics = layout.get_metrics();
+                Self {
+                    // Whole dips, so lines don't blur by starting partway
+                    // into a pixel at 96 DPI.
+                    line_height: metrics.height().ceil(),
+                    char_width: metrics.width(),
+                    ..Self::default()
+                }
+            }
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// The y of the top of a line, down from the top of the document.
+    ///
+    /// Far down a huge file the top of a line is past where an `f32` can
+    /// count single pixels, so positions down the document are `f64`. Only
+    /// those on screen are `f32`.
+    pub fn line_top(&self, line: usize) -> f64 {
+        (line as f64).mul_add(f64::from(self.line_height), f64::from(self.top_pad))
+    }
+
+    /// The y of the top of a line in a view scrolled to `scroll_offset`.
+    pub fn line_y(&self, line: usize, scroll_offset: f64) -> f32 {
+        (self.line_top(line) - scroll_offset) as f32
+    }
+
+    /// The line at `y` in a view scrolled to `scroll_offset`, which may be
+    /// past the last one. Above the first line is the first line.
+    pub fn line_at(&self, y: f32, scroll_offset: f64) -> usize {
+        let content_y = f64::from(y) + scroll_offset - f64::from(self.top_pad);
+        (content_y / f64::from(self.line_height)).floor().max(0.0) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+
+    #[test]
+    fn lines_stay_in_step_far_down() {
+        let metrics = Metrics::default();
+        let line = 1_999_999;
+        // Scrolled so that the line is at the top.
+        let scroll_offset = metrics.line_top(line) - f64::from(metrics.top_pad);
+        assert_eq!(metrics.line_y(line, scroll_offset), 0.0);
+        // Each line is exactly a line height below the one before.
+        for i in 1..40 {
+            let y = metrics.line_y(line + i, scroll_offset);
+            assert_eq!(y - metrics.line_y(line + i - 1, scroll_offset), 17.0);
+            assert_eq!(metrics.line_at(y + 1.0, scroll_offset), line + i);
+        }
+        assert_eq!(metrics.line_at(-100.0, 0.0), 0);
+    }
+}
@@ -0,0 +1,165 @@
+//! A stand-in for xi-core, so that the front end's side of the protocol can
+//! be tested without starting a real core. It plays back transcripts of
+//! recorded sessions, checking that the front end sends what it sent then,
+//! and answering as core did.
+//!
+//! A transcript has a JSON object per line, either `{"to_core": message}` or
+//! `{"from_core": message}`, in the order they were sent.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::protocol::Notification;
+use crate::rpc::{Core, Handler};
+use crate::xi_thread::XiPeer;
+
+/// How long to wait for the front end to send what the transcript expects.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct MockCore {
+    /// What the front end sent core.
+    sent: Receiver<String>,
+    /// Sends to the front end as core, until hung up.
+    reply: Sender<Value>,
+}
+
+impl MockCore {
+    /// Connects a [`Core`] to a mock core, with `handler` handling what the
+    /// mock sends.
+    pub fn connect<H>(handler: H) -> (Core, Self)
+    where
+        H: Handler + Send + 'static,
+    {
+        let (to_core_tx, to_core_rx) = channel();
+        let (from_core_tx, from_core_rx) = channel();
+        let core = Core::new(XiPeer::from_sender(to_core_tx), from_core_rx, handler);
+        let mock = Self {
+            sent: to_core_rx,
+            reply: from_core_tx,
+        };
+        (core, mock)
+    }
+
+    /// Sends a message to the front end, as core.
+    pub fn send(&self, msg: Value) {
+        self.reply.send(msg).unwrap();
+    }
+
+    /// The next message the front end sent.
+    pub fn recv(&self) -> Value {
+        let sent = self
+            .sent
+            .recv_timeout(TIMEOUT)
+            .expect("the front end sent nothing");
+        serde_json::from_str(&sent).unwrap()
+    }
+
+    /// Plays back a transcript. Its messages to core are sent through
+    /// `core`, as the front end would, and checked on arrival; its messages
+    /// from core are sent as they are. Answers to core's own requests come
+    /// from the handler, and are checked against the transcript's.
+    ///
+    /// Panics at the first message that differs, naming its line.
+    pub fn play(&self, core: &mut Core, transcript: &str) {
+        for (i, line) in transcript.lines().enumerate() {
+            let line_num = i + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Value = serde_json::from_str(line)
+                .unwrap_or_else(|err| panic!("line {line_num} of the transcript: {err}"));
+            if let Some(msg) = entry.get("from_core") {
+                self.send(msg.clone());
+            } else if let Some(msg) = entry.get("to_core") {
+                if let Some(method) = msg["method"].as_str() {
+                    if msg.get("id").is_some() {
+                        core.send_request(method, &msg["params"], |_| ());
+                    } else {
+                        core.send_notification(method, &msg["params"]);
+                    }
+                }
+                assert_eq!(self.recv(), *msg, "line {line_num} of the transcript");
+            } else {
+                panic!("line {line_num} of the transcript is neither to nor from core");
+            }
+        }
+    }
+
+    /// Hangs up, as core does when it stops. The handler is dropped once it
+    /// has handled everything sent before.
+    pub fn hang_up(self) {
+        drop(self.reply);
+    }
+}
+
+/// A handler which passes on each notification, parsed as the app parses
+/// them, or the method it couldn't parse. Core's requests are answered with
+/// `null`.
+pub struct Recorder {
+    tx: Sender<Result<Notification, String>>,
+}
+
+impl Recorder {
+    pub fn new() -> (Self, Receiver<Result<Notification, String>>) {
+        let (tx, rx) = channel();
+        (Self { tx }, rx)
+    }
+}
+
+impl Handler for Recorder {
+    fn notification(&self, method: &str, params: &Value) {
+        let notification = Notification::parse(method, params).map_err(|_| method.to_owned());
+        let _ignore = self.tx.send(notification);
+    }
+
+    fn request(&self, _method: &str, _params: &Value) -> Value {
+        Value::Null
+    }
+
+    fn disconnected(&self) {}
+}
+
+mod tests {
+    use super::*;
+    use crate::linecache::{Line, LineCache};
+
+    const OPEN_AND_TYPE: &str = include_str!("testdata/open_and_type.jsonl");
+
+    #[test]
+    fn a_recorded_session_plays_back() {
+        let (recorder, notifications) = Recorder::new();
+        let (mut core, mock) = MockCore::connect(recorder);
+        mock.play(&mut core, OPEN_AND_TYPE);
+        mock.hang_up();
+
+        let mut cache = LineCache::new();
+        let mut scrolled_to = vec![];
+        for notification in notifications.iter() {
+            match notification.expect("a notification didn't parse") {
+                Notification::Update { view_id, update } => {
+                    assert_eq!(view_id, "view-id-1");
+                    cache.apply_update(&update);
+                }
+                Notification::ScrollTo { line, .. } => scrolled_to.push(line),
+                _ => (),
+            }
+        }
+        let texts: Vec<Option<&str>> = (0..cache.total_lines())
+            .map(|i| cache.get_line(i).map(Line::text))
+            .collect();
+        assert_eq!(texts, [Some("hello\n"), Some("world")]);
+        assert_eq!(cache.first_caret_line(), Some(1));
+        assert_eq!(scrolled_to, [0, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "line 2 of the transcript")]
+    fn sending_something_else_fails_at_its_line() {
+        let (recorder, _notifications) = Recorder::new();
+        let (mut core, mock) = MockCore::connect(recorder);
+        core.send_notification("client_started", &json!({}));
+        mock.play(&mut core, OPEN_AND_TYPE);
+    }
+}
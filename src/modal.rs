@@ -0,0 +1,302 @@
+//! Vi style modal editing, turned on from the View menu.
+//!
+//! [`Modal`] turns the keys typed outside insert mode into xi-core edit
+//! actions and register operations, which the edit view carries out.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Normal => "NORMAL",
+            Self::Insert => "INSERT",
+            Self::Visual => "VISUAL",
+        })
+    }
+}
+
+/// The text to copy into the register, from the first caret.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Yank {
+    /// Characters from the caret on, up to the end of the line.
+    Chars(usize),
+    /// Whole lines, from the caret's.
+    Lines(usize),
+    /// The selected text.
+    Selection,
+}
+
+/// Something for the edit view to do in response to a key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Effect {
+    /// An edit action, sent to core.
+    Action(&'static str),
+    Yank(Yank),
+    /// Pastes the register `count` times, after the caret or before it.
+    Put {
+        after: bool,
+        count: usize,
+    },
+}
+
+/// The last yanked or deleted text.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Register {
+    pub text: String,
+    /// Whether the text is whole lines, which are put on lines of their own.
+    pub linewise: bool,
+}
+
+/// The state of modal editing: the mode, and what's been typed of a command
+/// so far.
+#[derive(Clone, Debug)]
+pub struct Modal {
+    mode: Mode,
+    /// The repeat count typed so far.
+    count: Option<usize>,
+    /// The first key of a two key command, such as the `d` of `dd`.
+    pending: Option<char>,
+    pub register: Register,
+}
+
+impl Default for Modal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Modal {
+    /// Starts in normal mode.
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Normal,
+            count: None,
+            pending: None,
+            register: Register::default(),
+        }
+    }
+
+    pub const fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Handles escape, which in any mode cancels what's been typed and goes
+    /// back to normal mode.
+    pub fn escape(&mut self) -> Vec<Effect> {
+        self.count = None;
+        self.pending = None;
+        match std::mem::replace(&mut self.mode, Mode::Normal) {
+            // Like vi, leaving insert mode steps back onto the last
+            // character inserted.
+            Mode::Insert => vec![Effect::Action("move_left")],
+            Mode::Normal | Mode::Visual => vec![Effect::Action("cancel_operation")],
+        }
+    }
+
+    /// Handles a character typed in normal or visual mode. Characters which
+    /// aren't commands are dropped, rather than inserted.
+    pub fn char(&mut self, ch: char) -> Vec<Effect> {
+        if self.mode == Mode::Insert {
+            return vec![];
+        }
+        if let Some(digit) = ch.to_digit(10) {
+            // A 0 with no count before it is a motion.
+            if digit != 0 || self.count.is_some() {
+                let count = self.count.unwrap_or(0).saturating_mul(10);
+                self.count = Some(count.saturating_add(digit as usize));
+                return vec![];
+            }
+        }
+        let count = self.count.take().unwrap_or(1);
+        let visual = self.mode == Mode::Visual;
+        let pending = self.pending.take();
+        let repeat = |action| vec![Effect::Action(action); count];
+
+        if let Some(motion) = motion(pending, ch, visual) {
+            return repeat(motion);
+        }
+        // Visual mode's d and y act on the selection straight away.
+        if pending.is_none() && matches!(ch, 'g' | 'd' | 'y') && (!visual || ch == 'g') {
+            self.pending = Some(ch);
+            // The count carries over to the second key.
+            self.count = Some(count).filter(|&n| n > 1);
+            return vec![];
+        }
+        if visual {
+            return match ch {
+                'v' => self.enter(Mode::Normal, vec![Effect::Action("cancel_operation")]),
+                'd' | 'x' => self.enter(
+                    Mode::Normal,
+                    vec![
+                        Effect::Yank(Yank::Selection),
+                        Effect::Action("delete_backward"),
+                    ],
+                ),
+                'y' => self.enter(
+                    Mode::Normal,
+                    vec![
+                        Effect::Yank(Yank::Selection),
+                        Effect::Action("cancel_operation"),
+                    ],
+                ),
+                _ => vec![],
+            };
+        }
+        match (pending, ch) {
+            (Some('d'), 'd') => {
+                let mut effects = vec![
+                    Effect::Yank(Yank::Lines(count)),
+                    Effect::Action("move_to_left_end_of_line"),
+                ];
+                effects.extend(vec![
+                    Effect::Action("move_down_and_modify_selection");
+                    count
+                ]);
+                // TODO(Olive): on the last line, this leaves the line break
+                // before it.
+                effects.push(Effect::Action("delete_backward"));
+                effects
+            }
+            (Some('d'), 'w') => {
+                // TODO(Olive): put the deleted words in the register.
+                let mut effects =
+                    vec![Effect::Action("move_word_right_and_modify_selection"); count];
+                effects.push(Effect::Action("delete_backward"));
+                effects
+            }
+            (Some('y'), 'y') => vec![Effect::Yank(Yank::Lines(count))],
+            (Some(_), _) => vec![],
+            (None, 'i') => self.enter(Mode::Insert, vec![]),
+            (None, 'a') => self.enter(Mode::Insert, vec![Effect::Action("move_right")]),
+            (None, 'I') => self.enter(
+                Mode::Insert,
+                vec![Effect::Action("move_to_left_end_of_line")],
+            ),
+            (None, 'A') => self.enter(
+                Mode::Insert,
+                vec![Effect::Action("move_to_right_end_of_line")],
+            ),
+            (None, 'o') => self.enter(
+                Mode::Insert,
+                vec![
+                    Effect::Action("move_to_right_end_of_line"),
+                    Effect::Action("insert_newline"),
+                ],
+            ),
+            (None, 'O') => self.enter(
+                Mode::Insert,
+                vec![
+                    Effect::Action("move_to_left_end_of_line"),
+                    Effect::Action("insert_newline"),
+                    Effect::Action("move_up"),
+                ],
+            ),
+            (None, 'v') => self.enter(Mode::Visual, vec![]),
+            (None, 'x') => {
+                let mut effects = vec![Effect::Yank(Yank::Chars(count))];
+                effects.extend(repeat("delete_forward"));
+                effects
+            }
+            (None, 'X') => repeat("delete_backward"),
+            (None, 'p') => vec![Effect::Put { after: true, count }],
+            (None, 'P') => vec![Effect::Put {
+                after: false,
+                count,
+            }],
+            (None, 'u') => repeat("undo"),
+            // Ctrl+R, which arrives as a control character.
+            (None, '\u{12}') => repeat("redo"),
+            (None, _) => vec![],
+        }
+    }
+
+    fn enter(&mut self, mode: Mode, effects: Vec<Effect>) -> Vec<Effect> {
+        self.mode = mode;
+        effects
+    }
+}
+
+/// The edit action moving the carets for a motion key, or in visual mode,
+/// extending the selections.
+fn motion(pending: Option<char>, ch: char, visual: bool) -> Option<&'static str> {
+    let (motion, extend) = match (pending, ch) {
+        (None, 'h') => ("move_left", "move_left_and_modify_selection"),
+        (None, 'j') => ("move_down", "move_down_and_modify_selection"),
+        (None, 'k') => ("move_up", "move_up_and_modify_selection"),
+        (None, 'l') => ("move_right", "move_right_and_modify_selection"),
+        (None, 'w' | 'e') => ("move_word_right", "move_word_right_and_modify_selection"),
+        (None, 'b') => ("move_word_left", "move_word_left_and_modify_selection"),
+        (None, '0' | '^') => (
+            "move_to_left_end_of_line",
+            "move_to_left_end_of_line_and_modify_selection",
+        ),
+        (None, '$') => (
+            "move_to_right_end_of_line",
+            "move_to_right_end_of_line_and_modify_selection",
+        ),
+        (None, 'G') => (
+            "move_to_end_of_document",
+            "move_to_end_of_document_and_modify_selection",
+        ),
+        (Some('g'), 'g') => (
+            "move_to_beginning_of_document",
+            "move_to_beginning_of_document_and_modify_selection",
+        ),
+        _ => return None,
+    };
+    Some(if visual { extend } else { motion })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Effect, Modal, Mode, Yank};
+
+    fn type_keys(modal: &mut Modal, keys: &str) -> Vec<Effect> {
+        keys.chars().flat_map(|ch| modal.char(ch)).collect()
+    }
+
+    #[test]
+    fn counts_repeat_commands() {
+        let mut modal = Modal::new();
+        assert_eq!(
+            type_keys(&mut modal, "3j"),
+            [Effect::Action("move_down"); 3]
+        );
+        assert_eq!(
+            type_keys(&mut modal, "0"),
+            [Effect::Action("move_to_left_end_of_line")]
+        );
+        assert_eq!(type_keys(&mut modal, "10l").len(), 10);
+        assert_eq!(type_keys(&mut modal, "2yy"), [Effect::Yank(Yank::Lines(2))]);
+        assert_eq!(
+            type_keys(&mut modal, "d2d")[0],
+            Effect::Yank(Yank::Lines(2))
+        );
+    }
+
+    #[test]
+    fn modes_change() {
+        let mut modal = Modal::new();
+        type_keys(&mut modal, "a");
+        assert_eq!(modal.mode(), Mode::Insert);
+        // Insert mode leaves typing to the view.
+        assert_eq!(type_keys(&mut modal, "hj"), []);
+        assert_eq!(modal.escape(), [Effect::Action("move_left")]);
+        assert_eq!(
+            type_keys(&mut modal, "vj"),
+            [Effect::Action("move_down_and_modify_selection")]
+        );
+        assert_eq!(modal.mode(), Mode::Visual);
+        type_keys(&mut modal, "y");
+        assert_eq!(modal.mode(), Mode::Normal);
+        // Unknown keys are dropped, along with a half typed command.
+        assert_eq!(type_keys(&mut modal, "dqj"), [Effect::Action("move_down")]);
+    }
+}
@@ -0,0 +1,102 @@
+//! Conversions between the utf-8 offsets core uses and the utf-16 offsets
+//! DirectWrite uses, and snapping offsets to grapheme clusters.
+
+use unicode_segmentation::GraphemeCursor;
+
+/// Counts the number of utf-16 code units in the given string.
+pub fn count_utf16(s: &str) -> usize {
+    s.chars().map(char::len_utf16).sum()
+}
+
+/// Converts a utf-16 offset into `s` to a utf-8 one. An offset between the
+/// two halves of a surrogate pair goes to the start of its character, and
+/// one past the end to the end.
+pub fn utf16_to_utf8(s: &str, utf16_offset: usize) -> usize {
+    let mut utf16_count = 0;
+    for (i, ch) in s.char_indices() {
+        utf16_count += ch.len_utf16();
+        if utf16_count > utf16_offset {
+            return i;
+        }
+    }
+    s.len()
+}
+
+/// Converts a utf-8 offset into `s` to a utf-16 one. An offset inside a
+/// character goes to its start, and one past the end to the end.
+pub fn utf8_to_utf16(s: &str, utf8_offset: usize) -> usize {
+    s.char_indices()
+        .take_while(|&(i, ch)| i + ch.len_utf8() <= utf8_offset)
+        .map(|(_, ch)| ch.len_utf16())
+        .sum()
+}
+
+/// The grapheme cluster boundary at or before a utf-8 offset into `s`, so
+/// that a caret never lands inside a cluster such as a letter and its
+/// combining accents.
+pub fn grapheme_start(s: &str, utf8_offset: usize) -> usize {
+    let mut offset = utf8_offset.min(s.len());
+    while !s.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let mut cursor = GraphemeCursor::new(offset, s.len(), true);
+    match cursor.is_boundary(s, 0) {
+        Ok(true) => offset,
+        _ => cursor.prev_boundary(s, 0).ok().flatten().unwrap_or(0),
+    }
+}
+
+/// The grapheme cluster boundary after a utf-8 offset into `s`, or the end
+/// of `s` if there is none.
+pub fn next_grapheme_boundary(s: &str, utf8_offset: usize) -> usize {
+    let offset = grapheme_start(s, utf8_offset);
+    let mut cursor = GraphemeCursor::new(offset, s.len(), true);
+    cursor.next_boundary(s, 0).ok().flatten().unwrap_or(s.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        count_utf16, grapheme_start, next_grapheme_boundary, utf16_to_utf8, utf8_to_utf16,
+    };
+
+    // "a", "é" (2 bytes, 1 unit), "😀" (4 bytes, 2 units), "e" + combining
+    // acute (1 + 2 bytes, 2 units), "z".
+    const TEXT: &str = "aé😀e\u{301}z";
+
+    #[test]
+    fn counts_units() {
+        assert_eq!(count_utf16(""), 0);
+        assert_eq!(count_utf16(TEXT), 7);
+    }
+
+    #[test]
+    fn converts_offsets() {
+        let pairs = [(0, 0), (1, 1), (3, 2), (7, 4), (8, 5), (10, 6), (11, 7)];
+        for (utf8, utf16) in pairs {
+            assert_eq!(utf16_to_utf8(TEXT, utf16), utf8, "utf-16 {utf16}");
+            assert_eq!(utf8_to_utf16(TEXT, utf8), utf16, "utf-8 {utf8}");
+        }
+        // Inside the surrogate pair of the emoji.
+        assert_eq!(utf16_to_utf8(TEXT, 3), 3);
+        // Inside the bytes of "é" and the emoji.
+        assert_eq!(utf8_to_utf16(TEXT, 2), 1);
+        assert_eq!(utf8_to_utf16(TEXT, 5), 2);
+        // Past the end.
+        assert_eq!(utf16_to_utf8(TEXT, 100), TEXT.len());
+        assert_eq!(utf8_to_utf16(TEXT, 100), 7);
+    }
+
+    #[test]
+    fn snaps_to_graphemes() {
+        // Between the "e" and its accent.
+        assert_eq!(grapheme_start(TEXT, 8), 7);
+        assert_eq!(next_grapheme_boundary(TEXT, 8), 10);
+        // Inside the emoji's bytes.
+        assert_eq!(grapheme_start(TEXT, 5), 3);
+        assert_eq!(next_grapheme_boundary(TEXT, 3), 7);
+        assert_eq!(grapheme_start(TEXT, 10), 10);
+        assert_eq!(next_grapheme_boundary(TEXT, 10), 11);
+        assert_eq!(next_grapheme_boundary(TEXT, 11), 11);
+    }
+}
@@ -0,0 +1,95 @@
+//! Commands provided by xi-core plugins.
+
+use serde_json::Value;
+
+use crate::protocol::PluginCmd;
+
+/// Finds the command which formats the document, or only the selections,
+/// among those the `running` plugins of a view declare in their manifests.
+/// Formatters edit the buffer through core, so what they change comes back
+/// as the usual updates.
+pub fn find_formatter<'a>(
+    commands: &'a [PluginCommand],
+    running: &[String],
+    selection_only: bool,
+) -> Option<&'a PluginCommand> {
+    commands.iter().find(|cmd| {
+        let name = format!("{} {}", cmd.method, cmd.title).to_lowercase();
+        running.contains(&cmd.receiver)
+            && name.contains("format")
+            && name.contains("selection") == selection_only
+    })
+}
+
+/// A command to run in a plugin, on behalf of one view.
+#[derive(Clone, Debug)]
+pub struct PluginCommand {
+    /// The name of the plugin to receive the command.
+    pub receiver: String,
+    /// As shown to the user.
+    pub title: String,
+    pub method: String,
+    pub params: Value,
+    /// `"notification"` or `"request"`.
+    pub rpc_type: String,
+}
+
+impl PluginCommand {
+    /// A command declared in the manifest of the plugin `receiver`.
+    pub fn from_manifest(receiver: &str, cmd: PluginCmd) -> Self {
+        Self {
+            receiver: receiver.to_owned(),
+            title: cmd.title,
+            method: cmd.rpc_cmd.method,
+            params: cmd.rpc_cmd.params,
+            rpc_type: cmd.rpc_cmd.rpc_type,
+        }
+    }
+
+    /// The params of the `plugin_rpc` notification which runs this command
+    /// for the given view.
+    pub fn to_rpc_params(&self, view_id: &str) -> Value {
+        json!({
+            "view_id": view_id,
+            "receiver": self.receiver,
+            "rpc": {
+                "rpc_type": self.rpc_type,
+                "method": self.method,
+                "params": self.params,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(title: &str, method: &str) -> PluginCommand {
+        PluginCommand {
+            receiver: "fmt".to_owned(),
+            title: title.to_owned(),
+            method: method.to_owned(),
+            params: Value::Null,
+            rpc_type: "notification".to_owned(),
+        }
+    }
+
+    #[test]
+    fn formatters_are_found_by_title_or_method() {
+        let commands = [
+            command("Sort Lines", "sort"),
+            command("Reformat Selection", "fmt_range"),
+            command("Tidy", "format_file"),
+        ];
+        let running = ["fmt".to_owned()];
+        let method = |cmd: &PluginCommand| cmd.method.clone();
+        let document = find_formatter(&commands, &running, false).map(method);
+        assert_eq!(document.as_deref(), Some("format_file"));
+        let selection = find_formatter(&commands, &running, true).map(method);
+        assert_eq!(selection.as_deref(), Some("fmt_range"));
+        assert!(find_formatter(&commands[..1], &running, false).is_none());
+        // Nor are the commands of plugins which aren't running.
+        assert!(find_formatter(&commands, &[], false).is_none());
+    }
+}
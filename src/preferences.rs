@@ -0,0 +1,437 @@
+//! The Preferences dialog (Edit > Preferences), for the settings of the xi
+//! config which are most often changed.
+//!
+//! The dialog is built from an in-memory template, as the app has no
+//! resource file. What it changes is sent to core with `modify_user_config`,
+//! and comes back to every view as `config_changed`.
+
+use std::ptr;
+
+use serde_json::{Map, Value};
+
+use winapi::shared::basetsd::INT_PTR;
+use winapi::shared::minwindef::{DWORD, FALSE, LOWORD, LPARAM, TRUE, UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::winuser::{
+    CheckDlgButton, DialogBoxIndirectParamW, EndDialog, GetDlgItemTextW, GetWindowLongPtrW,
+    IsDlgButtonChecked, SendDlgItemMessageW, SetDlgItemTextW, SetWindowLongPtrW, BST_CHECKED,
+    BST_UNCHECKED, BS_AUTOCHECKBOX, BS_DEFPUSHBUTTON, BS_PUSHBUTTON, CBS_DROPDOWNLIST,
+    CB_ADDSTRING, CB_GETCURSEL, CB_SETCURSEL, DLGTEMPLATE, DS_CENTER, DS_MODALFRAME, DS_SETFONT,
+    ES_AUTOHSCROLL, GWLP_USERDATA, IDCANCEL, IDOK, WM_COMMAND, WM_INITDIALOG, WS_BORDER,
+    WS_CAPTION, WS_CHILD, WS_POPUP, WS_SYSMENU, WS_TABSTOP, WS_VISIBLE, WS_VSCROLL,
+};
+
+use crate::color_scheme::ThemeMode;
+use crate::file_dialog::to_wide;
+use crate::protocol::ConfigChanges;
+
+/// The settings shown in the dialog.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preferences {
+    pub font_face: String,
+    /// In dips.
+    pub font_size: f32,
+    pub tab_size: usize,
+    pub translate_tabs_to_spaces: bool,
+    pub word_wrap: bool,
+    pub auto_indent: bool,
+    pub autodetect_whitespace: bool,
+    /// Done by the front end, as core has no such setting.
+    pub auto_close_brackets: bool,
+    /// Not an xi config setting, but the app's own, as chosen from the View
+    /// menu.
+    pub theme: ThemeMode,
+}
+
+impl Default for Preferences {
+    /// Core's defaults, until it has sent the settings.
+    fn default() -> Self {
+        Self {
+            font_face: "InconsolataGo".to_owned(),
+            font_size: 14.0,
+            tab_size: 4,
+            translate_tabs_to_spaces: true,
+            word_wrap: false,
+            auto_indent: true,
+            autodetect_whitespace: true,
+            auto_close_brackets: true,
+            theme: ThemeMode::Auto,
+        }
+    }
+}
+
+impl Preferences {
+    /// Applies the changes of a `config_changed` notification.
+    pub fn config_changed(&mut self, changes: &ConfigChanges) {
+        if let Some(font_face) = &changes.font_face {
+            self.font_face.clone_from(font_face);
+        }
+        if let Some(font_size) = changes.font_size {
+            self.font_size = font_size;
+        }
+        if let Some(tab_size) = changes.tab_size {
+            self.tab_size = tab_size;
+        }
+        if let Some(translate) = changes.translate_tabs_to_spaces {
+            self.translate_tabs_to_spaces = translate;
+        }
+        if let Some(word_wrap) = changes.word_wrap {
+            self.word_wrap = word_wrap;
+        }
+        if let Some(auto_indent) = changes.auto_indent {
+            self.auto_indent = auto_indent;
+        }
+        if let Some(detect) = changes.autodetect_whitespace {
+            self.autodetect_whitespace = detect;
+        }
+        if let Some(auto_close) = changes.auto_close_brackets {
+            self.auto_close_brackets = auto_close;
+        }
+    }
+
+    /// The xi config settings which differ from `old`, as the `changes` of
+    /// `modify_user_config`.
+    pub fn changes_from(&self, old: &Self) -> Map<String, Value> {
+        let mut changes = Map::new();
+        if self.font_face != old.font_face {
+            changes.insert("font_face".to_owned(), json!(self.font_face));
+        }
+        if self.font_size != old.font_size {
+            changes.insert("font_size".to_owned(), json!(self.font_size));
+        }
+        if self.tab_size != old.tab_size {
+            changes.insert("tab_size".to_owned(), json!(self.tab_size));
+        }
+        if self.translate_tabs_to_spaces != old.translate_tabs_to_spaces {
+            changes.insert(
+                "translate_tabs_to_spaces".to_owned(),
+                json!(self.translate_tabs_to_spaces),
+            );
+        }
+        if self.word_wrap != old.word_wrap {
+            changes.insert("word_wrap".to_owned(), json!(self.word_wrap));
+        }
+        if self.auto_indent != old.auto_indent {
+            changes.insert("auto_indent".to_owned(), json!(self.auto_indent));
+        }
+        if self.autodetect_whitespace != old.autodetect_whitespace {
+            changes.insert(
+                "autodetect_whitespace".to_owned(),
+                json!(self.autodetect_whitespace),
+            );
+        }
+        if self.auto_close_brackets != old.auto_close_brackets {
+            changes.insert(
+                "auto_close_brackets".to_owned(),
+                json!(self.auto_close_brackets),
+            );
+        }
+        changes
+    }
+}
+
+const ID_FONT_FACE: u16 = 101;
+const ID_FONT_SIZE: u16 = 102;
+const ID_TAB_SIZE: u16 = 103;
+const ID_SPACES: u16 = 104;
+const ID_WORD_WRAP: u16 = 105;
+const ID_AUTO_INDENT: u16 = 106;
+const ID_THEME: u16 = 107;
+const ID_AUTODETECT: u16 = 108;
+const ID_AUTO_CLOSE: u16 = 109;
+
+/// The predefined window classes, by atom.
+const BUTTON: u16 = 0x0080;
+const EDIT: u16 = 0x0081;
+const STATIC: u16 = 0x0082;
+const COMBO_BOX: u16 = 0x0085;
+
+/// The themes in the order of the combo box.
+const THEMES: [(ThemeMode, &str); 3] = [
+    (ThemeMode::Auto, "Auto"),
+    (ThemeMode::Light, "Light"),
+    (ThemeMode::Dark, "Dark"),
+];
+
+/// The dialog's state, while it's shown.
+struct Dialog {
+    old: Preferences,
+    new: Option<Preferences>,
+}
+
+/// Shows the dialog, returning the settings chosen, or `None` if it was
+/// cancelled.
+///
+/// # Safety
+///
+/// `owner` must be a window created by the current thread.
+pub unsafe fn show(owner: HWND, preferences: &Preferences) -> Option<Preferences> {
+    let template = template();
+    let mut dialog = Dialog {
+        old: preferences.clone(),
+        new: None,
+    };
+    DialogBoxIndirectParamW(
+        GetModuleHandleW(ptr::null()),
+        template.as_ptr().cast::<DLGTEMPLATE>(),
+        owner,
+        Some(dialog_proc),
+        (&mut dialog as *mut Dialog) as LPARAM,
+    );
+    dialog.new
+}
+
+/// The dialog's template, in dialog units. It's a `Vec<u32>` as the
+/// template must be aligned to a `DWORD`.
+fn template() -> Vec<u32> {
+    let mut template = Template::new("Preferences", (220, 178));
+    let label = WS_CHILD | WS_VISIBLE;
+    let field = WS_CHILD | WS_VISIBLE | WS_TABSTOP | WS_BORDER | ES_AUTOHSCROLL;
+    let check = WS_CHILD | WS_VISIBLE | WS_TABSTOP | BS_AUTOCHECKBOX;
+    template.item(STATIC, 0xffff, label, (7, 9, 50, 8), "&Font:");
+    template.item(EDIT, ID_FONT_FACE, field, (60, 7, 153, 12), "");
+    template.item(STATIC, 0xffff, label, (7, 27, 50, 8), "Font &size:");
+    template.item(EDIT, ID_FONT_SIZE, field, (60, 25, 40, 12), "");
+    template.item(STATIC, 0xffff, label, (7, 45, 50, 8), "&Tab size:");
+    template.item(EDIT, ID_TAB_SIZE, field, (60, 43, 40, 12), "");
+    template.item(
+        BUTTON,
+        ID_SPACES,
+        check,
+        (7, 62, 206, 10),
+        "Indent using s&paces",
+    );
+    template.item(BUTTON, ID_WORD_WRAP, check, (7, 76, 206, 10), "&Word wrap");
+    template.item(
+        BUTTON,
+        ID_AUTO_INDENT,
+        check,
+        (7, 90, 206, 10),
+        "&Auto-indent",
+    );
+    template.item(
+        BUTTON,
+        ID_AUTODETECT,
+        check,
+        (7, 104, 206, 10),
+        "&Detect indentation from content",
+    );
+    template.item(
+        BUTTON,
+        ID_AUTO_CLOSE,
+        check,
+        (7, 118, 206, 10),
+        "Auto-&close brackets and quotes",
+    );
+    template.item(STATIC, 0xffff, label, (7, 136, 50, 8), "T&heme:");
+    let combo = WS_CHILD | WS_VISIBLE | WS_TABSTOP | WS_VSCROLL | CBS_DROPDOWNLIST;
+    template.item(COMBO_BOX, ID_THEME, combo, (60, 134, 80, 60), "");
+    let button = WS_CHILD | WS_VISIBLE | WS_TABSTOP;
+    template.item(
+        BUTTON,
+        IDOK as u16,
+        button | BS_DEFPUSHBUTTON,
+        (106, 157, 50, 14),
+        "OK",
+    );
+    template.item(
+        BUTTON,
+        IDCANCEL as u16,
+        button | BS_PUSHBUTTON,
+        (163, 157, 50, 14),
+        "Cancel",
+    );
+    template.finish()
+}
+
+/// A `DLGTEMPLATE` and its `DLGITEMTEMPLATE`s, as the words they're made of.
+struct Template {
+    words: Vec<u16>,
+    items: u16,
+}
+
+impl Template {
+    fn new(title: &str, (cx, cy): (i16, i16)) -> Self {
+        let style = WS_POPUP | WS_CAPTION | WS_SYSMENU | DS_MODALFRAME | DS_CENTER | DS_SETFONT;
+        let mut template = Self {
+            words: vec![],
+            items: 0,
+        };
+        template.dword(style);
+        template.dword(0);
+        // The item count, filled in by `finish`.
+        template.words.push(0);
+        template.rect((0, 0, cx, cy));
+        // No menu, the default class, then the title and font.
+        template.words.extend([0, 0]);
+        template.words.extend(to_wide(title));
+        template.words.push(9);
+        template.words.extend(to_wide("Segoe UI"));
+        template
+    }
+
+    fn item(&mut self, class: u16, id: u16, style: DWORD, rect: (i16, i16, i16, i16), text: &str) {
+        if self.words.len() % 2 == 1 {
+            self.words.push(0);
+        }
+        self.dword(style);
+        self.dword(0);
+        self.rect(rect);
+        self.words.push(id);
+        self.words.extend([0xffff, class]);
+        self.words.extend(to_wide(text));
+        // No creation data.
+        self.words.push(0);
+        self.items += 1;
+    }
+
+    fn finish(mut self) -> Vec<u32> {
+        self.words[4] = self.items;
+        if self.words.len() % 2 == 1 {
+            self.words.push(0);
+        }
+        self.words
+            .chunks(2)
+            .map(|pair| u32::from(pair[0]) | u32::from(pair[1]) << 16)
+            .collect()
+    }
+
+    fn dword(&mut self, value: DWORD) {
+        self.words.extend([value as u16, (value >> 16) as u16]);
+    }
+
+    fn rect(&mut self, (x, y, cx, cy): (i16, i16, i16, i16)) {
+        self.words
+            .extend([x as u16, y as u16, cx as u16, cy as u16]);
+    }
+}
+
+unsafe extern "system" fn dialog_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> INT_PTR {
+    match msg {
+        WM_INITDIALOG => {
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, lparam);
+            let dialog = &*(lparam as *const Dialog);
+            let old = &dialog.old;
+            set_text(hwnd, ID_FONT_FACE, &old.font_face);
+            set_text(hwnd, ID_FONT_SIZE, &old.font_size.to_string());
+            set_text(hwnd, ID_TAB_SIZE, &old.tab_size.to_string());
+            set_checked(hwnd, ID_SPACES, old.translate_tabs_to_spaces);
+            set_checked(hwnd, ID_WORD_WRAP, old.word_wrap);
+            set_checked(hwnd, ID_AUTO_INDENT, old.auto_indent);
+            set_checked(hwnd, ID_AUTODETECT, old.autodetect_whitespace);
+            set_checked(hwnd, ID_AUTO_CLOSE, old.auto_close_brackets);
+            for (i, (theme, name)) in THEMES.iter().enumerate() {
+                let name = to_wide(name);
+                SendDlgItemMessageW(
+                    hwnd,
+                    i32::from(ID_THEME),
+                    CB_ADDSTRING,
+                    0,
+                    name.as_ptr() as LPARAM,
+                );
+                if *theme == old.theme {
+                    SendDlgItemMessageW(hwnd, i32::from(ID_THEME), CB_SETCURSEL, i, 0);
+                }
+            }
+            TRUE as INT_PTR
+        }
+        WM_COMMAND => {
+            let dialog = &mut *(GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut Dialog);
+            match i32::from(LOWORD(wparam as u32)) {
+                IDOK => {
+                    dialog.new = Some(read(hwnd, &dialog.old));
+                    EndDialog(hwnd, 1);
+                }
+                IDCANCEL => {
+                    EndDialog(hwnd, 0);
+                }
+                _ => return FALSE as INT_PTR,
+            }
+            TRUE as INT_PTR
+        }
+        _ => FALSE as INT_PTR,
+    }
+}
+
+/// The settings as the dialog shows them. Numbers which don't parse, or are
+/// out of range, are left as they were.
+unsafe fn read(hwnd: HWND, old: &Preferences) -> Preferences {
+    let font_face = get_text(hwnd, ID_FONT_FACE);
+    let font_size = get_text(hwnd, ID_FONT_SIZE).trim().parse::<f32>();
+    let tab_size = get_text(hwnd, ID_TAB_SIZE).trim().parse::<usize>();
+    let theme = SendDlgItemMessageW(hwnd, i32::from(ID_THEME), CB_GETCURSEL, 0, 0);
+    Preferences {
+        font_face: Some(font_face.trim().to_owned())
+            .filter(|face| !face.is_empty())
+            .unwrap_or_else(|| old.font_face.clone()),
+        font_size: font_size
+            .ok()
+            .filter(|size| (1.0..=200.0).contains(size))
+            .unwrap_or(old.font_size),
+        tab_size: tab_size
+            .ok()
+            .filter(|size| (1..=32).contains(size))
+            .unwrap_or(old.tab_size),
+        translate_tabs_to_spaces: is_checked(hwnd, ID_SPACES),
+        word_wrap: is_checked(hwnd, ID_WORD_WRAP),
+        auto_indent: is_checked(hwnd, ID_AUTO_INDENT),
+        autodetect_whitespace: is_checked(hwnd, ID_AUTODETECT),
+        auto_close_brackets: is_checked(hwnd, ID_AUTO_CLOSE),
+        theme: usize::try_from(theme)
+            .ok()
+            .and_then(|i| THEMES.get(i))
+            .map_or(old.theme, |&(theme, _)| theme),
+    }
+}
+
+unsafe fn set_text(hwnd: HWND, id: u16, text: &str) {
+    SetDlgItemTextW(hwnd, i32::from(id), to_wide(text).as_ptr());
+}
+
+unsafe fn get_text(hwnd: HWND, id: u16) -> String {
+    let mut buf = [0_u16; 256];
+    let len = GetDlgItemTextW(hwnd, i32::from(id), buf.as_mut_ptr(), buf.len() as i32);
+    String::from_utf16_lossy(&buf[..len as usize])
+}
+
+unsafe fn set_checked(hwnd: HWND, id: u16, checked: bool) {
+    let state = if checked { BST_CHECKED } else { BST_UNCHECKED };
+    CheckDlgButton(hwnd, i32::from(id), state);
+}
+
+unsafe fn is_checked(hwnd: HWND, id: u16) -> bool {
+    IsDlgButtonChecked(hwnd, i32::from(id)) == BST_CHECKED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_what_changed_is_sent() {
+        let mut old = Preferences::default();
+        let changes: ConfigChanges =
+            serde_json::from_value(json!({"font_face": "Consolas", "tab_size": 2})).unwrap();
+        old.config_changed(&changes);
+        assert_eq!(old.font_face, "Consolas");
+        assert_eq!(old.tab_size, 2);
+
+        let new = Preferences {
+            font_size: 12.0,
+            word_wrap: true,
+            theme: ThemeMode::Dark,
+            ..old.clone()
+        };
+        assert_eq!(
+            Value::Object(new.changes_from(&old)),
+            json!({"font_size": 12.0, "word_wrap": true})
+        );
+        assert!(old.changes_from(&old).is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+//! Laying out the lines just outside the viewport on a background thread,
+//! so that scrolling onto them doesn't wait on DirectWrite. The layouts come
+//! back to the edit view on the UI thread, which keeps them until they are
+//! scrolled into view.
+
+use std::fmt;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use directwrite::TextFormat;
+
+use crate::linecache::Line;
+use crate::metrics::Metrics;
+use crate::styles::StyleMap;
+use crate::textline::TextLine;
+
+/// Lines to lay out, with everything the layout depends on as it was when
+/// asked for.
+pub struct Job {
+    /// Tells the layouts of this job apart from those of jobs asked for
+    /// before the lines or their format changed.
+    pub generation: u64,
+    pub lines: Vec<(usize, Line)>,
+    pub format: Arc<TextFormat>,
+    pub styles: StyleMap,
+    pub tab_size: usize,
+    pub metrics: Metrics,
+}
+
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Job")
+            .field("generation", &self.generation)
+            .field("lines", &self.lines.len())
+            .field("format", &"...")
+            .field("tab_size", &self.tab_size)
+            .field("metrics", &self.metrics)
+            .finish()
+    }
+}
+
+/// The laid out lines of a [`Job`].
+pub struct Done {
+    pub generation: u64,
+    pub lines: Vec<(usize, TextLine)>,
+}
+
+impl fmt::Debug for Done {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Done")
+            .field("generation", &self.generation)
+            .field("lines", &self.lines.len())
+            .finish()
+    }
+}
+
+/// The handle of the layout thread. The thread stops once this is dropped.
+#[derive(Clone, Debug)]
+pub struct Prelayout {
+    tx: Sender<Job>,
+}
+
+impl Prelayout {
+    /// Starts the layout thread. `done` is called from it with each finished
+    /// job, and should hand the layouts on to the UI thread.
+    pub fn start<F>(done: F) -> Self
+    where
+        F: Fn(Done) + Send + 'static,
+    {
+        let (tx, rx) = channel::<Job>();
+        thread::spawn(move || {
+            // The factory is a shared one, so formats made with the edit
+            // view's own work with it.
+            let Ok(factory) = directwrite::Factory::new() else {
+                warn!("couldn't start the layout thread");
+                return;
+            };
+            while let Ok(mut job) = rx.recv() {
+                // A newer job is for where the view has scrolled to since,
+                // so those before it are skipped.
+                while let Ok(newer) = rx.try_recv() {
+                    job = newer;
+                }
+                let lines = job
+                    .lines
+                    .iter()
+                    .map(|(line_num, line)| {
+                        let textline = TextLine::create_from_line(
+                            line,
+                            &factory,
+                            &job.format,
+                            &job.styles,
+                            job.tab_size,
+                            &job.metrics,
+                        );
+                        (*line_num, textline)
+                    })
+                    .collect();
+                done(Done {
+                    generation: job.generation,
+                    lines,
+                });
+            }
+        });
+        Self { tx }
+    }
+
+    /// Asks for lines to be laid out, replacing any job not yet started.
+    pub fn request(&self, job: Job) {
+        let _ignore = self.tx.send(job);
+    }
+}
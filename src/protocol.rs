@@ -0,0 +1,222 @@
+//! Typed versions of the notifications core sends the front end, so that
+//! what core sent is checked once, on arrival, instead of unwrapped field by
+//! field wherever it's used.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A notification from core, by method.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Notification {
+    Update {
+        view_id: String,
+        update: Update,
+    },
+    ScrollTo {
+        view_id: String,
+        line: usize,
+    },
+    ShowHover {
+        request_id: usize,
+        #[serde(default)]
+        result: String,
+    },
+    FindStatus {
+        view_id: String,
+        queries: Vec<FindStatus>,
+    },
+    /// Not shown yet, as there is no replace bar.
+    ReplaceStatus(Value),
+    ConfigChanged {
+        view_id: String,
+        changes: ConfigChanges,
+    },
+    AvailablePlugins {
+        view_id: String,
+        plugins: Vec<PluginInfo>,
+    },
+    PluginStarted {
+        view_id: String,
+        plugin: String,
+    },
+    PluginStopped {
+        view_id: String,
+        plugin: String,
+    },
+    /// The commands of a plugin which started for a view.
+    UpdateCmds {
+        view_id: String,
+        plugin: String,
+        cmds: Vec<Lenient<PluginCmd>>,
+    },
+    LanguageChanged {
+        view_id: String,
+        language_id: String,
+    },
+    AvailableLanguages {
+        languages: Vec<String>,
+    },
+    AvailableThemes {
+        themes: Vec<String>,
+    },
+    ThemeChanged {
+        name: String,
+        /// Left untyped, as only some of its many keys are used.
+        theme: Value,
+    },
+    DefStyle(DefStyle),
+    Alert {
+        msg: String,
+    },
+}
+
+impl Notification {
+    /// Parses a notification, failing if the method is unknown or the
+    /// params don't fit it.
+    pub fn parse(method: &str, params: &Value) -> serde_json::Result<Self> {
+        serde_json::from_value(json!({ "method": method, "params": params }))
+    }
+}
+
+/// A `T`, or whatever core sent in its place if that wasn't one, so one
+/// malformed item doesn't spoil the rest of a list.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Lenient<T> {
+    Ok(T),
+    Malformed(Value),
+}
+
+/// The changes to a view's lines, and whether it's now unedited.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Update {
+    pub ops: Vec<Lenient<UpdateOp>>,
+    #[serde(default)]
+    pub pristine: Option<bool>,
+    /// Sent by newer versions of core in place of the selection and find
+    /// highlight styles.
+    #[serde(default)]
+    pub annotations: Option<Vec<Lenient<AnnotationUpdate>>>,
+}
+
+/// The ranges of the buffer marked by one type of annotation.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AnnotationUpdate {
+    /// Such as `"selection"` or `"find"`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Start line, start column, end line and end column, with columns in
+    /// utf-8 code units.
+    pub ranges: Vec<[usize; 4]>,
+}
+
+/// How to build the new line cache from the old one.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum UpdateOp {
+    /// New lines.
+    Ins { lines: Vec<Lenient<LineUpdate>> },
+    /// Lines kept from the old cache.
+    Copy { n: usize },
+    /// Lines dropped from the old cache.
+    Skip { n: usize },
+    /// Lines core hasn't sent yet.
+    Invalidate { n: usize },
+}
+
+/// A line as core sends it, with offsets in utf-8 code units.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LineUpdate {
+    pub text: String,
+    #[serde(default)]
+    pub cursor: Vec<usize>,
+    /// Triples of a start relative to the end of the span before, a length
+    /// and a style id.
+    #[serde(default)]
+    pub styles: Vec<i64>,
+}
+
+/// The state of one of a view's searches.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FindStatus {
+    /// How many matches there are, if core has counted them.
+    #[serde(default)]
+    pub matches: Option<usize>,
+}
+
+/// The settings which changed in a `config_changed` notification. Settings
+/// core knows nothing about are read from the user config as well.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ConfigChanges {
+    pub tab_size: Option<usize>,
+    pub translate_tabs_to_spaces: Option<bool>,
+    pub bell_style: Option<String>,
+    pub autoscroll_margin: Option<f32>,
+    pub autoscroll_max_speed: Option<f32>,
+    pub line_numbers: Option<bool>,
+    pub smooth_scroll: Option<bool>,
+    pub scroll_off: Option<usize>,
+    pub scroll_past_end: Option<bool>,
+    pub font_face: Option<String>,
+    /// In dips.
+    pub font_size: Option<f32>,
+    /// Wrapping is done by core, and only shown here.
+    pub word_wrap: Option<bool>,
+    /// Indenting is done by core.
+    pub auto_indent: Option<bool>,
+    /// Core guesses the indentation of files it opens, and so does this
+    /// front end, from the first lines it's sent.
+    pub autodetect_whitespace: Option<bool>,
+    pub auto_close_brackets: Option<bool>,
+    /// For Insert Number Sequence, which is done by this front end.
+    pub sequence_start: Option<i64>,
+    pub sequence_step: Option<i64>,
+    /// In MiB, for each view.
+    pub line_cache_limit: Option<usize>,
+    pub indent_guides: Option<bool>,
+    pub render_whitespace: Option<bool>,
+    pub minimap: Option<bool>,
+    /// `"cleartype"` or `"grayscale"`.
+    pub text_antialiasing: Option<String>,
+    pub color_fonts: Option<bool>,
+    /// In seconds, with 0 for off.
+    pub autosave_interval: Option<u64>,
+    pub autosave_on_focus_loss: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PluginInfo {
+    pub name: String,
+    pub running: bool,
+}
+
+/// A command from a plugin's manifest.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PluginCmd {
+    pub title: String,
+    pub rpc_cmd: PlaceholderRpc,
+}
+
+/// The RPC a plugin command sends to its plugin.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PlaceholderRpc {
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    pub rpc_type: String,
+}
+
+/// A style for spans to refer to by id. Colors are `0xAARRGGBB`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DefStyle {
+    pub id: usize,
+    pub fg_color: Option<u32>,
+    pub bg_color: Option<u32>,
+    pub weight: Option<u32>,
+    #[serde(default)]
+    pub italic: bool,
+    #[serde(default)]
+    pub underline: bool,
+}
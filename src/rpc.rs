@@ -1,10 +1,13 @@
 //! Front-end side implementation of RPC protocol.
 
 use std::collections::BTreeMap;
+use std::error::Error;
 use std::fmt;
+use std::mem;
 use std::sync::mpsc::Receiver;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use serde_json::Value;
 
@@ -15,10 +18,63 @@ pub struct Core {
     state: Arc<Mutex<CoreState>>,
 }
 
+/// How long [`Core::send_request`] waits for an answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often requests are checked for having timed out.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
 struct CoreState {
     xi_peer: XiPeer,
     id: u64,
-    pending: BTreeMap<u64, Box<dyn Callback>>,
+    pending: BTreeMap<u64, Pending>,
+    /// Notifications waiting for [`Core::flush`], in the order they were
+    /// sent.
+    outbox: Vec<Value>,
+    /// Asks for a flush once the current run-loop turn is done. Without one,
+    /// notifications go straight out.
+    waker: Option<Box<dyn Fn(&Core) + Send>>,
+}
+
+/// A request waiting on its answer.
+struct Pending {
+    callback: Box<dyn Callback>,
+    /// When to give up on the answer.
+    deadline: Instant,
+}
+
+/// Why a request has no result.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RpcError {
+    /// Core answered with an error.
+    Remote { code: i64, message: String },
+    /// Core didn't answer in time. An answer arriving later is dropped.
+    Timeout,
+    /// Core stopped before answering.
+    Disconnected,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remote { code, message } => write!(f, "core error {code}: {message}"),
+            Self::Timeout => write!(f, "core didn't answer in time"),
+            Self::Disconnected => write!(f, "core stopped"),
+        }
+    }
+}
+
+impl Error for RpcError {}
+
+impl RpcError {
+    /// Reads the `error` of a response.
+    fn from_response(error: &Value) -> Self {
+        Self::Remote {
+            code: error["code"].as_i64().unwrap_or(0),
+            message: error["message"]
+                .as_str()
+                .map_or_else(|| error.to_string(), str::to_owned),
+        }
+    }
 }
 
 impl fmt::Debug for CoreState {
@@ -27,20 +83,28 @@ impl fmt::Debug for CoreState {
             .field("xi_peer", &self.xi_peer)
             .field("id", &self.id)
             .field("pending", &"...")
+            .field("outbox", &self.outbox.len())
+            .field("waker", &"...")
             .finish()
     }
 }
 
 trait Callback: Send {
-    fn call(self: Box<Self>, result: &Value);
+    fn call(self: Box<Self>, result: Result<&Value, RpcError>);
 }
 
 pub trait Handler {
     fn notification(&self, method: &str, params: &Value);
+    /// Answers a request from core, such as `measure_width`. The result is
+    /// sent back to core as is.
+    fn request(&self, method: &str, params: &Value) -> Value;
+    /// Called once core has stopped, such as after it panicked. Nothing
+    /// more arrives through this connection.
+    fn disconnected(&self);
 }
 
-impl<F: FnOnce(&Value) + Send> Callback for F {
-    fn call(self: Box<F>, result: &Value) {
+impl<F: FnOnce(Result<&Value, RpcError>) + Send> Callback for F {
+    fn call(self: Box<F>, result: Result<&Value, RpcError>) {
         (*self)(result);
     }
 }
@@ -59,6 +123,8 @@ impl Core {
             xi_peer,
             id: 0,
             pending: BTreeMap::new(),
+            outbox: vec![],
+            waker: None,
         };
         let core = Self {
             state: Arc::new(Mutex::new(state)),
@@ -67,38 +133,117 @@ impl Core {
         thread::spawn(move || {
             while let Ok(msg) = rx.recv() {
                 if let Value::String(ref method) = msg["method"] {
-                    handler.notification(method, &msg["params"]);
+                    if let Some(id) = msg.get("id") {
+                        let result = handler.request(method, &msg["params"]);
+                        let response = json!({
+                            "id": id,
+                            "result": result,
+                        });
+                        rx_core_handle
+                            .state
+                            .lock()
+                            .unwrap()
+                            .xi_peer
+                            .send_json(&response);
+                    } else {
+                        handler.notification(method, &msg["params"]);
+                    }
                 } else if let Some(id) = msg["id"].as_u64() {
-                    let mut state = rx_core_handle.state.lock().unwrap();
-                    state.pending.remove(&id).map_or_else(
-                        || eprintln!("unexpected result"),
-                        |callback| {
-                            callback.call(&msg["result"]);
-                        },
-                    );
+                    // The lock is released before the callback runs, so a slow
+                    // callback can't hold up notifications sent meanwhile.
+                    let pending = rx_core_handle.state.lock().unwrap().pending.remove(&id);
+                    let Some(pending) = pending else {
+                        warn!("unexpected result {}", id);
+                        continue;
+                    };
+                    match msg.get("error") {
+                        Some(error) => {
+                            let err = RpcError::from_response(error);
+                            warn!("request {} failed: {}", id, err);
+                            pending.callback.call(Err(err));
+                        }
+                        None => pending.callback.call(Ok(&msg["result"])),
+                    }
                 } else {
-                    println!("got {:?} at rpc level", msg);
+                    warn!("got {:?} at rpc level", msg);
                 }
             }
+            let pending = mem::take(&mut rx_core_handle.state.lock().unwrap().pending);
+            for pending in pending.into_values() {
+                pending.callback.call(Err(RpcError::Disconnected));
+            }
+            handler.disconnected();
         });
+        let state = Arc::downgrade(&core.state);
+        thread::spawn(move || sweep_timeouts(&state));
         core
     }
 
+    /// Batches notifications from now on. `waker` is called with the core
+    /// when the first notification of a batch is queued, and should arrange
+    /// for [`flush`](Self::flush) to be called once the run loop is idle.
+    pub fn set_waker<F>(&self, waker: F)
+    where
+        F: Fn(&Self) + Send + 'static,
+    {
+        self.state.lock().unwrap().waker = Some(Box::new(waker));
+    }
+
+    /// Sends a notification to core.
+    ///
+    /// This never waits on core, notifications are queued and delivered in
+    /// the order they were sent. Once a waker is set they are held until the
+    /// next flush, and a view's `scroll` replaces the one it queued before,
+    /// as only the latest viewport matters.
     pub fn send_notification(&self, method: &str, params: &Value) {
         let cmd = json!({
             "method": method,
             "params": params,
         });
-        let state = self.state.lock().unwrap();
-        state.xi_peer.send_json(&cmd);
+        let mut state = self.state.lock().unwrap();
+        let Some(waker) = &state.waker else {
+            state.xi_peer.send_json(&cmd);
+            return;
+        };
+        if state.outbox.is_empty() {
+            waker(self);
+        }
+        if let Some(view_id) = scrolled_view(&cmd) {
+            state
+                .outbox
+                .retain(|queued| scrolled_view(queued) != Some(view_id));
+        }
+        state.outbox.push(cmd);
+    }
+
+    /// Sends the queued notifications.
+    pub fn flush(&self) {
+        self.state.lock().unwrap().flush();
     }
 
-    /// Calls the callback with the result (from a different thread).
+    /// Calls the callback with the result (from a different thread), or
+    /// with an error if core answers with one or takes too long.
     pub fn send_request<F>(&mut self, method: &str, params: &Value, callback: F)
     where
-        F: FnOnce(&Value) + Send + 'static,
+        F: FnOnce(Result<&Value, RpcError>) + Send + 'static,
+    {
+        self.send_request_with_timeout(method, params, REQUEST_TIMEOUT, callback);
+    }
+
+    /// Like [`send_request`](Self::send_request), but giving up after
+    /// `timeout`.
+    pub fn send_request_with_timeout<F>(
+        &mut self,
+        method: &str,
+        params: &Value,
+        timeout: Duration,
+        callback: F,
+    ) where
+        F: FnOnce(Result<&Value, RpcError>) + Send + 'static,
     {
         let mut state = self.state.lock().unwrap();
+        // Notifications sent before the request reach core before it.
+        state.flush();
         let id = state.id;
         let cmd = json!({
             "method": method,
@@ -106,7 +251,205 @@ impl Core {
             "id": id,
         });
         state.xi_peer.send_json(&cmd);
-        state.pending.insert(id, Box::new(callback));
+        let pending = Pending {
+            callback: Box::new(callback),
+            deadline: Instant::now() + timeout,
+        };
+        state.pending.insert(id, pending);
         state.id += 1;
     }
 }
+
+impl CoreState {
+    fn flush(&mut self) {
+        for cmd in mem::take(&mut self.outbox) {
+            self.xi_peer.send_json(&cmd);
+        }
+    }
+}
+
+/// The view a notification scrolls, if it is a `scroll` edit.
+fn scrolled_view(cmd: &Value) -> Option<&Value> {
+    if cmd["method"] == "edit" && cmd["params"]["method"] == "scroll" {
+        cmd["params"].get("view_id")
+    } else {
+        None
+    }
+}
+
+/// Fails the requests which have waited too long, until the connection is
+/// dropped.
+fn sweep_timeouts(state: &Weak<Mutex<CoreState>>) {
+    loop {
+        thread::sleep(SWEEP_INTERVAL);
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let now = Instant::now();
+        let expired = {
+            let mut state = state.lock().unwrap();
+            let (expired, waiting) = mem::take(&mut state.pending)
+                .into_iter()
+                .partition(|(_, pending)| pending.deadline <= now);
+            state.pending = waiting;
+            expired
+        };
+        // As with results, callbacks run without the lock held.
+        for (id, pending) in expired {
+            warn!("request {} timed out", id);
+            pending.callback.call(Err(RpcError::Timeout));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    struct NullHandler;
+
+    impl Handler for NullHandler {
+        fn notification(&self, _method: &str, _params: &Value) {}
+
+        fn request(&self, _method: &str, _params: &Value) -> Value {
+            Value::Null
+        }
+
+        fn disconnected(&self) {}
+    }
+
+    #[test]
+    fn typing_is_not_blocked_by_a_slow_callback() {
+        let (to_core_tx, to_core_rx) = channel();
+        let (from_core_tx, from_core_rx) = channel();
+        let mut core = Core::new(XiPeer::from_sender(to_core_tx), from_core_rx, NullHandler);
+
+        // A callback which runs until all the keystrokes have been sent.
+        let (started_tx, started_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+        let (done_tx, done_rx) = channel();
+        core.send_request("get_config", &json!({"view_id": "view-id-1"}), move |_| {
+            started_tx.send(()).unwrap();
+            let released = release_rx.recv_timeout(Duration::from_secs(5)).is_ok();
+            done_tx.send(released).unwrap();
+        });
+        from_core_tx.send(json!({"id": 0, "result": {}})).unwrap();
+        started_rx.recv().unwrap();
+
+        let typed: Vec<String> = (0..500)
+            .map(|i| char::from(b'a' + (i % 26) as u8).to_string())
+            .collect();
+        for chars in &typed {
+            let params = json!({
+                "method": "insert",
+                "params": {"chars": chars},
+                "view_id": "view-id-1",
+            });
+            core.send_notification("edit", &params);
+        }
+        let _ = release_tx.send(());
+        assert!(
+            done_rx.recv().unwrap(),
+            "sending was blocked by the callback"
+        );
+
+        let request: Value = serde_json::from_str(&to_core_rx.recv().unwrap()).unwrap();
+        assert_eq!(request["method"], "get_config");
+        let sent: Vec<String> = to_core_rx
+            .try_iter()
+            .map(|s| {
+                let msg: Value = serde_json::from_str(&s).unwrap();
+                assert_eq!(msg["params"]["method"], "insert");
+                msg["params"]["params"]["chars"]
+                    .as_str()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        assert_eq!(sent, typed);
+    }
+
+    #[test]
+    fn scrolls_are_coalesced_until_flushed() {
+        let (to_core_tx, to_core_rx) = channel();
+        let (_from_core_tx, from_core_rx) = channel();
+        let core = Core::new(XiPeer::from_sender(to_core_tx), from_core_rx, NullHandler);
+        let (wake_tx, wake_rx) = channel();
+        core.set_waker(move |_| wake_tx.send(()).unwrap());
+
+        let edit = |view_id: &str, method: &str, params: Value| {
+            core.send_notification(
+                "edit",
+                &json!({"method": method, "params": params, "view_id": view_id}),
+            );
+        };
+        edit("view-id-1", "scroll", json!([0, 10]));
+        edit("view-id-1", "insert", json!({"chars": "a"}));
+        edit("view-id-2", "scroll", json!([5, 15]));
+        edit("view-id-1", "scroll", json!([1, 11]));
+        edit("view-id-1", "scroll", json!([2, 12]));
+        assert_eq!(wake_rx.try_iter().count(), 1);
+        assert!(to_core_rx.try_recv().is_err());
+
+        core.flush();
+        let sent: Vec<(String, Value)> = to_core_rx
+            .try_iter()
+            .map(|s| {
+                let msg: Value = serde_json::from_str(&s).unwrap();
+                let view_id = msg["params"]["view_id"].as_str().unwrap().to_owned();
+                (view_id, msg["params"]["params"].clone())
+            })
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                ("view-id-1".to_owned(), json!({"chars": "a"})),
+                ("view-id-2".to_owned(), json!([5, 15])),
+                ("view-id-1".to_owned(), json!([2, 12])),
+            ]
+        );
+
+        // The next batch wakes again.
+        edit("view-id-1", "insert", json!({"chars": "b"}));
+        assert_eq!(wake_rx.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn failed_requests_reach_their_callbacks() {
+        let (to_core_tx, _to_core_rx) = channel();
+        let (from_core_tx, from_core_rx) = channel();
+        let mut core = Core::new(XiPeer::from_sender(to_core_tx), from_core_rx, NullHandler);
+
+        let (results_tx, results_rx) = channel();
+        let error_tx = results_tx.clone();
+        core.send_request("new_view", &json!({}), move |result| {
+            error_tx.send(result.map(Value::clone)).unwrap();
+        });
+        core.send_request_with_timeout(
+            "get_config",
+            &json!({"view_id": "view-id-1"}),
+            Duration::from_millis(10),
+            move |result| {
+                results_tx.send(result.map(Value::clone)).unwrap();
+            },
+        );
+        from_core_tx
+            .send(json!({"id": 0, "error": {"code": 5, "message": "no such file"}}))
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            results_rx.recv_timeout(timeout).unwrap(),
+            Err(RpcError::Remote {
+                code: 5,
+                message: "no such file".to_owned(),
+            })
+        );
+        assert_eq!(
+            results_rx.recv_timeout(timeout).unwrap(),
+            Err(RpcError::Timeout)
+        );
+    }
+}
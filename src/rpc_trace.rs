@@ -0,0 +1,121 @@
+//! Recording the messages between the front end and core, and playing a
+//! recording back, for reproducing protocol bugs now that there is no stdout
+//! to print them to.
+//!
+//! Recording is started with `--trace-rpc <file>`, or by setting
+//! `XI_WIN_TRACE_RPC` to the file. Each message is a line of the file, as
+//! `{"to_core": message, "time": secs}` or `{"from_core": message, "time":
+//! secs}`, with the seconds since recording started. This is the transcript
+//! format of the tests' mock core, so a recording can be made into a test.
+//!
+//! `--replay-rpc <file>` runs the app against a recording instead of core:
+//! what core sent is sent again with the same timing, and what the front end
+//! sends is only recorded, if anything is.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::xi_thread::XiPeer;
+
+/// The environment variable naming the file to record to, when not given
+/// with `--trace-rpc`.
+pub const TRACE_VAR: &str = "XI_WIN_TRACE_RPC";
+
+static TRACE: OnceLock<Trace> = OnceLock::new();
+
+struct Trace {
+    start: Instant,
+    file: Mutex<BufWriter<File>>,
+}
+
+/// Starts recording to `path`, replacing whatever it held. Only the first
+/// call does anything.
+pub fn start(path: &Path) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let _ignore = TRACE.set(Trace {
+        start: Instant::now(),
+        file: Mutex::new(file),
+    });
+    info!("recording rpc to {}", path.display());
+    Ok(())
+}
+
+/// Records a message sent to core, already serialized.
+pub fn to_core(msg: &str) {
+    let Some(trace) = TRACE.get() else {
+        return;
+    };
+    match serde_json::from_str::<Value>(msg) {
+        Ok(msg) => trace.record("to_core", msg),
+        Err(err) => warn!("not recording unparsable message to core: {}", err),
+    }
+}
+
+/// Records a message from core.
+pub fn from_core(msg: &Value) {
+    if let Some(trace) = TRACE.get() {
+        trace.record("from_core", msg.clone());
+    }
+}
+
+impl Trace {
+    fn record(&self, direction: &str, msg: Value) {
+        let time = self.start.elapsed().as_secs_f64();
+        let line = json!({ direction: msg, "time": time });
+        let mut file = self.file.lock().unwrap();
+        // Flushed as it goes, so a crash doesn't lose the messages leading
+        // up to it.
+        let written = writeln!(file, "{line}").and_then(|()| file.flush());
+        if let Err(err) = written {
+            warn!("couldn't record rpc: {}", err);
+        }
+    }
+}
+
+/// Plays back what core sent in a recording, in place of starting core.
+/// Returns the same as [`start_xi_thread`](crate::xi_thread::start_xi_thread).
+pub fn replay(path: &Path) -> io::Result<(XiPeer, Receiver<Value>)> {
+    let reader = BufReader::new(File::open(path)?);
+    // Nothing answers what the front end sends, other than the recording.
+    let (to_core_tx, _) = channel();
+    let (from_core_tx, from_core_rx) = channel();
+    thread::spawn(move || {
+        let start = Instant::now();
+        for (i, line) in reader.lines().enumerate() {
+            let Ok(line) = line else {
+                break;
+            };
+            let entry: Value = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    warn!("skipping line {} of the recording: {}", i + 1, err);
+                    continue;
+                }
+            };
+            let Some(msg) = entry.get("from_core") else {
+                continue;
+            };
+            if let Some(time) = entry["time"].as_f64() {
+                let due = start + Duration::from_secs_f64(time.max(0.0));
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+            }
+            from_core(msg);
+            if from_core_tx.send(msg.clone()).is_err() {
+                break;
+            }
+        }
+        info!("finished replaying rpc");
+        // Hanging up would look like core stopping.
+        loop {
+            thread::park();
+        }
+    });
+    Ok((XiPeer::from_sender(to_core_tx), from_core_rx))
+}
@@ -0,0 +1,179 @@
+//! The files and window layout to restore on the next launch, kept in
+//! `%LOCALAPPDATA%\xi-win\session.json`.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use winapi::shared::windef::{HWND, POINT, RECT};
+use winapi::um::winuser::{
+    GetWindowPlacement, SetWindowPlacement, SW_HIDE, SW_SHOWMAXIMIZED, WINDOWPLACEMENT,
+};
+
+/// An open file, in tab order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionFile {
+    pub path: String,
+    /// The view's scroll offset, in px.
+    pub scroll_offset: f64,
+    pub pinned: bool,
+}
+
+/// The main window's size and position when not maximized, in screen px.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Placement {
+    pub rect: (i32, i32, i32, i32),
+    pub maximized: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub files: Vec<SessionFile>,
+    /// The index in `files` of the focused view.
+    pub focused: Option<usize>,
+    pub placement: Option<Placement>,
+    /// The files last opened, most recent first.
+    pub recent: Vec<String>,
+}
+
+impl Session {
+    /// Reads the session saved by the last run, if there is one.
+    pub fn load() -> Option<Self> {
+        let text = fs::read_to_string(session_path()).ok()?;
+        let json: Value = serde_json::from_str(&text).ok()?;
+        Some(Self::from_json(&json))
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = session_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_json().to_string())
+    }
+
+    fn from_json(json: &Value) -> Self {
+        let files = json["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|file| {
+                Some(SessionFile {
+                    path: file["path"].as_str()?.to_owned(),
+                    scroll_offset: file["scroll_offset"].as_f64().unwrap_or(0.0),
+                    pinned: file["pinned"].as_bool().unwrap_or(false),
+                })
+            })
+            .collect();
+        let window = &json["window"];
+        let rect = ["left", "top", "right", "bottom"].map(|key| window[key].as_i64());
+        let placement = match rect {
+            [Some(left), Some(top), Some(right), Some(bottom)] => Some(Placement {
+                rect: (left as i32, top as i32, right as i32, bottom as i32),
+                maximized: window["maximized"].as_bool().unwrap_or(false),
+            }),
+            _ => None,
+        };
+        let recent = json["recent"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|path| Some(path.as_str()?.to_owned()))
+            .collect();
+        Self {
+            files,
+            focused: json["focused"].as_u64().map(|i| i as usize),
+            placement,
+            recent,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|file| {
+                json!({
+                    "path": file.path,
+                    "scroll_offset": file.scroll_offset,
+                    "pinned": file.pinned,
+                })
+            })
+            .collect();
+        let window = self.placement.map(|placement| {
+            let (left, top, right, bottom) = placement.rect;
+            json!({
+                "left": left,
+                "top": top,
+                "right": right,
+                "bottom": bottom,
+                "maximized": placement.maximized,
+            })
+        });
+        json!({
+            "files": files,
+            "focused": self.focused,
+            "window": window,
+            "recent": self.recent,
+        })
+    }
+}
+
+fn session_path() -> PathBuf {
+    env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join("xi-win")
+        .join("session.json")
+}
+
+/// Where the window is, to restore it with [`set_placement`].
+///
+/// # Safety
+///
+/// `hwnd` must be a valid window.
+pub unsafe fn get_placement(hwnd: HWND) -> Option<Placement> {
+    let mut placement: WINDOWPLACEMENT = mem::zeroed();
+    placement.length = mem::size_of::<WINDOWPLACEMENT>() as u32;
+    if GetWindowPlacement(hwnd, &mut placement) == 0 {
+        return None;
+    }
+    let RECT {
+        left,
+        top,
+        right,
+        bottom,
+    } = placement.rcNormalPosition;
+    Some(Placement {
+        rect: (left, top, right, bottom),
+        maximized: placement.showCmd == SW_SHOWMAXIMIZED as u32,
+    })
+}
+
+/// Moves the window to where it was, leaving it hidden. Maximizing it is up
+/// to the caller, once it is shown.
+///
+/// # Safety
+///
+/// `hwnd` must be a valid window.
+pub unsafe fn set_placement(hwnd: HWND, placement: &Placement) {
+    let (left, top, right, bottom) = placement.rect;
+    let mut window_placement: WINDOWPLACEMENT = mem::zeroed();
+    window_placement.length = mem::size_of::<WINDOWPLACEMENT>() as u32;
+    window_placement.showCmd = SW_HIDE as u32;
+    window_placement.ptMinPosition = POINT { x: -1, y: -1 };
+    window_placement.ptMaxPosition = POINT { x: -1, y: -1 };
+    window_placement.rcNormalPosition = RECT {
+        left,
+        top,
+        right,
+        bottom,
+    };
+    // Windows moves the window back on screen if the monitor it was on has
+    // since gone.
+    SetWindowPlacement(hwnd, &window_placement);
+}
@@ -0,0 +1,100 @@
+//! Handing files to an already running window, as the jump list's items do
+//! with `--reuse-window` rather than opening a second one.
+//!
+//! The window is marked with a window property so that it can be found, and
+//! the files are sent to it with `WM_COPYDATA`, as utf-16 paths each ended by
+//! a nul.
+
+use std::ptr;
+use std::slice;
+
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPARAM, LRESULT, TRUE};
+use winapi::shared::windef::HWND;
+use winapi::um::winuser::{
+    AllowSetForegroundWindow, EnumWindows, GetPropW, GetWindowThreadProcessId, IsIconic,
+    SendMessageW, SetForegroundWindow, SetPropW, ShowWindow, COPYDATASTRUCT, SW_RESTORE,
+    WM_COPYDATA,
+};
+
+use crate::file_dialog::to_wide;
+use crate::subclass::{self, HookId};
+
+/// The property marking the window, and the `dwData` of its `WM_COPYDATA`.
+const PROP_NAME: &str = "xi-win.files";
+const COPYDATA_FILES: usize = 0x7869_6669; // "xifi"
+
+/// Sends `files` to the running window, returning false if there isn't one.
+pub fn forward(files: &[String]) -> bool {
+    // SAFETY: The found window's handle is only used while enumerating, and
+    // the data sent outlives the synchronous `SendMessageW`.
+    unsafe {
+        let mut found: HWND = ptr::null_mut();
+        EnumWindows(Some(find_marked), (&mut found as *mut HWND) as LPARAM);
+        if found.is_null() {
+            return false;
+        }
+        let mut data: Vec<u16> = vec![];
+        for file in files {
+            data.extend(to_wide(file));
+        }
+        let copy = COPYDATASTRUCT {
+            dwData: COPYDATA_FILES,
+            cbData: (data.len() * 2) as DWORD,
+            lpData: data.as_mut_ptr().cast(),
+        };
+        // The running window may only take the foreground if allowed to.
+        let mut pid = 0;
+        GetWindowThreadProcessId(found, &mut pid);
+        AllowSetForegroundWindow(pid);
+        SendMessageW(
+            found,
+            WM_COPYDATA,
+            0,
+            (&copy as *const COPYDATASTRUCT) as LPARAM,
+        ) != 0
+    }
+}
+
+unsafe extern "system" fn find_marked(hwnd: HWND, found: LPARAM) -> BOOL {
+    if GetPropW(hwnd, to_wide(PROP_NAME).as_ptr()).is_null() {
+        return TRUE;
+    }
+    *(found as *mut HWND) = hwnd;
+    FALSE
+}
+
+/// Marks the window as the one to send files to, and calls `listener` with
+/// the files sent. Returns false if the window couldn't be hooked.
+///
+/// # Safety
+///
+/// `hwnd` must be a window created by the current thread.
+pub unsafe fn hook<F: Fn(Vec<String>) + 'static>(hwnd: HWND, listener: F) -> bool {
+    SetPropW(hwnd, to_wide(PROP_NAME).as_ptr(), 1 as _) != 0
+        && subclass::hook(hwnd, HookId::SingleInstance, move |hwnd, msg, _, lparam| {
+            if msg != WM_COPYDATA {
+                return None;
+            }
+            let copy = &*(lparam as *const COPYDATASTRUCT);
+            if copy.dwData != COPYDATA_FILES {
+                return None;
+            }
+            let len = copy.cbData as usize / 2;
+            let wide = if len == 0 {
+                &[][..]
+            } else {
+                slice::from_raw_parts(copy.lpData.cast::<u16>(), len)
+            };
+            let files = wide
+                .split(|&c| c == 0)
+                .filter(|file| !file.is_empty())
+                .map(String::from_utf16_lossy)
+                .collect();
+            if IsIconic(hwnd) != 0 {
+                ShowWindow(hwnd, SW_RESTORE);
+            }
+            SetForegroundWindow(hwnd);
+            listener(files);
+            Some(TRUE as LRESULT)
+        })
+}
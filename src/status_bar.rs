@@ -0,0 +1,250 @@
+//! The bar along the bottom of the window, showing where the caret is and
+//! what kind of file the focused view holds.
+
+use std::any::Any;
+use std::fmt;
+
+use direct2d::brush::SolidColorBrush;
+use direct2d::RenderTarget;
+use directwrite::{TextFormat, TextLayout};
+
+use druid_win_shell::util::default_text_options;
+use druid_win_shell::window::MouseButton;
+
+use druid::widget::Widget;
+use druid::Ui;
+use druid::{BoxConstraints, Geometry, LayoutResult};
+use druid::{HandlerCtx, Id, LayoutCtx, MouseEvent, PaintCtx};
+
+use crate::color_scheme::ColorScheme;
+use crate::dpi;
+use crate::edit_view::Indentation;
+use crate::modal::Mode;
+
+const BAR_HEIGHT: f32 = 22.0;
+const BAR_PAD: f32 = 8.0;
+/// The space between the items on the right.
+const ITEM_GAP: f32 = 20.0;
+const FONT_SIZE: f32 = 12.0;
+
+/// Where the caret of the focused view is.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CaretStatus {
+    /// The line and column of the first caret, counted from 1.
+    pub line: usize,
+    pub column: usize,
+    /// The number of characters selected, over all selections.
+    pub selected: usize,
+    /// The number of selections, each with a caret.
+    pub selections: usize,
+}
+
+/// The commands the [`StatusBar`] widget accepts through `poke`.
+#[derive(Debug)]
+pub enum StatusBarCommands {
+    SetCaret(CaretStatus),
+    /// The language and encoding of the focused view.
+    SetFileInfo {
+        language: Option<String>,
+        encoding: String,
+    },
+    SetColorScheme(ColorScheme),
+    /// The focused view's editing mode, or `None` if modal editing is off.
+    SetMode(Option<Mode>),
+    SetIndentation(Indentation),
+}
+
+/// The events the [`StatusBar`] widget sends to its listener.
+#[derive(Debug)]
+pub enum StatusBarEvent {
+    /// The indentation item was clicked, for a menu at the mouse to change
+    /// the shown settings.
+    Indentation(Indentation),
+}
+
+pub struct StatusBar {
+    caret: CaretStatus,
+    mode: Option<Mode>,
+    language: Option<String>,
+    encoding: String,
+    indentation: Option<Indentation>,
+    /// Where the indentation item was last painted, as the start and end x
+    /// in dips from the bar's left.
+    indentation_span: Option<(f32, f32)>,
+    color_scheme: ColorScheme,
+    dwrite_factory: directwrite::Factory,
+}
+
+impl fmt::Debug for StatusBar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StatusBar")
+            .field("caret", &self.caret)
+            .field("mode", &self.mode)
+            .field("language", &self.language)
+            .field("encoding", &self.encoding)
+            .field("indentation", &self.indentation)
+            .field("indentation_span", &self.indentation_span)
+            .field("color_scheme", &self.color_scheme)
+            .field("dwrite_factory", &"...")
+            .finish()
+    }
+}
+
+impl Widget for StatusBar {
+    fn paint(&mut self, paint_ctx: &mut PaintCtx, geom: &Geometry) {
+        let geom = &dpi::begin_paint(paint_ctx.render_target(), geom);
+        let rt = paint_ctx.render_target();
+        let [bg, fg] = [self.color_scheme.tab_inactive_bg, self.color_scheme.tab_fg].map(|color| {
+            SolidColorBrush::create(rt)
+                .with_color(color)
+                .build()
+                .unwrap()
+        });
+        let (x0, y0) = geom.pos;
+        let (width, height) = geom.size;
+        rt.fill_rectangle((x0, y0, x0 + width, y0 + height), &bg);
+
+        let format = TextFormat::create(&self.dwrite_factory)
+            .with_family("Segoe UI")
+            .with_size(FONT_SIZE)
+            .build()
+            .unwrap();
+        let CaretStatus {
+            line,
+            column,
+            selected,
+            selections,
+        } = self.caret;
+        let mut position = match self.mode {
+            Some(mode) => format!("{mode}    Ln {line}, Col {column}"),
+            None => format!("Ln {line}, Col {column}"),
+        };
+        if selected > 0 {
+            position.push_str(&format!(" ({selected} selected)"));
+        }
+        if selections > 1 {
+            position.push_str(&format!("    {selections} carets"));
+        }
+        if let Some(layout) = self.text_layout(&position, &format) {
+            let text_y = y0 + (height - layout.get_metrics().height()) / 2.0;
+            rt.draw_text_layout((x0 + BAR_PAD, text_y), &layout, &fg, default_text_options());
+        }
+
+        // The file info is right aligned, so it's laid out from the right.
+        let mut right = x0 + width - BAR_PAD;
+        let indentation = self.indentation.map(|indentation| {
+            if indentation.translate_tabs_to_spaces {
+                format!("Spaces: {}", indentation.tab_size)
+            } else {
+                format!("Tabs: {}", indentation.tab_size)
+            }
+        });
+        let items = [
+            Some(self.encoding.as_str()),
+            indentation.as_deref(),
+            self.language.as_deref(),
+        ];
+        self.indentation_span = None;
+        for (i, item) in items.into_iter().enumerate() {
+            let Some(layout) = item.and_then(|item| self.text_layout(item, &format)) else {
+                continue;
+            };
+            let metrics = layout.get_metrics();
+            let text_y = y0 + (height - metrics.height()) / 2.0;
+            let x = right - metrics.width();
+            rt.draw_text_layout((x, text_y), &layout, &fg, default_text_options());
+            // Remembered so that clicks on it can be told apart.
+            if i == 1 {
+                self.indentation_span = Some((x - x0, right - x0));
+            }
+            right = x - ITEM_GAP;
+        }
+        dpi::end_paint(rt);
+    }
+
+    fn layout(
+        &mut self,
+        bc: &BoxConstraints,
+        _children: &[Id],
+        _size: Option<(f32, f32)>,
+        _ctx: &mut LayoutCtx,
+    ) -> LayoutResult {
+        LayoutResult::Size(bc.constrain((f32::INFINITY, dpi::to_px(BAR_HEIGHT))))
+    }
+
+    fn mouse(&mut self, event: &MouseEvent, ctx: &mut HandlerCtx) -> bool {
+        if event.which == MouseButton::Left && event.count == 1 {
+            let x = dpi::to_dips(event.x);
+            if let (Some(indentation), Some((start, end))) =
+                (self.indentation, self.indentation_span)
+            {
+                if (start..end).contains(&x) {
+                    ctx.send_event(StatusBarEvent::Indentation(indentation));
+                }
+            }
+        }
+        true
+    }
+
+    fn poke(&mut self, payload: &mut dyn Any, ctx: &mut HandlerCtx) -> bool {
+        if let Some(cmd) = payload.downcast_ref::<StatusBarCommands>() {
+            match cmd {
+                StatusBarCommands::SetCaret(caret) => {
+                    if *caret == self.caret {
+                        return true;
+                    }
+                    self.caret = *caret;
+                }
+                StatusBarCommands::SetFileInfo { language, encoding } => {
+                    self.language = language.clone();
+                    self.encoding = encoding.clone();
+                }
+                StatusBarCommands::SetColorScheme(scheme) => {
+                    self.color_scheme = scheme.clone();
+                }
+                StatusBarCommands::SetMode(mode) => {
+                    self.mode = *mode;
+                }
+                StatusBarCommands::SetIndentation(indentation) => {
+                    self.indentation = Some(*indentation);
+                }
+            }
+            ctx.invalidate();
+        }
+        true
+    }
+}
+
+impl StatusBar {
+    pub fn new() -> Self {
+        Self {
+            caret: CaretStatus {
+                line: 1,
+                column: 1,
+                selected: 0,
+                selections: 1,
+            },
+            mode: None,
+            language: None,
+            encoding: String::new(),
+            indentation: None,
+            indentation_span: None,
+            color_scheme: ColorScheme::default(),
+            dwrite_factory: directwrite::Factory::new().unwrap(),
+        }
+    }
+
+    pub fn ui(self, ctx: &mut Ui) -> Id {
+        ctx.add(self, &[])
+    }
+
+    fn text_layout(&self, text: &str, format: &TextFormat) -> Option<TextLayout> {
+        TextLayout::create(&self.dwrite_factory)
+            .with_text(text)
+            .with_font(format)
+            .with_width(1e6)
+            .with_height(1e6)
+            .build()
+            .ok()
+    }
+}
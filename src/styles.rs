@@ -0,0 +1,40 @@
+//! The text styles core defines with `def_style`, such as the colors of
+//! syntax highlighting.
+
+use std::collections::HashMap;
+
+use crate::protocol::DefStyle;
+
+/// How the text of a style span is drawn. Colors are in `0xAARRGGBB` form,
+/// and `None` leaves the view's default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<u32>,
+    pub bg: Option<u32>,
+    /// A font weight, such as 400 for regular or 700 for bold.
+    pub weight: Option<u32>,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    /// The style of a `def_style` notification.
+    pub fn from_def_style(def: &DefStyle) -> Self {
+        Self {
+            fg: def.fg_color,
+            bg: def.bg_color.filter(|bg| bg >> 24 != 0),
+            weight: def.weight,
+            italic: def.italic,
+            underline: def.underline,
+        }
+    }
+}
+
+/// The styles defined so far, by style id.
+pub type StyleMap = HashMap<usize, Style>;
+
+/// Splits an `0xAARRGGBB` color into the `0x00RRGGBB` color and the alpha
+/// that brushes are created with.
+pub fn split_alpha(argb: u32) -> (u32, f32) {
+    (argb & 0x00ff_ffff, (argb >> 24) as f32 / 255.0)
+}
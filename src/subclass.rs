@@ -0,0 +1,60 @@
+//! Hooking the main window's messages before the shell sees them, for those
+//! it doesn't pass on, or handles in a way the app needs to change.
+
+use winapi::shared::basetsd::{DWORD_PTR, UINT_PTR};
+use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::commctrl::{DefSubclassProc, SetWindowSubclass};
+
+/// The hooks on the main window. Each needs its own id, as hooking a
+/// window again with the same id replaces the earlier hook.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HookId {
+    ClosePrompt,
+    Ime,
+    CaretBlink,
+    AutoSave,
+    Dpi,
+    SingleInstance,
+    SystemTheme,
+}
+
+/// Handles a message, returning its result if it isn't to be passed on.
+type Listener = Box<dyn Fn(HWND, UINT, WPARAM, LPARAM) -> Option<LRESULT>>;
+
+/// Calls `listener` with every message of the window before the shell
+/// sees it. Messages it returns `None` for are passed on. Returns false if
+/// the window couldn't be hooked.
+///
+/// # Safety
+///
+/// `hwnd` must be a window created by the current thread.
+pub unsafe fn hook<F>(hwnd: HWND, id: HookId, listener: F) -> bool
+where
+    F: Fn(HWND, UINT, WPARAM, LPARAM) -> Option<LRESULT> + 'static,
+{
+    // Leaked, as it's needed for as long as the window, and the window lasts
+    // as long as the app.
+    let listener: *mut Listener = Box::into_raw(Box::new(Box::new(listener)));
+    SetWindowSubclass(
+        hwnd,
+        Some(subclass_proc),
+        id as UINT_PTR,
+        listener as DWORD_PTR,
+    ) != 0
+}
+
+unsafe extern "system" fn subclass_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+    _id: UINT_PTR,
+    data: DWORD_PTR,
+) -> LRESULT {
+    let listener = &*(data as *const Listener);
+    match listener(hwnd, msg, wparam, lparam) {
+        Some(result) => result,
+        None => DefSubclassProc(hwnd, msg, wparam, lparam),
+    }
+}
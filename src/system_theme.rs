@@ -0,0 +1,98 @@
+//! Following the light or dark app theme chosen in the Windows settings.
+//!
+//! Changes to the setting are picked up from `WM_SETTINGCHANGE`, which
+//! druid doesn't pass on, so the window is subclassed for it.
+
+use std::cell::Cell;
+use std::mem;
+use std::ptr;
+use std::slice;
+
+use winapi::shared::minwindef::{BOOL, DWORD};
+use winapi::shared::windef::HWND;
+use winapi::shared::winerror::{ERROR_SUCCESS, SUCCEEDED};
+use winapi::um::dwmapi::DwmSetWindowAttribute;
+use winapi::um::winreg::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+use winapi::um::winuser::WM_SETTINGCHANGE;
+
+use crate::file_dialog::to_wide;
+use crate::subclass::{self, HookId};
+
+/// The `WM_SETTINGCHANGE` area of the app theme.
+const COLOR_SET: &str = "ImmersiveColorSet";
+const PERSONALIZE_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize";
+/// `DWMWA_USE_IMMERSIVE_DARK_MODE`, which isn't in winapi yet.
+const DWMWA_USE_IMMERSIVE_DARK_MODE: DWORD = 20;
+/// The same attribute, as numbered before Windows 10 20H1.
+const DWMWA_USE_IMMERSIVE_DARK_MODE_OLD: DWORD = 19;
+
+/// Whether apps should use a dark theme. Windows without the setting is
+/// light.
+pub fn is_dark() -> bool {
+    let mut light: DWORD = 1;
+    let mut size = mem::size_of::<DWORD>() as DWORD;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            to_wide(PERSONALIZE_KEY).as_ptr(),
+            to_wide("AppsUseLightTheme").as_ptr(),
+            RRF_RT_REG_DWORD,
+            ptr::null_mut(),
+            (&mut light as *mut DWORD).cast(),
+            &mut size,
+        )
+    };
+    status == ERROR_SUCCESS as i32 && light == 0
+}
+
+/// Calls `listener` with the new value of `is_dark` whenever it changes, as
+/// Windows tells the window with `WM_SETTINGCHANGE`. Returns false if the
+/// window couldn't be hooked.
+///
+/// # Safety
+///
+/// `hwnd` must be a window created by the current thread.
+pub unsafe fn watch<F: Fn(bool) + 'static>(hwnd: HWND, listener: F) -> bool {
+    // The last `is_dark`.
+    let last = Cell::new(is_dark());
+    subclass::hook(hwnd, HookId::SystemTheme, move |_, msg, _, lparam| {
+        // The theme is one of the settings of the "ImmersiveColorSet" area,
+        // which also has the accent color.
+        if msg == WM_SETTINGCHANGE && lparam != 0 && wide_eq(lparam as *const u16, COLOR_SET) {
+            let dark = is_dark();
+            if last.replace(dark) != dark {
+                listener(dark);
+            }
+        }
+        None
+    })
+}
+
+/// Whether a nul-terminated utf-16 string is `s`.
+unsafe fn wide_eq(wide: *const u16, s: &str) -> bool {
+    let len = (0..).take_while(|&i| *wide.add(i) != 0).count();
+    slice::from_raw_parts(wide, len)
+        .iter()
+        .copied()
+        .eq(s.encode_utf16())
+}
+
+/// Draws the title bar of a window dark or light. Windows versions without
+/// dark title bars are left as they are.
+pub unsafe fn set_dark_title_bar(hwnd: HWND, dark: bool) {
+    let value = BOOL::from(dark);
+    for attribute in [
+        DWMWA_USE_IMMERSIVE_DARK_MODE,
+        DWMWA_USE_IMMERSIVE_DARK_MODE_OLD,
+    ] {
+        let hr = DwmSetWindowAttribute(
+            hwnd,
+            attribute,
+            (&value as *const BOOL).cast(),
+            mem::size_of::<BOOL>() as DWORD,
+        );
+        if SUCCEEDED(hr) {
+            return;
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! The strip of tabs above the editor, one for each open view.
+
+use std::any::Any;
+use std::fmt;
+use std::ops::Range;
+
+use direct2d::brush::SolidColorBrush;
+use direct2d::RenderTarget;
+use directwrite::{TextFormat, TextLayout};
+
+use druid_win_shell::util::default_text_options;
+use druid_win_shell::window::MouseButton;
+
+use druid::widget::Widget;
+use druid::Ui;
+use druid::{BoxConstraints, Geometry, LayoutResult};
+use druid::{HandlerCtx, Id, LayoutCtx, MouseEvent, PaintCtx};
+
+use crate::color_scheme::ColorScheme;
+use crate::dpi;
+
+const TAB_HEIGHT: f32 = 28.0;
+const TAB_PAD: f32 = 12.0;
+/// The room left after the title for the unsaved changes dot.
+const DIRTY_WIDTH: f32 = 14.0;
+const FONT_SIZE: f32 = 12.0;
+/// How far the mouse moves, in dips, before a press on a tab becomes a drag.
+const DRAG_DISTANCE: f32 = 4.0;
+/// The width of the mark showing where a dragged tab will go.
+const INSERTION_WIDTH: f32 = 2.0;
+/// The height of the bar along the top of a pinned tab.
+const PIN_HEIGHT: f32 = 2.0;
+/// The room left after the unsaved changes dot for the close button, which
+/// pinned tabs don't have.
+const CLOSE_WIDTH: f32 = 16.0;
+/// Half the size of the cross drawn on the close button.
+const CLOSE_ARM: f32 = 3.5;
+/// The width of the button at the right end listing every tab, shown when
+/// they don't all fit.
+const OVERFLOW_WIDTH: f32 = 28.0;
+
+/// What a tab shows for one view.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tab {
+    pub view_id: String,
+    pub title: String,
+    /// Whether the view has unsaved changes.
+    pub dirty: bool,
+    /// Pinned tabs come first, and aren't closed by a middle-click.
+    pub pinned: bool,
+}
+
+/// The commands the [`TabBar`] widget accepts through `poke`.
+#[derive(Debug)]
+pub enum TabBarCommands {
+    /// Replaces the tabs, in display order, and which one is active.
+    SetTabs {
+        tabs: Vec<Tab>,
+        active: Option<usize>,
+    },
+    SetColorScheme(ColorScheme),
+}
+
+/// Sent to the tab bar's listeners when a tab is clicked or dragged.
+#[derive(Debug)]
+pub enum TabEvent {
+    Select(String),
+    /// A tab was dragged from display position `from` to `to`, counted
+    /// without it.
+    Move {
+        from: usize,
+        to: usize,
+    },
+    /// A tab was right-clicked, for its context menu at the mouse.
+    ContextMenu(String),
+    /// A tab was middle-clicked, or its close button clicked.
+    Close(String),
+    /// The button listing every tab was clicked, for that list at the mouse.
+    Overflow,
+}
+
+/// A press on a tab, which becomes a drag once the mouse moves far enough.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TabDrag {
+    from: usize,
+    /// Where the press was, in dips across the tab bar.
+    origin: f32,
+    /// The gap between tabs the tab would be dropped in, from 0 before the
+    /// first to the number of tabs after the last, once it is being dragged.
+    gap: Option<usize>,
+}
+
+pub struct TabBar {
+    tabs: Vec<Tab>,
+    active: Option<usize>,
+    /// The tab under the mouse.
+    hot: Option<usize>,
+    /// The left and right edges of each tab, as last painted.
+    extents: Vec<(f32, f32)>,
+    /// The left and right edges of each tab's close button, as last
+    /// painted.
+    close_buttons: Vec<Option<(f32, f32)>>,
+    /// The left and right edges of the button listing every tab, if the
+    /// tabs didn't all fit when last painted.
+    overflow: Option<(f32, f32)>,
+    drag: Option<TabDrag>,
+    color_scheme: ColorScheme,
+    dwrite_factory: directwrite::Factory,
+}
+
+impl fmt::Debug for TabBar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TabBar")
+            .field("tabs", &self.tabs)
+            .field("active", &self.active)
+            .field("hot", &self.hot)
+            .field("extents", &self.extents)
+            .field("close_buttons", &self.close_buttons)
+            .field("overflow", &self.overflow)
+            .field("drag", &self.drag)
+            .field("color_scheme", &self.color_scheme)
+            .field("dwrite_factory", &"...")
+            .finish()
+    }
+}
+
+impl Widget for TabBar {
+    fn paint(&mut self, paint_ctx: &mut PaintCtx, geom: &Geometry) {
+        let geom = &dpi::begin_paint(paint_ctx.render_target(), geom);
+        let rt = paint_ctx.render_target();
+        let colors = &self.color_scheme;
+        let [bg, active_bg, hot_bg, fg, dirty, close] = [
+            colors.tab_inactive_bg,
+            colors.tab_active_bg,
+            colors.tab_hover_bg,
+            colors.tab_fg,
+            colors.tab_dirty,
+            colors.tab_close,
+        ]
+        .map(|color| {
+            SolidColorBrush::create(rt)
+                .with_color(color)
+                .build()
+                .unwrap()
+        });
+        let (x0, y0) = geom.pos;
+        let (width, height) = geom.size;
+        rt.fill_rectangle((x0, y0, x0 + width, y0 + height), &bg);
+
+        let format = TextFormat::create(&self.dwrite_factory)
+            .with_family("Segoe UI")
+            .with_size(FONT_SIZE)
+            .build()
+            .unwrap();
+        let layouts: Vec<Option<TextLayout>> = self
+            .tabs
+            .iter()
+            .map(|tab| {
+                TextLayout::create(&self.dwrite_factory)
+                    .with_text(&tab.title)
+                    .with_font(&format)
+                    .with_width(1e6)
+                    .with_height(1e6)
+                    .build()
+                    .ok()
+            })
+            .collect();
+        let widths: Vec<f32> = self
+            .tabs
+            .iter()
+            .zip(&layouts)
+            .map(|(tab, layout)| {
+                let close_width = if tab.pinned { 0.0 } else { CLOSE_WIDTH };
+                let text_width = layout.as_ref().map_or(0.0, |l| l.get_metrics().width());
+                2.0f32.mul_add(TAB_PAD, text_width) + DIRTY_WIDTH + close_width
+            })
+            .collect();
+        let shown = shown_tabs(&widths, width, self.active);
+        self.overflow = (shown.len() < self.tabs.len()).then(|| (width - OVERFLOW_WIDTH, width));
+
+        self.extents.clear();
+        self.close_buttons.clear();
+        let mut x = x0;
+        for (i, tab) in self.tabs.iter().enumerate() {
+            // Tabs which don't fit take no room, so they're never hit.
+            let layout = layouts[i].as_ref().filter(|_| shown.contains(&i));
+            let Some(layout) = layout else {
+                self.close_buttons.push(None);
+                self.extents.push((x - x0, x - x0));
+                continue;
+            };
+            let metrics = layout.get_metrics();
+            let right = x + widths[i];
+            if Some(i) == self.active {
+                rt.fill_rectangle((x, y0, right, y0 + height), &active_bg);
+            } else if Some(i) == self.hot {
+                rt.fill_rectangle((x, y0, right, y0 + height), &hot_bg);
+            }
+            if tab.pinned {
+                rt.fill_rectangle((x, y0, right, y0 + PIN_HEIGHT), &fg);
+            }
+            let text_y = y0 + (height - metrics.height()) / 2.0;
+            rt.draw_text_layout((x + TAB_PAD, text_y), layout, &fg, default_text_options());
+            if tab.dirty {
+                let dot_x = x + TAB_PAD + metrics.width() + DIRTY_WIDTH / 2.0;
+                let dot_y = y0 + height / 2.0;
+                rt.fill_rectangle((dot_x - 3.0, dot_y - 3.0, dot_x + 3.0, dot_y + 3.0), &dirty);
+            }
+            let close_button = (!tab.pinned).then(|| {
+                let close_right = right - TAB_PAD;
+                (close_right - CLOSE_WIDTH - x0, close_right - x0)
+            });
+            // Like the tabs of browsers, only the active and hot tabs show
+            // their close button, but any can be clicked.
+            if let Some((close_left, close_right)) = close_button {
+                if Some(i) == self.active || Some(i) == self.hot {
+                    let cx = x0 + (close_left + close_right) / 2.0;
+                    let cy = y0 + height / 2.0;
+                    let (a, b) = (CLOSE_ARM, -CLOSE_ARM);
+                    rt.draw_line((cx + b, cy + b), (cx + a, cy + a), &close, 1.0, None);
+                    rt.draw_line((cx + b, cy + a), (cx + a, cy + b), &close, 1.0, None);
+                }
+            }
+            self.close_buttons.push(close_button);
+            self.extents.push((x - x0, right - x0));
+            x = right;
+        }
+        if let Some((left, right)) = self.overflow {
+            // A chevron pointing down, as on a dropdown.
+            let cx = x0 + (left + right) / 2.0;
+            let cy = y0 + height / 2.0;
+            let arm = CLOSE_ARM;
+            rt.draw_line(
+                (cx - arm, cy - arm / 2.0),
+                (cx, cy + arm / 2.0),
+                &fg,
+                1.0,
+                None,
+            );
+            rt.draw_line(
+                (cx, cy + arm / 2.0),
+                (cx + arm, cy - arm / 2.0),
+                &fg,
+                1.0,
+                None,
+            );
+        }
+        if let Some(gap) = self.drag.and_then(|drag| drag.gap) {
+            let gap_x = x0 + self.gap_x(gap);
+            let left = (gap_x - INSERTION_WIDTH / 2.0).max(x0);
+            rt.fill_rectangle((left, y0, left + INSERTION_WIDTH, y0 + height), &fg);
+        }
+        dpi::end_paint(rt);
+    }
+
+    fn layout(
+        &mut self,
+        bc: &BoxConstraints,
+        _children: &[Id],
+        _size: Option<(f32, f32)>,
+        _ctx: &mut LayoutCtx,
+    ) -> LayoutResult {
+        LayoutResult::Size(bc.constrain((f32::INFINITY, dpi::to_px(TAB_HEIGHT))))
+    }
+
+    fn mouse(&mut self, event: &MouseEvent, ctx: &mut HandlerCtx) -> bool {
+        let x = dpi::to_dips(event.x);
+        let on_overflow = self
+            .overflow
+            .map_or(false, |(left, right)| left <= x && x < right);
+        if event.which == MouseButton::Left && event.count == 1 && on_overflow {
+            ctx.send_event(TabEvent::Overflow);
+        } else if event.which == MouseButton::Left && event.count == 1 {
+            if let Some(i) = self.close_button_at(x) {
+                ctx.send_event(TabEvent::Close(self.tabs[i].view_id.clone()));
+            } else if let Some(i) = self.tab_at(x).filter(|&i| i < self.tabs.len()) {
+                ctx.send_event(TabEvent::Select(self.tabs[i].view_id.clone()));
+                self.drag = Some(TabDrag {
+                    from: i,
+                    origin: x,
+                    gap: None,
+                });
+                ctx.set_active(true);
+            }
+        } else if event.which == MouseButton::Left && event.count == 0 {
+            if let Some(TabDrag {
+                from,
+                gap: Some(gap),
+                ..
+            }) = self.drag.take()
+            {
+                // Taking the tab out moves the gaps after it back by one.
+                let to = if gap > from { gap - 1 } else { gap };
+                if to != from {
+                    ctx.send_event(TabEvent::Move { from, to });
+                }
+                ctx.invalidate();
+            }
+            ctx.set_active(false);
+        } else if event.count == 0 {
+            // Like Windows' own tabs, these act on release.
+            let tab = self.tab_at(x).and_then(|i| self.tabs.get(i));
+            match (event.which, tab) {
+                (MouseButton::Right, Some(tab)) => {
+                    ctx.send_event(TabEvent::ContextMenu(tab.view_id.clone()));
+                }
+                (MouseButton::Middle, Some(tab)) if !tab.pinned => {
+                    ctx.send_event(TabEvent::Close(tab.view_id.clone()));
+                }
+                _ => (),
+            }
+        }
+        true
+    }
+
+    fn mouse_moved(&mut self, x: f32, _y: f32, ctx: &mut HandlerCtx) {
+        let x = dpi::to_dips(x);
+        if let Some(mut drag) = self.drag {
+            if drag.gap.is_some() || (x - drag.origin).abs() > DRAG_DISTANCE {
+                drag.gap = Some(self.gap_at(x));
+            }
+            if self.drag != Some(drag) {
+                self.drag = Some(drag);
+                ctx.invalidate();
+            }
+            return;
+        }
+        let hot = self.tab_at(x);
+        if hot != self.hot {
+            self.hot = hot;
+            ctx.invalidate();
+        }
+    }
+
+    fn poke(&mut self, payload: &mut dyn Any, ctx: &mut HandlerCtx) -> bool {
+        if let Some(cmd) = payload.downcast_ref::<TabBarCommands>() {
+            match cmd {
+                TabBarCommands::SetTabs { tabs, active } => {
+                    self.tabs = tabs.clone();
+                    self.active = *active;
+                    self.hot = None;
+                    self.drag = None;
+                }
+                TabBarCommands::SetColorScheme(scheme) => {
+                    self.color_scheme = scheme.clone();
+                }
+            }
+            ctx.invalidate();
+        }
+        true
+    }
+}
+
+impl TabBar {
+    pub fn new() -> Self {
+        Self {
+            tabs: vec![],
+            active: None,
+            hot: None,
+            extents: vec![],
+            close_buttons: vec![],
+            overflow: None,
+            drag: None,
+            color_scheme: ColorScheme::default(),
+            dwrite_factory: directwrite::Factory::new().unwrap(),
+        }
+    }
+
+    pub fn ui(self, ctx: &mut Ui) -> Id {
+        ctx.add(self, &[])
+    }
+
+    /// The index of the tab at `x`, relative to the tab bar.
+    fn tab_at(&self, x: f32) -> Option<usize> {
+        self.extents
+            .iter()
+            .position(|&(left, right)| left <= x && x < right)
+    }
+
+    /// The index of the tab whose close button is at `x`, relative to the
+    /// tab bar.
+    fn close_button_at(&self, x: f32) -> Option<usize> {
+        self.close_buttons
+            .iter()
+            .position(|button| button.map_or(false, |(left, right)| left <= x && x < right))
+            .filter(|&i| i < self.tabs.len())
+    }
+
+    /// The gap between tabs nearest `x`, relative to the tab bar, from 0
+    /// before the first tab to the number of tabs after the last.
+    fn gap_at(&self, x: f32) -> usize {
+        self.extents
+            .iter()
+            .position(|&(left, right)| x < (left + right) / 2.0)
+            .unwrap_or(self.extents.len())
+    }
+
+    /// The x of a gap between tabs, relative to the tab bar.
+    fn gap_x(&self, gap: usize) -> f32 {
+        match self.extents.get(gap) {
+            Some(&(left, _)) => left,
+            None => self.extents.last().map_or(0.0, |&(_, right)| right),
+        }
+    }
+}
+
+/// The tabs which fit in `width`, given the width of each. When they don't
+/// all fit, room is left for the overflow button, and the run of tabs shown
+/// is the first one which includes the active tab.
+fn shown_tabs(widths: &[f32], width: f32, active: Option<usize>) -> Range<usize> {
+    if widths.iter().sum::<f32>() <= width {
+        return 0..widths.len();
+    }
+    let room = width - OVERFLOW_WIDTH;
+    let mut total = 0.0;
+    let fitting = widths
+        .iter()
+        .take_while(|&&w| {
+            total += w;
+            total <= room
+        })
+        .count();
+    match active {
+        Some(active) if active >= fitting && active < widths.len() => {
+            let mut first = active;
+            let mut total = widths[active];
+            while first > 0 && total + widths[first - 1] <= room {
+                first -= 1;
+                total += widths[first];
+            }
+            first..active + 1
+        }
+        // The first tab is shown even if it doesn't fit.
+        _ => 0..fitting.max(1).min(widths.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shown_tabs, OVERFLOW_WIDTH};
+
+    #[test]
+    fn tabs_which_dont_fit_overflow() {
+        let widths = [100.0; 5];
+        assert_eq!(shown_tabs(&widths, 500.0, Some(4)), 0..5);
+        let width = 300.0 + OVERFLOW_WIDTH;
+        assert_eq!(shown_tabs(&widths, width, Some(1)), 0..3);
+        assert_eq!(shown_tabs(&widths, width, None), 0..3);
+        // The active tab is always shown, with the tabs before it.
+        assert_eq!(shown_tabs(&widths, width, Some(4)), 2..5);
+        assert_eq!(shown_tabs(&widths, 50.0, Some(3)), 3..4);
+        assert_eq!(shown_tabs(&widths, 50.0, None), 0..1);
+        assert_eq!(shown_tabs(&[], 50.0, None), 0..0);
+    }
+}
@@ -0,0 +1,73 @@
+//! How the edit view's text is rendered, chosen with the `text_antialiasing`
+//! and `color_fonts` keys of the user config or from the View menu.
+//!
+//! Both kinds of antialiasing blend with the gamma and contrast DirectWrite
+//! reads from the system, as tuned in ClearType Text Tuner, so neither needs
+//! correcting here.
+
+use direct2d::enums::DrawTextOptions;
+use direct2d::RenderTarget;
+use winapi::um::d2d1::{D2D1_TEXT_ANTIALIAS_MODE_CLEARTYPE, D2D1_TEXT_ANTIALIAS_MODE_GRAYSCALE};
+
+use druid_win_shell::util::default_text_options;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Antialiasing {
+    /// Subpixel antialiasing, sharpest on LCD monitors.
+    ClearType,
+    /// Whole pixel antialiasing, for monitors ClearType suits badly, such as
+    /// rotated ones, and for those who see color fringes.
+    Grayscale,
+}
+
+impl Antialiasing {
+    /// The antialiasing named in the user config.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cleartype" => Some(Self::ClearType),
+            "grayscale" => Some(Self::Grayscale),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TextRendering {
+    pub antialiasing: Antialiasing,
+    /// Whether color fonts, such as Segoe UI Emoji, draw in color rather
+    /// than in the text's color.
+    pub color_fonts: bool,
+}
+
+impl Default for TextRendering {
+    fn default() -> Self {
+        Self {
+            antialiasing: Antialiasing::ClearType,
+            color_fonts: true,
+        }
+    }
+}
+
+impl TextRendering {
+    /// Sets the antialiasing of the text drawn to `rt` from now on.
+    pub fn apply<R: RenderTarget>(self, rt: &mut R) {
+        let mode = match self.antialiasing {
+            Antialiasing::ClearType => D2D1_TEXT_ANTIALIAS_MODE_CLEARTYPE,
+            Antialiasing::Grayscale => D2D1_TEXT_ANTIALIAS_MODE_GRAYSCALE,
+        };
+        // SAFETY: The raw pointer is valid for the lifetime of `rt`.
+        unsafe {
+            (*rt.get_raw()).SetTextAntialiasMode(mode);
+        }
+    }
+
+    /// The options to draw text with. The shell's defaults have color fonts
+    /// on wherever Windows supports them.
+    pub fn draw_options(self) -> DrawTextOptions {
+        if self.color_fonts {
+            default_text_options()
+        } else {
+            DrawTextOptions::NONE
+        }
+    }
+}
@@ -1,39 +1,82 @@
 //! A line of styled text, as much layout information precalculated as possible.
 
 use direct2d::brush::SolidColorBrush;
+use direct2d::enums::DrawTextOptions;
 use direct2d::RenderTarget;
 use directwrite::{TextFormat, TextLayout};
-use druid_win_shell::util::default_text_options;
+use std::collections::HashMap;
 use std::fmt;
+use std::ops::Range;
+use winapi::um::dwrite::{DWRITE_FONT_STYLE_ITALIC, DWRITE_TEXT_RANGE};
+use winapi::um::unknwnbase::IUnknown;
 
 use crate::linecache::{Line, StyleSpan};
+use crate::metrics::Metrics;
+use crate::offsets::{grapheme_start, next_grapheme_boundary, utf16_to_utf8};
+use crate::styles::StyleMap;
 
 pub struct TextLine {
     layout: TextLayout,
     /// This is in utf-16 code units. Can make the case it should be floats so we
     /// don't have to re-measure in draw_cursor, but whatever.
     cursor: Vec<usize>,
+    /// The length of the text without its line ending, in utf-16 code units.
+    /// Spans reaching past this include the newline.
+    len: usize,
 
     /// Style spans (internally in utf-16 code units). Arguably could be resolved
     /// to floats.
     styles: Vec<StyleSpan>,
+
+    /// The spaces and tabs, measured when laid out so that showing them is
+    /// cheap to draw.
+    whitespace: Vec<Whitespace>,
+    /// The x of each indentation guide, relative to the start of the line.
+    indent_guides: Vec<f32>,
+    /// The line height of the [`Metrics`] the line was laid out with.
+    height: f32,
+}
+
+/// A space or tab, and where it is relative to the start of the line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Whitespace {
+    x: f32,
+    width: f32,
+    tab: bool,
+}
+
+/// Which of the invisible parts of a line [`TextLine::draw_text`] shows.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Invisibles {
+    /// Lines down through the indentation, a level apart.
+    pub indent_guides: bool,
+    /// Dots for spaces and arrows for tabs.
+    pub whitespace: bool,
 }
 
 impl fmt::Debug for TextLine {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("TextLine")
             .field("cursor", &self.cursor)
+            .field("len", &self.len)
             .field("styles", &self.styles)
+            .field("whitespace", &self.whitespace)
+            .field("indent_guides", &self.indent_guides)
+            .field("height", &self.height)
             .field("layout", &"...")
             .finish()
     }
 }
 
 impl TextLine {
+    /// Lays out a line, with indentation guides every `tab_size` columns.
     pub fn create_from_line(
         line: &Line,
         factory: &directwrite::Factory,
         format: &TextFormat,
+        styles: &StyleMap,
+        tab_size: usize,
+        metrics: &Metrics,
     ) -> Self {
         let text = line.text();
         let trimmed_text = text.trim_end_matches(|c| c == '\r' || c == '\n');
@@ -44,72 +87,269 @@ impl TextLine {
             .with_height(1e6)
             .build()
             .expect("failed to construct text layout");
+        // Colors depend on the render target, so they are applied when
+        // drawing instead.
+        for span in line.styles() {
+            let Some(style) = styles.get(&span.style_id) else {
+                continue;
+            };
+            let range = text_range(span);
+            // SAFETY: The raw pointer is valid for the lifetime of `layout`.
+            unsafe {
+                let raw = &*layout.get_raw();
+                if let Some(weight) = style.weight {
+                    raw.SetFontWeight(weight, range);
+                }
+                if style.italic {
+                    raw.SetFontStyle(DWRITE_FONT_STYLE_ITALIC, range);
+                }
+                if style.underline {
+                    raw.SetUnderline(1, range);
+                }
+            }
+        }
+        let (whitespace, indent_guides) = measure_whitespace(&layout, trimmed_text, tab_size);
         Self {
             layout,
             cursor: line.cursor().to_vec(),
+            len: trimmed_text.encode_utf16().count(),
             styles: line.styles().to_vec(),
+            whitespace,
+            indent_guides,
+            height: metrics.line_height,
         }
     }
 
-    pub fn draw_bg<R: RenderTarget>(&self, rt: &mut R, x: f32, y: f32, bg: &SolidColorBrush) {
-        for style in &self.styles {
-            let maybe_start = self
-                .layout
-                .hit_test_text_position(style.range.start as u32, true);
-            let maybe_end = self
-                .layout
-                .hit_test_text_position(style.range.end as u32, true);
-            if let Some((start, end)) = maybe_start.zip(maybe_end) {
-                rt.fill_rectangle((x + start.point_x, y, x + end.point_x, y + 17.0), bg);
-            }
+    /// Draw the background of the spans with the given style.
+    ///
+    /// A span which includes the newline, as a selection running onto the
+    /// next line does, is drawn on to `right`.
+    pub fn draw_bg<R: RenderTarget>(
+        &self,
+        rt: &mut R,
+        x: f32,
+        y: f32,
+        right: f32,
+        style_id: usize,
+        bg: &SolidColorBrush,
+    ) {
+        for style in self
+            .styles
+            .iter()
+            .filter(|style| style.style_id == style_id)
+        {
+            self.draw_range_bg(rt, x, y, right, &style.range, bg);
+        }
+    }
+
+    /// Draw the background of a range of the line, in utf-16 code units,
+    /// such as an annotation's. Like [`draw_bg`](Self::draw_bg), a range
+    /// including the newline is drawn on to `right`.
+    pub fn draw_range_bg<R: RenderTarget>(
+        &self,
+        rt: &mut R,
+        x: f32,
+        y: f32,
+        right: f32,
+        range: &Range<usize>,
+        bg: &SolidColorBrush,
+    ) {
+        let maybe_start = self.layout.hit_test_text_position(range.start as u32, true);
+        let maybe_end = self.layout.hit_test_text_position(range.end as u32, true);
+        if let Some((start, end)) = maybe_start.zip(maybe_end) {
+            let end_x = if range.end > self.len {
+                right.max(x + end.point_x)
+            } else {
+                x + end.point_x
+            };
+            rt.fill_rectangle((x + start.point_x, y, end_x, y + self.height), bg);
         }
     }
 
     /// Draw the text at the specified coordinate. Does not draw background or cursor.
     ///
-    /// Note: the `fg` param will probably go away, as styles will be incorporated
-    /// into the [`TextLine`] itself.
-    pub fn draw_text<R: RenderTarget>(&self, rt: &mut R, x: f32, y: f32, fg: &SolidColorBrush) {
-        rt.draw_text_layout((x, y), &self.layout, fg, default_text_options());
+    /// Spans are drawn with the brush of their style in `style_fgs`, and the
+    /// rest of the line with `fg`. The `invisibles` shown are drawn beneath
+    /// the text with `invisible_fg`. The `options` are those of
+    /// [`TextRendering`](crate::text_rendering::TextRendering).
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_text<R: RenderTarget>(
+        &self,
+        rt: &mut R,
+        x: f32,
+        y: f32,
+        fg: &SolidColorBrush,
+        style_fgs: &HashMap<usize, SolidColorBrush>,
+        invisibles: Invisibles,
+        invisible_fg: &SolidColorBrush,
+        options: DrawTextOptions,
+    ) {
+        if invisibles.indent_guides {
+            for &guide_x in &self.indent_guides {
+                let gx = (x + guide_x).floor() + 0.5;
+                rt.draw_line((gx, y), (gx, y + self.height), invisible_fg, 1.0, None);
+            }
+        }
+        if invisibles.whitespace {
+            let mid_y = y + self.height / 2.0;
+            for ws in &self.whitespace {
+                let left = x + ws.x;
+                if ws.tab {
+                    let (start, end) = (left + 2.0, left + ws.width - 2.0);
+                    rt.draw_line((start, mid_y), (end, mid_y), invisible_fg, 1.0, None);
+                    rt.draw_line(
+                        (end - 3.0, mid_y - 3.0),
+                        (end, mid_y),
+                        invisible_fg,
+                        1.0,
+                        None,
+                    );
+                    rt.draw_line(
+                        (end - 3.0, mid_y + 3.0),
+                        (end, mid_y),
+                        invisible_fg,
+                        1.0,
+                        None,
+                    );
+                } else {
+                    let mid_x = left + ws.width / 2.0;
+                    rt.fill_rectangle(
+                        (mid_x - 1.0, mid_y - 1.0, mid_x + 1.0, mid_y + 1.0),
+                        invisible_fg,
+                    );
+                }
+            }
+        }
+        for span in &self.styles {
+            if let Some(brush) = style_fgs.get(&span.style_id) {
+                // SAFETY: The raw pointers are valid for the lifetimes of
+                // `self.layout` and `brush`, and the layout holds its own
+                // reference to the brush.
+                unsafe {
+                    (*self.layout.get_raw())
+                        .SetDrawingEffect(brush.get_raw().cast::<IUnknown>(), text_range(span));
+                }
+            }
+        }
+        rt.draw_text_layout((x, y), &self.layout, fg, options);
     }
 
-    /// Draw the carets.
-    pub fn draw_cursor<R: RenderTarget>(&self, rt: &mut R, x: f32, y: f32, fg: &SolidColorBrush) {
-        for &offset in &self.cursor {
+    /// Draw the carets. If `primary` is set, the first one is the primary
+    /// caret, which is drawn thicker and full height in `fg`; the others are
+    /// drawn thin and shorter in `secondary_fg`.
+    pub fn draw_cursor<R: RenderTarget>(
+        &self,
+        rt: &mut R,
+        x: f32,
+        y: f32,
+        primary: bool,
+        fg: &SolidColorBrush,
+        secondary_fg: &SolidColorBrush,
+    ) {
+        for (i, &offset) in self.cursor.iter().enumerate() {
             if let Some(pos) = self.layout.hit_test_text_position(offset as u32, true) {
                 let xc = x + pos.point_x;
-                rt.draw_line((xc, y), (xc, y + 17.0), fg, 1.0, None);
+                if primary && i == 0 {
+                    rt.draw_line((xc, y), (xc, y + self.height), fg, 2.0, None);
+                } else {
+                    let inset = (self.height * 0.15).round();
+                    let (top, bottom) = (y + inset, y + self.height - inset);
+                    rt.draw_line((xc, top), (xc, bottom), secondary_fg, 1.0, None);
+                }
             }
         }
     }
 
-    /// Return the utf-8 offset corresponding to the point (relative to top left corner).
+    /// The x of the first caret, relative to the start of the line.
+    pub fn caret_x(&self) -> Option<f32> {
+        self.offset_x(*self.cursor.first()?)
+    }
+
+    /// The x of a utf-16 offset, relative to the start of the line.
+    pub fn offset_x(&self, offset: usize) -> Option<f32> {
+        let pos = self.layout.hit_test_text_position(offset as u32, true)?;
+        Some(pos.point_x)
+    }
+
+    /// Return the utf-8 offset corresponding to the point (relative to top left corner),
+    /// at a grapheme cluster boundary. The right half of a cluster is after it,
+    /// as is anywhere past the end of the line.
     ///
     /// The `text` parameter is for utf-16 to utf-8 conversion, and is to avoid having
     /// to stash a separate copy.
     pub fn hit_test(&self, x: f32, y: f32, text: &str) -> usize {
         let hit = self.layout.hit_test_point(x, y);
         let utf16_offset = hit.metrics.text_position() as usize;
-        conv_utf16_to_utf8_offset(text, utf16_offset)
-        // TODO(Olive): if hit.is_trailing_hit is true, we want the next grapheme cluster
-        // boundary (requires wiring up unicode segmentation crate).
+        let offset = grapheme_start(text, utf16_to_utf8(text, utf16_offset));
+        if hit.is_trailing_hit {
+            // Never past the line ending, even on an empty line.
+            let len = text.trim_end_matches(['\r', '\n']).len();
+            next_grapheme_boundary(text, offset).min(len).max(offset)
+        } else {
+            offset
+        }
     }
 }
 
-/// Convert utf-16 code unit offset to utf-8 code unit offset.
-fn conv_utf16_to_utf8_offset(s: &str, utf16_offset: usize) -> usize {
-    let mut utf16_count = 0;
-    for (i, &b) in s.as_bytes().iter().enumerate() {
-        // TODO(Olive) - I can fix you.
-        if b as i8 >= -0x40 {
-            utf16_count += 1;
-        }
-        if b >= 0xf0 {
-            utf16_count += 1;
-        }
-        if utf16_count > utf16_offset {
-            return i;
+/// Measures the spaces and tabs of laid out text, and where its indentation
+/// guides go. A guide is at the start of each whole level of indentation.
+fn measure_whitespace(
+    layout: &TextLayout,
+    text: &str,
+    tab_size: usize,
+) -> (Vec<Whitespace>, Vec<f32>) {
+    let x_at = |offset: usize| {
+        layout
+            .hit_test_text_position(offset as u32, true)
+            .map_or(0.0, |pos| pos.point_x)
+    };
+    let tab_size = tab_size.max(1);
+    let mut whitespace = vec![];
+    // The columns of the indentation, and the column and x of each level.
+    let mut indent_cols = 0;
+    let mut levels = vec![];
+    let mut leading = true;
+    let mut offset = 0;
+    for ch in text.chars() {
+        if ch == ' ' || ch == '\t' {
+            let x = x_at(offset);
+            whitespace.push(Whitespace {
+                x,
+                width: x_at(offset + 1) - x,
+                tab: ch == '\t',
+            });
+            if leading {
+                if indent_cols % tab_size == 0 {
+                    levels.push((indent_cols, x));
+                }
+                indent_cols += if ch == '\t' {
+                    tab_size - indent_cols % tab_size
+                } else {
+                    1
+                };
+            }
+        } else {
+            leading = false;
         }
+        offset += ch.len_utf16();
+    }
+    // Only whole levels get a guide, and a line of only whitespace has no
+    // text to guide.
+    let indent_guides = if leading {
+        vec![]
+    } else {
+        levels
+            .into_iter()
+            .filter(|&(col, _)| col + tab_size <= indent_cols)
+            .map(|(_, x)| x)
+            .collect()
+    };
+    (whitespace, indent_guides)
+}
+
+fn text_range(span: &StyleSpan) -> DWRITE_TEXT_RANGE {
+    DWRITE_TEXT_RANGE {
+        startPosition: span.range.start as u32,
+        length: (span.range.end - span.range.start) as u32,
     }
-    s.len()
 }
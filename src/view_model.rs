@@ -0,0 +1,444 @@
+//! The parts of the edit view which don't draw: scrolling, which lines are
+//! showing, what the built-in keys do, and the notifications waiting for
+//! core. They are kept apart from [`EditView`](crate::edit_view::EditView)
+//! so that they can be tested without a window or a render target.
+
+use std::ops::Range;
+
+use serde_json::Value;
+
+use winapi::um::winuser::{
+    VK_BACK, VK_DELETE, VK_DOWN, VK_END, VK_ESCAPE, VK_HOME, VK_LEFT, VK_NEXT, VK_PRIOR, VK_RETURN,
+    VK_RIGHT, VK_TAB, VK_UP,
+};
+
+use druid_win_shell::window::{M_ALT, M_CTRL, M_SHIFT};
+
+use crate::metrics::Metrics;
+
+/// The modifiers of the arrow keys which grow a block selection, by a
+/// selection on the line above or below, or by a character on each line.
+const BLOCK_MODS: u32 = M_CTRL | M_ALT | M_SHIFT;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditViewModel {
+    /// In dips, down the document, which is too far for an `f32` to count
+    /// single dips in a huge file.
+    pub scroll_offset: f64,
+    pub size: (f32, f32), // in dips, see `dpi`
+    /// The spacing of the text, measured along with the text format.
+    pub metrics: Metrics,
+    /// The lines kept showing above and below the caret when scrolling to
+    /// it, set by the `scroll_off` key of the user config.
+    pub scroll_off: usize,
+    /// Whether the view scrolls on until the last line is at the top, set by
+    /// the `scroll_past_end` key of the user config. Otherwise it stops with
+    /// the last line at the bottom.
+    pub scroll_past_end: bool,
+    /// Edit notifications made before the view had a connection to core,
+    /// as method and params, sent in order once it has one.
+    pub pending: Vec<(String, Value)>,
+}
+
+impl Default for EditViewModel {
+    fn default() -> Self {
+        Self {
+            scroll_offset: 0.0,
+            size: (0.0, 0.0),
+            metrics: Metrics::default(),
+            scroll_off: 0,
+            scroll_past_end: true,
+            pending: vec![],
+        }
+    }
+}
+
+impl EditViewModel {
+    /// How far a document of `total_lines` lines scrolls.
+    pub fn max_scroll(&self, total_lines: usize) -> f64 {
+        if self.scroll_past_end {
+            self.metrics.line_top(total_lines.saturating_sub(1))
+        } else {
+            (self.metrics.line_top(total_lines) - f64::from(self.size.1)).max(0.0)
+        }
+    }
+
+    pub fn constrain_scroll(&mut self, total_lines: usize) {
+        let max_scroll = self.max_scroll(total_lines);
+        if self.scroll_offset < 0.0 {
+            self.scroll_offset = 0.0;
+        } else if self.scroll_offset > max_scroll {
+            self.scroll_offset = max_scroll;
+        }
+    }
+
+    // Takes y in screen-space px.
+    pub fn y_to_line(&self, y: f32, total_lines: usize) -> usize {
+        let line = self.metrics.line_at(y, self.scroll_offset);
+        line.min(total_lines)
+    }
+
+    /// The lines showing, wholly or in part.
+    pub fn lines_showing(&self, total_lines: usize) -> Range<usize> {
+        let first = self.y_to_line(0.0, total_lines);
+        let last = (self.y_to_line(self.size.1, total_lines) + 1).min(total_lines);
+        first..last
+    }
+
+    /// Where to scroll from `offset` to bring a line into view, with
+    /// `scroll_off` lines of context around it, or `None` if it is in view
+    /// already.
+    pub fn scroll_to_line(&self, line: usize, offset: f64) -> Option<f64> {
+        let Metrics { line_height, .. } = self.metrics;
+        let height = f64::from(self.size.1);
+        // The context lines are cut back in a view too short for them, so
+        // the line itself always fits.
+        let context = f64::from(
+            (self.scroll_off as f32 * line_height)
+                .min((self.size.1 - line_height) / 2.0)
+                .max(0.0),
+        );
+        let top = self.metrics.line_top(line) - context;
+        let bottom = self.metrics.line_top(line + 1) + context;
+        if top < offset {
+            Some(top)
+        } else if bottom > offset + height {
+            Some(bottom - height)
+        } else {
+            None
+        }
+    }
+
+    /// The lines to keep loaded for a view scrolled to `scroll_offset`,
+    /// those showing and a page either side.
+    pub fn lines_to_keep(&self, scroll_offset: f64) -> Range<usize> {
+        let Metrics {
+            line_height,
+            top_pad,
+            ..
+        } = self.metrics;
+        let first =
+            ((scroll_offset - f64::from(top_pad)) / f64::from(line_height)).max(0.0) as usize;
+        let page = (self.size.1 / line_height) as usize + 1;
+        first.saturating_sub(page)..first + 2 * page
+    }
+}
+
+/// What one of the built-in keys does.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyAction {
+    /// Sends an edit action to core. With `bell`, the action has nowhere to
+    /// go, as the carets are already at that end of the document.
+    Send { action: &'static str, bell: bool },
+    /// Scrolls by a number of lines, leaving the carets where they are.
+    ScrollLines(i32),
+}
+
+impl KeyAction {
+    const fn send(action: &'static str) -> Self {
+        Self::Send {
+            action,
+            bell: false,
+        }
+    }
+}
+
+/// What a key does, if it is one of the built-in keys. `at_edge` says
+/// whether the carets are at the end of the document (given `true`) or at
+/// the start (given `false`), and is only asked when it matters.
+pub fn key_action(vk_code: i32, mods: u32, at_edge: impl Fn(bool) -> bool) -> Option<KeyAction> {
+    // Moving the carets without a selection toward an edge they are at
+    // rings the bell.
+    let bell_if = |forward: bool| mods == 0 && at_edge(forward);
+    let action = match vk_code {
+        VK_RETURN => {
+            // TODO(Olive): modifiers are variants of open
+            KeyAction::send("insert_newline")
+        }
+        VK_TAB => {
+            // TODO(Olive): modified versions
+            KeyAction::send("insert_tab")
+        }
+        VK_UP if mods == BLOCK_MODS => KeyAction::send("add_selection_above"),
+        VK_DOWN if mods == BLOCK_MODS => KeyAction::send("add_selection_below"),
+        VK_LEFT if mods == BLOCK_MODS => KeyAction::send("move_left_and_modify_selection"),
+        VK_RIGHT if mods == BLOCK_MODS => KeyAction::send("move_right_and_modify_selection"),
+        VK_UP if mods == M_CTRL => KeyAction::ScrollLines(-1),
+        VK_DOWN if mods == M_CTRL => KeyAction::ScrollLines(1),
+        // NOTE: The goal column for vertical movement is tracked by core, so
+        // nothing may send a gesture between moves.
+        // TODO(Olive): swap line up is ctrl + shift
+        VK_UP => KeyAction::Send {
+            action: s(mods, "move_up", "move_up_and_modify_selection"),
+            bell: bell_if(false),
+        },
+        VK_DOWN => KeyAction::Send {
+            action: s(mods, "move_down", "move_down_and_modify_selection"),
+            bell: bell_if(true),
+        },
+        // TODO(Olive): there is a subtle distinction between alt and ctrl
+        VK_LEFT => KeyAction::Send {
+            action: if (mods & (M_ALT | M_CTRL)) == 0 {
+                s(mods, "move_left", "move_left_and_modify_selection")
+            } else {
+                s(
+                    mods,
+                    "move_word_left",
+                    "move_word_left_and_modify_selection",
+                )
+            },
+            bell: bell_if(false),
+        },
+        VK_RIGHT => KeyAction::Send {
+            action: if (mods & (M_ALT | M_CTRL)) == 0 {
+                s(mods, "move_right", "move_right_and_modify_selection")
+            } else {
+                s(
+                    mods,
+                    "move_word_right",
+                    "move_word_right_and_modify_selection",
+                )
+            },
+            bell: bell_if(true),
+        },
+        VK_PRIOR => KeyAction::send(s(mods, "scroll_page_up", "page_up_and_modify_selection")),
+        VK_NEXT => KeyAction::send(s(
+            mods,
+            "scroll_page_down",
+            "page_down_and_modify_selection",
+        )),
+        VK_HOME => KeyAction::send(if (mods & M_CTRL) == 0 {
+            s(
+                mods,
+                "move_to_left_end_of_line",
+                "move_to_left_end_of_line_and_modify_selection",
+            )
+        } else {
+            s(
+                mods,
+                "move_to_beginning_of_document",
+                "move_to_beginning_of_document_and_modify_selection",
+            )
+        }),
+        VK_END => KeyAction::send(if (mods & M_CTRL) == 0 {
+            s(
+                mods,
+                "move_to_right_end_of_line",
+                "move_to_right_end_of_line_and_modify_selection",
+            )
+        } else {
+            s(
+                mods,
+                "move_to_end_of_document",
+                "move_to_end_of_document_and_modify_selection",
+            )
+        }),
+        VK_ESCAPE => KeyAction::send("cancel_operation"),
+        VK_BACK => KeyAction::Send {
+            action: if (mods & M_CTRL) == 0 {
+                "delete_backward"
+            } else {
+                // should be "delete to beginning of paragraph" but not supported
+                s(mods, "delete_word_backward", "delete_to_beginning_of_line")
+            },
+            bell: at_edge(false),
+        },
+        VK_DELETE => KeyAction::Send {
+            action: if (mods & M_CTRL) == 0 {
+                // TODO(Olive): shift-delete should be "delete line"
+                "delete_forward"
+            } else {
+                s(mods, "delete_word_forward", "delete_to_end_of_paragraph")
+            },
+            bell: at_edge(true),
+        },
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// What typing a character does when brackets and quotes are auto-closed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Typed {
+    /// Inserted as it is.
+    Insert,
+    /// Inserted with the given closing character after it, and the caret
+    /// left between them.
+    Pair(char),
+    /// Typed over the same closing character already after the caret.
+    Skip,
+}
+
+/// What typing `ch` between `before` and `after`, the characters either
+/// side of the caret, does when brackets and quotes are auto-closed.
+pub fn typed(ch: char, before: Option<char>, after: Option<char>) -> Typed {
+    let closer = match ch {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' | '\'' | '`' => Some(ch),
+        _ => None,
+    };
+    let is_closer = matches!(ch, ')' | ']' | '}' | '"' | '\'' | '`');
+    if is_closer && after == Some(ch) {
+        return Typed::Skip;
+    }
+    let Some(closer) = closer else {
+        return Typed::Insert;
+    };
+    // Only where nothing would end up inside the pair, which is typed in
+    // front of a word more often than around it.
+    let open_after = after.map_or(true, |c| c.is_whitespace() || matches!(c, ')' | ']' | '}'));
+    // A quote after a word is more likely an apostrophe, or closing one
+    // opened before.
+    let quote_after_word = closer == ch && before.map_or(false, |c| c.is_alphanumeric() || c == ch);
+    if open_after && !quote_after_word {
+        Typed::Pair(closer)
+    } else {
+        Typed::Insert
+    }
+}
+
+// Helper function for choosing between normal and shifted action
+const fn s<'a>(mods: u32, normal: &'a str, shifted: &'a str) -> &'a str {
+    if (mods & M_SHIFT) == 0 {
+        normal
+    } else {
+        shifted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{key_action, typed, EditViewModel, KeyAction, Typed, BLOCK_MODS};
+    use druid_win_shell::window::{M_ALT, M_CTRL, M_SHIFT};
+    use winapi::um::winuser::{VK_BACK, VK_DOWN, VK_F5, VK_HOME, VK_LEFT, VK_RIGHT, VK_UP};
+
+    fn action(vk_code: i32, mods: u32) -> Option<KeyAction> {
+        key_action(vk_code, mods, |_| false)
+    }
+
+    fn send(action: &'static str) -> Option<KeyAction> {
+        Some(KeyAction::Send {
+            action,
+            bell: false,
+        })
+    }
+
+    #[test]
+    fn keys_pick_their_actions() {
+        assert_eq!(action(VK_LEFT, 0), send("move_left"));
+        assert_eq!(
+            action(VK_LEFT, M_SHIFT),
+            send("move_left_and_modify_selection")
+        );
+        assert_eq!(action(VK_LEFT, M_CTRL), send("move_word_left"));
+        assert_eq!(action(VK_RIGHT, M_ALT), send("move_word_right"));
+        assert_eq!(
+            action(VK_RIGHT, M_CTRL | M_SHIFT),
+            send("move_word_right_and_modify_selection")
+        );
+        assert_eq!(
+            action(VK_HOME, M_CTRL | M_SHIFT),
+            send("move_to_beginning_of_document_and_modify_selection")
+        );
+        assert_eq!(action(VK_UP, M_CTRL), Some(KeyAction::ScrollLines(-1)));
+        assert_eq!(action(VK_DOWN, M_CTRL), Some(KeyAction::ScrollLines(1)));
+        assert_eq!(action(VK_UP, BLOCK_MODS), send("add_selection_above"));
+        assert_eq!(
+            action(VK_RIGHT, BLOCK_MODS),
+            send("move_right_and_modify_selection")
+        );
+        assert_eq!(action(VK_BACK, M_CTRL), send("delete_word_backward"));
+        assert_eq!(action(VK_F5, 0), None);
+    }
+
+    #[test]
+    fn moving_past_an_edge_rings_the_bell() {
+        let at_start = |forward: bool| !forward;
+        let rings = |vk_code, mods| {
+            matches!(
+                key_action(vk_code, mods, at_start),
+                Some(KeyAction::Send { bell: true, .. })
+            )
+        };
+        assert!(rings(VK_UP, 0));
+        assert!(rings(VK_LEFT, 0));
+        assert!(rings(VK_BACK, 0));
+        assert!(!rings(VK_DOWN, 0));
+        assert!(!rings(VK_RIGHT, 0));
+        // Extending a selection toward the edge isn't a mistake.
+        assert!(!rings(VK_LEFT, M_SHIFT));
+    }
+
+    fn model(height: f32) -> EditViewModel {
+        EditViewModel {
+            size: (400.0, height),
+            ..EditViewModel::default()
+        }
+    }
+
+    #[test]
+    fn scrolling_is_clamped_to_the_document() {
+        // 100 lines of 17 dips, below 6 dips of padding.
+        let mut model = model(170.0);
+        model.scroll_offset = -50.0;
+        model.constrain_scroll(100);
+        assert_eq!(model.scroll_offset, 0.0);
+
+        // Past the end, the last line can be scrolled to the top.
+        model.scroll_offset = 1e6;
+        model.constrain_scroll(100);
+        assert_eq!(model.scroll_offset, 6.0 + 99.0 * 17.0);
+        assert_eq!(model.lines_showing(100), 99..100);
+
+        // Otherwise it stops at the bottom.
+        model.scroll_past_end = false;
+        model.constrain_scroll(100);
+        assert_eq!(model.scroll_offset, 6.0 + 100.0 * 17.0 - 170.0);
+
+        // A document shorter than the view doesn't scroll.
+        model.constrain_scroll(3);
+        assert_eq!(model.scroll_offset, 0.0);
+        assert_eq!(model.lines_showing(3), 0..3);
+    }
+
+    #[test]
+    fn scrolling_to_a_line_keeps_context() {
+        let mut model = model(170.0);
+        assert_eq!(model.scroll_to_line(5, 0.0), None);
+        // Just below the view, the line comes in at the bottom.
+        assert_eq!(
+            model.scroll_to_line(20, 0.0),
+            Some(6.0 + 21.0 * 17.0 - 170.0)
+        );
+
+        model.scroll_off = 2;
+        assert_eq!(
+            model.scroll_to_line(8, 0.0),
+            Some(6.0 + 11.0 * 17.0 - 170.0)
+        );
+        assert_eq!(model.scroll_to_line(10, 170.0), Some(6.0 + 8.0 * 17.0));
+        // Too much context for the view is cut back to keep the line in it.
+        model.scroll_off = 100;
+        let top = model.scroll_to_line(50, 0.0).unwrap();
+        assert!(top <= model.metrics.line_top(50));
+        assert!(top + 170.0 >= model.metrics.line_top(51));
+    }
+
+    #[test]
+    fn brackets_and_quotes_close_themselves() {
+        assert_eq!(typed('(', None, None), Typed::Pair(')'));
+        assert_eq!(typed('{', Some(' '), Some(')')), Typed::Pair('}'));
+        assert_eq!(typed('"', Some('('), Some('\n')), Typed::Pair('"'));
+        // Not in front of a word, nor for an apostrophe.
+        assert_eq!(typed('[', None, Some('x')), Typed::Insert);
+        assert_eq!(typed('\'', Some('n'), Some('t')), Typed::Insert);
+        assert_eq!(typed('\'', Some('n'), None), Typed::Insert);
+        // Closing characters type over the ones already there.
+        assert_eq!(typed(')', Some('('), Some(')')), Typed::Skip);
+        assert_eq!(typed('"', Some('x'), Some('"')), Typed::Skip);
+        assert_eq!(typed(')', None, None), Typed::Insert);
+        assert_eq!(typed('a', None, Some('a')), Typed::Insert);
+    }
+}
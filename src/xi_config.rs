@@ -0,0 +1,47 @@
+//! The config directory, `%APPDATA%\xi-win`, which is handed to core in
+//! `client_started`. Core reads the user's settings from
+//! `preferences.xiconfig` in it, and those of a language from a file named
+//! after the language, such as `Rust.xiconfig`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where the app's own settings files live as well as core's.
+pub fn config_dir() -> PathBuf {
+    env::var_os("APPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join("xi-win")
+}
+
+/// Whether the user's settings for `language` set the indentation, which
+/// then wins over what is detected from a file's content.
+pub fn overrides_indentation(language: &str) -> bool {
+    let path = config_dir().join(format!("{language}.xiconfig"));
+    fs::read_to_string(path).map_or(false, |toml| sets_indentation(&toml))
+}
+
+/// Whether a TOML table has a top-level indentation key. Only the keys are
+/// looked at, as the values are core's to parse.
+fn sets_indentation(toml: &str) -> bool {
+    toml.lines()
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, _)| matches!(key.trim(), "tab_size" | "translate_tabs_to_spaces"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indentation_keys_are_found() {
+        assert!(sets_indentation("tab_size = 2\n"));
+        assert!(sets_indentation(
+            "font_size = 12\ntranslate_tabs_to_spaces=false\n"
+        ));
+        assert!(!sets_indentation("font_size = 12\n# tab_size\n"));
+        assert!(!sets_indentation("[other]\ntab_size = 2\n"));
+    }
+}
@@ -15,13 +15,22 @@ use serde_json::{self, Value};
 use xi_core_lib::XiCore;
 use xi_rpc::RpcLoop;
 
+use crate::rpc_trace;
+
 #[derive(Debug)]
 pub struct XiPeer {
     tx: Sender<String>,
 }
 
 impl XiPeer {
+    /// A peer which sends messages to `tx` instead of a running core, as
+    /// when replaying a recording or in tests.
+    pub fn from_sender(tx: Sender<String>) -> Self {
+        Self { tx }
+    }
+
     pub fn send(&self, s: String) {
+        rpc_trace::to_core(&s);
         let _ignore = self.tx.send(s);
     }
 
@@ -91,6 +100,7 @@ impl Write for ChanWriter {
 
     fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
         let json = serde_json::from_slice::<Value>(buf).unwrap();
+        rpc_trace::from_core(&json);
         //thread::sleep(Duration::from_secs(1));
         self.sender
             .send(json)